@@ -0,0 +1,361 @@
+//! A client for the bunny.net management API at api.bunny.net, which looks
+//! after pull zones, storage zones and the account rather than files. It
+//! authenticates with the account API key, which is not the same as a
+//! storage zone password.
+
+use anyhow::{Result, anyhow};
+use chrono::NaiveDate;
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::{Map, Value, json};
+use std::fmt;
+use ureq::{
+    Agent, Body, SendBody,
+    http::{HeaderValue, Request, Response, StatusCode},
+    middleware::MiddlewareNext,
+};
+
+use crate::storage::{self, AgentOptions};
+
+const API_URL: &str = "https://api.bunny.net";
+const ACCESS_KEY_HEADER: &str = "AccessKey";
+
+/// A pull zone as the API describes it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PullZone {
+    pub id: u64,
+    pub name: String,
+    #[serde(default)]
+    pub hostnames: Vec<Hostname>,
+    /// Edge rules as the API gives them, so they can be compared field by
+    /// field with declared ones.
+    #[serde(default)]
+    pub edge_rules: Vec<Map<String, Value>>,
+    /// Every other setting, by its API name.
+    #[serde(flatten)]
+    pub settings: Map<String, Value>,
+}
+
+impl PullZone {
+    /// The storage zone the pull zone serves, if it serves one.
+    pub fn storage_zone_id(&self) -> Option<u64> {
+        self.settings
+            .get("StorageZoneId")
+            .and_then(Value::as_u64)
+            .filter(|id| *id != 0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Hostname {
+    pub value: String,
+    /// The b-cdn.net hostname every pull zone has, which can't be removed.
+    #[serde(default)]
+    pub is_system_hostname: bool,
+}
+
+/// A storage zone as the API describes it.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct StorageZone {
+    pub id: u64,
+    pub name: String,
+    /// The main region, like `DE` or `NY`.
+    #[serde(default)]
+    pub region: String,
+    #[serde(default)]
+    pub replication_regions: Vec<String>,
+    #[serde(default)]
+    pub storage_used: u64,
+    #[serde(default)]
+    pub files_stored: u64,
+    /// The password for the storage API, which bunnysync calls the API key.
+    #[serde(default)]
+    pub password: String,
+}
+
+/// Traffic through a pull zone over some days.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Statistics {
+    #[serde(default)]
+    pub total_bandwidth_used: u64,
+    #[serde(default)]
+    pub total_requests_served: u64,
+}
+
+/// A session with the management API.
+pub struct Client {
+    agent: Agent,
+}
+
+impl Client {
+    pub fn new(account_key: &str, options: &AgentOptions) -> Result<Self> {
+        let mut key = HeaderValue::from_str(account_key)?;
+        key.set_sensitive(true);
+        let add_key = move |mut req: Request<SendBody>,
+                            next: MiddlewareNext|
+              -> Result<Response<Body>, ureq::Error> {
+            req.headers_mut().insert(ACCESS_KEY_HEADER, key.clone());
+            next.handle(req)
+        };
+        let config = Agent::config_builder()
+            .user_agent(storage::USER_AGENT)
+            .https_only(true)
+            .http_status_as_error(false)
+            .middleware(add_key);
+        Ok(Client {
+            agent: storage::build_agent(config, API_URL, options)?,
+        })
+    }
+
+    /// The pull zone with an ID.
+    pub fn pull_zone(&self, id: u64) -> Result<PullZone> {
+        let mut response = self
+            .agent
+            .get(&format!("{}/pullzone/{}", API_URL, id))
+            .query("includeCertificate", "false")
+            .call()?;
+        read(&mut response, "get the pull zone")
+    }
+
+    /// The pull zones whose names or hostnames contain some text. An empty
+    /// search lists them all.
+    pub fn search_pull_zones(&self, search: &str) -> Result<Vec<PullZone>> {
+        let mut response = self
+            .agent
+            .get(&format!("{}/pullzone", API_URL))
+            .query("search", search)
+            .query("includeCertificate", "false")
+            .call()?;
+        Ok(read::<Page<PullZone>>(&mut response, "list pull zones")?.items())
+    }
+
+    /// The pull zone with a name.
+    pub fn find_pull_zone(&self, name: &str) -> Result<PullZone> {
+        self.search_pull_zones(name)?
+            .into_iter()
+            .find(|zone| zone.name == name)
+            .ok_or_else(|| anyhow!("No pull zone named {}", name))
+    }
+
+    /// The pull zone that answers to a hostname.
+    pub fn find_pull_zone_by_hostname(&self, hostname: &str) -> Result<PullZone> {
+        self.search_pull_zones(hostname)?
+            .into_iter()
+            .find(|zone| zone.hostnames.iter().any(|name| name.value == hostname))
+            .ok_or_else(|| anyhow!("No pull zone with hostname {}", hostname))
+    }
+
+    /// The storage zones on the account.
+    pub fn storage_zones(&self) -> Result<Vec<StorageZone>> {
+        let mut response = self.agent.get(&format!("{}/storagezone", API_URL)).call()?;
+        Ok(read::<Page<StorageZone>>(&mut response, "list storage zones")?.items())
+    }
+
+    /// The storage zone with a name.
+    pub fn find_storage_zone(&self, name: &str) -> Result<StorageZone> {
+        self.storage_zones()?
+            .into_iter()
+            .find(|zone| zone.name == name)
+            .ok_or_else(|| anyhow!("No storage zone named {}", name))
+    }
+
+    /// The traffic through a pull zone between two days, both included.
+    pub fn statistics(
+        &self,
+        pull_zone_id: u64,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Statistics> {
+        let mut response = self
+            .agent
+            .get(&format!("{}/statistics", API_URL))
+            .query("pullZone", pull_zone_id.to_string())
+            .query("dateFrom", from.to_string())
+            .query("dateTo", to.to_string())
+            .call()?;
+        read(&mut response, "get statistics")
+    }
+
+    /// Make a storage zone in a region, given by its API code like `DE`.
+    pub fn create_storage_zone(
+        &self,
+        name: &str,
+        region: &str,
+        replication_regions: &[&str],
+    ) -> Result<StorageZone> {
+        let mut response = self
+            .agent
+            .post(&format!("{}/storagezone", API_URL))
+            .send_json(json!({
+                "Name": name,
+                "Region": region,
+                "ReplicationRegions": replication_regions,
+            }))?;
+        read(&mut response, "create the storage zone")
+    }
+
+    /// Make a pull zone that serves a storage zone.
+    pub fn create_pull_zone(&self, name: &str, storage_zone_id: u64) -> Result<PullZone> {
+        let mut response = self
+            .agent
+            .post(&format!("{}/pullzone", API_URL))
+            .send_json(json!({
+                "Name": name,
+                // Origin type 2 is a storage zone.
+                "OriginType": 2,
+                "StorageZoneId": storage_zone_id,
+            }))?;
+        read(&mut response, "create the pull zone")
+    }
+
+    pub fn update_pull_zone(&self, id: u64, settings: &Map<String, Value>) -> Result<()> {
+        let response = self
+            .agent
+            .post(&pull_zone_url(id, ""))
+            .send_json(settings)?;
+        check(&response, "update pull zone")
+    }
+
+    pub fn add_hostname(&self, id: u64, hostname: &str) -> Result<()> {
+        let response = self
+            .agent
+            .post(&pull_zone_url(id, "/addHostname"))
+            .send_json(json!({ "Hostname": hostname }))?;
+        check(&response, "update pull zone")
+    }
+
+    pub fn remove_hostname(&self, id: u64, hostname: &str) -> Result<()> {
+        let response = self
+            .agent
+            .delete(&pull_zone_url(id, "/removeHostname"))
+            .force_send_body()
+            .send_json(json!({ "Hostname": hostname }))?;
+        check(&response, "update pull zone")
+    }
+
+    /// Add an edge rule, or update the one with the rule's `Guid`.
+    pub fn save_edge_rule(&self, id: u64, rule: &Map<String, Value>) -> Result<()> {
+        let response = self
+            .agent
+            .post(&pull_zone_url(id, "/edgerules/addOrUpdate"))
+            .send_json(rule)?;
+        check(&response, "update pull zone")
+    }
+
+    pub fn remove_edge_rule(&self, id: u64, guid: &str) -> Result<()> {
+        let response = self
+            .agent
+            .delete(&pull_zone_url(id, &format!("/edgerules/{}", guid)))
+            .call()?;
+        check(&response, "update pull zone")
+    }
+
+    /// Purge one URL from the CDN cache.
+    pub fn purge_url(&self, url: &str) -> Result<()> {
+        let response = self
+            .agent
+            .post(&format!("{}/purge", API_URL))
+            .query("url", url)
+            .send_empty()?;
+        check(&response, "purge the CDN cache")
+    }
+
+    /// Purge everything a pull zone has cached.
+    pub fn purge_pull_zone(&self, id: u64) -> Result<()> {
+        let response = self
+            .agent
+            .post(&pull_zone_url(id, "/purgeCache"))
+            .send_empty()?;
+        check(&response, "purge the CDN cache")
+    }
+}
+
+/// A list the API may page, wrapping it in `Items`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Page<T> {
+    Paged {
+        #[serde(rename = "Items")]
+        items: Vec<T>,
+    },
+    All(Vec<T>),
+}
+
+impl<T> Page<T> {
+    fn items(self) -> Vec<T> {
+        match self {
+            Page::Paged { items } | Page::All(items) => items,
+        }
+    }
+}
+
+fn pull_zone_url(id: u64, rest: &str) -> String {
+    format!("{}/pullzone/{}{}", API_URL, id, rest)
+}
+
+/// A request the account key was turned down for.
+#[derive(Debug)]
+pub struct Unauthorized;
+
+impl fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Account API unauthorized, check the account key")
+    }
+}
+
+impl std::error::Error for Unauthorized {}
+
+fn check<B>(response: &Response<B>, what: &str) -> Result<()> {
+    match response.status() {
+        StatusCode::UNAUTHORIZED => Err(Unauthorized.into()),
+        status if status.is_success() => Ok(()),
+        status => Err(anyhow!("Failed to {}: HTTP {}", what, status)),
+    }
+}
+
+fn read<T: DeserializeOwned>(response: &mut Response<Body>, what: &str) -> Result<T> {
+    check(response, what)?;
+    Ok(response.body_mut().read_json()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pull_zone() {
+        let zone: PullZone = serde_json::from_value(json!({
+            "Id": 7,
+            "Name": "site",
+            "CacheControlMaxAgeOverride": 60,
+            "Hostnames": [{ "Value": "site.b-cdn.net", "IsSystemHostname": true }],
+            "EdgeRules": [{ "Guid": "g1", "Description": "https" }],
+        }))
+        .unwrap();
+        assert_eq!(zone.id, 7);
+        assert_eq!(
+            zone.hostnames,
+            vec![Hostname {
+                value: "site.b-cdn.net".to_string(),
+                is_system_hostname: true,
+            }]
+        );
+        assert_eq!(zone.edge_rules[0]["Guid"], "g1");
+        assert_eq!(zone.settings["CacheControlMaxAgeOverride"], 60);
+        assert_eq!(zone.storage_zone_id(), None);
+        assert!(!zone.settings.contains_key("Hostnames"));
+    }
+
+    #[test]
+    fn test_page() {
+        let zones = json!([{ "Id": 1, "Name": "a" }]);
+        let all: Page<StorageZone> = serde_json::from_value(zones.clone()).unwrap();
+        let paged: Page<StorageZone> = serde_json::from_value(json!({ "Items": zones })).unwrap();
+        let paged = paged.items();
+        assert_eq!(all.items(), paged);
+        assert_eq!(paged[0].name, "a");
+    }
+}
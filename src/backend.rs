@@ -0,0 +1,46 @@
+//! Storage outside bunny.net that `bunnysync export` can copy a zone into.
+//! Each kind of storage is named by the scheme of its URL.
+
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+
+use crate::{s3::Bucket, webdav::WebDav};
+
+/// A file in a backend, under the directory being exported to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackendFile {
+    pub url: String,
+    /// The path below the directory, like `css/site.css`.
+    pub relative: String,
+    pub length: u64,
+    pub last_modified: DateTime<Utc>,
+}
+
+/// A directory in some storage that files can be listed, written and
+/// deleted in. Files are named by URL, as [`Backend::url_for`] makes them.
+pub trait Backend {
+    /// The URL of the file at a path below the directory.
+    fn url_for(&self, relative: &str) -> String;
+
+    /// Every file below the directory.
+    fn list(&self) -> Result<Vec<BackendFile>>;
+
+    /// Write a file, replacing any that is there.
+    fn upload(&self, url: &str, data: &[u8]) -> Result<()>;
+
+    /// Delete a file. One that is already gone is not an error.
+    fn delete(&self, url: &str) -> Result<()>;
+}
+
+/// Open the backend a URL names: `s3://` for S3 compatible buckets and
+/// `http://` or `https://` for WebDAV servers.
+pub fn open(url: &str) -> Result<Box<dyn Backend>> {
+    match url.split_once("://").map(|(scheme, _)| scheme) {
+        Some("s3") => Ok(Box::new(Bucket::from_url(url)?)),
+        Some("http" | "https") => Ok(Box::new(WebDav::from_url(url)?)),
+        _ => Err(anyhow!(
+            "{} is not an s3://, http:// or https:// URL to export to",
+            url
+        )),
+    }
+}
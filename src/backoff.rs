@@ -0,0 +1,171 @@
+//! Backing off from directories where writes run into contention.
+//!
+//! bunny.net sometimes turns writes away with a 409 or a 5xx when many of
+//! them hit one directory at once. Failures are tracked per directory, and
+//! while a directory has recent failures its writes wait out a growing delay
+//! and run one at a time. Writes to other directories carry on as normal.
+
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    i18n::{Message, tr},
+    storage::Contention,
+};
+
+/// How many times a write is tried before giving up.
+const MAX_ATTEMPTS: u32 = 5;
+/// The longest a write waits before trying again.
+const MAX_DELAY: Duration = Duration::from_secs(8);
+
+pub struct Backoff {
+    base_delay: Duration,
+    prefixes: Mutex<HashMap<String, Arc<Prefix>>>,
+}
+
+/// The contention seen in one directory.
+#[derive(Default)]
+struct Prefix {
+    state: Mutex<PrefixState>,
+    /// Held for the duration of a write while the directory is contended.
+    serial: Mutex<()>,
+}
+
+#[derive(Default)]
+struct PrefixState {
+    /// Recent failures. Each success takes one away.
+    failures: u32,
+    /// No write starts before this.
+    resume_at: Option<Instant>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::new(Duration::from_millis(250))
+    }
+}
+
+impl Backoff {
+    pub fn new(base_delay: Duration) -> Self {
+        Backoff {
+            base_delay,
+            prefixes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Run a write to a remote path, retrying it while it fails with
+    /// [`Contention`]. Other errors are returned straight away.
+    pub fn run<T>(&self, path: &str, mut write: impl FnMut() -> Result<T>) -> Result<T> {
+        let prefix = self.prefix(path);
+        let mut attempt = 1;
+        loop {
+            let resume_at = prefix.state.lock().unwrap().resume_at;
+            if let Some(wait) = resume_at.and_then(|at| at.checked_duration_since(Instant::now())) {
+                thread::sleep(wait);
+            }
+            let result = if prefix.state.lock().unwrap().failures > 0 {
+                let _serial = prefix.serial.lock().unwrap();
+                write()
+            } else {
+                write()
+            };
+
+            let mut state = prefix.state.lock().unwrap();
+            match result {
+                Err(e) if e.is::<Contention>() && attempt < MAX_ATTEMPTS => {
+                    state.failures += 1;
+                    let delay = self
+                        .base_delay
+                        .saturating_mul(1 << (state.failures - 1).min(16))
+                        .min(MAX_DELAY);
+                    state.resume_at = Some(Instant::now() + delay);
+                    drop(state);
+                    eprintln!("{}", tr(Message::Retrying { error: &e }));
+                    attempt += 1;
+                }
+                result => {
+                    if result.is_ok() {
+                        state.failures = state.failures.saturating_sub(1);
+                        if state.failures == 0 {
+                            state.resume_at = None;
+                        }
+                    }
+                    return result;
+                }
+            }
+        }
+    }
+
+    /// Whether writes to a path's directory are currently held back.
+    #[cfg(test)]
+    fn contended(&self, path: &str) -> bool {
+        self.prefix(path).state.lock().unwrap().failures > 0
+    }
+
+    fn prefix(&self, path: &str) -> Arc<Prefix> {
+        let dir = path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        let mut prefixes = self.prefixes.lock().unwrap();
+        prefixes.entry(dir.to_string()).or_default().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use ureq::http::StatusCode;
+
+    fn contention() -> anyhow::Error {
+        Contention {
+            action: "put object to",
+            url: "https://storage/zone/a/b.txt".to_string(),
+            status: StatusCode::CONFLICT,
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_retries_contention() {
+        let backoff = Backoff::new(Duration::from_millis(1));
+        let mut calls = 0;
+        let result = backoff.run("zone/a/b.txt", || {
+            calls += 1;
+            if calls < 3 {
+                Err(contention())
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+        // One success takes away one of the two failures.
+        assert!(backoff.contended("zone/a/c.txt"));
+        assert!(!backoff.contended("zone/other/c.txt"));
+        backoff.run("zone/a/c.txt", || Ok(())).unwrap();
+        assert!(!backoff.contended("zone/a/c.txt"));
+    }
+
+    #[test]
+    fn test_gives_up() {
+        let backoff = Backoff::new(Duration::from_millis(1));
+        let mut calls = 0;
+        let result: Result<()> = backoff.run("zone/a/b.txt", || {
+            calls += 1;
+            Err(contention())
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, MAX_ATTEMPTS);
+
+        let mut calls = 0;
+        let result: Result<()> = backoff.run("zone/a/b.txt", || {
+            calls += 1;
+            Err(anyhow!("Remote unauthorized"))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+}
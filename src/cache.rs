@@ -0,0 +1,433 @@
+//! Caches kept between runs in the state directory of the local directory,
+//! and at the top of the state directory for what belongs to the account
+//! rather than a directory. They are only ever a shortcut, so a cache that
+//! is missing or can't be read is treated as empty.
+
+use anyhow::Result;
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    checksum::{Checksum, Xxh64},
+    local::LocalFile,
+    plan::{SyncAction, object_key},
+    storage::{self, StorageObject},
+    store,
+    sync::read_upload,
+    timestamp::RemoteTime,
+    transform::{Pipeline, TransformRule},
+};
+
+pub const HASHES_FILE: &str = "hashes.json";
+const LISTINGS_DIR: &str = "listings";
+const ZONES_FILE: &str = "zones.json";
+const REGIONS_FILE: &str = "regions.json";
+
+/// How long zone names are used for completion before asking again.
+const ZONES_TTL: TimeDelta = TimeDelta::minutes(10);
+
+/// The algorithm for hashes in the cache.
+const CACHE_CHECKSUM: Xxh64 = Xxh64;
+
+/// The size and hash of local files as uploaded, so that files which
+/// haven't changed since the last run needn't be read again. The hashes
+/// never leave this machine, so they use the fast [`CACHE_CHECKSUM`].
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct HashCache {
+    /// The algorithm the entries were hashed with.
+    #[serde(default)]
+    checksum: String,
+    /// The transforms in effect when the entries were made. Entries are
+    /// dropped when these change, as the uploaded data would too.
+    transforms: Vec<TransformRule>,
+    /// Entries by path relative to the local directory.
+    files: BTreeMap<String, HashEntry>,
+    /// Files forgotten since loading, which saving mustn't bring back.
+    #[serde(skip)]
+    forgotten: BTreeSet<String>,
+}
+
+/// What a file looked like when it was hashed, and the result.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HashEntry {
+    pub length: u64,
+    pub modified: DateTime<Utc>,
+    pub upload_length: u64,
+    pub hash: String,
+}
+
+impl HashCache {
+    pub fn load(dir: &Path, transforms: &Pipeline) -> Self {
+        let cache: HashCache = read(&dir.join(HASHES_FILE));
+        if cache.transforms != transforms.rules() || cache.checksum != CACHE_CHECKSUM.name() {
+            return HashCache {
+                checksum: CACHE_CHECKSUM.name().to_string(),
+                transforms: transforms.rules().to_vec(),
+                ..Default::default()
+            };
+        }
+        cache
+    }
+
+    /// Save the entries, along with any that another run saved in the
+    /// meantime for files this one didn't hash.
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        store::update(&dir.join(HASHES_FILE), |saved: &mut HashCache| {
+            if saved.checksum != self.checksum || saved.transforms != self.transforms {
+                saved.checksum = self.checksum.clone();
+                saved.transforms = self.transforms.clone();
+                saved.files.clear();
+            }
+            saved.files.retain(|key, _| !self.forgotten.contains(key));
+            saved.files.extend(self.files.clone());
+        })
+    }
+
+    /// The size and hash of a file as it will be uploaded. The file is only
+    /// read when its size or modification time differ from the cached entry.
+    pub fn upload_info(&mut self, file: &LocalFile, transforms: &Pipeline) -> Result<&HashEntry> {
+        let key = file.relative_path.to_string_lossy().into_owned();
+        let fresh = self.files.get(&key).is_some_and(|entry| {
+            entry.length == file.length && entry.modified == file.last_changed
+        });
+        if !fresh {
+            let data = read_upload(&file.path, transforms)?;
+            let entry = HashEntry {
+                length: file.length,
+                modified: file.last_changed,
+                upload_length: data.len() as u64,
+                hash: CACHE_CHECKSUM.hex(&data),
+            };
+            self.files.insert(key.clone(), entry);
+        }
+        Ok(&self.files[&key])
+    }
+
+    /// Forget files that are no longer there.
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        let forgotten = &mut self.forgotten;
+        self.files.retain(|key, _| {
+            let kept = keep(key);
+            if !kept {
+                forgotten.insert(key.clone());
+            }
+            kept
+        });
+    }
+}
+
+/// The zone listing of a remote path as last seen by a push, for
+/// `--cached-listing`. Each remote path has a file of its own, so that
+/// pushes to different zones at the same time never write the same file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ListingCache {
+    remote: String,
+    objects: Vec<StorageObject>,
+}
+
+impl ListingCache {
+    /// The cached listing of a remote path, if there is one.
+    pub fn load(dir: &Path, remote: &str) -> Option<Vec<StorageObject>> {
+        let cache: ListingCache = read(&listing_path(dir, remote));
+        (cache.remote == remote).then_some(cache.objects)
+    }
+
+    pub fn save(dir: &Path, remote: &str, objects: Vec<StorageObject>) -> Result<()> {
+        let cache = ListingCache {
+            remote: remote.to_string(),
+            objects,
+        };
+        store::write(&listing_path(dir, remote), &cache)
+    }
+
+    /// Forget the listing of a remote path.
+    pub fn remove(dir: &Path, remote: &str) -> Result<()> {
+        match std::fs::remove_file(listing_path(dir, remote)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn listing_path(dir: &Path, remote: &str) -> PathBuf {
+    let name = CACHE_CHECKSUM.hex(remote.as_bytes()).to_ascii_lowercase();
+    dir.join(LISTINGS_DIR).join(name + ".json")
+}
+
+/// The storage zone names on an account, for completing `zone://` on the
+/// command line.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ZoneCache {
+    /// A hash of the account key the names were fetched with, so that
+    /// switching accounts doesn't complete the old account's zones.
+    account: String,
+    fetched: Option<DateTime<Utc>>,
+    zones: Vec<String>,
+}
+
+impl ZoneCache {
+    pub fn new(account_key: &str, zones: Vec<String>) -> Self {
+        ZoneCache {
+            account: CACHE_CHECKSUM.hex(account_key.as_bytes()),
+            fetched: Some(Utc::now()),
+            zones,
+        }
+    }
+
+    pub fn load() -> Self {
+        store::root()
+            .map(|dir| read(&dir.join(ZONES_FILE)))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        match store::root() {
+            Some(dir) => store::write(&dir.join(ZONES_FILE), self),
+            None => Ok(()),
+        }
+    }
+
+    /// The cached names, if they are for this account and recent enough.
+    pub fn zones(&self, account_key: &str, now: DateTime<Utc>) -> Option<&[String]> {
+        let fresh = self
+            .fetched
+            .is_some_and(|fetched| now - fetched < ZONES_TTL);
+        (fresh && self.account == CACHE_CHECKSUM.hex(account_key.as_bytes()))
+            .then_some(self.zones.as_slice())
+    }
+}
+
+/// The regions storage zones were found in by `--region auto`, by zone
+/// name. A zone never moves, so they are kept for good.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RegionCache {
+    zones: BTreeMap<String, String>,
+}
+
+impl RegionCache {
+    pub fn load() -> Self {
+        store::root()
+            .map(|dir| read(&dir.join(REGIONS_FILE)))
+            .unwrap_or_default()
+    }
+
+    /// Save the regions, along with any another run found in the meantime.
+    pub fn save(&self) -> Result<()> {
+        match store::root() {
+            Some(dir) => store::update(&dir.join(REGIONS_FILE), |saved: &mut RegionCache| {
+                saved.zones.extend(self.zones.clone())
+            }),
+            None => Ok(()),
+        }
+    }
+
+    pub fn get(&self, zone: &str) -> Option<&str> {
+        self.zones.get(zone).map(String::as_str)
+    }
+
+    pub fn insert(&mut self, zone: &str, region: &str) {
+        self.zones.insert(zone.to_string(), region.to_string());
+    }
+}
+
+/// Bring a listing up to date with the actions a push carried out, leaving
+/// out the `pending` keys that were never acted on. Uploaded objects get the
+/// current time as their modification time.
+pub fn apply_actions(
+    mut objects: Vec<StorageObject>,
+    actions: &[SyncAction],
+    pending: &[String],
+) -> Vec<StorageObject> {
+    let now = RemoteTime::from_utc(Utc::now());
+    for action in actions {
+        if pending.iter().any(|key| key == action.key()) {
+            continue;
+        }
+        match action {
+            SyncAction::Upload { key, length, .. } => {
+                objects.retain(|object| object.is_directory || object_key(object) != *key);
+                let (path, name) = key.rsplit_once('/').unwrap_or(("", key));
+                objects.push(StorageObject {
+                    storage_zone_name: storage::zone_name(key),
+                    path: format!("{}/", path),
+                    object_name: name.to_string(),
+                    length: *length,
+                    last_changed: now,
+                    date_created: now,
+                    ..Default::default()
+                });
+            }
+            SyncAction::DeleteRemote { key, .. } => {
+                objects.retain(|object| object.is_directory || object_key(object) != *key);
+            }
+            _ => {}
+        }
+    }
+    objects
+}
+
+fn read<T: DeserializeOwned + Default>(path: &Path) -> T {
+    store::read(path).ok().flatten().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upload_info_uses_cache() {
+        let dir = std::env::temp_dir().join(format!("bunnysync-cache-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), "abc").unwrap();
+        let file = LocalFile {
+            relative_path: "a.txt".into(),
+            path: dir.join("a.txt"),
+            is_directory: false,
+            last_changed: DateTime::from_timestamp(100, 0).unwrap(),
+            length: 3,
+        };
+        let pipeline = Pipeline::default();
+        let mut cache = HashCache::load(&dir, &pipeline);
+        let entry = cache.upload_info(&file, &pipeline).unwrap().clone();
+        assert_eq!(entry.upload_length, 3);
+        assert_eq!(entry.hash, Xxh64.hex(b"abc"));
+        cache.save(&dir).unwrap();
+
+        // An unchanged file is answered from the cache without reading it.
+        std::fs::remove_file(dir.join("a.txt")).unwrap();
+        let mut cache = HashCache::load(&dir, &pipeline);
+        assert_eq!(cache.upload_info(&file, &pipeline).unwrap(), &entry);
+
+        // Different transforms invalidate everything.
+        let rules = [TransformRule {
+            pattern: "*.css".to_string(),
+            name: "minify-css".to_string(),
+        }];
+        let cache = HashCache::load(&dir, &Pipeline::new(&rules).unwrap());
+        assert!(cache.files.is_empty());
+
+        // So do hashes made with another algorithm.
+        let text = std::fs::read_to_string(dir.join(HASHES_FILE)).unwrap();
+        std::fs::write(
+            dir.join(HASHES_FILE),
+            text.replace("\"xxh64\"", "\"sha256\""),
+        )
+        .unwrap();
+        assert!(HashCache::load(&dir, &pipeline).files.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_runs_at_the_same_time() {
+        let dir = std::env::temp_dir().join(format!("bunnysync-runs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let entry = |hash: &str| HashEntry {
+            length: 1,
+            modified: DateTime::from_timestamp(100, 0).unwrap(),
+            upload_length: 1,
+            hash: hash.to_string(),
+        };
+        let pipeline = Pipeline::default();
+        let mut first = HashCache::load(&dir, &pipeline);
+        first.files.insert("old.txt".to_string(), entry("0"));
+        first.save(&dir).unwrap();
+
+        // Two runs load the same hashes, then each saves its own.
+        let mut first = HashCache::load(&dir, &pipeline);
+        let mut second = HashCache::load(&dir, &pipeline);
+        first.files.insert("a.txt".to_string(), entry("a"));
+        first.retain(|key| key != "old.txt");
+        second.files.insert("b.txt".to_string(), entry("b"));
+        first.save(&dir).unwrap();
+        second.save(&dir).unwrap();
+        let saved = HashCache::load(&dir, &pipeline);
+        let keys: Vec<_> = saved.files.keys().map(String::as_str).collect();
+        assert_eq!(keys, vec!["a.txt", "b.txt", "old.txt"]);
+
+        // Pushes to different zones keep their listings apart.
+        let listing = |name: &str| {
+            vec![StorageObject {
+                object_name: name.to_string(),
+                ..Default::default()
+            }]
+        };
+        ListingCache::save(&dir, "/one/", listing("a")).unwrap();
+        ListingCache::save(&dir, "/two/", listing("b")).unwrap();
+        ListingCache::remove(&dir, "/one/").unwrap();
+        assert_eq!(ListingCache::load(&dir, "/one/"), None);
+        assert_eq!(ListingCache::load(&dir, "/two/"), Some(listing("b")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_apply_actions() {
+        let object = |name: &str, length| StorageObject {
+            storage_zone_name: "myzone".to_string(),
+            path: "/myzone/css/".to_string(),
+            object_name: name.to_string(),
+            length,
+            ..Default::default()
+        };
+        let objects = vec![
+            object("old.css", 1),
+            object("site.css", 2),
+            object("kept.css", 3),
+        ];
+        let actions = vec![
+            SyncAction::Upload {
+                key: "/myzone/css/site.css".to_string(),
+                path: "site.css".into(),
+                length: 20,
+            },
+            SyncAction::Upload {
+                key: "/myzone/css/new.css".to_string(),
+                path: "new.css".into(),
+                length: 5,
+            },
+            SyncAction::DeleteRemote {
+                key: "/myzone/css/old.css".to_string(),
+                length: 1,
+            },
+            SyncAction::DeleteRemote {
+                key: "/myzone/css/kept.css".to_string(),
+                length: 3,
+            },
+        ];
+        let pending = ["/myzone/css/kept.css".to_string()];
+        let objects = apply_actions(objects, &actions, &pending);
+        let mut lengths: Vec<_> = objects
+            .iter()
+            .map(|object| {
+                (
+                    object_key(object),
+                    object.length,
+                    object.storage_zone_name.as_str(),
+                )
+            })
+            .collect();
+        lengths.sort();
+        assert_eq!(
+            lengths,
+            vec![
+                ("/myzone/css/kept.css".to_string(), 3, "myzone"),
+                ("/myzone/css/new.css".to_string(), 5, "myzone"),
+                ("/myzone/css/site.css".to_string(), 20, "myzone"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_zone_cache() {
+        let cache = ZoneCache::new("key", vec!["site".to_string()]);
+        let fetched = cache.fetched.unwrap();
+        assert_eq!(cache.zones("key", fetched), Some(&["site".to_string()][..]));
+        assert_eq!(cache.zones("other key", fetched), None);
+        assert_eq!(cache.zones("key", fetched + TimeDelta::hours(1)), None);
+        assert_eq!(ZoneCache::default().zones("key", fetched), None);
+    }
+}
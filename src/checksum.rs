@@ -0,0 +1,151 @@
+//! Hashing file contents. bunny.net reports SHA256, so anything compared
+//! with the server uses [`Sha256`]. Hashes that never leave this machine,
+//! like those in the local cache, use the much faster [`Xxh64`].
+
+use sha2::Digest;
+
+/// A hash algorithm for file contents.
+pub trait Checksum {
+    /// The algorithm's name, recorded next to hashes it made.
+    fn name(&self) -> &'static str;
+
+    /// The hash of some data as upper case hex.
+    fn hex(&self, data: &[u8]) -> String;
+}
+
+/// SHA256, the hash bunny.net reports in an object's `Checksum`.
+pub struct Sha256;
+
+impl Checksum for Sha256 {
+    fn name(&self) -> &'static str {
+        "sha256"
+    }
+
+    fn hex(&self, data: &[u8]) -> String {
+        to_hex(&sha2::Sha256::digest(data))
+    }
+}
+
+/// XXH64 with a seed of zero. Not cryptographic, but fast enough to hash
+/// files at disk speed, which is all change detection needs.
+pub struct Xxh64;
+
+impl Checksum for Xxh64 {
+    fn name(&self) -> &'static str {
+        "xxh64"
+    }
+
+    fn hex(&self, data: &[u8]) -> String {
+        format!("{:016X}", xxh64(data, 0))
+    }
+}
+
+/// The SHA256 of some data as upper case hex, the form bunny.net reports in
+/// an object's `Checksum`.
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256.hex(data)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+const PRIME64_1: u64 = 0x9E37_79B1_85EB_CA87;
+const PRIME64_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const PRIME64_3: u64 = 0x1656_67B1_9E37_79F9;
+const PRIME64_4: u64 = 0x85EB_CA77_C2B2_AE63;
+const PRIME64_5: u64 = 0x27D4_EB2F_1656_67C5;
+
+fn xxh64(data: &[u8], seed: u64) -> u64 {
+    fn round(acc: u64, lane: u64) -> u64 {
+        acc.wrapping_add(lane.wrapping_mul(PRIME64_2))
+            .rotate_left(31)
+            .wrapping_mul(PRIME64_1)
+    }
+    fn merge(acc: u64, value: u64) -> u64 {
+        (acc ^ round(0, value))
+            .wrapping_mul(PRIME64_1)
+            .wrapping_add(PRIME64_4)
+    }
+    let u64_at = |bytes: &[u8]| u64::from_le_bytes(bytes[..8].try_into().unwrap());
+    let u32_at = |bytes: &[u8]| u32::from_le_bytes(bytes[..4].try_into().unwrap()) as u64;
+
+    let mut rest = data;
+    let mut hash = if data.len() >= 32 {
+        let mut acc = [
+            seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2),
+            seed.wrapping_add(PRIME64_2),
+            seed,
+            seed.wrapping_sub(PRIME64_1),
+        ];
+        while rest.len() >= 32 {
+            for (i, acc) in acc.iter_mut().enumerate() {
+                *acc = round(*acc, u64_at(&rest[i * 8..]));
+            }
+            rest = &rest[32..];
+        }
+        let mut hash = acc[0]
+            .rotate_left(1)
+            .wrapping_add(acc[1].rotate_left(7))
+            .wrapping_add(acc[2].rotate_left(12))
+            .wrapping_add(acc[3].rotate_left(18));
+        for acc in acc {
+            hash = merge(hash, acc);
+        }
+        hash
+    } else {
+        seed.wrapping_add(PRIME64_5)
+    };
+    hash = hash.wrapping_add(data.len() as u64);
+
+    while rest.len() >= 8 {
+        hash = (hash ^ round(0, u64_at(rest)))
+            .rotate_left(27)
+            .wrapping_mul(PRIME64_1)
+            .wrapping_add(PRIME64_4);
+        rest = &rest[8..];
+    }
+    if rest.len() >= 4 {
+        hash = (hash ^ u32_at(rest).wrapping_mul(PRIME64_1))
+            .rotate_left(23)
+            .wrapping_mul(PRIME64_2)
+            .wrapping_add(PRIME64_3);
+        rest = &rest[4..];
+    }
+    for &byte in rest {
+        hash = (hash ^ (byte as u64).wrapping_mul(PRIME64_5))
+            .rotate_left(11)
+            .wrapping_mul(PRIME64_1);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(PRIME64_2);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(PRIME64_3);
+    hash ^ (hash >> 32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD"
+        );
+    }
+
+    #[test]
+    fn test_xxh64() {
+        assert_eq!(xxh64(b"", 0), 0xEF46DB3751D8E999);
+        assert_eq!(xxh64(b"a", 0), 0xD24EC4F1A98C6E5B);
+        assert_eq!(xxh64(b"abc", 0), 0x44BC2CF5AD770999);
+        assert_eq!(
+            xxh64(b"Nobody inspects the spammish repetition", 0),
+            0xFBCEA83C8A378BF1
+        );
+        assert_eq!(Xxh64.hex(b"abc"), "44BC2CF5AD770999");
+    }
+}
@@ -0,0 +1,24 @@
+//! Subcommands other than sync.
+
+pub mod apply;
+pub mod cat;
+pub mod cdn;
+pub mod completions;
+pub mod diff;
+pub mod doctor;
+pub mod du;
+pub mod export;
+pub mod filters;
+pub mod freeze;
+pub mod history;
+pub mod import;
+pub mod init;
+pub mod login;
+pub mod ls;
+pub mod preview;
+pub mod promote;
+pub mod run;
+pub mod stat;
+pub mod state;
+pub mod stats;
+pub mod zones;
@@ -0,0 +1,50 @@
+use anyhow::Result;
+use std::path::PathBuf;
+use ureq::Agent;
+
+use crate::{
+    freeze,
+    i18n::{Message, tr},
+    output::{OutputFormat, Reporter},
+    plan::{PlanFile, SyncAction},
+    sync::{self, SyncOptions},
+    transform::Pipeline,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct ApplyArgs {
+    /// A plan written by `sync --dryrun --plan`
+    file: PathBuf,
+
+    /// How to print results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Print a detailed breakdown of the run instead of a one line summary
+    #[arg(long)]
+    stats: bool,
+}
+
+/// Carry out exactly the actions in a saved plan, without planning again.
+pub fn run(agent: &Agent, base_url: &str, args: &ApplyArgs) -> Result<()> {
+    let plan = PlanFile::load(&args.file)?;
+    // Plans are often made before a freeze and applied after it.
+    if let Some(action) = plan.actions.iter().find(|action| {
+        matches!(
+            action,
+            SyncAction::Upload { .. } | SyncAction::DeleteRemote { .. }
+        )
+    }) {
+        freeze::check(agent, base_url, action.key())?;
+    }
+    let options = SyncOptions {
+        transforms: Pipeline::new(&plan.transforms)?,
+        ..Default::default()
+    };
+    let mut reporter = Reporter::new(args.output, false, args.stats);
+    reporter.scanned(plan.actions.len() as u64);
+    sync::execute(agent, base_url, &plan.actions, &options, &mut reporter)?;
+    reporter.finish();
+    reporter.message(&tr(Message::SyncComplete));
+    Ok(())
+}
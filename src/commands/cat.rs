@@ -0,0 +1,23 @@
+use anyhow::Result;
+use std::io::Write;
+use ureq::Agent;
+
+use crate::storage;
+
+#[derive(clap::Args, Debug)]
+pub struct CatArgs {
+    /// The storage zone object to print, e.g. zone://my-zone/logs/latest.txt
+    path: String,
+}
+
+/// Write a remote object to stdout.
+pub fn run(agent: &Agent, base_url: &str, args: &CatArgs) -> Result<()> {
+    let path = storage::strip_zone_prefix(&args.path);
+    let data = storage::get_object(agent, base_url, path)?;
+    let mut stdout = std::io::stdout().lock();
+    match stdout.write_all(&data).and_then(|_| stdout.flush()) {
+        // The reader went away, e.g. `bunnysync cat ... | head`.
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        result => Ok(result?),
+    }
+}
@@ -0,0 +1,477 @@
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::path::PathBuf;
+
+use crate::{
+    api::{Client, PullZone},
+    i18n::{Message, tr},
+    s3::uri_encode,
+    storage::AgentOptions,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct CdnArgs {
+    #[command(subcommand)]
+    command: CdnCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum CdnCommand {
+    /// Bring pull zones in line with a declarative config file
+    Apply {
+        /// The file declaring the pull zones
+        #[arg(long, default_value = "bunny.toml")]
+        file: PathBuf,
+
+        /// Show the changes without making them
+        #[arg(long = "dryrun")]
+        dry_run: bool,
+
+        /// Remove hostnames and edge rules that the file doesn't declare
+        #[arg(long)]
+        prune: bool,
+
+        /// Your bunny.net account API key, which is not the same as a storage
+        /// zone password
+        #[arg(long, env = "BUNNYSYNC_ACCOUNT_KEY")]
+        account_key: Option<String>,
+    },
+}
+
+impl CdnArgs {
+    /// The account key given on the command line, for filling in from the
+    /// config file.
+    pub fn account_key_mut(&mut self) -> &mut Option<String> {
+        match &mut self.command {
+            CdnCommand::Apply { account_key, .. } => account_key,
+        }
+    }
+}
+
+/// The pull zones declared in `bunny.toml`. Settings and edge rules use the
+/// field names of the bunny.net API, e.g.
+///
+/// ```toml
+/// [[pull_zone]]
+/// name = "my-site"
+/// hostnames = ["www.example.com"]
+///
+/// [pull_zone.settings]
+/// CacheControlMaxAgeOverride = 3600
+///
+/// [[pull_zone.edge_rule]]
+/// Description = "Redirect the old blog"
+/// ActionType = 1
+/// ActionParameter1 = "https://www.example.com/blog/"
+/// Triggers = [{ Type = 0, PatternMatches = ["*/old-blog/*"] }]
+/// ```
+#[derive(Debug, Deserialize)]
+struct CdnConfig {
+    #[serde(default)]
+    pull_zone: Vec<PullZoneConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PullZoneConfig {
+    name: String,
+    #[serde(default)]
+    hostnames: Vec<String>,
+    #[serde(default)]
+    settings: Map<String, Value>,
+    /// Edge rules are matched to existing ones by description.
+    #[serde(default)]
+    edge_rule: Vec<Map<String, Value>>,
+}
+
+/// A change needed to bring a pull zone in line with its declaration.
+#[derive(Debug, PartialEq)]
+pub enum CdnChange {
+    UpdateSettings {
+        zone: String,
+        settings: Map<String, Value>,
+    },
+    AddHostname {
+        zone: String,
+        hostname: String,
+    },
+    RemoveHostname {
+        zone: String,
+        hostname: String,
+    },
+    AddEdgeRule {
+        zone: String,
+        rule: Map<String, Value>,
+    },
+    UpdateEdgeRule {
+        zone: String,
+        rule: Map<String, Value>,
+    },
+    RemoveEdgeRule {
+        zone: String,
+        description: String,
+        guid: String,
+    },
+}
+
+impl CdnChange {
+    /// The description of the edge rule a change is about.
+    pub fn rule_description(rule: &Map<String, Value>) -> &str {
+        rule.get("Description")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+    }
+}
+
+pub fn run(args: &CdnArgs, agent_options: &AgentOptions) -> Result<()> {
+    let CdnCommand::Apply {
+        file,
+        dry_run,
+        prune,
+        account_key,
+    } = &args.command;
+    let text = std::fs::read_to_string(file)
+        .map_err(|e| anyhow!("Could not read {}: {}", file.to_string_lossy(), e))?;
+    let config: CdnConfig = toml::from_str(&text)?;
+    let Some(account_key) = account_key else {
+        return Err(anyhow!(tr(Message::MissingAccountKey)));
+    };
+    let client = Client::new(account_key, agent_options)?;
+
+    for declared in &config.pull_zone {
+        let current = client.find_pull_zone(&declared.name)?;
+        for change in plan_zone(declared, &current, *prune)? {
+            if !dry_run {
+                apply_change(&client, current.id, &change)?;
+            }
+            println!(
+                "{}",
+                tr(Message::CdnChange {
+                    change: &change,
+                    dry_run: *dry_run,
+                })
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Work out what has to change on a pull zone, given its current settings as
+/// returned by the API.
+fn plan_zone(declared: &PullZoneConfig, current: &PullZone, prune: bool) -> Result<Vec<CdnChange>> {
+    let zone = declared.name.clone();
+    let mut changes = Vec::new();
+
+    let settings: Map<_, _> = declared
+        .settings
+        .iter()
+        .filter(|(key, value)| {
+            !current
+                .settings
+                .get(key.as_str())
+                .is_some_and(|current| matches_declared(value, current))
+        })
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    if !settings.is_empty() {
+        changes.push(CdnChange::UpdateSettings {
+            zone: zone.clone(),
+            settings,
+        });
+    }
+
+    let has_hostname = |name: &str| {
+        current
+            .hostnames
+            .iter()
+            .any(|hostname| hostname.value == name)
+    };
+    for hostname in &declared.hostnames {
+        if !has_hostname(hostname) {
+            changes.push(CdnChange::AddHostname {
+                zone: zone.clone(),
+                hostname: hostname.clone(),
+            });
+        }
+    }
+    if prune {
+        for hostname in &current.hostnames {
+            // The b-cdn.net hostname can't be removed.
+            if hostname.is_system_hostname {
+                continue;
+            }
+            if !declared.hostnames.contains(&hostname.value) {
+                changes.push(CdnChange::RemoveHostname {
+                    zone: zone.clone(),
+                    hostname: hostname.value.clone(),
+                });
+            }
+        }
+    }
+
+    let find_rule = |description: &str| {
+        current
+            .edge_rules
+            .iter()
+            .find(|rule| CdnChange::rule_description(rule) == description)
+    };
+    for rule in &declared.edge_rule {
+        let description = CdnChange::rule_description(rule);
+        if description.is_empty() {
+            return Err(anyhow!(
+                "Every edge rule for pull zone {} needs a Description",
+                zone
+            ));
+        }
+        match find_rule(description) {
+            None => changes.push(CdnChange::AddEdgeRule {
+                zone: zone.clone(),
+                rule: rule.clone(),
+            }),
+            Some(existing)
+                if !matches_declared(
+                    &Value::Object(rule.clone()),
+                    &Value::Object(existing.clone()),
+                ) =>
+            {
+                let mut rule = rule.clone();
+                rule.insert(
+                    "Guid".to_string(),
+                    existing.get("Guid").cloned().unwrap_or_default(),
+                );
+                changes.push(CdnChange::UpdateEdgeRule {
+                    zone: zone.clone(),
+                    rule,
+                });
+            }
+            Some(_) => {}
+        }
+    }
+    if prune {
+        for rule in &current.edge_rules {
+            let description = CdnChange::rule_description(rule);
+            let declared = declared
+                .edge_rule
+                .iter()
+                .any(|declared| CdnChange::rule_description(declared) == description);
+            if !declared {
+                changes.push(CdnChange::RemoveEdgeRule {
+                    zone: zone.clone(),
+                    description: description.to_string(),
+                    guid: rule
+                        .get("Guid")
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_string(),
+                });
+            }
+        }
+    }
+    Ok(changes)
+}
+
+/// Check if a current value already has everything the declared value asks
+/// for. Fields the declaration leaves out are ignored.
+fn matches_declared(declared: &Value, current: &Value) -> bool {
+    match (declared, current) {
+        (Value::Object(declared), Value::Object(current)) => declared.iter().all(|(key, value)| {
+            current
+                .get(key)
+                .is_some_and(|current| matches_declared(value, current))
+        }),
+        (Value::Array(declared), Value::Array(current)) => {
+            declared.len() == current.len()
+                && declared
+                    .iter()
+                    .zip(current)
+                    .all(|(declared, current)| matches_declared(declared, current))
+        }
+        (Value::Number(declared), Value::Number(current)) => declared.as_f64() == current.as_f64(),
+        (declared, current) => declared == current,
+    }
+}
+
+/// Purge the cached copies of some zone paths, like `css/site.css`, from a
+/// pull zone given by ID or by hostname. A pull zone given by ID is purged
+/// through each of its hostnames.
+pub fn purge_paths(client: &Client, pull_zone: &str, paths: &[String]) -> Result<()> {
+    let hostnames = match pull_zone.parse::<u64>() {
+        Ok(id) => hostnames(&client.pull_zone(id)?),
+        Err(_) => vec![pull_zone.to_string()],
+    };
+    for hostname in &hostnames {
+        for path in paths {
+            client.purge_url(&purge_url(hostname, path))?;
+        }
+    }
+    Ok(())
+}
+
+/// Purge everything a pull zone, given by ID or by hostname, has cached.
+pub fn purge_all(client: &Client, pull_zone: &str) -> Result<()> {
+    let id = match pull_zone.parse::<u64>() {
+        Ok(id) => id,
+        Err(_) => client.find_pull_zone_by_hostname(pull_zone)?.id,
+    };
+    client.purge_pull_zone(id)
+}
+
+/// The hostnames a pull zone answers to.
+fn hostnames(zone: &PullZone) -> Vec<String> {
+    zone.hostnames
+        .iter()
+        .map(|hostname| hostname.value.clone())
+        .collect()
+}
+
+/// The URL of a zone path through a pull zone hostname.
+fn purge_url(hostname: &str, path: &str) -> String {
+    format!("https://{}/{}", hostname, uri_encode(path, false))
+}
+
+fn apply_change(client: &Client, id: u64, change: &CdnChange) -> Result<()> {
+    match change {
+        CdnChange::UpdateSettings { settings, .. } => client.update_pull_zone(id, settings),
+        CdnChange::AddHostname { hostname, .. } => client.add_hostname(id, hostname),
+        CdnChange::RemoveHostname { hostname, .. } => client.remove_hostname(id, hostname),
+        CdnChange::AddEdgeRule { rule, .. } | CdnChange::UpdateEdgeRule { rule, .. } => {
+            client.save_edge_rule(id, rule)
+        }
+        CdnChange::RemoveEdgeRule { guid, .. } => client.remove_edge_rule(id, guid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn declared(toml: &str) -> PullZoneConfig {
+        let config: CdnConfig = toml::from_str(toml).unwrap();
+        config.pull_zone.into_iter().next().unwrap()
+    }
+
+    fn current() -> PullZone {
+        serde_json::from_value(json!({
+            "Id": 7,
+            "Name": "site",
+            "CacheControlMaxAgeOverride": 60,
+            "EnableQueryStringOrdering": true,
+            "Hostnames": [
+                { "Value": "site.b-cdn.net", "IsSystemHostname": true },
+                { "Value": "old.example.com", "IsSystemHostname": false },
+            ],
+            "EdgeRules": [
+                {
+                    "Guid": "g1",
+                    "Description": "https",
+                    "ActionType": 0,
+                    "Triggers": [{ "Type": 0, "PatternMatches": ["*"], "Parameter1": null }],
+                },
+                { "Guid": "g2", "Description": "legacy", "ActionType": 1 },
+            ],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_purge_url() {
+        assert_eq!(
+            hostnames(&current()),
+            vec!["site.b-cdn.net", "old.example.com"]
+        );
+        assert_eq!(
+            purge_url("site.b-cdn.net", "blog/my post.html"),
+            "https://site.b-cdn.net/blog/my%20post.html"
+        );
+    }
+
+    #[test]
+    fn test_plan_zone_in_sync() {
+        let zone = declared(
+            r#"
+            [[pull_zone]]
+            name = "site"
+            hostnames = ["old.example.com"]
+            [pull_zone.settings]
+            CacheControlMaxAgeOverride = 60
+            [[pull_zone.edge_rule]]
+            Description = "https"
+            ActionType = 0
+            Triggers = [{ Type = 0, PatternMatches = ["*"] }]
+            "#,
+        );
+        assert_eq!(plan_zone(&zone, &current(), false).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_plan_zone_changes() {
+        let zone = declared(
+            r#"
+            [[pull_zone]]
+            name = "site"
+            hostnames = ["www.example.com"]
+            [pull_zone.settings]
+            CacheControlMaxAgeOverride = 3600
+            EnableQueryStringOrdering = true
+            [[pull_zone.edge_rule]]
+            Description = "https"
+            ActionType = 2
+            [[pull_zone.edge_rule]]
+            Description = "new"
+            ActionType = 1
+            "#,
+        );
+        let zone_name = || "site".to_string();
+        let object = |value: Value| value.as_object().unwrap().clone();
+        assert_eq!(
+            plan_zone(&zone, &current(), false).unwrap(),
+            vec![
+                CdnChange::UpdateSettings {
+                    zone: zone_name(),
+                    settings: object(json!({ "CacheControlMaxAgeOverride": 3600 })),
+                },
+                CdnChange::AddHostname {
+                    zone: zone_name(),
+                    hostname: "www.example.com".to_string(),
+                },
+                CdnChange::UpdateEdgeRule {
+                    zone: zone_name(),
+                    rule: object(json!({ "Description": "https", "ActionType": 2, "Guid": "g1" })),
+                },
+                CdnChange::AddEdgeRule {
+                    zone: zone_name(),
+                    rule: object(json!({ "Description": "new", "ActionType": 1 })),
+                },
+            ]
+        );
+
+        let pruned = plan_zone(&zone, &current(), true).unwrap();
+        assert!(pruned.contains(&CdnChange::RemoveHostname {
+            zone: zone_name(),
+            hostname: "old.example.com".to_string(),
+        }));
+        assert!(pruned.contains(&CdnChange::RemoveEdgeRule {
+            zone: zone_name(),
+            description: "legacy".to_string(),
+            guid: "g2".to_string(),
+        }));
+        assert!(!pruned.iter().any(|change| matches!(
+            change,
+            CdnChange::RemoveHostname { hostname, .. } if hostname == "site.b-cdn.net"
+        )));
+    }
+
+    #[test]
+    fn test_edge_rules_need_descriptions() {
+        let zone = declared(
+            r#"
+            [[pull_zone]]
+            name = "site"
+            [[pull_zone.edge_rule]]
+            ActionType = 1
+            "#,
+        );
+        assert!(plan_zone(&zone, &current(), false).is_err());
+    }
+}
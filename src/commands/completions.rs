@@ -0,0 +1,299 @@
+use anyhow::{Result, anyhow};
+use chrono::Utc;
+use clap::{Arg, Command};
+
+use crate::{
+    api::Client,
+    cache::ZoneCache,
+    i18n::{Message, tr},
+    storage::AgentOptions,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct CompletionsArgs {
+    /// The shell to print a completion script for
+    #[arg(value_enum, required_unless_present = "zones")]
+    shell: Option<Shell>,
+
+    /// Print the account's storage zone names, for the completion scripts
+    #[arg(long, hide = true)]
+    zones: bool,
+
+    /// Your bunny.net account API key. Without one, zone names aren't
+    /// completed
+    #[arg(long, env = "BUNNYSYNC_ACCOUNT_KEY")]
+    account_key: Option<String>,
+}
+
+impl CompletionsArgs {
+    /// The account key given on the command line, for filling in from the
+    /// config file.
+    pub fn account_key_mut(&mut self) -> &mut Option<String> {
+        &mut self.account_key
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Print a completion script for `cli`, or the zone names the scripts
+/// complete `zone://` with.
+pub fn run(args: &CompletionsArgs, mut cli: Command, agent_options: &AgentOptions) -> Result<()> {
+    if args.zones {
+        let Some(account_key) = &args.account_key else {
+            return Err(anyhow!(tr(Message::MissingAccountKey)));
+        };
+        for zone in zone_names(account_key, agent_options)? {
+            println!("{}", zone);
+        }
+        return Ok(());
+    }
+    cli.build();
+    let script = match args.shell {
+        Some(Shell::Bash) => bash(&cli),
+        Some(Shell::Zsh) => format!(
+            "autoload -U +X bashcompinit && bashcompinit\n{}",
+            bash(&cli)
+        ),
+        Some(Shell::Fish) => fish(&cli),
+        None => unreachable!(),
+    };
+    print!("{}", script);
+    Ok(())
+}
+
+/// The account's storage zone names, from the cache while it is fresh.
+fn zone_names(account_key: &str, agent_options: &AgentOptions) -> Result<Vec<String>> {
+    let cache = ZoneCache::load();
+    if let Some(zones) = cache.zones(account_key, Utc::now()) {
+        return Ok(zones.to_vec());
+    }
+    let zones: Vec<String> = Client::new(account_key, agent_options)?
+        .storage_zones()?
+        .into_iter()
+        .map(|zone| zone.name)
+        .collect();
+    ZoneCache::new(account_key, zones.clone()).save()?;
+    Ok(zones)
+}
+
+/// The options a command takes itself.
+fn own_options(command: &Command) -> Vec<&Arg> {
+    command
+        .get_arguments()
+        .filter(|arg| !arg.is_positional() && !arg.is_hide_set())
+        .collect()
+}
+
+/// The options a command takes, including those of its subcommands.
+fn options(command: &Command) -> Vec<&Arg> {
+    let mut all = own_options(command);
+    for subcommand in command.get_subcommands() {
+        merge(&mut all, options(subcommand));
+    }
+    all
+}
+
+/// Add options that aren't there yet.
+fn merge<'a>(options: &mut Vec<&'a Arg>, more: Vec<&'a Arg>) {
+    for arg in more {
+        if !options.iter().any(|known| known.get_id() == arg.get_id()) {
+            options.push(arg);
+        }
+    }
+}
+
+/// The ways of spelling an option, like `-a --api-key`.
+fn spellings(args: &[&Arg]) -> String {
+    let mut words = Vec::new();
+    for arg in args {
+        if let Some(short) = arg.get_short() {
+            words.push(format!("-{}", short));
+        }
+        if let Some(long) = arg.get_long() {
+            words.push(format!("--{}", long));
+        }
+    }
+    words.join(" ")
+}
+
+/// The subcommands to complete, leaving out `help`.
+fn subcommands(cli: &Command) -> impl Iterator<Item = &Command> {
+    cli.get_subcommands()
+        .filter(|subcommand| subcommand.get_name() != "help")
+}
+
+/// Options before any subcommand go to `sync`, so those are offered too.
+fn top_level_options(cli: &Command) -> Vec<&Arg> {
+    let mut top = own_options(cli);
+    if let Some(sync) = cli.find_subcommand("sync") {
+        merge(&mut top, options(sync));
+    }
+    top
+}
+
+const BASH: &str = r#"# bash completion for bunnysync. Load it with
+#   source <(bunnysync completions bash)
+# Zone names are completed after zone:// when BUNNYSYNC_ACCOUNT_KEY is set.
+_bunnysync() {
+    local line="${COMP_LINE:0:COMP_POINT}"
+    local cur="${line##*[[:space:]]}"
+    local cmd="" word
+    for word in "${COMP_WORDS[@]:1:COMP_CWORD-1}"; do
+        case "$word" in
+            -*) ;;
+            *) cmd="$word"; break ;;
+        esac
+    done
+    COMPREPLY=()
+    case "$cur" in
+        zone:*)
+            local zones
+            zones="$(bunnysync completions --zones 2>/dev/null)"
+            COMPREPLY=($(compgen -P zone:// -S / -W "$zones" -- "${cur#zone://}"))
+            # The word being completed starts after the colon.
+            if [[ "$COMP_WORDBREAKS" == *:* ]]; then
+                COMPREPLY=("${COMPREPLY[@]#zone:}")
+            fi
+            compopt -o nospace 2>/dev/null
+            return ;;
+        -*)
+            local options
+            case "$cmd" in
+@CASES@                *) options="@TOP_OPTIONS@" ;;
+            esac
+            COMPREPLY=($(compgen -W "$options" -- "$cur"))
+            return ;;
+    esac
+    if [[ -z "$cmd" ]]; then
+        COMPREPLY=($(compgen -W "@SUBCOMMANDS@" -- "$cur"))
+    fi
+    COMPREPLY+=($(compgen -f -- "$cur"))
+}
+complete -o filenames -F _bunnysync bunnysync
+"#;
+
+fn bash(cli: &Command) -> String {
+    let mut cases = String::new();
+    for subcommand in subcommands(cli) {
+        cases.push_str(&format!(
+            "                {}) options=\"{}\" ;;\n",
+            subcommand.get_name(),
+            spellings(&options(subcommand))
+        ));
+    }
+    let names: Vec<_> = subcommands(cli).map(Command::get_name).collect();
+    BASH.replace("@CASES@", &cases)
+        .replace("@TOP_OPTIONS@", &spellings(&top_level_options(cli)))
+        .replace("@SUBCOMMANDS@", &names.join(" "))
+}
+
+/// Quote text for fish.
+fn fish_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// One line of fish completions for an option.
+fn fish_option(condition: &str, arg: &Arg) -> String {
+    let mut line = format!("complete -c bunnysync -n {}", fish_quote(condition));
+    if let Some(short) = arg.get_short() {
+        line.push_str(&format!(" -s {}", short));
+    }
+    if let Some(long) = arg.get_long() {
+        line.push_str(&format!(" -l {}", long));
+    }
+    if let Some(help) = arg.get_help() {
+        let help = help.to_string();
+        let first = help.lines().next().unwrap_or_default();
+        line.push_str(&format!(" -d {}", fish_quote(first)));
+    }
+    line.push('\n');
+    line
+}
+
+fn fish(cli: &Command) -> String {
+    let mut script = String::from(
+        "# fish completion for bunnysync. Load it with\n\
+         #   bunnysync completions fish | source\n\
+         # Zone names are completed after zone:// when BUNNYSYNC_ACCOUNT_KEY is set.\n",
+    );
+    let names: Vec<_> = subcommands(cli).map(Command::get_name).collect();
+    let no_subcommand = format!("not __fish_seen_subcommand_from {}", names.join(" "));
+    for subcommand in subcommands(cli) {
+        let about = subcommand
+            .get_about()
+            .map(|about| about.to_string())
+            .unwrap_or_default();
+        script.push_str(&format!(
+            "complete -c bunnysync -n {} -a {} -d {}\n",
+            fish_quote(&no_subcommand),
+            subcommand.get_name(),
+            fish_quote(&about)
+        ));
+    }
+    for arg in top_level_options(cli) {
+        script.push_str(&fish_option(&no_subcommand, arg));
+    }
+    for subcommand in subcommands(cli) {
+        let condition = format!("__fish_seen_subcommand_from {}", subcommand.get_name());
+        for arg in options(subcommand) {
+            script.push_str(&fish_option(&condition, arg));
+        }
+    }
+    script.push_str(
+        "complete -c bunnysync -n 'string match -q \"zone:*\" -- (commandline -ct)' -f \
+         -a '(bunnysync completions --zones 2>/dev/null | string replace -r \"(.+)\" \"zone://\\$1/\")'\n",
+    );
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cli() -> Command {
+        let mut cli = Command::new("bunnysync")
+            .arg(Arg::new("api-key").short('a').long("api-key").global(true))
+            .subcommand(
+                Command::new("sync")
+                    .about("Sync a local directory with a storage zone")
+                    .arg(Arg::new("source"))
+                    .arg(
+                        Arg::new("dryrun")
+                            .long("dryrun")
+                            .help("Perform a dry run")
+                            .action(clap::ArgAction::SetTrue),
+                    ),
+            )
+            .subcommand(Command::new("cdn").subcommand(
+                Command::new("apply").arg(Arg::new("prune").long("prune").hide(false)),
+            ));
+        cli.build();
+        cli
+    }
+
+    #[test]
+    fn test_bash() {
+        let script = bash(&cli());
+        assert!(script.contains(r#"sync) options="--dryrun -a --api-key -h --help" ;;"#));
+        // Nested subcommands' options are offered for their parent.
+        assert!(script.contains(r#"cdn) options="-a --api-key -h --help --prune" ;;"#));
+        assert!(script.contains(r#"compgen -W "sync cdn" -- "$cur""#));
+        assert!(script.contains("bunnysync completions --zones"));
+        assert!(!script.contains("@CASES@") && !script.contains("@SUBCOMMANDS@"));
+    }
+
+    #[test]
+    fn test_fish() {
+        let script = fish(&cli());
+        assert!(script.contains(
+            "complete -c bunnysync -n '__fish_seen_subcommand_from sync' -l dryrun -d 'Perform a dry run'"
+        ));
+        assert!(script.contains("-a sync -d 'Sync a local directory with a storage zone'"));
+        assert_eq!(fish_quote("it's"), r"'it\'s'");
+    }
+}
@@ -0,0 +1,220 @@
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use ureq::Agent;
+
+use crate::{
+    exit::ChangesFound,
+    filter::{Filter, IgnoreRules},
+    i18n::{Message, tr},
+    paths::PathMap,
+    plan::RemoteIndex,
+    storage::{self, AgentOptions, StorageObject},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct DiffArgs {
+    /// The zone directory to compare from, e.g. zone://staging/
+    first: String,
+
+    /// The zone directory to compare with, e.g. zone://production/
+    second: String,
+
+    /// Exclude files matching the pattern
+    #[arg(short, long)]
+    exclude: Vec<String>,
+
+    /// The API key for the second zone, when it differs from the first
+    #[arg(long, env = "BUNNYSYNC_OTHER_API_KEY")]
+    other_api_key: Option<String>,
+
+    /// The region of the second zone, when it differs from the first
+    #[arg(long, value_parser = clap::builder::PossibleValuesParser::new(["uk", "de", "us_ny",
+    "ny" , "us_la", "la","sg", "se", "br", "sa", "au", "au_syd", "syd"]))]
+    other_region: Option<String>,
+
+    /// Exit with status 4 when the zones differ and 0 when they don't
+    #[arg(long)]
+    exit_code: bool,
+}
+
+/// How a file differs between two zones.
+#[derive(Debug, PartialEq)]
+enum Difference {
+    OnlyFirst(String),
+    OnlySecond(String),
+    Changed(String),
+}
+
+impl Difference {
+    fn path(&self) -> &str {
+        match self {
+            Difference::OnlyFirst(path)
+            | Difference::OnlySecond(path)
+            | Difference::Changed(path) => path,
+        }
+    }
+}
+
+/// Compare the listings of two zone directories without downloading
+/// anything, printing `-` for files only in the first, `+` for files only in
+/// the second and `~` for files in both that differ.
+pub fn run(
+    agent: &Agent,
+    base_url: &str,
+    args: &DiffArgs,
+    agent_options: &AgentOptions,
+) -> Result<()> {
+    let other_base_url = match &args.other_region {
+        Some(region) => {
+            storage::base_url(region).ok_or_else(|| anyhow!("Invalid region {}", region))?
+        }
+        None => base_url.to_string(),
+    };
+    let other = match &args.other_api_key {
+        Some(key) => {
+            let keys = storage::ApiKeys {
+                primary: Some(key.clone()),
+                ..Default::default()
+            };
+            Some(storage::agent(&keys, &other_base_url, agent_options)?)
+        }
+        None => None,
+    };
+    let first = PathMap::new(&args.first);
+    let second = PathMap::new(&args.second);
+    let first_index = index(agent, base_url, &first, &args.exclude)?;
+    let second_index = index(
+        other.as_ref().unwrap_or(agent),
+        &other_base_url,
+        &second,
+        &args.exclude,
+    )?;
+
+    let differences = compare(&first_index, &first.root(), &second_index, &second.root());
+    let mut counts = [0; 3];
+    for difference in &differences {
+        let (mark, count) = match difference {
+            Difference::OnlyFirst(_) => ('-', &mut counts[0]),
+            Difference::OnlySecond(_) => ('+', &mut counts[1]),
+            Difference::Changed(_) => ('~', &mut counts[2]),
+        };
+        *count += 1;
+        println!("{} {}", mark, difference.path());
+    }
+    println!(
+        "{}",
+        tr(Message::ZoneDiff {
+            only_first: counts[0],
+            only_second: counts[1],
+            changed: counts[2],
+        })
+    );
+    if args.exit_code && !differences.is_empty() {
+        return Err(ChangesFound.into());
+    }
+    Ok(())
+}
+
+fn index(
+    agent: &Agent,
+    base_url: &str,
+    paths: &PathMap,
+    exclude: &[String],
+) -> Result<RemoteIndex> {
+    let filter = Filter::new(exclude.to_vec(), IgnoreRules::default(), paths.root());
+    let remote = paths.root().trim_start_matches('/').to_string();
+    Ok(RemoteIndex::new(
+        storage::get_all_objects(agent, base_url, &remote)?,
+        &filter,
+    ))
+}
+
+/// The differences between two indexes, by path relative to their roots.
+/// Files differ when their sizes do, or their checksums when both zones
+/// have one.
+fn compare(
+    first: &RemoteIndex,
+    first_root: &str,
+    second: &RemoteIndex,
+    second_root: &str,
+) -> Vec<Difference> {
+    let first = relative(first, first_root);
+    let second = relative(second, second_root);
+
+    let mut differences = Vec::new();
+    for (path, object) in &first {
+        match second.get(path) {
+            None => differences.push(Difference::OnlyFirst(path.clone())),
+            Some(other) => {
+                let checksums_differ = match (&object.checksum, &other.checksum) {
+                    (Some(a), Some(b)) => !a.eq_ignore_ascii_case(b),
+                    _ => false,
+                };
+                if object.length != other.length || checksums_differ {
+                    differences.push(Difference::Changed(path.clone()));
+                }
+            }
+        }
+    }
+    for path in second.keys() {
+        if !first.contains_key(path) {
+            differences.push(Difference::OnlySecond(path.clone()));
+        }
+    }
+    differences.sort_by(|a, b| a.path().cmp(b.path()));
+    differences
+}
+
+/// The files of an index by path relative to its root.
+fn relative<'a>(index: &'a RemoteIndex, root: &str) -> HashMap<String, &'a StorageObject> {
+    index
+        .files
+        .iter()
+        .map(|(key, object)| (key.strip_prefix(root).unwrap_or(key).to_string(), object))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(path: &str, name: &str, length: u64, checksum: Option<&str>) -> StorageObject {
+        StorageObject {
+            path: path.to_string(),
+            object_name: name.to_string(),
+            length,
+            checksum: checksum.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let staging = RemoteIndex::new(
+            vec![
+                object("/staging/", "index.html", 10, Some("AA")),
+                object("/staging/", "new.html", 5, None),
+                object("/staging/css/", "site.css", 7, Some("bb")),
+                object("/staging/", "same.txt", 3, None),
+            ],
+            &Filter::default(),
+        );
+        let production = RemoteIndex::new(
+            vec![
+                object("/production/", "index.html", 10, Some("aa")),
+                object("/production/", "old.html", 5, None),
+                object("/production/css/", "site.css", 7, Some("CC")),
+                object("/production/", "same.txt", 3, Some("dd")),
+            ],
+            &Filter::default(),
+        );
+        assert_eq!(
+            compare(&staging, "/staging/", &production, "/production/"),
+            vec![
+                Difference::Changed("css/site.css".to_string()),
+                Difference::OnlyFirst("new.html".to_string()),
+                Difference::OnlySecond("old.html".to_string()),
+            ]
+        );
+    }
+}
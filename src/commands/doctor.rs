@@ -0,0 +1,124 @@
+use anyhow::{Result, anyhow};
+use ureq::Agent;
+
+use crate::{
+    i18n::{Message, tr},
+    storage::{self, Access, Key},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct DoctorArgs {
+    /// The storage zone to check, e.g. zone://my-zone. Defaults to zone in
+    /// .bunnysync
+    zone: Option<String>,
+
+    /// Fail unless every API key given may do exactly this, so that CI can
+    /// make sure a pipeline gets no more access than it needs
+    #[arg(long, value_enum, value_name = "ACCESS")]
+    expect: Option<Expected>,
+}
+
+/// The access a key is meant to have, for `--expect`.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Expected {
+    /// List, download and change the zone
+    ReadWrite,
+    /// List and download but not change the zone, like a read-only password
+    ReadOnly,
+}
+
+impl Expected {
+    fn access(self) -> Access {
+        match self {
+            Expected::ReadWrite => Access::ReadWrite,
+            Expected::ReadOnly => Access::ReadOnly,
+        }
+    }
+}
+
+impl DoctorArgs {
+    /// The zone given on the command line, for filling in from the config
+    /// file.
+    pub fn zone_mut(&mut self) -> &mut Option<String> {
+        &mut self.zone
+    }
+}
+
+/// Report what each API key may do in a zone, so a read-only password shows
+/// up as such rather than as a failed upload later.
+pub fn run(agents: &[(Key, Agent)], base_url: &str, args: &DoctorArgs) -> Result<()> {
+    let Some(zone) = &args.zone else {
+        return Err(anyhow!(tr(Message::ZoneNeeded)));
+    };
+    let zone = storage::zone_name(storage::strip_zone_prefix(zone));
+    let mut results = Vec::new();
+    for (key, agent) in agents {
+        let access = storage::access(agent, base_url, &zone)?;
+        // A key without access is reported as the error, after the others.
+        if access != Access::None {
+            println!(
+                "{}",
+                tr(Message::KeyAccess {
+                    key: *key,
+                    zone: &zone,
+                    access,
+                })
+            );
+        }
+        results.push((*key, access));
+    }
+    check(&zone, &results, args.expect)
+}
+
+/// Fail when a key has no access at all, or may do more or less than
+/// expected.
+fn check(zone: &str, results: &[(Key, Access)], expected: Option<Expected>) -> Result<()> {
+    for &(key, access) in results {
+        if access == Access::None {
+            return Err(anyhow!(tr(Message::KeyAccess { key, zone, access })));
+        }
+        if let Some(expected) = expected
+            && expected.access() != access
+        {
+            return Err(anyhow!(tr(Message::UnexpectedAccess {
+                key,
+                expected: expected.access()
+            })));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let check_one = |access, expected| check("z", &[(Key::Primary, access)], expected);
+        assert!(check_one(Access::ReadWrite, None).is_ok());
+        assert!(check_one(Access::ReadOnly, None).is_ok());
+        assert!(check_one(Access::None, None).is_err());
+        assert!(check_one(Access::ReadOnly, Some(Expected::ReadOnly)).is_ok());
+        assert!(check_one(Access::ReadWrite, Some(Expected::ReadWrite)).is_ok());
+        assert!(check_one(Access::ReadWrite, Some(Expected::ReadOnly)).is_err());
+        assert!(check_one(Access::ReadOnly, Some(Expected::ReadWrite)).is_err());
+    }
+
+    #[test]
+    fn test_check_each_key() {
+        let both = [
+            (Key::Primary, Access::ReadWrite),
+            (Key::ReadOnly, Access::ReadOnly),
+        ];
+        assert!(check("z", &both, None).is_ok());
+        // Every key is held to the expectation, not just the first.
+        assert!(check("z", &both, Some(Expected::ReadWrite)).is_err());
+        let dead = [
+            (Key::Primary, Access::ReadWrite),
+            (Key::Secondary, Access::None),
+        ];
+        let error = check("z", &dead, None).unwrap_err().to_string();
+        assert!(error.contains("secondary"), "{}", error);
+    }
+}
@@ -0,0 +1,134 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use ureq::Agent;
+
+use crate::{
+    plan::object_key,
+    storage::{self, StorageObject},
+    units,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct DuArgs {
+    /// The storage zone path to measure, e.g. zone://my-zone/assets
+    path: String,
+
+    /// Print sizes in KiB, MiB, GiB and so on
+    #[arg(short = 'H', long)]
+    human_readable: bool,
+}
+
+/// Size and file count of a group of files.
+#[derive(Debug, Default, PartialEq)]
+struct Usage {
+    bytes: u64,
+    files: u64,
+}
+
+/// Report how much space each top level directory under a path uses.
+pub fn run(agent: &Agent, base_url: &str, args: &DuArgs) -> Result<()> {
+    let path = storage::strip_zone_prefix(&args.path);
+    let path = format!("{}/", path.trim_end_matches('/'));
+    let objects = storage::get_all_objects(agent, base_url, &path)?;
+    let prefix = format!("/{}", path.trim_start_matches('/'));
+    let (groups, total) = summarize(&objects, &prefix);
+
+    let size = |bytes: u64| {
+        if args.human_readable {
+            units::format_bytes(bytes)
+        } else {
+            bytes.to_string()
+        }
+    };
+    for (name, usage) in &groups {
+        println!(
+            "{:>12}  {:>8} files  {}",
+            size(usage.bytes),
+            usage.files,
+            name
+        );
+    }
+    println!("{:>12}  {:>8} files  total", size(total.bytes), total.files);
+    Ok(())
+}
+
+/// Group files by the top level directory below the prefix. Files directly
+/// in the prefix are grouped under `./`. Every file counts towards the
+/// zone's storage, bunnysync's own manifest and freeze files included.
+fn summarize(objects: &[StorageObject], prefix: &str) -> (BTreeMap<String, Usage>, Usage) {
+    let mut groups: BTreeMap<String, Usage> = BTreeMap::new();
+    let mut total = Usage::default();
+    for file in objects.iter().filter(|object| !object.is_directory) {
+        let key = object_key(file);
+        let relative = key.strip_prefix(prefix).unwrap_or(&key);
+        let group = match relative.split_once('/') {
+            Some((dir, _)) => format!("{}/", dir),
+            None => "./".to_string(),
+        };
+        let usage = groups.entry(group).or_default();
+        usage.bytes += file.length;
+        usage.files += 1;
+        total.bytes += file.length;
+        total.files += 1;
+    }
+    (groups, total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::MANIFEST_FILE;
+
+    fn file(path: &str, name: &str, length: u64) -> StorageObject {
+        StorageObject {
+            path: path.to_string(),
+            object_name: name.to_string(),
+            length,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        let objects = vec![
+            file("/zone/site/", "index.html", 10),
+            file("/zone/site/", MANIFEST_FILE, 3),
+            StorageObject {
+                is_directory: true,
+                ..file("/zone/site/", "css", 0)
+            },
+            file("/zone/site/css/", "a.css", 5),
+            file("/zone/site/css/vendor/", "b.css", 7),
+            file("/zone/site/img/", "logo.png", 100),
+        ];
+        let (groups, total) = summarize(&objects, "/zone/site/");
+        assert_eq!(
+            groups["./"],
+            Usage {
+                bytes: 13,
+                files: 2
+            }
+        );
+        assert_eq!(
+            groups["css/"],
+            Usage {
+                bytes: 12,
+                files: 2
+            }
+        );
+        assert_eq!(
+            groups["img/"],
+            Usage {
+                bytes: 100,
+                files: 1
+            }
+        );
+        assert_eq!(
+            total,
+            Usage {
+                bytes: 125,
+                files: 5
+            }
+        );
+    }
+}
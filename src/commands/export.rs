@@ -0,0 +1,168 @@
+use anyhow::Result;
+use chrono::TimeDelta;
+use std::{collections::HashMap, path::PathBuf};
+use ureq::Agent;
+
+use crate::{
+    backend::{self, Backend, BackendFile},
+    filter::{Filter, IgnoreRules},
+    i18n::{Message, tr},
+    local::LocalFile,
+    output::{Event, OutputFormat, Reporter},
+    paths::PathMap,
+    plan::{self, RemoteIndex, SyncAction},
+    storage::{self, StorageObject},
+    timestamp::RemoteTime,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct ExportArgs {
+    /// The storage zone directory to copy from, e.g. zone://my-zone/
+    source: String,
+
+    /// The bucket directory to copy into, e.g. s3://my-backups/site/
+    destination: String,
+
+    /// Perform a dry run
+    #[arg(long = "dryrun")]
+    dry_run: bool,
+
+    /// Delete objects in the bucket that are not in the zone
+    #[arg(long)]
+    delete: bool,
+
+    /// Exclude files matching the pattern
+    #[arg(short, long)]
+    exclude: Vec<String>,
+
+    /// How to print results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Print a detailed breakdown of the run instead of a one line summary
+    #[arg(long)]
+    stats: bool,
+}
+
+/// Copy a zone to storage away from bunny.net, as a backup. It is planned
+/// like a push, with the zone as the source.
+pub fn run(agent: &Agent, base_url: &str, args: &ExportArgs) -> Result<()> {
+    let backend = backend::open(&args.destination)?;
+    let paths = PathMap::new(&args.source);
+    let filter = Filter::new(args.exclude.clone(), IgnoreRules::default(), paths.root());
+    let mut reporter = Reporter::new(args.output, args.dry_run, args.stats);
+
+    let remote = paths.root().trim_start_matches('/').to_string();
+    let zone = RemoteIndex::new(storage::get_all_objects(agent, base_url, &remote)?, &filter);
+    let files = source_files(backend.as_ref(), &zone, &paths);
+    reporter.scanned(files.len() as u64);
+    let index = backend_index(backend.list()?, &filter);
+    let actions = plan::plan_to_remote(&files, &index, args.delete, TimeDelta::zero());
+
+    for action in &actions {
+        let event = match action {
+            SyncAction::Upload { key, path, length } => {
+                let mut bytes = *length;
+                if !args.dry_run {
+                    let path = path.to_string_lossy();
+                    let data = storage::get_object(agent, base_url, path.trim_start_matches('/'))?;
+                    bytes = data.len() as u64;
+                    backend.upload(key, &data)?;
+                }
+                Event::Uploaded {
+                    path: key.clone(),
+                    local_path: path.to_string_lossy().into_owned(),
+                    bytes,
+                    dry_run: args.dry_run,
+                }
+            }
+            SyncAction::DeleteRemote { key, length } => {
+                if !args.dry_run {
+                    backend.delete(key)?;
+                }
+                Event::Deleted {
+                    path: key.clone(),
+                    local_path: None,
+                    bytes: *length,
+                    dry_run: args.dry_run,
+                }
+            }
+            SyncAction::Skip { key, reason } => Event::Skipped {
+                path: key.clone(),
+                reason: *reason,
+            },
+            SyncAction::Download { .. } | SyncAction::DeleteLocal { .. } => unreachable!(),
+        };
+        reporter.report(event);
+    }
+    reporter.finish();
+    reporter.message(&tr(Message::SyncComplete));
+    Ok(())
+}
+
+/// The zone's files as if they were local files, by the URL they would have
+/// at the destination. Their paths are their keys in the zone.
+fn source_files(
+    backend: &dyn Backend,
+    zone: &RemoteIndex,
+    paths: &PathMap,
+) -> HashMap<String, LocalFile> {
+    let root = paths.root();
+    zone.files
+        .iter()
+        .filter_map(|(key, object)| {
+            let relative = key.strip_prefix(&root)?;
+            let file = LocalFile {
+                relative_path: PathBuf::from(relative),
+                path: PathBuf::from(key),
+                is_directory: false,
+                last_changed: object.last_changed.utc(),
+                length: object.length,
+            };
+            Some((backend.url_for(relative), file))
+        })
+        .collect()
+}
+
+/// The files at the destination, indexed by URL like a zone listing.
+fn backend_index(files: Vec<BackendFile>, filter: &Filter) -> RemoteIndex {
+    let mut index = RemoteIndex::default();
+    for file in files {
+        if filter.excludes(&file.relative, false) {
+            continue;
+        }
+        let stored = StorageObject {
+            length: file.length,
+            last_changed: RemoteTime::from_utc(file.last_modified),
+            ..Default::default()
+        };
+        index.files.insert(file.url, stored);
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    #[test]
+    fn test_backend_index() {
+        let file = |relative: &str| BackendFile {
+            url: format!("s3://backups/site/{}", relative),
+            relative: relative.to_string(),
+            length: 3,
+            last_modified: DateTime::from_timestamp(100, 0).unwrap(),
+        };
+        let filter = Filter::new(
+            vec!["*.tmp".to_string()],
+            IgnoreRules::default(),
+            "/myzone/".to_string(),
+        );
+        let index = backend_index(vec![file("index.html"), file("notes.tmp")], &filter);
+        assert_eq!(index.files.len(), 1);
+        let stored = &index.files["s3://backups/site/index.html"];
+        assert_eq!(stored.length, 3);
+        assert_eq!(stored.last_changed.utc().timestamp(), 100);
+    }
+}
@@ -0,0 +1,162 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::{
+    i18n::{Message, tr},
+    local,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct FiltersArgs {
+    #[command(subcommand)]
+    command: FiltersCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum FiltersCommand {
+    /// Write the effective filter rules (defaults, config and command line)
+    /// to a file
+    Export {
+        /// The file to write the rules to
+        file: PathBuf,
+
+        /// Exclude files that match a pattern. You can use * as a wildcard
+        #[arg(long = "exclude", value_parser, num_args = 1.., value_delimiter = ',')]
+        exclude: Vec<String>,
+    },
+    /// Add the filter rules from a file to the .bunnysync config
+    Import {
+        /// The file to read the rules from
+        file: PathBuf,
+    },
+}
+
+impl FiltersArgs {
+    /// The exclude patterns given on the command line, if the subcommand
+    /// takes any.
+    pub fn exclude_mut(&mut self) -> Option<&mut Vec<String>> {
+        match &mut self.command {
+            FiltersCommand::Export { exclude, .. } => Some(exclude),
+            FiltersCommand::Import { .. } => None,
+        }
+    }
+}
+
+/// A shareable set of filter rules. It uses the same keys as the config file.
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+struct FilterRules {
+    #[serde(default)]
+    exclude: Vec<String>,
+}
+
+/// Run a filters subcommand. Imported rules are added to `config_file`.
+pub fn run(args: &FiltersArgs, config_file: &Path) -> Result<()> {
+    match &args.command {
+        FiltersCommand::Export { file, exclude } => {
+            let rules = effective_rules(exclude);
+            std::fs::write(file, toml::to_string(&rules)?)?;
+            println!(
+                "{}",
+                tr(Message::Exported {
+                    count: rules.exclude.len(),
+                    file: &file.to_string_lossy(),
+                })
+            );
+        }
+        FiltersCommand::Import { file } => {
+            let rules: FilterRules = toml::from_str(&std::fs::read_to_string(file)?)?;
+            let config = std::fs::read_to_string(config_file).unwrap_or_default();
+            let (config, added) = merge_rules(&config, &rules)?;
+            std::fs::write(config_file, config)?;
+            println!(
+                "{}",
+                tr(Message::Imported {
+                    count: added,
+                    file: &config_file.to_string_lossy(),
+                })
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Combine the given patterns with the defaults, dropping duplicates.
+fn effective_rules(exclude: &[String]) -> FilterRules {
+    let mut rules = FilterRules::default();
+    let defaults = [local::CONFIG_FILE.to_string()];
+    for pattern in exclude.iter().chain(defaults.iter()) {
+        if !rules.exclude.contains(pattern) {
+            rules.exclude.push(pattern.clone());
+        }
+    }
+    rules
+}
+
+/// Add rules to a config document, keeping its existing contents and
+/// comments. Returns the new document and how many patterns were added.
+fn merge_rules(config: &str, rules: &FilterRules) -> Result<(String, usize)> {
+    let mut doc: toml_edit::DocumentMut = config.parse()?;
+    let exclude = doc
+        .entry("exclude")
+        .or_insert_with(|| toml_edit::value(toml_edit::Array::new()))
+        .as_array_mut()
+        .ok_or_else(|| anyhow!("exclude in {} is not a list", local::CONFIG_FILE))?;
+
+    let mut added = 0;
+    for pattern in &rules.exclude {
+        if !exclude.iter().any(|p| p.as_str() == Some(pattern)) {
+            exclude.push(pattern.as_str());
+            added += 1;
+        }
+    }
+    Ok((doc.to_string(), added))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_effective_rules() {
+        let rules = effective_rules(&[
+            "*.tmp".to_string(),
+            ".bunnysync".to_string(),
+            "*.tmp".to_string(),
+        ]);
+        assert_eq!(rules.exclude, vec!["*.tmp", ".bunnysync"]);
+    }
+
+    #[test]
+    fn test_rules_round_trip() {
+        let rules = effective_rules(&["*.log".to_string()]);
+        let text = toml::to_string(&rules).unwrap();
+        assert_eq!(toml::from_str::<FilterRules>(&text).unwrap(), rules);
+    }
+
+    #[test]
+    fn test_merge_rules_keeps_config() {
+        let config = "# Deploy settings\nregion = \"uk\"\nexclude = [\"*.tmp\"]\n";
+        let rules = FilterRules {
+            exclude: vec!["*.tmp".to_string(), "*.log".to_string()],
+        };
+        let (merged, added) = merge_rules(config, &rules).unwrap();
+        assert_eq!(added, 1);
+        assert!(merged.starts_with("# Deploy settings\nregion = \"uk\"\n"));
+        let parsed: toml::Table = toml::from_str(&merged).unwrap();
+        assert_eq!(
+            parsed["exclude"],
+            toml::Value::from(vec!["*.tmp".to_string(), "*.log".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_merge_rules_into_empty_config() {
+        let rules = FilterRules {
+            exclude: vec!["*.log".to_string()],
+        };
+        let (merged, added) = merge_rules("", &rules).unwrap();
+        assert_eq!(added, 1);
+        assert_eq!(merged, "exclude = [\"*.log\"]\n");
+    }
+}
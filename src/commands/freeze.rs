@@ -0,0 +1,44 @@
+use anyhow::Result;
+use ureq::Agent;
+
+use crate::{
+    freeze::Freeze,
+    i18n::{Message, tr},
+    paths::PathMap,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct FreezeArgs {
+    /// The storage zone to freeze, e.g. zone://my-zone
+    zone: String,
+
+    /// Why the zone is frozen, shown to anyone whose sync is refused
+    #[arg(long)]
+    reason: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct UnfreezeArgs {
+    /// The storage zone to unfreeze, e.g. zone://my-zone
+    zone: String,
+}
+
+/// Stop bunnysync from changing a zone until it is unfrozen.
+pub fn run(agent: &Agent, base_url: &str, args: &FreezeArgs) -> Result<()> {
+    let paths = PathMap::new(&args.zone);
+    Freeze::new(args.reason.clone()).save(agent, base_url, paths.zone())?;
+    println!("{}", tr(Message::FrozenZone { zone: paths.zone() }));
+    Ok(())
+}
+
+/// Let bunnysync change a frozen zone again.
+pub fn run_unfreeze(agent: &Agent, base_url: &str, args: &UnfreezeArgs) -> Result<()> {
+    let paths = PathMap::new(&args.zone);
+    let message = if Freeze::lift(agent, base_url, paths.zone())? {
+        Message::Unfrozen { zone: paths.zone() }
+    } else {
+        Message::NotFrozen { zone: paths.zone() }
+    };
+    println!("{}", tr(message));
+    Ok(())
+}
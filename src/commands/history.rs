@@ -0,0 +1,154 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::{
+    history::{self, Outcome, RunRecord},
+    storage, store,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct HistoryArgs {
+    /// Only show runs that synced with this zone, e.g. zone://my-zone
+    #[arg(long)]
+    zone: Option<String>,
+
+    /// Only show runs that succeeded
+    #[arg(long, conflicts_with = "failed")]
+    succeeded: bool,
+
+    /// Only show runs that failed
+    #[arg(long)]
+    failed: bool,
+
+    /// How many of the latest runs to show
+    #[arg(short = 'n', long, default_value_t = 20)]
+    limit: usize,
+
+    /// Print every detail of each run as JSON, one run per line
+    #[arg(long)]
+    json: bool,
+}
+
+/// List the syncs run from the current directory, latest first.
+pub fn run(args: &HistoryArgs) -> Result<()> {
+    let runs = history::load(&store::dir(Path::new(".")))?;
+    for run in select(&runs, args) {
+        if args.json {
+            println!("{}", serde_json::to_string(run)?);
+        } else {
+            println!("{}", describe(run));
+        }
+    }
+    Ok(())
+}
+
+/// The runs the arguments ask for, latest first.
+fn select<'a>(runs: &'a [RunRecord], args: &HistoryArgs) -> Vec<&'a RunRecord> {
+    let zone = args
+        .zone
+        .as_deref()
+        .map(|zone| storage::zone_name(storage::strip_zone_prefix(zone)));
+    runs.iter()
+        .rev()
+        .filter(|run| zone.as_ref().is_none_or(|zone| run.zone() == *zone))
+        .filter(|run| !args.succeeded || run.outcome == Outcome::Succeeded)
+        .filter(|run| !args.failed || run.outcome == Outcome::Failed)
+        .take(args.limit)
+        .collect()
+}
+
+/// One line about a run, and a second with the error if it failed.
+fn describe(run: &RunRecord) -> String {
+    let outcome = match (run.outcome, run.dry_run) {
+        (Outcome::Failed, _) => "failed",
+        (Outcome::Succeeded, true) => "dry run",
+        (Outcome::Succeeded, false) => "ok",
+    };
+    let summary = &run.summary;
+    let mut line = format!(
+        "{}  {:<7}  {:>7.1}s  {} -> {}  {} uploaded, {} downloaded, {} deleted",
+        run.started.format("%Y-%m-%d %H:%M:%S UTC"),
+        outcome,
+        run.duration_ms as f64 / 1000.0,
+        run.source,
+        run.destination,
+        summary.uploaded,
+        summary.downloaded,
+        summary.deleted
+    );
+    if let Some(error) = &run.error {
+        line.push_str("\n    ");
+        line.push_str(error);
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Summary;
+    use chrono::DateTime;
+
+    fn run(destination: &str, outcome: Outcome) -> RunRecord {
+        RunRecord {
+            started: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            duration_ms: 1500,
+            args: vec![],
+            source: "site".to_string(),
+            destination: destination.to_string(),
+            dry_run: false,
+            outcome,
+            error: (outcome == Outcome::Failed).then(|| "Remote unauthorized".to_string()),
+            summary: Summary {
+                uploaded: 3,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_select() {
+        let runs = vec![
+            run("zone://a/", Outcome::Succeeded),
+            run("zone://b/", Outcome::Succeeded),
+            run("zone://a/", Outcome::Failed),
+        ];
+        let args = |zone: Option<&str>, succeeded, limit| HistoryArgs {
+            zone: zone.map(String::from),
+            succeeded,
+            failed: false,
+            limit,
+            json: false,
+        };
+        let picked = |args| -> Vec<(&str, Outcome)> {
+            select(&runs, &args)
+                .into_iter()
+                .map(|run| (run.destination.as_str(), run.outcome))
+                .collect()
+        };
+        assert_eq!(
+            picked(args(None, false, 20)),
+            vec![
+                ("zone://a/", Outcome::Failed),
+                ("zone://b/", Outcome::Succeeded),
+                ("zone://a/", Outcome::Succeeded),
+            ]
+        );
+        assert_eq!(
+            picked(args(Some("zone://a"), true, 20)),
+            vec![("zone://a/", Outcome::Succeeded)]
+        );
+        assert_eq!(
+            picked(args(None, false, 1)),
+            vec![("zone://a/", Outcome::Failed)]
+        );
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(
+            describe(&run("zone://a/", Outcome::Failed)),
+            "2023-11-14 22:13:20 UTC  failed       1.5s  site -> zone://a/  3 uploaded, 0 downloaded, 0 deleted\n    Remote unauthorized"
+        );
+    }
+}
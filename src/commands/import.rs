@@ -0,0 +1,114 @@
+use anyhow::Result;
+use chrono::TimeDelta;
+use std::{collections::HashMap, path::PathBuf};
+use ureq::Agent;
+
+use crate::{
+    filter::{Filter, IgnoreRules},
+    freeze,
+    i18n::{Message, tr},
+    local::LocalFile,
+    output::{OutputFormat, Reporter},
+    paths::PathMap,
+    plan::{self, RemoteIndex},
+    s3::{Bucket, S3Object},
+    storage,
+    sync::{self, Origin, SyncOptions},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct ImportArgs {
+    /// The bucket directory to copy from, e.g. s3://my-bucket/site/
+    source: String,
+
+    /// The storage zone directory to copy into, e.g. zone://my-zone/
+    destination: String,
+
+    /// Perform a dry run
+    #[arg(long = "dryrun")]
+    dry_run: bool,
+
+    /// Delete files in the zone that are not in the bucket
+    #[arg(long)]
+    delete: bool,
+
+    /// Exclude files matching the pattern
+    #[arg(short, long)]
+    exclude: Vec<String>,
+
+    /// How to print results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Print a detailed breakdown of the run instead of a one line summary
+    #[arg(long)]
+    stats: bool,
+}
+
+/// Copy the objects in an S3 bucket into a zone, planned like a push. Each
+/// object is downloaded and uploaded in turn, without a local copy.
+pub fn run(agent: &Agent, base_url: &str, args: &ImportArgs) -> Result<()> {
+    let bucket = Bucket::from_url(&args.source)?;
+    if !args.dry_run {
+        freeze::check(agent, base_url, &args.destination)?;
+    }
+    let paths = PathMap::new(&args.destination);
+    let filter = Filter::new(args.exclude.clone(), IgnoreRules::default(), paths.root());
+    let mut reporter = Reporter::new(args.output, args.dry_run, args.stats);
+
+    let files = source_files(&bucket, bucket.list()?, &paths, &filter);
+    reporter.scanned(files.len() as u64);
+    let remote = format!(
+        "{}/",
+        storage::strip_zone_prefix(&args.destination).trim_end_matches('/')
+    );
+    let objects =
+        sync::missing_as_empty(storage::get_all_objects(agent, base_url, &remote), &remote)?;
+    let index = RemoteIndex::new(objects, &filter);
+    let actions = plan::plan_to_remote(&files, &index, args.delete, TimeDelta::zero());
+
+    let options = SyncOptions {
+        dry_run: args.dry_run,
+        delete: args.delete,
+        filter,
+        origin: Some(Origin::Bucket(bucket)),
+        ..Default::default()
+    };
+    sync::execute(agent, base_url, &actions, &options, &mut reporter)?;
+    reporter.finish();
+    reporter.message(&tr(Message::SyncComplete));
+    Ok(())
+}
+
+/// The bucket's objects as if they were local files, by the key they would
+/// have in the zone. Their paths are the objects' URLs.
+fn source_files(
+    bucket: &Bucket,
+    objects: Vec<S3Object>,
+    paths: &PathMap,
+    filter: &Filter,
+) -> HashMap<String, LocalFile> {
+    objects
+        .into_iter()
+        .filter_map(|object| {
+            let relative = bucket.relative(&object.key)?;
+            // Keys like `a//b` or `../b` have no place in a zone.
+            if relative
+                .split('/')
+                .any(|part| matches!(part, "" | "." | ".."))
+                || filter.excludes(relative, false)
+            {
+                return None;
+            }
+            let relative_path = PathBuf::from(relative);
+            let file = LocalFile {
+                path: PathBuf::from(bucket.url(&object.key)),
+                relative_path,
+                is_directory: false,
+                last_changed: object.last_modified,
+                length: object.size,
+            };
+            Some((paths.remote_key(&file.relative_path), file))
+        })
+        .collect()
+}
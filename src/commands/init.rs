@@ -0,0 +1,155 @@
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::{io::Write, path::Path};
+
+use crate::{
+    api::Client,
+    i18n::{Message, tr},
+    storage::{self, AgentOptions},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct InitArgs {
+    /// The name of the storage zone to create
+    name: String,
+
+    /// Regions to replicate the zone to, e.g. ny,sg
+    #[arg(long, value_delimiter = ',')]
+    replicas: Vec<String>,
+
+    /// Also create a pull zone serving the storage zone, named after it
+    /// unless a name is given
+    #[arg(long, value_name = "NAME")]
+    pull_zone: Option<Option<String>>,
+
+    /// Your bunny.net account API key
+    #[arg(long, env = "BUNNYSYNC_ACCOUNT_KEY")]
+    account_key: Option<String>,
+}
+
+impl InitArgs {
+    /// The account key given on the command line, for filling in from the
+    /// config file.
+    pub fn account_key_mut(&mut self) -> &mut Option<String> {
+        &mut self.account_key
+    }
+}
+
+/// What init writes to `.bunnysync`.
+#[derive(Debug, PartialEq, Serialize)]
+struct InitConfig {
+    api_key: String,
+    region: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pull_zone: Option<String>,
+}
+
+/// Create a storage zone in the region from the global options, and a pull
+/// zone for it if asked, then write a config file for the new zone.
+pub fn run(
+    args: &InitArgs,
+    region: &str,
+    config_file: &Path,
+    agent_options: &AgentOptions,
+) -> Result<()> {
+    let Some(account_key) = &args.account_key else {
+        return Err(anyhow!(tr(Message::MissingAccountKey)));
+    };
+    // Check everything before creating anything.
+    if config_file.exists() {
+        return Err(anyhow!(tr(Message::ConfigExists {
+            file: &config_file.to_string_lossy(),
+        })));
+    }
+    let code = storage::region_code(region).ok_or_else(|| anyhow!("Invalid region {}", region))?;
+    let replicas = args
+        .replicas
+        .iter()
+        .map(|replica| {
+            storage::region_code(replica).ok_or_else(|| anyhow!("Invalid region {}", replica))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let client = Client::new(account_key, agent_options)?;
+    let zone = client.create_storage_zone(&args.name, code, &replicas)?;
+    println!(
+        "{}",
+        tr(Message::CreatedStorageZone {
+            name: &zone.name,
+            region: code,
+        })
+    );
+    let pull_zone = match &args.pull_zone {
+        Some(name) => {
+            let name = name.as_deref().unwrap_or(&args.name);
+            let pull_zone = client.create_pull_zone(name, zone.id)?;
+            let hostname = pull_zone
+                .hostnames
+                .first()
+                .map_or(pull_zone.name.as_str(), |hostname| hostname.value.as_str());
+            println!(
+                "{}",
+                tr(Message::CreatedPullZone {
+                    name: &pull_zone.name,
+                    hostname,
+                })
+            );
+            Some(pull_zone.id.to_string())
+        }
+        None => None,
+    };
+
+    let config = InitConfig {
+        api_key: zone.password,
+        region: region.to_string(),
+        pull_zone,
+    };
+    write_config(config_file, &config)?;
+    println!(
+        "{}",
+        tr(Message::WroteConfig {
+            file: &config_file.to_string_lossy(),
+        })
+    );
+    Ok(())
+}
+
+/// Write the config where only the owner can read it, as it holds the zone
+/// password.
+fn write_config(path: &Path, config: &InitConfig) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .map_err(|e| anyhow!("Could not write {}: {}", path.to_string_lossy(), e))?;
+    file.write_all(toml::to_string(config)?.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local;
+
+    #[test]
+    fn test_write_config() {
+        let dir = std::env::temp_dir().join(format!("bunnysync-init-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(local::CONFIG_FILE);
+        let config = InitConfig {
+            api_key: "zone-password".to_string(),
+            region: "ny".to_string(),
+            pull_zone: Some("42".to_string()),
+        };
+        write_config(&path, &config).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "api_key = \"zone-password\"\nregion = \"ny\"\npull_zone = \"42\"\n"
+        );
+        // An existing config is never overwritten.
+        assert!(write_config(&path, &config).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,21 @@
+use anyhow::{Result, anyhow};
+
+use crate::{
+    i18n::{Message, tr},
+    keychain, prompt,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct LoginArgs {}
+
+/// Ask for the API key of a storage zone and keep it in the OS keychain
+/// under a profile, for when no key is given any other way.
+pub fn run(_args: &LoginArgs, profile: &str) -> Result<()> {
+    let key = prompt::read_secret(&tr(Message::EnterApiKey { profile }))?;
+    if key.is_empty() {
+        return Err(anyhow!(tr(Message::MissingApiKey)));
+    }
+    keychain::store(profile, &key)?;
+    println!("{}", tr(Message::StoredApiKey { profile }));
+    Ok(())
+}
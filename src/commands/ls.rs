@@ -0,0 +1,108 @@
+use anyhow::Result;
+use ureq::Agent;
+
+use crate::storage::{self, StorageObject};
+
+#[derive(clap::Args, Debug)]
+pub struct LsArgs {
+    /// The storage zone path to list, e.g. zone://my-zone/path
+    path: String,
+
+    /// List subdirectories recursively
+    #[arg(short = 'R', long)]
+    recursive: bool,
+
+    /// Print the listing as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+/// List the objects under a storage zone path.
+pub fn run(agent: &Agent, base_url: &str, args: &LsArgs) -> Result<()> {
+    let path = directory_path(storage::strip_zone_prefix(&args.path));
+    let mut objects = if args.recursive {
+        storage::get_all_objects(agent, base_url, &path)?
+    } else {
+        storage::get_objects(agent, base_url, &path)?
+    };
+    objects.sort_by(|a, b| (&a.path, &a.object_name).cmp(&(&b.path, &b.object_name)));
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&objects)?);
+        return Ok(());
+    }
+    let prefix = format!("/{}", path.trim_start_matches('/'));
+    for object in &objects {
+        let size = if object.is_directory {
+            "-".to_string()
+        } else {
+            object.length.to_string()
+        };
+        println!(
+            "{}  {:>12}  {}",
+            object.last_changed.utc().format("%Y-%m-%d %H:%M:%S"),
+            size,
+            display_name(object, &prefix)
+        );
+    }
+    Ok(())
+}
+
+/// Make sure a path refers to a directory listing rather than an object.
+fn directory_path(path: &str) -> String {
+    if path.ends_with('/') {
+        path.to_string()
+    } else {
+        format!("{}/", path)
+    }
+}
+
+/// The name of an object relative to the listed prefix. Directories get a
+/// trailing slash.
+fn display_name(object: &StorageObject, prefix: &str) -> String {
+    let full = format!("{}{}", object.path, object.object_name);
+    let name = full.strip_prefix(prefix).unwrap_or(&full);
+    if object.is_directory {
+        format!("{}/", name)
+    } else {
+        name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(path: &str, object_name: &str, is_directory: bool) -> StorageObject {
+        StorageObject {
+            storage_zone_name: "myzone".to_string(),
+            path: path.to_string(),
+            object_name: object_name.to_string(),
+            is_directory,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_directory_path() {
+        assert_eq!(directory_path("myzone"), "myzone/");
+        assert_eq!(directory_path("myzone/path/"), "myzone/path/");
+    }
+
+    #[test]
+    fn test_display_name() {
+        let prefix = "/myzone/path/";
+        assert_eq!(
+            display_name(&object("/myzone/path/", "file.txt", false), prefix),
+            "file.txt"
+        );
+        assert_eq!(
+            display_name(&object("/myzone/path/sub/", "file.txt", false), prefix),
+            "sub/file.txt"
+        );
+        assert_eq!(
+            display_name(&object("/myzone/path/", "sub", true), prefix),
+            "sub/"
+        );
+    }
+}
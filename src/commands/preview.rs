@@ -0,0 +1,251 @@
+use anyhow::{Result, anyhow};
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+};
+use ureq::Agent;
+
+use crate::{
+    i18n::{Message, tr},
+    storage, webdav,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct PreviewArgs {
+    /// The storage zone to serve, e.g. zone://my-zone/site/. Defaults to
+    /// zone in .bunnysync
+    zone: Option<String>,
+
+    /// The port to serve on. Only this machine can connect
+    #[arg(long, default_value_t = 8080)]
+    port: u16,
+}
+
+impl PreviewArgs {
+    /// The zone given on the command line, for filling in from the config
+    /// file.
+    pub fn zone_mut(&mut self) -> &mut Option<String> {
+        &mut self.zone
+    }
+}
+
+/// Serve a zone over HTTP on localhost, fetching each file from the zone the
+/// first time it is asked for. A reload that skips the browser's cache
+/// fetches it again.
+pub fn run(agent: &Agent, base_url: &str, args: &PreviewArgs) -> Result<()> {
+    let Some(zone) = &args.zone else {
+        return Err(anyhow!(tr(Message::ZoneNeeded)));
+    };
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, args.port))?;
+    let url = format!("http://localhost:{}/", args.port);
+    println!("{}", tr(Message::Previewing { zone, url: &url }));
+    let preview = Preview {
+        agent,
+        base_url,
+        root: storage::strip_zone_prefix(zone)
+            .trim_end_matches('/')
+            .to_string(),
+        cache: Mutex::new(HashMap::new()),
+    };
+    std::thread::scope(|scope| {
+        for stream in listener.incoming().flatten() {
+            let preview = &preview;
+            scope.spawn(move || {
+                if let Err(e) = preview.serve(stream) {
+                    eprintln!("{}", tr(Message::Warning { warning: &e }));
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+struct Preview<'a> {
+    agent: &'a Agent,
+    base_url: &'a str,
+    /// The zone and path files are served from, without a trailing slash.
+    root: String,
+    /// The files fetched so far, by their path in the zone.
+    cache: Mutex<HashMap<String, Arc<Vec<u8>>>>,
+}
+
+/// A response to send back.
+struct Reply {
+    status: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: Arc<Vec<u8>>,
+}
+
+impl Reply {
+    fn text(status: &'static str, text: String) -> Self {
+        Reply {
+            status,
+            headers: vec![("Content-Type", "text/plain; charset=utf-8".to_string())],
+            body: Arc::new(text.into_bytes()),
+        }
+    }
+}
+
+impl Preview<'_> {
+    /// Answer one request, then close the connection.
+    fn serve(&self, stream: TcpStream) -> Result<()> {
+        let mut reader = BufReader::new(&stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let mut fresh = false;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                let name = name.trim().to_ascii_lowercase();
+                fresh |= (name == "cache-control" || name == "pragma")
+                    && value.to_ascii_lowercase().contains("no-cache");
+            }
+        }
+        let mut parts = request_line.split_whitespace();
+        let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        let reply = match (method, object_path(target)) {
+            ("GET" | "HEAD", Some(path)) => self.reply(&path, fresh),
+            ("GET" | "HEAD", None) => Reply::text("400 Bad Request", "Bad request\n".into()),
+            _ => Reply::text("405 Method Not Allowed", "Method not allowed\n".into()),
+        };
+        println!("{} {} {}", method, target, reply.status);
+
+        let mut stream = &stream;
+        let mut head = format!(
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n",
+            reply.status,
+            reply.body.len()
+        );
+        for (name, value) in &reply.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes())?;
+        if method != "HEAD" {
+            stream.write_all(&reply.body)?;
+        }
+        stream.flush()?;
+        Ok(())
+    }
+
+    fn reply(&self, path: &str, fresh: bool) -> Reply {
+        let file = match path.strip_suffix('/') {
+            Some(dir) => format!("{}/{}", dir, INDEX),
+            None => path.to_string(),
+        };
+        match self.fetch(&file, fresh) {
+            Ok(Some(body)) => Reply {
+                status: "200 OK",
+                headers: vec![("Content-Type", content_type(&file).to_string())],
+                body,
+            },
+            // A directory asked for without its trailing slash, which links
+            // in its index page need to resolve.
+            Ok(None) if !path.ends_with('/') => {
+                let index = format!("{}/{}", path, INDEX);
+                match self.fetch(&index, fresh) {
+                    Ok(Some(_)) => Reply {
+                        status: "301 Moved Permanently",
+                        headers: vec![("Location", format!("{}/", path))],
+                        body: Arc::default(),
+                    },
+                    _ => Reply::text("404 Not Found", "Not found\n".into()),
+                }
+            }
+            Ok(None) => Reply::text("404 Not Found", "Not found\n".into()),
+            Err(e) => Reply::text("502 Bad Gateway", format!("{}\n", e)),
+        }
+    }
+
+    /// A file from the cache, or from the zone when it isn't cached yet or a
+    /// fresh copy is wanted. Files that aren't there are looked for again
+    /// every time, so that new ones show up.
+    fn fetch(&self, path: &str, fresh: bool) -> Result<Option<Arc<Vec<u8>>>> {
+        if !fresh && let Some(data) = self.cache.lock().unwrap().get(path) {
+            return Ok(Some(data.clone()));
+        }
+        let key = format!("{}{}", self.root, path);
+        let Some(data) = storage::find_object(self.agent, self.base_url, &key)? else {
+            self.cache.lock().unwrap().remove(path);
+            return Ok(None);
+        };
+        let data = Arc::new(data);
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), data.clone());
+        Ok(Some(data))
+    }
+}
+
+/// The page served for a directory.
+const INDEX: &str = "index.html";
+
+/// The path in the zone that a request target asks for, starting with a
+/// slash. Targets that would reach outside the zone get none.
+fn object_path(target: &str) -> Option<String> {
+    let path = target.split(['?', '#']).next()?;
+    if !path.starts_with('/') {
+        return None;
+    }
+    let path = webdav::percent_decode(path);
+    if path.split('/').any(|segment| segment == "..") || path.contains('\\') {
+        return None;
+    }
+    Some(path)
+}
+
+/// The Content-Type for a file, by its extension.
+fn content_type(path: &str) -> &'static str {
+    let extension = path
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+    match extension.as_deref().unwrap_or("") {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "xml" => "application/xml",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object_path() {
+        assert_eq!(object_path("/").unwrap(), "/");
+        assert_eq!(
+            object_path("/blog/my%20post.html?ref=home").unwrap(),
+            "/blog/my post.html"
+        );
+        assert_eq!(object_path("/docs/#intro").unwrap(), "/docs/");
+        assert_eq!(object_path("/../secret"), None);
+        assert_eq!(object_path("/a/%2e%2e/b"), None);
+        assert_eq!(object_path("http://example.com/"), None);
+        assert_eq!(content_type("/index.HTML"), "text/html; charset=utf-8");
+        assert_eq!(content_type("/LICENSE"), "application/octet-stream");
+    }
+}
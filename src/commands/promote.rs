@@ -0,0 +1,308 @@
+//! Promoting the zone of one profile to that of another, such as staging to
+//! production. The steps are set in the `[promote.<TO>]` table of
+//! `.bunnysync`, so the blessed path is one command that is recorded in the
+//! history like a sync.
+
+use anyhow::{Result, anyhow};
+use chrono::TimeDelta;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+use ureq::Agent;
+
+use crate::{
+    api,
+    commands::run,
+    filter::{Filter, IgnoreRules},
+    freeze,
+    history::{self, RunRecord},
+    i18n::{Message, tr},
+    local::LocalFile,
+    output::{OutputFormat, Reporter},
+    paths::PathMap,
+    plan::{self, RemoteIndex, SkipReason, SyncAction},
+    storage::{self, StorageObject},
+    store,
+    sync::{self, Origin, SyncOptions},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct PromoteArgs {
+    /// The profile to promote from, e.g. staging
+    pub from: String,
+
+    /// The profile to promote to, e.g. prod
+    pub to: String,
+
+    /// Perform a dry run
+    #[arg(long = "dryrun")]
+    pub dry_run: bool,
+
+    /// How to print results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Print a detailed breakdown of the run instead of a one line summary
+    #[arg(long)]
+    stats: bool,
+}
+
+/// The steps of promoting to a profile.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Promotion {
+    /// The only profiles that may be promoted from. Any may when empty.
+    #[serde(default)]
+    pub from: Vec<String>,
+    /// Delete files that aren't in the zone promoted from.
+    #[serde(default)]
+    pub delete: bool,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Purge the changed files from the pull zone of the profile promoted
+    /// to once done.
+    #[serde(default)]
+    pub purge: bool,
+    /// Purge the whole pull zone instead.
+    #[serde(default)]
+    pub purge_all: bool,
+    /// A shell command to run once done, such as posting to a chat.
+    pub notify: Option<String>,
+}
+
+/// The zone of a profile and how to reach it.
+pub struct End {
+    /// The zone directory, like `zone://my-site/`.
+    pub zone: String,
+    pub agent: Agent,
+    pub base_url: String,
+}
+
+/// Copy one zone onto another, then purge and notify as the promotion says.
+pub fn run(
+    args: &PromoteArgs,
+    promotion: &Promotion,
+    from: End,
+    to: &End,
+    purge: Option<(api::Client, String)>,
+) -> Result<()> {
+    if !promotion.from.is_empty() && !promotion.from.contains(&args.from) {
+        return Err(anyhow!(tr(Message::NotPromotable {
+            from: &args.from,
+            to: &args.to,
+        })));
+    }
+    let mut record = RunRecord::start(&from.zone, &to.zone, args.dry_run);
+    let mut reporter = Reporter::new(args.output, args.dry_run, args.stats);
+    let result = promote(args, promotion, from, to, purge, &mut reporter);
+    record.finish(&result, reporter.summary());
+    if let Err(e) = history::append(&store::dir(Path::new(".")), &record) {
+        eprintln!("{}", tr(Message::Warning { warning: &e }));
+    }
+    result
+}
+
+fn promote(
+    args: &PromoteArgs,
+    promotion: &Promotion,
+    from: End,
+    to: &End,
+    purge: Option<(api::Client, String)>,
+    reporter: &mut Reporter,
+) -> Result<()> {
+    if !args.dry_run {
+        freeze::check(&to.agent, &to.base_url, &to.zone)?;
+    }
+    let from_paths = PathMap::new(&from.zone);
+    let to_paths = PathMap::new(&to.zone);
+    let index = |end: &End, paths: &PathMap| -> Result<RemoteIndex> {
+        let filter = Filter::new(
+            promotion.exclude.clone(),
+            IgnoreRules::default(),
+            paths.root(),
+        );
+        let remote = paths.root().trim_start_matches('/').to_string();
+        let objects = sync::missing_as_empty(
+            storage::get_all_objects(&end.agent, &end.base_url, &remote),
+            &remote,
+        )?;
+        Ok(RemoteIndex::new(objects, &filter))
+    };
+    let source = index(&from, &from_paths)?;
+    let destination = index(to, &to_paths)?;
+
+    let files = source_files(&source, &from_paths, &to_paths);
+    reporter.scanned(files.len() as u64);
+    let mut actions =
+        plan::plan_to_remote(&files, &destination, promotion.delete, TimeDelta::zero());
+    compare_checksums(&mut actions, &files, &source, &destination);
+
+    let options = SyncOptions {
+        dry_run: args.dry_run,
+        delete: promotion.delete,
+        filter: Filter::new(
+            promotion.exclude.clone(),
+            IgnoreRules::default(),
+            to_paths.root(),
+        ),
+        origin: Some(Origin::Zone {
+            agent: from.agent,
+            base_url: from.base_url,
+        }),
+        ..Default::default()
+    };
+    sync::execute(&to.agent, &to.base_url, &actions, &options, reporter)?;
+    reporter.finish();
+    if args.dry_run {
+        return Ok(());
+    }
+    if let Some((api, pull_zone)) = &purge {
+        sync::purge_cdn(api, pull_zone, promotion.purge_all, &actions, reporter)?;
+    }
+    if let Some(notify) = &promotion.notify {
+        let summary = reporter.summary();
+        let status = run::shell(notify)
+            .env("BUNNYSYNC_FROM", &args.from)
+            .env("BUNNYSYNC_TO", &args.to)
+            .env("BUNNYSYNC_UPLOADED", summary.uploaded.to_string())
+            .env("BUNNYSYNC_DELETED", summary.deleted.to_string())
+            .status()?;
+        if !status.success() {
+            return Err(anyhow!("The notify command failed: {}", status));
+        }
+    }
+    reporter.message(&tr(Message::Promoted {
+        from: &args.from,
+        to: &args.to,
+    }));
+    Ok(())
+}
+
+/// The files of the zone promoted from as if they were local files, by the
+/// key they would have in the zone promoted to. Their paths are their keys.
+fn source_files(source: &RemoteIndex, from: &PathMap, to: &PathMap) -> HashMap<String, LocalFile> {
+    let root = from.root();
+    source
+        .files
+        .iter()
+        .filter_map(|(key, object)| {
+            let relative = PathBuf::from(key.strip_prefix(&root)?);
+            let file = LocalFile {
+                path: PathBuf::from(key),
+                is_directory: false,
+                last_changed: object.last_changed.utc(),
+                length: object.length,
+                relative_path: relative.clone(),
+            };
+            Some((to.remote_key(&relative), file))
+        })
+        .collect()
+}
+
+/// Let checksums decide where both zones have them, as times only say when
+/// each copy was uploaded.
+fn compare_checksums(
+    actions: &mut [SyncAction],
+    files: &HashMap<String, LocalFile>,
+    source: &RemoteIndex,
+    destination: &RemoteIndex,
+) {
+    let checksum = |index: &RemoteIndex, key: &str| -> Option<String> {
+        index
+            .files
+            .get(key)
+            .and_then(|object: &StorageObject| object.checksum.as_deref())
+            .map(str::to_ascii_lowercase)
+    };
+    for action in actions {
+        let key = match action {
+            SyncAction::Upload { key, .. } => key.clone(),
+            SyncAction::Skip {
+                key,
+                reason: SkipReason::Unchanged,
+            } => key.clone(),
+            _ => continue,
+        };
+        let Some(file) = files.get(&key) else {
+            continue;
+        };
+        let (Some(from), Some(to)) = (
+            checksum(source, &file.path.to_string_lossy()),
+            checksum(destination, &key),
+        ) else {
+            continue;
+        };
+        *action = if from == to {
+            SyncAction::Skip {
+                key,
+                reason: SkipReason::Unchanged,
+            }
+        } else {
+            SyncAction::Upload {
+                key,
+                path: file.path.clone(),
+                length: file.length,
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timestamp::RemoteTime;
+    use chrono::DateTime;
+
+    fn object(path: &str, name: &str, changed: i64, checksum: &str) -> StorageObject {
+        StorageObject {
+            path: path.to_string(),
+            object_name: name.to_string(),
+            length: 10,
+            last_changed: RemoteTime::from_utc(DateTime::from_timestamp(changed, 0).unwrap()),
+            checksum: Some(checksum.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_plan_promotion() {
+        let source = RemoteIndex::new(
+            vec![
+                // Uploaded to staging again, but unchanged.
+                object("/staging/", "same.html", 200, "aa"),
+                // Changed in staging before the last promotion of another
+                // file, at the same size.
+                object("/staging/", "edited.html", 50, "bb"),
+                object("/staging/css/", "new.css", 50, "cc"),
+            ],
+            &Filter::default(),
+        );
+        let destination = RemoteIndex::new(
+            vec![
+                object("/prod/", "same.html", 100, "AA"),
+                object("/prod/", "edited.html", 100, "00"),
+            ],
+            &Filter::default(),
+        );
+        let from = PathMap::new("zone://staging/");
+        let to = PathMap::new("zone://prod/");
+        let files = source_files(&source, &from, &to);
+        assert_eq!(
+            files["/prod/css/new.css"].path,
+            PathBuf::from("/staging/css/new.css")
+        );
+
+        let mut actions = plan::plan_to_remote(&files, &destination, false, TimeDelta::zero());
+        compare_checksums(&mut actions, &files, &source, &destination);
+        let uploads: Vec<&str> = actions
+            .iter()
+            .filter_map(|action| match action {
+                SyncAction::Upload { key, .. } => Some(key.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(uploads, vec!["/prod/css/new.css", "/prod/edited.html"]);
+    }
+}
@@ -0,0 +1,361 @@
+//! Named jobs, kept in the `[jobs]` table of `.bunnysync` so a setup with
+//! several zones lives in the config rather than in shell scripts. A job is
+//! either a sync or a shell command, and can name other jobs to run first.
+
+use anyhow::{Result, anyhow};
+use clap::{Args, FromArgMatches};
+use serde::Deserialize;
+use std::{collections::BTreeMap, path::Path, time::Instant};
+
+use crate::{
+    exit::ChangesFound,
+    i18n::{Message, tr},
+    sync::SyncArgs,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct RunArgs {
+    /// The job to run, after the jobs it depends on. Lists the jobs when
+    /// left out
+    pub job: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    pub source: Option<String>,
+    pub destination: Option<String>,
+    /// Sync options, written as on the command line, like `["--delete"]`.
+    #[serde(default)]
+    pub flags: Vec<String>,
+    /// A shell command to run instead of a sync, like building the site.
+    pub command: Option<String>,
+    /// Jobs to run before this one.
+    #[serde(default)]
+    pub after: Vec<String>,
+    /// The storage zone password, for jobs on zones other than the default.
+    pub api_key: Option<String>,
+    pub region: Option<String>,
+    /// When the job is meant to run, like `hourly`. Only shown in the job
+    /// list, as bunnysync doesn't schedule anything itself.
+    pub schedule: Option<String>,
+}
+
+impl Job {
+    /// The sync the job stands for.
+    pub fn sync_args(&self, name: &str) -> Result<SyncArgs> {
+        let (Some(source), Some(destination)) = (&self.source, &self.destination) else {
+            return Err(anyhow!(
+                "Job {} needs a source and a destination, or a command",
+                name
+            ));
+        };
+        let command = SyncArgs::augment_args(clap::Command::new("sync"));
+        let args = [source.as_str(), destination.as_str()]
+            .into_iter()
+            .chain(self.flags.iter().map(String::as_str));
+        let matches = command
+            .try_get_matches_from(std::iter::once("sync").chain(args))
+            .map_err(|e| anyhow!("Bad flags for job {}: {}", name, first_line(&e)))?;
+        Ok(SyncArgs::from_arg_matches(&matches)?)
+    }
+
+    /// Fail if the job is neither a sync nor a command, or both.
+    fn check(&self, name: &str) -> Result<()> {
+        match &self.command {
+            Some(_) if self.source.is_some() || self.destination.is_some() => Err(anyhow!(
+                "Job {} has both a command and a source or destination",
+                name
+            )),
+            Some(_) => Ok(()),
+            None => self.sync_args(name).map(|_| ()),
+        }
+    }
+}
+
+/// The gist of a clap error, without the usage that follows it.
+fn first_line(e: &clap::Error) -> String {
+    let text = e.render().to_string();
+    let line = text.lines().next().unwrap_or_default();
+    line.strip_prefix("error: ").unwrap_or(line).to_string()
+}
+
+#[derive(Deserialize, Default)]
+struct JobsConfig {
+    #[serde(default)]
+    jobs: BTreeMap<String, Job>,
+}
+
+/// The jobs in a config file, by name. There are none without one.
+pub fn load(config_file: Option<&Path>) -> Result<BTreeMap<String, Job>> {
+    let Some(config_file) = config_file else {
+        return Ok(BTreeMap::new());
+    };
+    match std::fs::read_to_string(config_file) {
+        Ok(text) => Ok(toml::from_str::<JobsConfig>(&text)?.jobs),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The jobs to run for a job, each after the jobs it depends on and ending
+/// with the job itself.
+fn order<'a>(jobs: &'a BTreeMap<String, Job>, name: &'a str) -> Result<Vec<&'a str>> {
+    fn visit<'a>(
+        jobs: &'a BTreeMap<String, Job>,
+        name: &'a str,
+        path: &mut Vec<&'a str>,
+        order: &mut Vec<&'a str>,
+    ) -> Result<()> {
+        if order.contains(&name) {
+            return Ok(());
+        }
+        if path.contains(&name) {
+            path.push(name);
+            return Err(anyhow!("Jobs depend on each other: {}", path.join(" -> ")));
+        }
+        let job = jobs
+            .get(name)
+            .ok_or_else(|| anyhow!(tr(Message::NoSuchJob { name })))?;
+        path.push(name);
+        for before in &job.after {
+            visit(jobs, before, path, order)?;
+        }
+        path.pop();
+        order.push(name);
+        Ok(())
+    }
+
+    let mut order = Vec::new();
+    visit(jobs, name, &mut Vec::new(), &mut order)?;
+    Ok(order)
+}
+
+/// Run a job after the jobs it depends on, stopping at the first that
+/// fails. Syncs are handed to `sync`, as they need the global options. With
+/// no job given, list the jobs instead.
+pub fn run(
+    args: &RunArgs,
+    config_file: Option<&Path>,
+    sync: impl Fn(&str, &Job) -> Result<()>,
+) -> Result<()> {
+    let jobs = load(config_file)?;
+    let Some(name) = &args.job else {
+        if jobs.is_empty() {
+            println!("{}", tr(Message::NoJobs));
+        }
+        for (name, job) in &jobs {
+            println!("{}", describe(name, job));
+        }
+        return Ok(());
+    };
+    let order = order(&jobs, name)?;
+    // Find mistakes before anything runs, not halfway through.
+    for name in &order {
+        jobs[*name].check(name)?;
+    }
+
+    let mut report = Vec::new();
+    let mut failure = None;
+    // A job that found changes with `--exit-code` still succeeded, but the
+    // run ends with the status saying so.
+    let mut changes = false;
+    for name in &order {
+        if failure.is_some() {
+            report.push(tr(Message::JobSkipped { name }));
+            continue;
+        }
+        let job = &jobs[*name];
+        let started = Instant::now();
+        let result = match &job.command {
+            Some(command) => run_command(name, command),
+            None => sync(name, job),
+        };
+        let result = match result {
+            Err(e) if e.is::<ChangesFound>() => {
+                changes = true;
+                Ok(())
+            }
+            result => result,
+        };
+        match result {
+            Ok(()) => report.push(tr(Message::JobSucceeded {
+                name,
+                seconds: started.elapsed().as_secs_f64(),
+            })),
+            Err(e) => {
+                report.push(tr(Message::JobFailed { name }));
+                failure = Some(e);
+            }
+        }
+    }
+    if order.len() > 1 {
+        for line in report {
+            println!("{}", line);
+        }
+    }
+    match failure {
+        Some(e) => Err(e),
+        None if changes => Err(ChangesFound.into()),
+        None => Ok(()),
+    }
+}
+
+/// Run a job's command with the shell.
+fn run_command(name: &str, command: &str) -> Result<()> {
+    let status = shell(command).status()?;
+    if !status.success() {
+        return Err(anyhow!("The command for job {} failed: {}", name, status));
+    }
+    Ok(())
+}
+
+/// A command line run by the shell.
+pub fn shell(command: &str) -> std::process::Command {
+    let mut shell = if cfg!(windows) {
+        let mut shell = std::process::Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = std::process::Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command);
+    shell
+}
+
+fn describe(name: &str, job: &Job) -> String {
+    let mut line = match (&job.command, &job.source, &job.destination) {
+        (Some(command), _, _) => format!("{}  $ {}", name, command),
+        (None, source, destination) => format!(
+            "{}  {} -> {}",
+            name,
+            source.as_deref().unwrap_or_default(),
+            destination.as_deref().unwrap_or_default()
+        ),
+    };
+    if !job.flags.is_empty() {
+        line.push_str(&format!("  {}", job.flags.join(" ")));
+    }
+    if !job.after.is_empty() {
+        line.push_str(&format!("  after {}", job.after.join(", ")));
+    }
+    if let Some(schedule) = &job.schedule {
+        line.push_str(&format!("  ({})", schedule));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jobs(toml: &str) -> BTreeMap<String, Job> {
+        toml::from_str::<JobsConfig>(toml).unwrap().jobs
+    }
+
+    #[test]
+    fn test_jobs() {
+        let jobs = jobs(
+            r#"
+            [jobs.site]
+            source = "./public/"
+            destination = "zone://my-site/"
+            flags = ["--delete", "--exclude", "*.map"]
+            schedule = "on every deploy"
+            "#,
+        );
+        let job = &jobs["site"];
+        let args = job.sync_args("site").unwrap();
+        assert_eq!(args.exclude, vec!["*.map"]);
+        assert_eq!(
+            describe("site", job),
+            "site  ./public/ -> zone://my-site/  --delete --exclude *.map  (on every deploy)"
+        );
+
+        let bad = Job {
+            flags: vec!["--no-such-flag".to_string()],
+            ..job.clone()
+        };
+        assert!(bad.check("site").is_err());
+        let both = Job {
+            command: Some("make".to_string()),
+            ..job.clone()
+        };
+        assert!(both.check("site").is_err());
+    }
+
+    #[test]
+    fn test_run_goes_on_past_changes() {
+        let config = std::env::temp_dir().join(format!("bunnysync-jobs-{}", std::process::id()));
+        std::fs::write(
+            &config,
+            r#"
+            [jobs.check]
+            source = "./site/"
+            destination = "zone://site/"
+            flags = ["--dryrun", "--exit-code"]
+            [jobs.deploy]
+            source = "./site/"
+            destination = "zone://site/"
+            after = ["check"]
+            "#,
+        )
+        .unwrap();
+        let args = RunArgs {
+            job: Some("deploy".to_string()),
+        };
+        let ran = std::sync::Mutex::new(Vec::new());
+        let result = run(&args, Some(&config), |name, _| {
+            ran.lock().unwrap().push(name.to_string());
+            match name {
+                "check" => Err(ChangesFound.into()),
+                _ => Ok(()),
+            }
+        });
+        assert!(result.unwrap_err().is::<ChangesFound>());
+        assert_eq!(*ran.lock().unwrap(), vec!["check", "deploy"]);
+        std::fs::remove_file(&config).unwrap();
+    }
+
+    #[test]
+    fn test_order() {
+        let jobs = jobs(
+            r#"
+            [jobs.build]
+            command = "make"
+            [jobs.assets]
+            source = "./assets/"
+            destination = "zone://assets/"
+            after = ["build"]
+            [jobs.pages]
+            source = "./pages/"
+            destination = "zone://pages/"
+            after = ["build", "assets"]
+            [jobs.loop-a]
+            command = "true"
+            after = ["loop-b"]
+            [jobs.loop-b]
+            command = "true"
+            after = ["loop-a"]
+            [jobs.broken]
+            command = "true"
+            after = ["missing"]
+            "#,
+        );
+        assert_eq!(
+            order(&jobs, "pages").unwrap(),
+            vec!["build", "assets", "pages"]
+        );
+        assert_eq!(order(&jobs, "build").unwrap(), vec!["build"]);
+        assert!(
+            order(&jobs, "loop-a")
+                .unwrap_err()
+                .to_string()
+                .contains("loop-a -> loop-b -> loop-a")
+        );
+        assert!(order(&jobs, "broken").is_err());
+        assert_eq!(describe("build", &jobs["build"]), "build  $ make");
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::{Result, anyhow};
+use ureq::Agent;
+
+use crate::storage::{self, StorageObject};
+
+#[derive(clap::Args, Debug)]
+pub struct StatArgs {
+    /// The storage zone object to describe, e.g. zone://my-zone/index.html
+    path: String,
+
+    /// Print the metadata as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+/// Print everything bunny.net knows about a single object.
+pub fn run(agent: &Agent, base_url: &str, args: &StatArgs) -> Result<()> {
+    let path = storage::strip_zone_prefix(&args.path);
+    let (parent, name) =
+        split_path(path).ok_or_else(|| anyhow!("{} is not a path inside a zone", args.path))?;
+    // There is no metadata endpoint, so find the object in its parent listing.
+    let object = storage::get_objects(agent, base_url, &parent)?
+        .into_iter()
+        .find(|object| object.object_name == name)
+        .ok_or_else(|| anyhow!("Not found: Path {} does not exist", path))?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&object)?);
+    } else {
+        print!("{}", format_object(&object));
+    }
+    Ok(())
+}
+
+/// Split a zone path into its parent directory and object name, e.g.
+/// `zone/dir/file.txt` into `zone/dir/` and `file.txt`.
+fn split_path(path: &str) -> Option<(String, String)> {
+    let path = path.trim_matches('/');
+    let (parent, name) = path.rsplit_once('/')?;
+    Some((format!("{}/", parent), name.to_string()))
+}
+
+/// Lay the metadata out as aligned `Field: value` lines.
+fn format_object(object: &StorageObject) -> String {
+    let none = || "-".to_string();
+    let fields = [
+        ("Path", format!("{}{}", object.path, object.object_name)),
+        (
+            "Type",
+            if object.is_directory {
+                "directory"
+            } else {
+                "file"
+            }
+            .to_string(),
+        ),
+        ("Length", object.length.to_string()),
+        ("ContentType", object.content_type.clone()),
+        ("Checksum", object.checksum.clone().unwrap_or_else(none)),
+        ("LastChanged", object.last_changed.to_string()),
+        ("DateCreated", object.date_created.to_string()),
+        ("Guid", object.guid.clone()),
+        ("StorageZoneName", object.storage_zone_name.clone()),
+        ("StorageZoneId", object.storage_zone_id.to_string()),
+        (
+            "ReplicatedZones",
+            object.replicated_zones.clone().unwrap_or_else(none),
+        ),
+        ("UserId", object.user_id.clone()),
+        ("ServerId", object.server_id.to_string()),
+        ("ArrayNumber", object.array_number.to_string()),
+    ];
+    fields
+        .iter()
+        .map(|(name, value)| format!("{:<16} {}\n", format!("{}:", name), value))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_path() {
+        assert_eq!(
+            split_path("zone/dir/file.txt"),
+            Some(("zone/dir/".to_string(), "file.txt".to_string()))
+        );
+        assert_eq!(
+            split_path("/zone/dir/"),
+            Some(("zone/".to_string(), "dir".to_string()))
+        );
+        assert_eq!(split_path("zone"), None);
+    }
+
+    #[test]
+    fn test_format_object() {
+        let object = StorageObject {
+            path: "/zone/".to_string(),
+            object_name: "index.html".to_string(),
+            length: 42,
+            checksum: Some("ABC".to_string()),
+            ..Default::default()
+        };
+        let text = format_object(&object);
+        assert!(text.starts_with("Path:            /zone/index.html\nType:            file\n"));
+        assert!(text.contains("Checksum:        ABC\n"));
+        assert!(text.contains("ReplicatedZones: -\n"));
+    }
+}
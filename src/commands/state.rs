@@ -0,0 +1,96 @@
+//! Looking into and clearing out what bunnysync keeps in the state
+//! directory.
+
+use anyhow::Result;
+use std::path::PathBuf;
+
+use crate::{
+    i18n::{Message, tr},
+    store::{self, DirState},
+    units,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct StateArgs {
+    #[command(subcommand)]
+    command: StateCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum StateCommand {
+    /// Show what is kept for a local directory
+    Show {
+        /// The local directory
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+
+        /// Show what is kept for every local directory instead
+        #[arg(long, conflicts_with = "dir")]
+        all: bool,
+    },
+    /// Remove what is kept for a local directory. Caches are rebuilt as
+    /// needed, but the history is lost and bidirectional syncs start over
+    Clean {
+        /// The local directory
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+
+        /// Remove the whole state directory instead
+        #[arg(long, conflicts_with = "dir")]
+        all: bool,
+    },
+}
+
+pub fn run(args: &StateArgs) -> Result<()> {
+    match &args.command {
+        StateCommand::Show { all: true, .. } => {
+            if let Some(root) = store::root() {
+                println!("{}", root.display());
+            }
+            for state in store::all()? {
+                println!("{}", describe(&state));
+            }
+        }
+        StateCommand::Show { dir, .. } => match store::find(dir)? {
+            Some(state) => {
+                println!("{}", describe(&state));
+                for (name, bytes) in &state.files {
+                    println!("  {:<16} {:>10}", name, units::format_bytes(*bytes));
+                }
+            }
+            None => println!("{}", tr(Message::NoState { path: dir })),
+        },
+        StateCommand::Clean { all: true, .. } => {
+            if let Some(root) = store::root()
+                && root.exists()
+            {
+                std::fs::remove_dir_all(&root)?;
+                println!("{}", tr(Message::StateCleaned { path: &root }));
+            }
+        }
+        StateCommand::Clean { dir, .. } => match store::find(dir)? {
+            Some(state) => {
+                std::fs::remove_dir_all(&state.dir)?;
+                println!("{}", tr(Message::StateCleaned { path: dir }));
+            }
+            None => println!("{}", tr(Message::NoState { path: dir })),
+        },
+    }
+    Ok(())
+}
+
+/// One line about the state of a local directory, marking directories that
+/// are gone.
+fn describe(state: &DirState) -> String {
+    let local = match &state.local {
+        Some(local) if local.exists() => local.display().to_string(),
+        Some(local) => format!("{} (gone)", local.display()),
+        None => "?".to_string(),
+    };
+    format!(
+        "{}  {}  {}",
+        local,
+        units::format_bytes(state.bytes()),
+        state.dir.display()
+    )
+}
@@ -0,0 +1,151 @@
+use anyhow::{Result, anyhow};
+use chrono::{Days, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::{
+    api::{Client, PullZone, Statistics, StorageZone},
+    i18n::{Message, tr},
+    paths::PathMap,
+    storage::AgentOptions,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct StatsArgs {
+    /// The storage zone, e.g. zone://my-zone
+    zone: String,
+
+    /// How many days of bandwidth to count, ending today
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u32).range(1..))]
+    days: u32,
+
+    /// Print the statistics as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Your bunny.net account API key
+    #[arg(long, env = "BUNNYSYNC_ACCOUNT_KEY")]
+    account_key: Option<String>,
+}
+
+impl StatsArgs {
+    /// The account key given on the command line, for filling in from the
+    /// config file.
+    pub fn account_key_mut(&mut self) -> &mut Option<String> {
+        &mut self.account_key
+    }
+}
+
+/// The usage of a storage zone, as printed by `stats --json`.
+#[derive(Debug, PartialEq, Serialize, JsonSchema)]
+pub struct ZoneStats {
+    pub zone: String,
+    /// Bytes stored in the zone.
+    pub storage_used: u64,
+    pub files_stored: u64,
+    /// How many days the bandwidth covers, ending today.
+    pub days: u32,
+    /// Bytes served by the pull zones in front of the zone.
+    pub bandwidth_used: u64,
+    pub requests_served: u64,
+    /// The names of the pull zones in front of the zone.
+    pub pull_zones: Vec<String>,
+}
+
+impl ZoneStats {
+    /// Add up the statistics of the pull zones serving a storage zone.
+    fn new(zone: &StorageZone, days: u32, pull_zones: &[(PullZone, Statistics)]) -> Self {
+        ZoneStats {
+            zone: zone.name.clone(),
+            storage_used: zone.storage_used,
+            files_stored: zone.files_stored,
+            days,
+            bandwidth_used: pull_zones
+                .iter()
+                .map(|(_, stats)| stats.total_bandwidth_used)
+                .sum(),
+            requests_served: pull_zones
+                .iter()
+                .map(|(_, stats)| stats.total_requests_served)
+                .sum(),
+            pull_zones: pull_zones
+                .iter()
+                .map(|(pull_zone, _)| pull_zone.name.clone())
+                .collect(),
+        }
+    }
+}
+
+/// Show how much a zone stores and how much traffic the pull zones in front
+/// of it have served recently.
+pub fn run(args: &StatsArgs, agent_options: &AgentOptions) -> Result<()> {
+    let Some(account_key) = &args.account_key else {
+        return Err(anyhow!(tr(Message::MissingAccountKey)));
+    };
+    let client = Client::new(account_key, agent_options)?;
+    let zone = client.find_storage_zone(PathMap::new(&args.zone).zone())?;
+    let to = Utc::now().date_naive();
+    let from = to - Days::new(u64::from(args.days) - 1);
+    let pull_zones = client
+        .search_pull_zones("")?
+        .into_iter()
+        .filter(|pull_zone| pull_zone.storage_zone_id() == Some(zone.id))
+        .map(|pull_zone| {
+            let stats = client.statistics(pull_zone.id, from, to)?;
+            Ok((pull_zone, stats))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let stats = ZoneStats::new(&zone, args.days, &pull_zones);
+
+    if args.json {
+        println!("{}", serde_json::to_string(&stats)?);
+    } else {
+        println!("{}", tr(Message::ZoneStats { stats: &stats }));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_zone_stats() {
+        let zone: StorageZone = serde_json::from_value(json!({
+            "Id": 3,
+            "Name": "my-site",
+            "StorageUsed": 1000,
+            "FilesStored": 4,
+        }))
+        .unwrap();
+        let pull_zone = |name: &str| -> PullZone {
+            serde_json::from_value(json!({ "Id": 9, "Name": name, "StorageZoneId": 3 })).unwrap()
+        };
+        let traffic = |bytes, requests| Statistics {
+            total_bandwidth_used: bytes,
+            total_requests_served: requests,
+        };
+        assert_eq!(pull_zone("cdn").storage_zone_id(), Some(3));
+        let stats = ZoneStats::new(
+            &zone,
+            7,
+            &[
+                (pull_zone("cdn"), traffic(500, 5)),
+                (pull_zone("images"), traffic(250, 2)),
+            ],
+        );
+        assert_eq!(
+            stats,
+            ZoneStats {
+                zone: "my-site".to_string(),
+                storage_used: 1000,
+                files_stored: 4,
+                days: 7,
+                bandwidth_used: 750,
+                requests_served: 7,
+                pull_zones: vec!["cdn".to_string(), "images".to_string()],
+            }
+        );
+    }
+}
@@ -0,0 +1,82 @@
+use anyhow::{Result, anyhow};
+
+use crate::{
+    api::{Client, StorageZone},
+    i18n::{Message, tr},
+    storage::AgentOptions,
+    units,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct ZonesArgs {
+    /// Print sizes in KiB, MiB, GiB and so on
+    #[arg(short = 'H', long)]
+    human_readable: bool,
+
+    /// Your bunny.net account API key
+    #[arg(long, env = "BUNNYSYNC_ACCOUNT_KEY")]
+    account_key: Option<String>,
+}
+
+impl ZonesArgs {
+    /// The account key given on the command line, for filling in from the
+    /// config file.
+    pub fn account_key_mut(&mut self) -> &mut Option<String> {
+        &mut self.account_key
+    }
+}
+
+/// List the account's storage zones with their size and regions, the main
+/// region first.
+pub fn run(args: &ZonesArgs, agent_options: &AgentOptions) -> Result<()> {
+    let Some(account_key) = &args.account_key else {
+        return Err(anyhow!(tr(Message::MissingAccountKey)));
+    };
+    let mut zones = Client::new(account_key, agent_options)?.storage_zones()?;
+    zones.sort_by(|a, b| a.name.cmp(&b.name));
+    for zone in &zones {
+        println!("{}", describe(zone, args.human_readable));
+    }
+    Ok(())
+}
+
+fn describe(zone: &StorageZone, human_readable: bool) -> String {
+    let size = if human_readable {
+        units::format_bytes(zone.storage_used)
+    } else {
+        zone.storage_used.to_string()
+    };
+    let regions: Vec<&str> = std::iter::once(zone.region.as_str())
+        .chain(zone.replication_regions.iter().map(String::as_str))
+        .collect();
+    format!(
+        "{:>12}  {:>8} files  {:<12}  {}",
+        size,
+        zone.files_stored,
+        regions.join(","),
+        zone.name
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe() {
+        let zone = StorageZone {
+            id: 1,
+            name: "my-site".to_string(),
+            region: "DE".to_string(),
+            replication_regions: vec!["NY".to_string(), "SG".to_string()],
+            storage_used: 2048,
+            files_stored: 3,
+            password: String::new(),
+        };
+        assert_eq!(
+            describe(&zone, false),
+            "        2048         3 files  DE,NY,SG      my-site"
+        );
+        assert!(describe(&zone, true).starts_with(&format!("{:>12}", units::format_bytes(2048))));
+    }
+}
@@ -0,0 +1,84 @@
+//! The statuses bunnysync exits with, so that scripts and CI can tell a bad
+//! key from a flaky network or a zone that has drifted from the site. A run
+//! that succeeds, or a dry run with nothing to do, exits with 0.
+
+use std::fmt;
+
+use crate::{
+    api,
+    i18n::{Message, tr},
+    storage,
+    sync::FilesFailed,
+    webdav,
+};
+
+/// How a run that did not succeed ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The command line or config was wrong, or the run failed.
+    Error = 1,
+    /// A server turned down the key or password it was given.
+    Auth = 2,
+    /// Some files failed with `--continue-on-error`. The rest were synced.
+    PartialFailure = 3,
+    /// A dry run with `--exit-code` found changes to make.
+    Changes = 4,
+}
+
+/// A dry run or diff with `--exit-code` that found changes. It ends the run
+/// like an error so that it reaches `main`, but isn't reported as one.
+#[derive(Debug)]
+pub struct ChangesFound;
+
+impl fmt::Display for ChangesFound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", tr(Message::ChangesFound))
+    }
+}
+
+impl std::error::Error for ChangesFound {}
+
+/// End the process with a status.
+pub fn exit(status: Status) -> ! {
+    std::process::exit(status as i32)
+}
+
+/// The status for a run that failed with an error.
+pub fn status_of(error: &anyhow::Error) -> Status {
+    let auth = error.chain().any(|cause| {
+        cause.is::<storage::Unauthorized>()
+            || cause.is::<storage::ReadOnlyKey>()
+            || cause.is::<api::Unauthorized>()
+            || cause.is::<webdav::Unauthorized>()
+    });
+    if auth {
+        Status::Auth
+    } else if error.is::<FilesFailed>() {
+        Status::PartialFailure
+    } else if error.is::<ChangesFound>() {
+        Status::Changes
+    } else {
+        Status::Error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_status_of() {
+        let unauthorized: anyhow::Error = storage::Unauthorized { write: false }.into();
+        assert_eq!(status_of(&unauthorized), Status::Auth);
+        let listing = unauthorized.context("Could not list the zone");
+        assert_eq!(status_of(&listing), Status::Auth);
+        assert_eq!(status_of(&api::Unauthorized.into()), Status::Auth);
+        assert_eq!(
+            status_of(&FilesFailed { count: 2 }.into()),
+            Status::PartialFailure
+        );
+        assert_eq!(status_of(&ChangesFound.into()), Status::Changes);
+        assert_eq!(status_of(&anyhow!("HTTP 500")), Status::Error);
+    }
+}
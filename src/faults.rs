@@ -0,0 +1,102 @@
+//! Made up failures for `--simulate-failures`, so retries and resuming can be
+//! tried out and shown without a flaky network. A share of the requests to
+//! the storage API fail with a dropped connection, are turned down with a
+//! 429, or are held up before they are sent.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// What to do to a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Fail as if the connection was reset.
+    Reset,
+    /// Answer 429 Too Many Requests without sending it.
+    TooManyRequests,
+    /// Send it after waiting this long.
+    Slow(Duration),
+}
+
+/// Picks which requests fail, and how.
+#[derive(Debug)]
+pub struct Faults {
+    /// The share of requests that fail, from 0 to 1.
+    rate: f64,
+    /// The state of a xorshift generator. Never zero.
+    state: AtomicU64,
+}
+
+impl Faults {
+    /// Fail a percentage of requests.
+    pub fn new(percent: f64) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos() as u64)
+            ^ u64::from(std::process::id());
+        Self::with_seed(percent / 100.0, seed)
+    }
+
+    fn with_seed(rate: f64, seed: u64) -> Self {
+        Faults {
+            rate,
+            state: AtomicU64::new(seed.max(1)),
+        }
+    }
+
+    fn next(&self) -> u64 {
+        let step = |mut x: u64| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        };
+        let previous = self
+            .state
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| Some(step(x)))
+            .expect("the update always succeeds");
+        step(previous)
+    }
+
+    /// A number from 0 up to but not including 1.
+    fn fraction(&self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// The fault for the next request, if it is to fail.
+    pub fn draw(&self) -> Option<Fault> {
+        if self.fraction() >= self.rate {
+            return None;
+        }
+        Some(match self.next() % 3 {
+            0 => Fault::Reset,
+            1 => Fault::TooManyRequests,
+            _ => Fault::Slow(Duration::from_millis(500 + self.next() % 2500)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_faults() {
+        let never = Faults::with_seed(0.0, 7);
+        assert!((0..1000).all(|_| never.draw().is_none()));
+        let always = Faults::with_seed(1.0, 7);
+        let faults: Vec<Fault> = (0..300).filter_map(|_| always.draw()).collect();
+        assert_eq!(faults.len(), 300);
+        assert!(faults.contains(&Fault::Reset));
+        assert!(faults.contains(&Fault::TooManyRequests));
+        assert!(faults.iter().all(|fault| match fault {
+            Fault::Slow(wait) => (500..3000).contains(&wait.as_millis()),
+            _ => true,
+        }));
+
+        let some = Faults::with_seed(0.2, 42);
+        let failed = (0..10_000).filter(|_| some.draw().is_some()).count();
+        assert!((1500..2500).contains(&failed), "{} failed", failed);
+    }
+}
@@ -0,0 +1,339 @@
+//! Deciding which files a sync leaves out, from `--exclude` patterns and the
+//! `.bunnyignore` file.
+
+use anyhow::{Result, anyhow};
+use std::path::Path;
+
+use crate::sync::is_excluded;
+
+/// Rules in gitignore syntax at the root of the local directory. Files they
+/// match are left out on both sides, as with `--exclude`.
+pub const IGNORE_FILE: &str = ".bunnyignore";
+
+/// Git's ignore files, read with `--git-ignore`. They can be in any
+/// directory and apply to what is under it.
+const GITIGNORE: &str = ".gitignore";
+
+/// One line of an ignore file.
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    /// The directory of the file the rule came from, like `docs/`, or empty
+    /// for the root. The rule only applies under it.
+    base: String,
+    pattern: String,
+    /// The rule starts with `!`, bringing back what earlier rules left out.
+    negated: bool,
+    /// The rule ends with `/`, so it only matches directories.
+    dir_only: bool,
+    /// The rule has a `/` before its end, so it matches paths from the root
+    /// rather than names at any depth.
+    anchored: bool,
+}
+
+/// Rules in gitignore syntax. Later rules take precedence over earlier ones,
+/// and as in git, nothing under a directory that is left out can be brought
+/// back.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+impl IgnoreRules {
+    /// Parse rules from an ignore file in the directory `base`, like `docs/`,
+    /// or empty for the root.
+    pub fn parse(text: &str, base: &str) -> Self {
+        let rules = text
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim_end();
+                if line.is_empty() || line.starts_with('#') {
+                    return None;
+                }
+                let (negated, line) = match line.strip_prefix('!') {
+                    Some(rest) => (true, rest),
+                    // A leading backslash escapes a literal `!` or `#`.
+                    None => (false, line.strip_prefix('\\').unwrap_or(line)),
+                };
+                let (dir_only, line) = match line.strip_suffix('/') {
+                    Some(rest) => (true, rest),
+                    None => (false, line),
+                };
+                let anchored = line.contains('/');
+                let pattern = line.trim_start_matches('/').to_string();
+                (!pattern.is_empty()).then_some(Rule {
+                    base: base.to_string(),
+                    pattern,
+                    negated,
+                    dir_only,
+                    anchored,
+                })
+            })
+            .collect();
+        IgnoreRules { rules }
+    }
+
+    /// Read the ignore file in a directory. A directory without one has no
+    /// rules.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let mut rules = IgnoreRules::default();
+        rules.read(&dir.join(IGNORE_FILE), "")?;
+        Ok(rules)
+    }
+
+    /// Read git's ignore rules for a directory: `.git/info/exclude` and the
+    /// `.gitignore` files in it and its subdirectories. As in git, rules in
+    /// deeper directories take precedence.
+    pub fn load_git(dir: &Path) -> Result<Self> {
+        let mut rules = IgnoreRules::default();
+        rules.read(&dir.join(".git/info/exclude"), "")?;
+        rules.read_gitignores(dir, "")?;
+        Ok(rules)
+    }
+
+    /// Add rules that take precedence over these.
+    pub fn extend(&mut self, more: IgnoreRules) {
+        self.rules.extend(more.rules);
+    }
+
+    /// Add the rules in a file, if there is one.
+    fn read(&mut self, path: &Path, base: &str) -> Result<()> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => self.extend(Self::parse(&text, base)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(())
+    }
+
+    /// Add the `.gitignore` rules in `root/relative` and the directories
+    /// under it, passing over directories that are already ignored.
+    fn read_gitignores(&mut self, root: &Path, relative: &str) -> Result<()> {
+        let dir = root.join(relative);
+        self.read(&dir.join(GITIGNORE), relative)?;
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut subdirs = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type()?.is_dir() && name != ".git" {
+                subdirs.push(format!("{}{}", relative, name));
+            }
+        }
+        subdirs.sort();
+        for subdir in subdirs {
+            if !self.is_ignored(&subdir, true) {
+                self.read_gitignores(root, &format!("{}/", subdir))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check a path relative to the root, like `css/site.css`, along with
+    /// every directory above it.
+    pub fn is_ignored(&self, relative: &str, is_dir: bool) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+        let mut end = 0;
+        while let Some(slash) = relative[end..].find('/') {
+            end += slash;
+            if self.decide(&relative[..end], true) {
+                return true;
+            }
+            end += 1;
+        }
+        self.decide(relative, is_dir)
+    }
+
+    /// Whether the last rule matching a path leaves it out.
+    fn decide(&self, path: &str, is_dir: bool) -> bool {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| {
+                let Some(path) = path.strip_prefix(&rule.base) else {
+                    return false;
+                };
+                (is_dir || !rule.dir_only)
+                    && glob_match::glob_match(
+                        &rule.pattern,
+                        if rule.anchored { path } else { name },
+                    )
+            })
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+/// Read patterns from a file for `--exclude-from` or `--include-from`, one
+/// per line. Blank lines and lines starting with `#` are passed over.
+pub fn read_patterns(path: &Path) -> Result<Vec<String>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Could not read {}: {}", path.display(), e))?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+/// What a sync leaves out: files whose names match an `--exclude` pattern,
+/// and paths matched by the ignore file, unless their names match an
+/// `--include` pattern.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    exclude: Vec<String>,
+    include: Vec<String>,
+    ignore: IgnoreRules,
+    /// The key of the zone directory being synced, like `/zone/dir/`, which
+    /// remote keys are made relative to.
+    root: String,
+}
+
+impl Filter {
+    pub fn new(exclude: Vec<String>, ignore: IgnoreRules, root: String) -> Self {
+        Filter {
+            exclude,
+            include: Vec::new(),
+            ignore,
+            root,
+        }
+    }
+
+    /// Keep files whose names match these patterns, whatever the other
+    /// rules say.
+    pub fn with_include(mut self, include: Vec<String>) -> Self {
+        self.include = include;
+        self
+    }
+
+    /// Check a path relative to the local directory. A directory is only
+    /// left out when no `--include` pattern could bring back a file in it.
+    pub fn excludes(&self, relative: &str, is_dir: bool) -> bool {
+        if is_dir {
+            return self.include.is_empty() && self.ignore.is_ignored(relative, true);
+        }
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        if is_excluded(name, &self.include) {
+            return false;
+        }
+        is_excluded(name, &self.exclude) || self.ignore.is_ignored(relative, false)
+    }
+
+    /// Check a remote file by its key.
+    pub fn excludes_key(&self, key: &str) -> bool {
+        self.excludes(key.strip_prefix(&self.root).unwrap_or(key), false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignore_rules() {
+        let rules = IgnoreRules::parse(
+            "# Build output\n\
+             *.log\n\
+             !keep.log\n\
+             node_modules/\n\
+             /drafts\n\
+             docs/*.tmp\n\
+             \\#notes\n\
+             \n",
+            "",
+        );
+        assert!(rules.is_ignored("debug.log", false));
+        assert!(rules.is_ignored("logs/debug.log", false));
+        assert!(!rules.is_ignored("logs/keep.log", false));
+        assert!(rules.is_ignored("#notes", false));
+
+        // Directory patterns match directories and what's in them.
+        assert!(rules.is_ignored("node_modules", true));
+        assert!(rules.is_ignored("app/node_modules/lib/index.js", false));
+        assert!(!rules.is_ignored("node_modules", false));
+
+        // Anchored patterns only match from the root.
+        assert!(rules.is_ignored("drafts/post.md", false));
+        assert!(!rules.is_ignored("blog/drafts/post.md", false));
+        assert!(rules.is_ignored("docs/a.tmp", false));
+        assert!(!rules.is_ignored("other/docs/a.tmp", false));
+
+        assert!(!rules.is_ignored("index.html", false));
+    }
+
+    #[test]
+    fn test_ignored_directory_stays_ignored() {
+        let rules = IgnoreRules::parse("build/\n!build/keep.txt\n", "");
+        assert!(rules.is_ignored("build/keep.txt", false));
+    }
+
+    #[test]
+    fn test_load_git() {
+        let dir = std::env::temp_dir().join(format!("bunnysync-gitignore-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join(".git/info")).unwrap();
+        std::fs::create_dir_all(dir.join("app/node_modules/pkg")).unwrap();
+        std::fs::create_dir_all(dir.join("docs")).unwrap();
+        std::fs::write(dir.join(".git/info/exclude"), "*.swp\n").unwrap();
+        std::fs::write(dir.join(".gitignore"), ".env\nnode_modules/\n/build\n").unwrap();
+        std::fs::write(dir.join("docs/.gitignore"), "/build\n!.env\n").unwrap();
+        // Nothing in an ignored directory is read.
+        std::fs::write(dir.join("app/node_modules/pkg/.gitignore"), "*\n").unwrap();
+
+        let rules = IgnoreRules::load_git(&dir).unwrap();
+        assert!(rules.is_ignored("notes.swp", false));
+        assert!(rules.is_ignored(".env", false));
+        assert!(rules.is_ignored("app/.env", false));
+        assert!(rules.is_ignored("app/node_modules/pkg/index.js", false));
+        assert!(rules.is_ignored("build/app.js", false));
+        assert!(!rules.is_ignored("app/build/app.js", false));
+        // A nested .gitignore applies to its own directory only.
+        assert!(rules.is_ignored("docs/build/index.html", false));
+        assert!(!rules.is_ignored("docs/.env", false));
+        assert!(!rules.is_ignored("app/index.js", false));
+
+        assert!(
+            IgnoreRules::load_git(&dir.join("missing"))
+                .unwrap()
+                .rules
+                .is_empty()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = Filter::new(
+            vec!["*.tmp".to_string()],
+            IgnoreRules::parse("drafts/\n", ""),
+            "/zone/site/".to_string(),
+        );
+        assert!(filter.excludes("notes.tmp", false));
+        assert!(filter.excludes("drafts/post.md", false));
+        assert!(filter.excludes_key("/zone/site/a/notes.tmp"));
+        assert!(filter.excludes_key("/zone/site/drafts/post.md"));
+        assert!(!filter.excludes_key("/zone/site/posts/drafts.md"));
+        assert!(filter.excludes("drafts", true));
+
+        let filter = filter.with_include(vec!["keep.*".to_string()]);
+        assert!(!filter.excludes("keep.tmp", false));
+        assert!(!filter.excludes("drafts/keep.md", false));
+        assert!(filter.excludes("drafts/post.md", false));
+        assert!(!filter.excludes("drafts", true));
+    }
+
+    #[test]
+    fn test_read_patterns() {
+        let path = std::env::temp_dir().join(format!("bunnysync-patterns-{}", std::process::id()));
+        std::fs::write(&path, "# Build leftovers\n*.map\n\n  *.log  \n").unwrap();
+        assert_eq!(read_patterns(&path).unwrap(), vec!["*.map", "*.log"]);
+        std::fs::remove_file(&path).unwrap();
+        assert!(read_patterns(&path).is_err());
+    }
+}
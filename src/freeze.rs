@@ -0,0 +1,105 @@
+//! Freezing a zone. `bunnysync freeze` leaves a marker at the zone root, and
+//! every run that would change the zone looks for it first and refuses to
+//! go on until `bunnysync unfreeze` takes it away again.
+
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use ureq::Agent;
+
+use crate::{
+    i18n::{Message, tr},
+    paths::PathMap,
+    storage,
+};
+
+/// The marker's name, at the root of the frozen zone.
+pub const FREEZE_FILE: &str = ".bunnysync-freeze.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Freeze {
+    pub since: DateTime<Utc>,
+    /// Who froze the zone, from the user name of the machine it was done on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl Freeze {
+    /// A freeze starting now.
+    pub fn new(reason: Option<String>) -> Self {
+        Freeze {
+            since: Utc::now(),
+            by: std::env::var("USER")
+                .or_else(|_| std::env::var("USERNAME"))
+                .ok(),
+            reason,
+        }
+    }
+
+    /// The freeze on a zone, if it is frozen. A marker that can't be read
+    /// still freezes the zone, just without the details.
+    pub fn fetch(agent: &Agent, base_url: &str, zone: &str) -> Result<Option<Self>> {
+        let Some(data) = storage::find_object(agent, base_url, &path(zone))? else {
+            return Ok(None);
+        };
+        Ok(Some(serde_json::from_slice(&data).unwrap_or(Freeze {
+            since: DateTime::UNIX_EPOCH,
+            by: None,
+            reason: None,
+        })))
+    }
+
+    pub fn save(&self, agent: &Agent, base_url: &str, zone: &str) -> Result<()> {
+        storage::put_object(agent, base_url, &path(zone), &serde_json::to_vec(self)?)
+    }
+
+    /// Take the marker away. Returns false if the zone wasn't frozen.
+    pub fn lift(agent: &Agent, base_url: &str, zone: &str) -> Result<bool> {
+        match storage::delete_object(agent, base_url, &path(zone)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.is::<storage::NotFound>() => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Where the marker for a zone is stored.
+fn path(zone: &str) -> String {
+    format!("{}/{}", zone, FREEZE_FILE)
+}
+
+/// Fail if the zone of a remote path, like `zone://my-zone/site/`, is frozen.
+/// Called before anything in the zone is changed.
+pub fn check(agent: &Agent, base_url: &str, remote: &str) -> Result<()> {
+    let paths = PathMap::new(remote);
+    match Freeze::fetch(agent, base_url, paths.zone())? {
+        Some(freeze) => Err(anyhow!(tr(Message::Frozen {
+            zone: paths.zone(),
+            freeze: &freeze,
+        }))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_freeze_round_trip() {
+        let freeze = Freeze {
+            since: DateTime::from_timestamp(100, 0).unwrap(),
+            by: Some("alice".to_string()),
+            reason: Some("Incident 42".to_string()),
+        };
+        let json = serde_json::to_string(&freeze).unwrap();
+        assert_eq!(serde_json::from_str::<Freeze>(&json).unwrap(), freeze);
+
+        let bare: Freeze = serde_json::from_str(r#"{"since":"1970-01-01T00:01:40Z"}"#).unwrap();
+        assert_eq!(bare.since, freeze.since);
+        assert_eq!(bare.reason, None);
+        assert_eq!(path("my-zone"), "my-zone/.bunnysync-freeze.json");
+    }
+}
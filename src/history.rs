@@ -0,0 +1,200 @@
+//! A record of each sync run from a directory, kept for `bunnysync history`
+//! so that unattended syncs can be checked on afterwards. Runs are appended
+//! to [`HISTORY_FILE`] in the state directory of the directory bunnysync
+//! runs from, one JSON object per line.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{io::Write, path::Path};
+
+use crate::{output::Summary, storage};
+
+/// The file runs are appended to.
+pub const HISTORY_FILE: &str = "history.jsonl";
+
+/// Flags whose values are secrets and are left out of the history.
+const SECRET_FLAGS: [&str; 5] = [
+    "-a",
+    "--api-key",
+    "--secondary-api-key",
+    "--read-only-api-key",
+    "--account-key",
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub started: DateTime<Utc>,
+    pub duration_ms: u64,
+    /// The command line, with secrets replaced.
+    pub args: Vec<String>,
+    pub source: String,
+    pub destination: String,
+    pub dry_run: bool,
+    pub outcome: Outcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub summary: Summary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Succeeded,
+    Failed,
+}
+
+impl RunRecord {
+    /// Start recording a run that is about to begin.
+    pub fn start(source: &str, destination: &str, dry_run: bool) -> Self {
+        RunRecord {
+            started: Utc::now(),
+            duration_ms: 0,
+            args: redact(std::env::args()),
+            source: source.to_string(),
+            destination: destination.to_string(),
+            dry_run,
+            outcome: Outcome::Succeeded,
+            error: None,
+            summary: Summary::default(),
+        }
+    }
+
+    /// Fill in how the run went.
+    pub fn finish<T>(&mut self, result: &Result<T>, summary: &Summary) {
+        self.duration_ms = (Utc::now() - self.started).num_milliseconds().max(0) as u64;
+        self.summary = summary.clone();
+        if let Err(e) = result {
+            self.outcome = Outcome::Failed;
+            self.error = Some(e.to_string());
+        }
+    }
+
+    /// The storage zone the run synced with.
+    pub fn zone(&self) -> String {
+        [&self.source, &self.destination]
+            .into_iter()
+            .find(|path| path.starts_with("zone://"))
+            .map(|path| storage::zone_name(storage::strip_zone_prefix(path)))
+            .unwrap_or_default()
+    }
+}
+
+/// Add a run to the history in a state directory.
+pub fn append(dir: &Path, record: &RunRecord) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(HISTORY_FILE))?;
+    // One write for the whole line, so that lines from runs at the same time
+    // don't interleave.
+    file.write_all((serde_json::to_string(record)? + "\n").as_bytes())?;
+    Ok(())
+}
+
+/// Read the history in a state directory, oldest run first. Lines that can't be
+/// read, such as one cut short by a crash, are passed over.
+pub fn load(dir: &Path) -> Result<Vec<RunRecord>> {
+    let text = match std::fs::read_to_string(dir.join(HISTORY_FILE)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(text
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Replace the values of secret flags in a command line.
+fn redact(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut redacted = Vec::new();
+    let mut secret_next = false;
+    for arg in args {
+        if secret_next {
+            redacted.push("***".to_string());
+            secret_next = false;
+        } else if SECRET_FLAGS.contains(&arg.as_str()) {
+            redacted.push(arg);
+            secret_next = true;
+        } else if let Some(flag) = SECRET_FLAGS
+            .iter()
+            .find(|flag| flag.starts_with("--") && arg.starts_with(&format!("{}=", flag)))
+        {
+            redacted.push(format!("{}=***", flag));
+        } else if arg.starts_with("-a") && !arg.starts_with("--") {
+            redacted.push("-a***".to_string());
+        } else {
+            redacted.push(arg);
+        }
+    }
+    redacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_redact() {
+        let args = [
+            "bunnysync",
+            "-a",
+            "key1",
+            "--api-key=key2",
+            "-akey3",
+            "--account-key",
+            "key4",
+            "--secondary-api-key=key5",
+            "--dryrun",
+            "site",
+        ]
+        .map(String::from);
+        assert_eq!(
+            redact(args),
+            vec![
+                "bunnysync",
+                "-a",
+                "***",
+                "--api-key=***",
+                "-a***",
+                "--account-key",
+                "***",
+                "--secondary-api-key=***",
+                "--dryrun",
+                "site",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_append_and_load() {
+        let dir = std::env::temp_dir().join(format!("bunnysync-history-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(load(&dir).unwrap().is_empty());
+
+        let mut ok = RunRecord::start("site", "zone://my-zone/", false);
+        ok.finish(&Ok(()), &Summary::default());
+        let mut failed = RunRecord::start("zone://other/", "backup", false);
+        failed.finish::<()>(&Err(anyhow!("Remote unauthorized")), &Summary::default());
+        append(&dir, &ok).unwrap();
+        append(&dir, &failed).unwrap();
+        // A line cut short is passed over.
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.join(HISTORY_FILE))
+            .unwrap();
+        write!(file, "{{\"started\":").unwrap();
+
+        let runs = load(&dir).unwrap();
+        assert_eq!(runs, vec![ok, failed]);
+        assert_eq!(runs[0].zone(), "my-zone");
+        assert_eq!(runs[1].zone(), "other");
+        assert_eq!(runs[1].outcome, Outcome::Failed);
+        assert_eq!(runs[1].error.as_deref(), Some("Remote unauthorized"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1211 @@
+//! Translations of the messages bunnysync prints. Each message is a variant of
+//! [`Message`] and every language has a catalog function that renders it, so
+//! a message missing from a catalog is a compile error.
+//!
+//! JSON output and command line help are not translated.
+
+use std::{fmt::Display, path::Path, sync::OnceLock};
+
+use crate::{
+    commands::{cdn::CdnChange, stats::ZoneStats},
+    freeze::Freeze,
+    output::Summary,
+    site_checks::Problem,
+    storage::{Access, Key},
+    units::format_bytes,
+};
+
+/// A language with a message catalog.
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum Lang {
+    #[default]
+    En,
+    De,
+}
+
+/// A message shown to the user.
+pub enum Message<'a> {
+    Updated {
+        from: &'a str,
+        to: &'a str,
+    },
+    WouldUpdate {
+        from: &'a str,
+        to: &'a str,
+    },
+    Linked {
+        from: &'a str,
+        to: &'a str,
+    },
+    WouldLink {
+        from: &'a str,
+        to: &'a str,
+    },
+    Deleted {
+        path: &'a str,
+    },
+    WouldDelete {
+        path: &'a str,
+    },
+    SourceMissing,
+    DestinationMissing,
+    DestinationNeeded,
+    ZoneNeeded,
+    KeyAccess {
+        key: Key,
+        zone: &'a str,
+        access: Access,
+    },
+    UnexpectedAccess {
+        key: Key,
+        expected: Access,
+    },
+    Previewing {
+        zone: &'a str,
+        url: &'a str,
+    },
+    InvalidSourceAndDestination,
+    WatchNeedsZone,
+    PushOnly {
+        flag: &'a str,
+    },
+    InitialSyncComplete,
+    SyncComplete,
+    Watching {
+        path: &'a str,
+    },
+    QuotaWarning {
+        usage: u64,
+        threshold: u64,
+    },
+    MissingApiKey,
+    MissingAccountKey,
+    EnterApiKey {
+        profile: &'a str,
+    },
+    StoredApiKey {
+        profile: &'a str,
+    },
+    NoKeychain {
+        error: &'a dyn Display,
+    },
+    KeychainUnsupported,
+    PurgeNeedsPullZone,
+    Purged {
+        count: usize,
+        pull_zone: &'a str,
+    },
+    PurgedAll {
+        pull_zone: &'a str,
+    },
+    SecondaryKey,
+    NeedsWriteKey,
+    FoundRegion {
+        zone: &'a str,
+        region: &'a str,
+    },
+    FastestRegion {
+        region: &'a str,
+        millis: u128,
+    },
+    DeadlineReached,
+    Interrupted,
+    Resuming {
+        count: usize,
+    },
+    SimulatingFailures {
+        percent: f64,
+    },
+    TooManyDeletes {
+        deletes: usize,
+        existing: usize,
+        limit: &'a dyn Display,
+    },
+    Warning {
+        warning: &'a dyn Display,
+    },
+    Retrying {
+        error: &'a dyn Display,
+    },
+    Confirm {
+        action: &'a str,
+    },
+    Conflict {
+        path: &'a str,
+    },
+    Vanished {
+        path: &'a str,
+    },
+    Failed {
+        path: &'a str,
+        error: &'a str,
+    },
+    FailureReport {
+        count: usize,
+    },
+    FilesFailed {
+        count: u64,
+    },
+    ChangesFound,
+    SpecialFile {
+        path: &'a str,
+    },
+    ClockSkew {
+        seconds: i64,
+    },
+    ChooseSide {
+        path: &'a str,
+    },
+    PlanSaved {
+        count: usize,
+        file: &'a str,
+    },
+    SiteChecksFailed,
+    SpotCheckPassed {
+        checked: usize,
+        uploaded: usize,
+    },
+    SpotCheckFailed {
+        mismatched: usize,
+        checked: usize,
+    },
+    SiteProblem {
+        problem: &'a Problem,
+    },
+    Exported {
+        count: usize,
+        file: &'a str,
+    },
+    Imported {
+        count: usize,
+        file: &'a str,
+    },
+    CdnChange {
+        change: &'a CdnChange,
+        dry_run: bool,
+    },
+    Frozen {
+        zone: &'a str,
+        freeze: &'a Freeze,
+    },
+    FrozenZone {
+        zone: &'a str,
+    },
+    Unfrozen {
+        zone: &'a str,
+    },
+    NotFrozen {
+        zone: &'a str,
+    },
+    NoSuchJob {
+        name: &'a str,
+    },
+    NoJobs,
+    ProfileNeedsZone {
+        name: &'a str,
+    },
+    NotPromotable {
+        from: &'a str,
+        to: &'a str,
+    },
+    Promoted {
+        from: &'a str,
+        to: &'a str,
+    },
+    NoSuchProfile {
+        name: &'a str,
+    },
+    NoSuchPlugin {
+        name: &'a str,
+    },
+    NewerState {
+        version: u64,
+    },
+    NoState {
+        path: &'a Path,
+    },
+    StateCleaned {
+        path: &'a Path,
+    },
+    ZoneStats {
+        stats: &'a ZoneStats,
+    },
+    ZoneDiff {
+        only_first: usize,
+        only_second: usize,
+        changed: usize,
+    },
+    ConfigExists {
+        file: &'a str,
+    },
+    CreatedStorageZone {
+        name: &'a str,
+        region: &'a str,
+    },
+    CreatedPullZone {
+        name: &'a str,
+        hostname: &'a str,
+    },
+    WroteConfig {
+        file: &'a str,
+    },
+    JobSucceeded {
+        name: &'a str,
+        seconds: f64,
+    },
+    JobFailed {
+        name: &'a str,
+    },
+    JobSkipped {
+        name: &'a str,
+    },
+    Error {
+        error: &'a dyn Display,
+    },
+    /// One line totals for a sync.
+    Summary {
+        summary: &'a Summary,
+    },
+    /// The `--stats` breakdown for a sync.
+    Stats {
+        summary: &'a Summary,
+    },
+}
+
+static LANGUAGE: OnceLock<Lang> = OnceLock::new();
+
+/// Choose the language for the rest of the run. Only the first call counts.
+pub fn set_language(lang: Lang) {
+    let _ = LANGUAGE.set(lang);
+}
+
+/// Work out the language from the locale environment variables, in the order
+/// POSIX gives them precedence. Unknown locales fall back to English.
+pub fn detect() -> Lang {
+    detect_from(|name| std::env::var(name).ok())
+}
+
+fn detect_from(var: impl Fn(&str) -> Option<String>) -> Lang {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| var(name))
+        .find(|value| !value.is_empty())
+        .and_then(|value| from_locale(&value))
+        .unwrap_or_default()
+}
+
+/// The language of a locale name such as `de_DE.UTF-8`.
+fn from_locale(locale: &str) -> Option<Lang> {
+    let language = locale.split(['_', '-', '.', '@']).next()?;
+    match language.to_ascii_lowercase().as_str() {
+        "en" => Some(Lang::En),
+        "de" => Some(Lang::De),
+        _ => None,
+    }
+}
+
+/// Render a message in the chosen language.
+pub fn tr(message: Message) -> String {
+    match LANGUAGE.get().copied().unwrap_or_default() {
+        Lang::En => english(&message),
+        Lang::De => german(&message),
+    }
+}
+
+fn english(message: &Message) -> String {
+    match message {
+        Message::Updated { from, to } => format!("Updated: {} -> {}", from, to),
+        Message::WouldUpdate { from, to } => format!("Would update: {} -> {}", from, to),
+        Message::Linked { from, to } => format!("Linked: {} -> {}", from, to),
+        Message::WouldLink { from, to } => format!("Would link: {} -> {}", from, to),
+        Message::Deleted { path } => format!("Deleted: {}", path),
+        Message::WouldDelete { path } => format!("Would delete: {}", path),
+        Message::SourceMissing => "Source path does not exist".to_string(),
+        Message::DestinationMissing => "Destination path does not exist".to_string(),
+        Message::DestinationNeeded => {
+            "Please give a destination, or a zone in .bunnysync".to_string()
+        }
+        Message::ZoneNeeded => "Please give a zone, or set zone in .bunnysync".to_string(),
+        Message::KeyAccess { key, zone, access } => {
+            let key = english_key(key);
+            match access {
+                Access::None => format!("{} can't access zone {}", key, zone),
+                Access::ReadOnly => format!(
+                    "{} can list and download from zone {} but not change it",
+                    key, zone
+                ),
+                Access::ReadWrite => format!("{} can read and change zone {}", key, zone),
+            }
+        }
+        Message::UnexpectedAccess { key, expected } => {
+            let key = english_key(key);
+            match expected {
+                Access::None => format!("{} was expected to have no access to the zone", key),
+                Access::ReadOnly => {
+                    format!("{} was expected to be read-only, unable to change the zone", key)
+                }
+                Access::ReadWrite => format!("{} was expected to be able to change the zone", key),
+            }
+        }
+        Message::Previewing { zone, url } => {
+            format!("Serving {} at {}. Press Ctrl-C to stop", zone, url)
+        }
+        Message::InvalidSourceAndDestination => "Invalid source and destination".to_string(),
+        Message::WatchNeedsZone => {
+            "Watch mode is only supported when syncing to a zone".to_string()
+        }
+        Message::PushOnly { flag } => {
+            format!("{} is only supported when syncing to a zone", flag)
+        }
+        Message::InitialSyncComplete => "Initial sync complete".to_string(),
+        Message::SyncComplete => "Sync complete".to_string(),
+        Message::Watching { path } => format!("Watching {} for changes", path),
+        Message::QuotaWarning { usage, threshold } => format!(
+            "Sync would bring zone usage to {}, above the quota warning threshold of {}",
+            format_bytes(*usage),
+            format_bytes(*threshold)
+        ),
+        Message::MissingApiKey => "Please provide an API key".to_string(),
+        Message::MissingAccountKey => "Please provide an account API key".to_string(),
+        Message::EnterApiKey { profile } => {
+            format!("Storage zone password for profile {}: ", profile)
+        }
+        Message::StoredApiKey { profile } => {
+            format!("Stored the API key for profile {} in the keychain", profile)
+        }
+        Message::NoKeychain { error } => format!(
+            "No keychain to store the API key in ({}). On Linux this needs secret-tool from libsecret",
+            error
+        ),
+        Message::KeychainUnsupported => {
+            "Storing the API key in a keychain isn't supported on Windows. Give it with --api-key-file, --api-key-stdin or BUNNYSYNC_API_KEY instead"
+                .to_string()
+        }
+        Message::PurgeNeedsPullZone => {
+            "Please name the pull zone to purge with --pull-zone or pull_zone in .bunnysync"
+                .to_string()
+        }
+        Message::Purged { count, pull_zone } => {
+            format!("Purged {} changed files from the cache of {}", count, pull_zone)
+        }
+        Message::PurgedAll { pull_zone } => format!("Purged the cache of {}", pull_zone),
+        Message::SecondaryKey => {
+            "Warning: the API key was turned down, using the secondary key. Replace the primary key to finish rotating keys".to_string()
+        }
+        Message::FoundRegion { zone, region } => {
+            format!("Found storage zone {} in region {}", zone, region)
+        }
+        Message::FastestRegion { region, millis } => {
+            format!("Downloading from region {}, which answered in {} ms", region, millis)
+        }
+        Message::DeadlineReached => {
+            "The deadline was reached before the sync finished. Run it again with --resume to sync the rest".to_string()
+        }
+        Message::Interrupted => {
+            "Interrupted. Transfers under way were finished, run the sync again with --resume to do the rest".to_string()
+        }
+        Message::Resuming { count } => {
+            format!("Resuming, skipping {} actions done by the last run", count)
+        }
+        Message::SimulatingFailures { percent } => {
+            format!("Simulating failures for {}% of requests", percent)
+        }
+        Message::NeedsWriteKey => {
+            "Only the read-only API key is set, which can't change the zone. Give the zone's password with --api-key or api_key".to_string()
+        }
+        Message::TooManyDeletes {
+            deletes,
+            existing,
+            limit,
+        } => format!(
+            "Refusing to delete {} of {} files, more than --max-delete {} allows. Use --force to delete anyway",
+            deletes, existing, limit
+        ),
+        Message::Warning { warning } => format!("Warning: {}", warning),
+        Message::Retrying { error } => format!("{}, trying again", error),
+        Message::Confirm { action } => format!("{}. Continue? [y/n/a/q] ", action),
+        Message::Conflict { path } => format!(
+            "{} changed on both sides since the last sync and was left alone",
+            path
+        ),
+        Message::Vanished { path } => {
+            format!("{} went away before it could be uploaded and was skipped", path)
+        }
+        Message::Failed { path, error } => format!("{} failed: {}", path, error),
+        Message::FailureReport { count } => format!("{} files failed:", count),
+        Message::FilesFailed { count } => format!(
+            "{} files could not be synced. Run the sync again to retry them",
+            count
+        ),
+        Message::ChangesFound => "There are changes to make".to_string(),
+        Message::SpecialFile { path } => {
+            format!("{} is not a regular file or directory and was skipped", path)
+        }
+        Message::ClockSkew { seconds } => format!(
+            "The zone has files changed {}s later than this computer's clock says it is. Modification times are compared across both, so check the clock, or --server-timezone if the server isn't bunny.net",
+            seconds
+        ),
+        Message::ChooseSide { path } => format!(
+            "{} changed on both sides since the last sync. Keep the local or remote copy, or skip? [l/r/s] ",
+            path
+        ),
+        Message::PlanSaved { count, file } => {
+            format!("Saved {} planned actions to {}", count, file)
+        }
+        Message::SiteChecksFailed => "Site checks failed, nothing was deployed:".to_string(),
+        Message::SpotCheckPassed { checked, uploaded } => format!(
+            "Spot check passed, {} of {} uploaded files match",
+            checked, uploaded
+        ),
+        Message::SpotCheckFailed {
+            mismatched,
+            checked,
+        } => format!(
+            "Spot check failed, {} of {} sampled files differ from what was uploaded:",
+            mismatched, checked
+        ),
+        Message::SiteProblem { problem } => match problem {
+            Problem::MissingIndex => "index.html is missing".to_string(),
+            Problem::Missing404 => {
+                "404.html is missing, add one at the top level or in bunnycdn_errors/".to_string()
+            }
+            Problem::LocalhostUrl { file, line, url } => {
+                format!("{}:{} links to a local address: {}", file, line, url)
+            }
+        },
+        Message::Exported { count, file } => format!("Exported {} patterns to {}", count, file),
+        Message::Frozen { zone, freeze } => {
+            let mut text = format!(
+                "zone://{} has been frozen since {}",
+                zone,
+                freeze.since.format("%Y-%m-%d %H:%M UTC")
+            );
+            if let Some(by) = &freeze.by {
+                text.push_str(&format!(" by {}", by));
+            }
+            if let Some(reason) = &freeze.reason {
+                text.push_str(&format!(": {}", reason));
+            }
+            format!(
+                "{}. Nothing was changed. Run `bunnysync unfreeze zone://{}` once changes are allowed again",
+                text, zone
+            )
+        }
+        Message::FrozenZone { zone } => format!(
+            "Froze zone://{}. Syncs that would change it will refuse to run until it is unfrozen",
+            zone
+        ),
+        Message::Unfrozen { zone } => format!("Unfroze zone://{}", zone),
+        Message::NotFrozen { zone } => format!("zone://{} was not frozen", zone),
+        Message::NoSuchJob { name } => format!("No job named {} in .bunnysync", name),
+        Message::NoJobs => "No jobs in .bunnysync".to_string(),
+        Message::ProfileNeedsZone { name } => {
+            format!("Please set the zone of profile {} in .bunnysync", name)
+        }
+        Message::NotPromotable { from, to } => format!(
+            "{} may not be promoted to {}. See [promote.{}] in .bunnysync",
+            from, to, to
+        ),
+        Message::Promoted { from, to } => format!("Promoted {} to {}", from, to),
+        Message::NoSuchProfile { name } => format!("No profile named {} in .bunnysync", name),
+        Message::NoSuchPlugin { name } => format!("No plugin bunnysync-{} on the PATH", name),
+        Message::NewerState { version } => format!(
+            "Written by a newer bunnysync, at version {}. Upgrade or run `bunnysync state clean`",
+            version
+        ),
+        Message::NoState { path } => format!("Nothing is kept for {}", path.display()),
+        Message::StateCleaned { path } => format!("Removed what was kept for {}", path.display()),
+        Message::ZoneStats { stats } => {
+            let mut text = format!(
+                "Stored:    {} in {} files",
+                format_bytes(stats.storage_used),
+                stats.files_stored
+            );
+            if stats.pull_zones.is_empty() {
+                text.push_str("\nBandwidth: no pull zone serves this zone");
+            } else {
+                text.push_str(&format!(
+                    "\nBandwidth: {} and {} requests in the last {} days, through {}",
+                    format_bytes(stats.bandwidth_used),
+                    stats.requests_served,
+                    stats.days,
+                    stats.pull_zones.join(", ")
+                ));
+            }
+            text
+        }
+        Message::ZoneDiff {
+            only_first,
+            only_second,
+            changed,
+        } => format!(
+            "{} only in the first zone, {} only in the second, {} differ",
+            only_first, only_second, changed
+        ),
+        Message::ConfigExists { file } => {
+            format!("{} already exists. Move it away to set up a new zone here", file)
+        }
+        Message::CreatedStorageZone { name, region } => {
+            format!("Created storage zone {} in {}", name, region)
+        }
+        Message::CreatedPullZone { name, hostname } => {
+            format!("Created pull zone {}, served at https://{}", name, hostname)
+        }
+        Message::WroteConfig { file } => format!("Wrote the zone password to {}", file),
+        Message::JobSucceeded { name, seconds } => format!("ok       {:>7.1}s  {}", seconds, name),
+        Message::JobFailed { name } => format!("failed             {}", name),
+        Message::JobSkipped { name } => format!("skipped            {}", name),
+        Message::Imported { count, file } => format!("Imported {} patterns into {}", count, file),
+        Message::CdnChange { change, dry_run } => {
+            let (done, would) = match change {
+                CdnChange::UpdateSettings { zone, settings } => {
+                    let keys: Vec<_> = settings.keys().map(String::as_str).collect();
+                    let what = format!("settings of {}: {}", zone, keys.join(", "));
+                    (
+                        format!("Updated {}", what),
+                        format!("Would update {}", what),
+                    )
+                }
+                CdnChange::AddHostname { zone, hostname } => {
+                    let what = format!("hostname {} to {}", hostname, zone);
+                    (format!("Added {}", what), format!("Would add {}", what))
+                }
+                CdnChange::RemoveHostname { zone, hostname } => {
+                    let what = format!("hostname {} from {}", hostname, zone);
+                    (
+                        format!("Removed {}", what),
+                        format!("Would remove {}", what),
+                    )
+                }
+                CdnChange::AddEdgeRule { zone, rule } => {
+                    let what = format!(
+                        "edge rule \"{}\" to {}",
+                        CdnChange::rule_description(rule),
+                        zone
+                    );
+                    (format!("Added {}", what), format!("Would add {}", what))
+                }
+                CdnChange::UpdateEdgeRule { zone, rule } => {
+                    let what = format!(
+                        "edge rule \"{}\" of {}",
+                        CdnChange::rule_description(rule),
+                        zone
+                    );
+                    (
+                        format!("Updated {}", what),
+                        format!("Would update {}", what),
+                    )
+                }
+                CdnChange::RemoveEdgeRule {
+                    zone, description, ..
+                } => {
+                    let what = format!("edge rule \"{}\" from {}", description, zone);
+                    (
+                        format!("Removed {}", what),
+                        format!("Would remove {}", what),
+                    )
+                }
+            };
+            if *dry_run { would } else { done }
+        }
+        Message::Error { error } => format!("Error: {}", error),
+        Message::Summary { summary } if summary.dry_run => format!(
+            "Dry run: would upload {}, download {}, delete {} and skip {} of {} files, {} to transfer",
+            summary.uploaded,
+            summary.downloaded,
+            summary.deleted,
+            summary.skipped,
+            summary.scanned,
+            format_bytes(summary.bytes_transferred)
+        ),
+        Message::Summary { summary } => {
+            let line = format!(
+                "Uploaded {}, downloaded {}, deleted {} and skipped {} of {} files, {} in {} ({}/s)",
+                summary.uploaded,
+                summary.downloaded,
+                summary.deleted,
+                summary.skipped,
+                summary.scanned,
+                format_bytes(summary.bytes_transferred),
+                seconds(summary.elapsed_ms),
+                format_bytes(summary.throughput())
+            );
+            let line = match summary.vanished {
+                0 => line,
+                vanished => format!(
+                    "{}. {} files went away before they could be uploaded",
+                    line, vanished
+                ),
+            };
+            let line = match summary.warnings {
+                0 => line,
+                warnings => format!("{}. {} warnings", line, warnings),
+            };
+            match summary.failed {
+                0 => line,
+                failed => format!("{}. {} failed", line, failed),
+            }
+        }
+        Message::Stats { summary } => stats_table(
+            summary,
+            [
+                "Files scanned",
+                "Uploaded",
+                "Downloaded",
+                "Deleted",
+                "Skipped",
+                "Transferred",
+                "Elapsed",
+                "Throughput",
+                "Vanished",
+                "Warnings",
+                "Failed",
+                "Dry run, nothing was changed",
+            ],
+        ),
+    }
+}
+
+fn german(message: &Message) -> String {
+    match message {
+        Message::Updated { from, to } => format!("Aktualisiert: {} -> {}", from, to),
+        Message::WouldUpdate { from, to } => format!("Würde aktualisieren: {} -> {}", from, to),
+        Message::Linked { from, to } => format!("Verknüpft: {} -> {}", from, to),
+        Message::WouldLink { from, to } => format!("Würde verknüpfen: {} -> {}", from, to),
+        Message::Deleted { path } => format!("Gelöscht: {}", path),
+        Message::WouldDelete { path } => format!("Würde löschen: {}", path),
+        Message::SourceMissing => "Der Quellpfad existiert nicht".to_string(),
+        Message::DestinationMissing => "Der Zielpfad existiert nicht".to_string(),
+        Message::DestinationNeeded => {
+            "Bitte ein Ziel angeben oder eine Zone in .bunnysync festlegen".to_string()
+        }
+        Message::ZoneNeeded => {
+            "Bitte eine Zone angeben oder in .bunnysync festlegen".to_string()
+        }
+        Message::KeyAccess { key, zone, access } => {
+            let key = german_key(key);
+            match access {
+                Access::None => format!("{} hat keinen Zugriff auf Zone {}", key, zone),
+                Access::ReadOnly => format!(
+                    "{} kann Zone {} auflisten und herunterladen, aber nicht ändern",
+                    key, zone
+                ),
+                Access::ReadWrite => format!("{} kann Zone {} lesen und ändern", key, zone),
+            }
+        }
+        Message::UnexpectedAccess { key, expected } => {
+            let key = german_key(key);
+            match expected {
+                Access::None => format!("{} sollte keinen Zugriff auf die Zone haben", key),
+                Access::ReadOnly => {
+                    format!("{} sollte nur lesen und die Zone nicht ändern können", key)
+                }
+                Access::ReadWrite => format!("{} sollte die Zone ändern können", key),
+            }
+        }
+        Message::Previewing { zone, url } => {
+            format!("{} wird unter {} bereitgestellt. Mit Strg-C beenden", zone, url)
+        }
+        Message::InvalidSourceAndDestination => "Ungültige Quelle und ungültiges Ziel".to_string(),
+        Message::WatchNeedsZone => {
+            "Der Überwachungsmodus wird nur beim Synchronisieren in eine Zone unterstützt"
+                .to_string()
+        }
+        Message::PushOnly { flag } => {
+            format!("{} wird nur beim Synchronisieren in eine Zone unterstützt", flag)
+        }
+        Message::InitialSyncComplete => "Erste Synchronisierung abgeschlossen".to_string(),
+        Message::SyncComplete => "Synchronisierung abgeschlossen".to_string(),
+        Message::Watching { path } => format!("Überwache {} auf Änderungen", path),
+        Message::QuotaWarning { usage, threshold } => format!(
+            "Die Synchronisierung würde die Zonennutzung auf {} bringen, über der Warnschwelle von {}",
+            format_bytes(*usage),
+            format_bytes(*threshold)
+        ),
+        Message::MissingApiKey => "Bitte einen API-Schlüssel angeben".to_string(),
+        Message::MissingAccountKey => "Bitte einen API-Schlüssel für das Konto angeben".to_string(),
+        Message::EnterApiKey { profile } => {
+            format!("Passwort der Storage-Zone für Profil {}: ", profile)
+        }
+        Message::StoredApiKey { profile } => {
+            format!("API-Schlüssel für Profil {} im Schlüsselbund gespeichert", profile)
+        }
+        Message::NoKeychain { error } => format!(
+            "Kein Schlüsselbund für den API-Schlüssel gefunden ({}). Unter Linux wird secret-tool aus libsecret benötigt",
+            error
+        ),
+        Message::KeychainUnsupported => {
+            "Das Speichern des API-Schlüssels im Schlüsselbund wird unter Windows nicht unterstützt. Bitte stattdessen --api-key-file, --api-key-stdin oder BUNNYSYNC_API_KEY verwenden"
+                .to_string()
+        }
+        Message::PurgeNeedsPullZone => {
+            "Bitte die zu leerende Pull-Zone mit --pull-zone oder pull_zone in .bunnysync angeben"
+                .to_string()
+        }
+        Message::Purged { count, pull_zone } => format!(
+            "{} geänderte Dateien aus dem Cache von {} entfernt",
+            count, pull_zone
+        ),
+        Message::PurgedAll { pull_zone } => format!("Cache von {} geleert", pull_zone),
+        Message::SecondaryKey => {
+            "Warnung: Der API-Schlüssel wurde abgelehnt, der zweite Schlüssel wird verwendet. Den ersten Schlüssel ersetzen, um den Schlüsselwechsel abzuschließen".to_string()
+        }
+        Message::FoundRegion { zone, region } => {
+            format!("Speicherzone {} in Region {} gefunden", zone, region)
+        }
+        Message::FastestRegion { region, millis } => {
+            format!("Download aus Region {}, die in {} ms geantwortet hat", region, millis)
+        }
+        Message::DeadlineReached => {
+            "Die Frist ist abgelaufen, bevor die Synchronisierung fertig war. Mit --resume erneut ausführen, um den Rest zu synchronisieren".to_string()
+        }
+        Message::Interrupted => {
+            "Abgebrochen. Laufende Übertragungen wurden beendet, die Synchronisierung mit --resume erneut ausführen, um den Rest zu erledigen".to_string()
+        }
+        Message::Resuming { count } => {
+            format!("Setze fort, {} bereits erledigte Aktionen werden übersprungen", count)
+        }
+        Message::SimulatingFailures { percent } => {
+            format!("Simuliere Fehler bei {}% der Anfragen", percent)
+        }
+        Message::NeedsWriteKey => {
+            "Nur der schreibgeschützte API-Schlüssel ist gesetzt, der die Zone nicht ändern kann. Das Passwort der Zone mit --api-key oder api_key angeben".to_string()
+        }
+        Message::TooManyDeletes {
+            deletes,
+            existing,
+            limit,
+        } => format!(
+            "Löschen von {} von {} Dateien abgelehnt, mehr als --max-delete {} erlaubt. Mit --force trotzdem löschen",
+            deletes, existing, limit
+        ),
+        Message::Warning { warning } => format!("Warnung: {}", warning),
+        Message::Retrying { error } => format!("{}, neuer Versuch", error),
+        Message::Confirm { action } => format!("{}. Fortfahren? [j/n/a/q] ", action),
+        Message::Conflict { path } => format!(
+            "{} wurde seit der letzten Synchronisierung auf beiden Seiten geändert und nicht angefasst",
+            path
+        ),
+        Message::Vanished { path } => format!(
+            "{} ist vor dem Hochladen verschwunden und wurde übersprungen",
+            path
+        ),
+        Message::Failed { path, error } => format!("{} ist fehlgeschlagen: {}", path, error),
+        Message::FailureReport { count } => format!("{} Dateien sind fehlgeschlagen:", count),
+        Message::FilesFailed { count } => format!(
+            "{} Dateien konnten nicht synchronisiert werden. Die Synchronisierung erneut ausführen, um sie noch einmal zu versuchen",
+            count
+        ),
+        Message::ChangesFound => "Es gibt Änderungen vorzunehmen".to_string(),
+        Message::SpecialFile { path } => format!(
+            "{} ist weder eine reguläre Datei noch ein Verzeichnis und wurde übersprungen",
+            path
+        ),
+        Message::ClockSkew { seconds } => format!(
+            "Die Zone enthält Dateien, die {}s später geändert wurden, als es laut der Uhr dieses Computers ist. Änderungszeiten werden zwischen beiden verglichen, daher die Uhr prüfen, oder --server-timezone, wenn der Server nicht bunny.net ist",
+            seconds
+        ),
+        Message::ChooseSide { path } => format!(
+            "{} wurde seit der letzten Synchronisierung auf beiden Seiten geändert. Lokale oder entfernte Kopie behalten, oder überspringen? [l/r/ü] ",
+            path
+        ),
+        Message::PlanSaved { count, file } => {
+            format!("{} geplante Aktionen in {} gespeichert", count, file)
+        }
+        Message::SiteChecksFailed => {
+            "Seitenprüfung fehlgeschlagen, nichts wurde veröffentlicht:".to_string()
+        }
+        Message::SpotCheckPassed { checked, uploaded } => format!(
+            "Stichprobe bestanden, {} von {} hochgeladenen Dateien stimmen überein",
+            checked, uploaded
+        ),
+        Message::SpotCheckFailed {
+            mismatched,
+            checked,
+        } => format!(
+            "Stichprobe fehlgeschlagen, {} von {} geprüften Dateien weichen vom Hochgeladenen ab:",
+            mismatched, checked
+        ),
+        Message::SiteProblem { problem } => match problem {
+            Problem::MissingIndex => "index.html fehlt".to_string(),
+            Problem::Missing404 => {
+                "404.html fehlt, auf oberster Ebene oder in bunnycdn_errors/ anlegen".to_string()
+            }
+            Problem::LocalhostUrl { file, line, url } => {
+                format!(
+                    "{}:{} verweist auf eine lokale Adresse: {}",
+                    file, line, url
+                )
+            }
+        },
+        Message::Exported { count, file } => format!("{} Muster nach {} exportiert", count, file),
+        Message::Frozen { zone, freeze } => {
+            let mut text = format!(
+                "zone://{} ist seit {} eingefroren",
+                zone,
+                freeze.since.format("%d.%m.%Y %H:%M UTC")
+            );
+            if let Some(by) = &freeze.by {
+                text.push_str(&format!(" von {}", by));
+            }
+            if let Some(reason) = &freeze.reason {
+                text.push_str(&format!(": {}", reason));
+            }
+            format!(
+                "{}. Nichts wurde geändert. `bunnysync unfreeze zone://{}` ausführen, sobald Änderungen wieder erlaubt sind",
+                text, zone
+            )
+        }
+        Message::FrozenZone { zone } => format!(
+            "zone://{} eingefroren. Synchronisierungen, die sie ändern würden, werden bis zum Auftauen abgelehnt",
+            zone
+        ),
+        Message::Unfrozen { zone } => format!("zone://{} aufgetaut", zone),
+        Message::NotFrozen { zone } => format!("zone://{} war nicht eingefroren", zone),
+        Message::NoSuchJob { name } => format!("Kein Job namens {} in .bunnysync", name),
+        Message::NoJobs => "Keine Jobs in .bunnysync".to_string(),
+        Message::ProfileNeedsZone { name } => {
+            format!("Bitte die Zone des Profils {} in .bunnysync festlegen", name)
+        }
+        Message::NotPromotable { from, to } => format!(
+            "{} darf nicht nach {} übernommen werden. Siehe [promote.{}] in .bunnysync",
+            from, to, to
+        ),
+        Message::Promoted { from, to } => format!("{} nach {} übernommen", from, to),
+        Message::NoSuchProfile { name } => format!("Kein Profil namens {} in .bunnysync", name),
+        Message::NoSuchPlugin { name } => format!("Kein Plugin bunnysync-{} im PATH", name),
+        Message::NewerState { version } => format!(
+            "Von einem neueren bunnysync geschrieben, in Version {}. bunnysync aktualisieren oder `bunnysync state clean` ausführen",
+            version
+        ),
+        Message::NoState { path } => format!("Für {} ist nichts gespeichert", path.display()),
+        Message::StateCleaned { path } => {
+            format!("Für {} Gespeichertes entfernt", path.display())
+        }
+        Message::ZoneStats { stats } => {
+            let mut text = format!(
+                "Gespeichert:  {} in {} Dateien",
+                format_bytes(stats.storage_used),
+                stats.files_stored
+            );
+            if stats.pull_zones.is_empty() {
+                text.push_str("\nBandbreite:   keine Pull-Zone liefert diese Zone aus");
+            } else {
+                text.push_str(&format!(
+                    "\nBandbreite:   {} und {} Anfragen in den letzten {} Tagen, über {}",
+                    format_bytes(stats.bandwidth_used),
+                    stats.requests_served,
+                    stats.days,
+                    stats.pull_zones.join(", ")
+                ));
+            }
+            text
+        }
+        Message::ZoneDiff {
+            only_first,
+            only_second,
+            changed,
+        } => format!(
+            "{} nur in der ersten Zone, {} nur in der zweiten, {} unterschiedlich",
+            only_first, only_second, changed
+        ),
+        Message::ConfigExists { file } => format!(
+            "{} existiert bereits. Bitte verschieben, um hier eine neue Zone einzurichten",
+            file
+        ),
+        Message::CreatedStorageZone { name, region } => {
+            format!("Storage-Zone {} in {} angelegt", name, region)
+        }
+        Message::CreatedPullZone { name, hostname } => {
+            format!("Pull-Zone {} angelegt, erreichbar unter https://{}", name, hostname)
+        }
+        Message::WroteConfig { file } => format!("Zonenpasswort in {} gespeichert", file),
+        Message::JobSucceeded { name, seconds } => {
+            format!("ok            {:>7.1}s  {}", seconds, name)
+        }
+        Message::JobFailed { name } => format!("fehlgeschlagen          {}", name),
+        Message::JobSkipped { name } => format!("übersprungen            {}", name),
+        Message::Imported { count, file } => format!("{} Muster in {} importiert", count, file),
+        Message::CdnChange { change, dry_run } => {
+            let (what, done, would) = match change {
+                CdnChange::UpdateSettings { zone, settings } => {
+                    let keys: Vec<_> = settings.keys().map(String::as_str).collect();
+                    (
+                        format!("Einstellungen von {} ({})", zone, keys.join(", ")),
+                        "aktualisiert",
+                        "aktualisieren",
+                    )
+                }
+                CdnChange::AddHostname { zone, hostname } => (
+                    format!("Hostname {} zu {}", hostname, zone),
+                    "hinzugefügt",
+                    "hinzufügen",
+                ),
+                CdnChange::RemoveHostname { zone, hostname } => (
+                    format!("Hostname {} aus {}", hostname, zone),
+                    "entfernt",
+                    "entfernen",
+                ),
+                CdnChange::AddEdgeRule { zone, rule } => (
+                    format!(
+                        "Edge-Regel \"{}\" zu {}",
+                        CdnChange::rule_description(rule),
+                        zone
+                    ),
+                    "hinzugefügt",
+                    "hinzufügen",
+                ),
+                CdnChange::UpdateEdgeRule { zone, rule } => (
+                    format!(
+                        "Edge-Regel \"{}\" von {}",
+                        CdnChange::rule_description(rule),
+                        zone
+                    ),
+                    "aktualisiert",
+                    "aktualisieren",
+                ),
+                CdnChange::RemoveEdgeRule {
+                    zone, description, ..
+                } => (
+                    format!("Edge-Regel \"{}\" aus {}", description, zone),
+                    "entfernt",
+                    "entfernen",
+                ),
+            };
+            if *dry_run {
+                format!("Würde {} {}", what, would)
+            } else {
+                format!("{} {}", what, done)
+            }
+        }
+        Message::Error { error } => format!("Fehler: {}", error),
+        Message::Summary { summary } if summary.dry_run => format!(
+            "Probelauf: {} würden hochgeladen, {} heruntergeladen, {} gelöscht und {} übersprungen von {} Dateien, {} zu übertragen",
+            summary.uploaded,
+            summary.downloaded,
+            summary.deleted,
+            summary.skipped,
+            summary.scanned,
+            format_bytes(summary.bytes_transferred)
+        ),
+        Message::Summary { summary } => {
+            let line = format!(
+                "{} hochgeladen, {} heruntergeladen, {} gelöscht und {} übersprungen von {} Dateien, {} in {} ({}/s)",
+                summary.uploaded,
+                summary.downloaded,
+                summary.deleted,
+                summary.skipped,
+                summary.scanned,
+                format_bytes(summary.bytes_transferred),
+                seconds(summary.elapsed_ms),
+                format_bytes(summary.throughput())
+            );
+            let line = match summary.vanished {
+                0 => line,
+                vanished => format!(
+                    "{}. {} Dateien sind vor dem Hochladen verschwunden",
+                    line, vanished
+                ),
+            };
+            let line = match summary.warnings {
+                0 => line,
+                warnings => format!("{}. {} Warnungen", line, warnings),
+            };
+            match summary.failed {
+                0 => line,
+                failed => format!("{}. {} fehlgeschlagen", line, failed),
+            }
+        }
+        Message::Stats { summary } => stats_table(
+            summary,
+            [
+                "Geprüfte Dateien",
+                "Hochgeladen",
+                "Heruntergeladen",
+                "Gelöscht",
+                "Übersprungen",
+                "Übertragen",
+                "Dauer",
+                "Durchsatz",
+                "Verschwunden",
+                "Warnungen",
+                "Fehlgeschlagen",
+                "Probelauf, nichts wurde geändert",
+            ],
+        ),
+    }
+}
+
+/// The `--stats` breakdown, one labelled line per counter. Vanished files,
+/// warnings and failures only get a line when there are any. The last label
+/// is a note added on dry runs.
+fn stats_table(summary: &Summary, labels: [&str; 12]) -> String {
+    let counted = |count: u64| (count > 0).then(|| count.to_string());
+    let rows = [
+        Some(summary.scanned.to_string()),
+        Some(format!(
+            "{} ({})",
+            summary.uploaded,
+            format_bytes(summary.bytes_uploaded)
+        )),
+        Some(format!(
+            "{} ({})",
+            summary.downloaded,
+            format_bytes(summary.bytes_downloaded)
+        )),
+        Some(format!(
+            "{} ({})",
+            summary.deleted,
+            format_bytes(summary.bytes_deleted)
+        )),
+        Some(summary.skipped.to_string()),
+        Some(format_bytes(summary.bytes_transferred)),
+        Some(seconds(summary.elapsed_ms)),
+        Some(format!("{}/s", format_bytes(summary.throughput()))),
+        counted(summary.vanished),
+        counted(summary.warnings),
+        counted(summary.failed),
+    ];
+    let rows: Vec<_> = labels
+        .iter()
+        .zip(rows)
+        .filter_map(|(label, value)| Some((label, value?)))
+        .collect();
+    let width = rows
+        .iter()
+        .map(|(label, _)| label.chars().count())
+        .max()
+        .unwrap_or(0)
+        + 1;
+    let mut lines: Vec<_> = rows
+        .iter()
+        .map(|(label, value)| {
+            let label = format!("{}:", label);
+            format!("{:<width$} {}", label, value, width = width)
+        })
+        .collect();
+    if summary.dry_run {
+        lines.push(labels[11].to_string());
+    }
+    lines.join("\n")
+}
+
+/// A key as the subject of a sentence about what it may do.
+fn english_key(key: &Key) -> &'static str {
+    match key {
+        Key::Primary => "The API key",
+        Key::Secondary => "The secondary API key",
+        Key::ReadOnly => "The read-only password",
+    }
+}
+
+fn german_key(key: &Key) -> &'static str {
+    match key {
+        Key::Primary => "Der API-Schlüssel",
+        Key::Secondary => "Der zweite API-Schlüssel",
+        Key::ReadOnly => "Das Nur-Lese-Passwort",
+    }
+}
+
+fn seconds(millis: u64) -> String {
+    format!("{:.1}s", millis as f64 / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_locale() {
+        assert_eq!(from_locale("de_DE.UTF-8"), Some(Lang::De));
+        assert_eq!(from_locale("de"), Some(Lang::De));
+        assert_eq!(from_locale("en-GB"), Some(Lang::En));
+        assert_eq!(from_locale("C"), None);
+        assert_eq!(from_locale("fr_FR.UTF-8"), None);
+    }
+
+    #[test]
+    fn test_detect_precedence() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        assert_eq!(detect_from(env(&[])), Lang::En);
+        assert_eq!(detect_from(env(&[("LANG", "de_DE.UTF-8")])), Lang::De);
+        assert_eq!(
+            detect_from(env(&[("LC_ALL", "en_US.UTF-8"), ("LANG", "de_DE.UTF-8")])),
+            Lang::En
+        );
+        assert_eq!(
+            detect_from(env(&[("LC_ALL", ""), ("LC_MESSAGES", "de_AT")])),
+            Lang::De
+        );
+    }
+
+    #[test]
+    fn test_catalogs() {
+        let message = Message::Updated {
+            from: "site/a.html",
+            to: "/zone/a.html",
+        };
+        assert_eq!(english(&message), "Updated: site/a.html -> /zone/a.html");
+        assert_eq!(
+            german(&message),
+            "Aktualisiert: site/a.html -> /zone/a.html"
+        );
+        let error = Message::Error {
+            error: &"timed out",
+        };
+        assert_eq!(english(&error), "Error: timed out");
+        assert_eq!(german(&error), "Fehler: timed out");
+    }
+
+    #[test]
+    fn test_summary_messages() {
+        let summary = Summary {
+            scanned: 14,
+            uploaded: 3,
+            deleted: 1,
+            skipped: 10,
+            bytes_uploaded: 3072,
+            bytes_transferred: 3072,
+            elapsed_ms: 2000,
+            ..Default::default()
+        };
+        assert_eq!(
+            english(&Message::Summary { summary: &summary }),
+            "Uploaded 3, downloaded 0, deleted 1 and skipped 10 of 14 files, 3.0 KiB in 2.0s (1.5 KiB/s)"
+        );
+        assert_eq!(
+            english(&Message::Stats { summary: &summary }),
+            "Files scanned: 14\n\
+             Uploaded:      3 (3.0 KiB)\n\
+             Downloaded:    0 (0 B)\n\
+             Deleted:       1 (0 B)\n\
+             Skipped:       10\n\
+             Transferred:   3.0 KiB\n\
+             Elapsed:       2.0s\n\
+             Throughput:    1.5 KiB/s"
+        );
+
+        let vanished = Summary {
+            skipped: 2,
+            vanished: 2,
+            warnings: 3,
+            failed: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            english(&Message::Summary { summary: &vanished }),
+            "Uploaded 0, downloaded 0, deleted 0 and skipped 2 of 0 files, 0 B in 0.0s (0 B/s). \
+             2 files went away before they could be uploaded. 3 warnings. 1 failed"
+        );
+        assert!(
+            german(&Message::Stats { summary: &vanished })
+                .ends_with("Verschwunden:     2\nWarnungen:        3\nFehlgeschlagen:   1")
+        );
+    }
+}
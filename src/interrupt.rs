@@ -0,0 +1,35 @@
+//! Stopping a sync cleanly on Ctrl-C. The first interrupt only sets a flag,
+//! so the transfer in progress finishes and no more are started. A second
+//! one ends the process at once, as it would without a handler.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether Ctrl-C has been pressed since [`install`].
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+    // Only storing to an atomic and resetting the handler are safe here.
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+    }
+}
+
+/// Catch Ctrl-C and termination for the rest of the run.
+#[cfg(unix)]
+pub fn install() {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+/// Elsewhere Ctrl-C ends the process as before.
+#[cfg(not(unix))]
+pub fn install() {}
@@ -0,0 +1,208 @@
+//! A record of what a sync has done so far, for `--resume`. Each action is
+//! appended to a journal in the state directory as soon as it is carried
+//! out, and the journal is removed once the sync finishes. A sync that was
+//! cut short leaves it behind, and resuming skips what it lists. Nothing is
+//! written until the first action is done.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::{
+    checksum::{Checksum, Xxh64},
+    plan::SyncAction,
+};
+
+/// Where journals are kept in a local directory's directory.
+const JOURNALS_DIR: &str = "journals";
+
+/// The first line of a journal, saying which sync it is for.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Header {
+    source: String,
+    destination: String,
+}
+
+/// The journal of a sync from a source to a destination.
+#[derive(Debug)]
+pub struct Journal {
+    header: Header,
+    path: PathBuf,
+    /// The actions an earlier run got done, as JSON.
+    done: HashSet<String>,
+    /// The journal file, once something has been written to it.
+    file: Mutex<Option<File>>,
+}
+
+impl Journal {
+    /// Start the journal of a sync in a state directory. Resuming keeps what
+    /// the last run recorded; otherwise it is forgotten.
+    pub fn start(dir: &Path, source: &str, destination: &str, resume: bool) -> Result<Self> {
+        let header = Header {
+            source: source.to_string(),
+            destination: destination.to_string(),
+        };
+        let path = journal_path(dir, &header);
+        let done = if resume {
+            load(&path, &header)?
+        } else {
+            remove(&path)?;
+            HashSet::new()
+        };
+        Ok(Journal {
+            header,
+            path,
+            done,
+            file: Mutex::new(None),
+        })
+    }
+
+    /// How many actions an earlier run got done.
+    pub fn resumed(&self) -> usize {
+        self.done.len()
+    }
+
+    /// Whether an earlier run already carried out an action.
+    pub fn is_done(&self, action: &SyncAction) -> bool {
+        !self.done.is_empty()
+            && serde_json::to_string(action).is_ok_and(|action| self.done.contains(&action))
+    }
+
+    /// Note that an action has been carried out.
+    pub fn record(&self, action: &SyncAction) -> Result<()> {
+        let mut file = self.file.lock().unwrap();
+        let file = match &mut *file {
+            Some(file) => file,
+            None => file.insert(self.create()?),
+        };
+        let line = serde_json::to_string(action)? + "\n";
+        // One write for the whole line, so a crash cuts off at most the last.
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Write the header and what was done before, to add to from then on.
+    fn create(&self) -> Result<File> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = File::create(&self.path)?;
+        let mut text = serde_json::to_string(&self.header)? + "\n";
+        for action in &self.done {
+            text.push_str(action);
+            text.push('\n');
+        }
+        file.write_all(text.as_bytes())?;
+        Ok(file)
+    }
+
+    /// Forget the journal once the sync has finished.
+    pub fn remove(self) -> Result<()> {
+        drop(self.file);
+        remove(&self.path)
+    }
+}
+
+fn remove(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn journal_path(dir: &Path, header: &Header) -> PathBuf {
+    let name = Xxh64
+        .hex(format!("{}\n{}", header.source, header.destination).as_bytes())
+        .to_ascii_lowercase();
+    dir.join(JOURNALS_DIR).join(name + ".jsonl")
+}
+
+/// The actions recorded in a journal. Lines that can't be read, such as one
+/// cut short by a crash, are passed over, and so is a journal for another
+/// sync.
+fn load(path: &Path, header: &Header) -> Result<HashSet<String>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut lines = text.lines();
+    let recorded = lines
+        .next()
+        .and_then(|line| serde_json::from_str(line).ok());
+    if recorded.as_ref() != Some(header) {
+        return Ok(HashSet::new());
+    }
+    Ok(lines
+        .filter_map(|line| serde_json::from_str::<SyncAction>(line).ok())
+        .filter_map(|action| serde_json::to_string(&action).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::SkipReason;
+
+    #[test]
+    fn test_journal() {
+        let dir = std::env::temp_dir().join(format!("bunnysync-journal-{}", std::process::id()));
+        let upload = |key: &str| SyncAction::Upload {
+            key: key.to_string(),
+            path: PathBuf::from(key),
+            length: 3,
+        };
+        let journal = Journal::start(&dir, "site", "zone://zone", false).unwrap();
+        assert!(!journal.is_done(&upload("a.txt")));
+        journal.record(&upload("a.txt")).unwrap();
+        drop(journal);
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(journal_path(
+                &dir,
+                &Header {
+                    source: "site".to_string(),
+                    destination: "zone://zone".to_string(),
+                },
+            ))
+            .unwrap()
+            .write_all(b"{\"action\":\"upl")
+            .unwrap();
+
+        let journal = Journal::start(&dir, "site", "zone://zone", true).unwrap();
+        assert_eq!(journal.resumed(), 1);
+        assert!(journal.is_done(&upload("a.txt")));
+        assert!(!journal.is_done(&upload("b.txt")));
+        assert!(!journal.is_done(&SyncAction::Skip {
+            key: "a.txt".to_string(),
+            reason: SkipReason::Unchanged,
+        }));
+        journal.record(&upload("b.txt")).unwrap();
+        drop(journal);
+
+        // Another sync has a journal of its own.
+        let other = Journal::start(&dir, "site", "zone://other", true).unwrap();
+        assert_eq!(other.resumed(), 0);
+        other.remove().unwrap();
+
+        let journal = Journal::start(&dir, "site", "zone://zone", true).unwrap();
+        assert_eq!(journal.resumed(), 2);
+        journal.remove().unwrap();
+        let journal = Journal::start(&dir, "site", "zone://zone", true).unwrap();
+        assert_eq!(journal.resumed(), 0);
+        journal.record(&upload("c.txt")).unwrap();
+        drop(journal);
+        let journal = Journal::start(&dir, "site", "zone://zone", false).unwrap();
+        assert_eq!(journal.resumed(), 0);
+        drop(journal);
+        let journal = Journal::start(&dir, "site", "zone://zone", true).unwrap();
+        assert_eq!(journal.resumed(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+//! API keys kept in the OS keychain rather than in dotfiles, through the
+//! `security` tool on macOS and `secret-tool` from libsecret elsewhere.
+//! There is no such tool on Windows, so keys can't be stored there and none
+//! are ever loaded.
+
+use anyhow::{Result, anyhow};
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use crate::i18n::{Message, tr};
+
+const SERVICE: &str = "bunnysync";
+
+/// The profile keys are stored under when no `--profile` is given.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Store the API key for a profile, replacing any stored before.
+pub fn store(profile: &str, key: &str) -> Result<()> {
+    if cfg!(windows) {
+        return Err(anyhow!(tr(Message::KeychainUnsupported)));
+    }
+    // The key goes in on stdin rather than as an argument others could see.
+    let (mut command, input) = if cfg!(target_os = "macos") {
+        let mut command = Command::new("security");
+        command.arg("-i");
+        let input = format!(
+            "add-generic-password -U -s {} -a {} -w {}\n",
+            quote(SERVICE),
+            quote(profile),
+            quote(key)
+        );
+        (command, input)
+    } else {
+        let mut command = Command::new("secret-tool");
+        command.args(["store", "--label", &format!("bunnysync {}", profile)]);
+        command.args(["service", SERVICE, "profile", profile]);
+        (command, key.to_string())
+    };
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|e| anyhow!(tr(Message::NoKeychain { error: &e })))?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(input.as_bytes())?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow!(
+            "Could not store the API key in the keychain: {}",
+            status
+        ));
+    }
+    Ok(())
+}
+
+/// The API key stored for a profile, if there is a keychain with one.
+pub fn load(profile: &str) -> Option<String> {
+    if cfg!(windows) {
+        return None;
+    }
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new("security");
+        command.args(["find-generic-password", "-s", SERVICE, "-a", profile, "-w"]);
+        command
+    } else {
+        let mut command = Command::new("secret-tool");
+        command.args(["lookup", "service", SERVICE, "profile", profile]);
+        command
+    };
+    let output = command.stderr(Stdio::null()).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let key = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!key.is_empty()).then_some(key)
+}
+
+/// Quote an argument for `security -i`, which splits its input like a shell.
+fn quote(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("prod"), "\"prod\"");
+        assert_eq!(quote(r#"a "b" \c"#), r#""a \"b\" \\c""#);
+    }
+}
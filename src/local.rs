@@ -1,6 +1,17 @@
 use anyhow::Result;
 use chrono::{DateTime, Utc};
-use std::path::{Path, PathBuf};
+use std::{
+    ffi::OsStr,
+    path::{Component, Path, PathBuf},
+    sync::RwLock,
+};
+
+use crate::{
+    filter::{Filter, IGNORE_FILE},
+    freeze::FREEZE_FILE,
+    manifest::MANIFEST_FILE,
+    paths::slash_path,
+};
 
 pub struct LocalFile {
     pub relative_path: PathBuf,
@@ -10,15 +21,166 @@ pub struct LocalFile {
     pub length: u64,
 }
 
-/// Get all files in a directory and its subdirectories.
-pub fn get_files(path: &Path) -> Result<Vec<LocalFile>> {
-    let mut files = Vec::new();
-    for entry in walkdir::WalkDir::new(path) {
+/// The per-project config file. It likely contains secrets so it is never
+/// synced.
+pub const CONFIG_FILE: &str = ".bunnysync";
+
+/// The config file for every directory, with defaults that a `.bunnysync`
+/// can override. It is `bunnysync/config.toml` in `$XDG_CONFIG_HOME`, which
+/// defaults to `~/.config`, or in `%APPDATA%` on Windows.
+pub fn global_config_file() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| Path::new(dir).is_absolute())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    }?;
+    Some(dir.join("bunnysync").join("config.toml"))
+}
+
+/// Where bidirectional sync kept its snapshot of the last sync before the
+/// state directory. It is never synced.
+pub const LEGACY_STATE_FILE: &str = ".bunnysync-state";
+
+/// Where hashes and zone listings were cached before the state directory,
+/// and where state is kept without one. It is never synced.
+pub const CACHE_DIR: &str = ".bunnysync-cache";
+
+/// Where each sync was recorded before the state directory. It is never
+/// synced.
+pub const LEGACY_HISTORY_FILE: &str = ".bunnysync-history.jsonl";
+
+/// The end of the name of a file being downloaded, which is renamed into
+/// place once it is whole. One left behind by a crash is never synced.
+const PART_SUFFIX: &str = ".bunnysync-part";
+
+/// Where a file is written while it is downloaded.
+pub fn part_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}{}", name, PART_SUFFIX))
+}
+
+fn is_part_file(name: &OsStr) -> bool {
+    name.to_str()
+        .is_some_and(|name| name.starts_with('.') && name.ends_with(PART_SUFFIX))
+}
+
+/// Remove the files left behind by downloads that were cut short, as by a
+/// second Ctrl-C or a crash.
+pub fn remove_part_files(dir: &Path) -> Result<()> {
+    let walker = walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(|entry| !entry.file_type().is_dir() || !is_ignored(entry));
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e)
+                if e.io_error().map(std::io::Error::kind) == Some(std::io::ErrorKind::NotFound) =>
+            {
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if entry.file_type().is_file() && is_part_file(entry.file_name()) {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Version control metadata directories, which are never synced.
+const VCS_DIRS: [&str; 3] = [".git", ".hg", ".svn"];
+
+/// Check if a directory entry is skipped regardless of exclude patterns.
+fn is_ignored(entry: &walkdir::DirEntry) -> bool {
+    is_ignored_name(entry.file_name(), entry.file_type().is_dir())
+}
+
+/// Check if a relative path to a file is skipped regardless of exclude
+/// patterns, because of its name or a directory it is in.
+pub fn is_ignored_path(relative_path: &Path) -> bool {
+    let parts: Vec<_> = relative_path.components().collect();
+    parts.iter().enumerate().any(|(i, part)| {
+        let is_dir = i + 1 < parts.len();
+        matches!(part, Component::Normal(name) if is_ignored_name(name, is_dir))
+    })
+}
+
+fn is_ignored_name(name: &OsStr, is_dir: bool) -> bool {
+    if is_dir {
+        name == CACHE_DIR || VCS_DIRS.iter().any(|dir| name == *dir)
+    } else {
+        name == CONFIG_FILE
+            || name == IGNORE_FILE
+            || name == LEGACY_STATE_FILE
+            || name == LEGACY_HISTORY_FILE
+            || name == MANIFEST_FILE
+            || name == FREEZE_FILE
+            || is_part_file(name)
+    }
+}
+
+/// Files holding secrets, such as the config file bunnysync was started
+/// with, that must never be uploaded wherever they sit.
+static PROTECTED: RwLock<Vec<PathBuf>> = RwLock::new(Vec::new());
+
+/// Never upload the file at `path`. Does nothing if it doesn't exist.
+pub fn protect(path: &Path) {
+    if let Ok(path) = path.canonicalize() {
+        PROTECTED.write().unwrap().push(path);
+    }
+}
+
+/// Check if a file must never be uploaded. Any file named like the config
+/// file is, as well as the files passed to [`protect`].
+pub fn is_protected(path: &Path) -> bool {
+    if path.file_name().is_some_and(|name| name == CONFIG_FILE) {
+        return true;
+    }
+    let protected = PROTECTED.read().unwrap();
+    if protected.is_empty() {
+        return false;
+    }
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    protected.contains(&path)
+}
+
+/// The files found in a local directory.
+#[derive(Default)]
+pub struct Scan {
+    pub files: Vec<LocalFile>,
+    /// Things that are neither files nor directories, like sockets and
+    /// FIFOs, which can't be synced. Paths are relative, as for files.
+    pub special: Vec<PathBuf>,
+}
+
+/// Get all files in a directory and its subdirectories, leaving out those
+/// the filter excludes. Paths are relative to the canonicalized directory,
+/// so `.` and `./dist/../dist` map the same way as an absolute path.
+pub fn get_files(path: &Path, filter: &Filter) -> Result<Scan> {
+    let mut scan = Scan::default();
+    let path = path.canonicalize()?;
+    let walker = walkdir::WalkDir::new(&path)
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0
+                || !is_ignored(entry)
+                    && !entry.path().strip_prefix(&path).is_ok_and(|relative| {
+                        filter.excludes(&slash_path(relative), entry.file_type().is_dir())
+                    })
+        });
+    for entry in walker {
         let entry = entry?;
         let file_path = entry.path();
-        let relative_path = file_path.strip_prefix(path)?;
+        let relative_path = file_path.strip_prefix(&path)?;
         let metadata = entry.metadata()?;
         let file_type = metadata.file_type();
+        if !file_type.is_file() && !file_type.is_dir() && !file_type.is_symlink() {
+            scan.special.push(relative_path.to_path_buf());
+            continue;
+        }
         let last_changed = metadata.modified()?;
         let file = LocalFile {
             path: file_path.to_path_buf(),
@@ -27,9 +189,9 @@ pub fn get_files(path: &Path) -> Result<Vec<LocalFile>> {
             last_changed: last_changed.into(),
             length: metadata.len(),
         };
-        files.push(file);
+        scan.files.push(file);
     }
-    Ok(files)
+    Ok(scan)
 }
 
 /// Get a local file path for the supplied remote path. For example, if
@@ -46,8 +208,23 @@ pub fn get_path(local_base: &str, zone_name: &str, remote_path: &str) -> PathBuf
         .strip_prefix(&zone_prefix)
         .unwrap_or(remote_path.as_path());
 
-    // Append the remote path to the local base.
-    local_base.push(&remote_path);
+    // Append the remote path to the local base. A key can come from
+    // anywhere, so parts that would lead out of the base, like a `..` too
+    // many or a root, are left out.
+    let mut depth = 0;
+    for component in remote_path.components() {
+        match component {
+            Component::Normal(part) => {
+                local_base.push(part);
+                depth += 1;
+            }
+            Component::ParentDir if depth > 0 => {
+                local_base.push("..");
+                depth -= 1;
+            }
+            _ => {}
+        }
+    }
 
     local_base.canonicalize().unwrap_or(local_base)
 }
@@ -55,8 +232,131 @@ pub fn get_path(local_base: &str, zone_name: &str, remote_path: &str) -> PathBuf
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::filter::IgnoreRules;
     use std::path::PathBuf;
 
+    /// Create a fresh directory under the system temp dir for a test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bunnysync-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn relative_files(path: &Path) -> Vec<PathBuf> {
+        let mut files: Vec<_> = get_files(path, &Filter::default())
+            .unwrap()
+            .files
+            .into_iter()
+            .filter(|file| !file.is_directory)
+            .map(|file| file.relative_path)
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_get_files_skips_config_and_vcs() {
+        let dir = test_dir("skips");
+        std::fs::create_dir_all(dir.join(".git/objects")).unwrap();
+        std::fs::create_dir_all(dir.join("sub/.svn")).unwrap();
+        std::fs::write(dir.join(".git/config"), "").unwrap();
+        std::fs::write(dir.join(".git/objects/ab"), "").unwrap();
+        std::fs::write(dir.join("sub/.svn/entries"), "").unwrap();
+        std::fs::write(dir.join(".bunnysync"), "api_key = \"secret\"").unwrap();
+        std::fs::create_dir_all(dir.join(CACHE_DIR)).unwrap();
+        std::fs::write(dir.join(CACHE_DIR).join("hashes.json"), "{}").unwrap();
+        std::fs::write(dir.join("index.html"), "").unwrap();
+        std::fs::write(dir.join("sub/page.html"), "").unwrap();
+        // A file merely named like a VCS directory is still synced.
+        std::fs::write(dir.join(".git-keep"), "").unwrap();
+
+        assert_eq!(
+            relative_files(&dir),
+            vec![
+                PathBuf::from(".git-keep"),
+                PathBuf::from("index.html"),
+                PathBuf::from("sub/page.html"),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_get_files_relative_spellings_agree() {
+        let dir = test_dir("spellings");
+        std::fs::create_dir_all(dir.join("dist/css")).unwrap();
+        std::fs::write(dir.join("dist/index.html"), "").unwrap();
+        std::fs::write(dir.join("dist/css/site.css"), "").unwrap();
+
+        let expect = vec![PathBuf::from("css/site.css"), PathBuf::from("index.html")];
+        assert_eq!(relative_files(&dir.join("dist")), expect);
+        assert_eq!(relative_files(&dir.join("dist/")), expect);
+        assert_eq!(relative_files(&dir.join("./dist/../dist/.")), expect);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_get_files_skips_ignored() {
+        let dir = test_dir("ignored");
+        std::fs::create_dir_all(dir.join("node_modules/lib")).unwrap();
+        std::fs::create_dir_all(dir.join("logs")).unwrap();
+        std::fs::write(dir.join("node_modules/lib/index.js"), "").unwrap();
+        std::fs::write(dir.join("logs/debug.log"), "").unwrap();
+        std::fs::write(dir.join("logs/keep.log"), "").unwrap();
+        std::fs::write(dir.join("index.html"), "").unwrap();
+        std::fs::write(dir.join(IGNORE_FILE), "").unwrap();
+
+        let ignore = IgnoreRules::parse("node_modules/\n*.log\n!keep.log\n", "");
+        let filter = Filter::new(Vec::new(), ignore, String::new());
+        let mut files: Vec<_> = get_files(&dir, &filter)
+            .unwrap()
+            .files
+            .into_iter()
+            .filter(|file| !file.is_directory)
+            .map(|file| file.relative_path)
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![PathBuf::from("index.html"), PathBuf::from("logs/keep.log")]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_remove_part_files() {
+        let dir = test_dir("parts");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("index.html"), "").unwrap();
+        std::fs::write(part_path(&dir.join("index.html")), "").unwrap();
+        std::fs::write(part_path(&dir.join("sub/page.html")), "").unwrap();
+        // Only bunnysync's own names count.
+        std::fs::write(dir.join("notes.bunnysync-part"), "").unwrap();
+
+        let expect = vec![
+            PathBuf::from("index.html"),
+            PathBuf::from("notes.bunnysync-part"),
+        ];
+        assert_eq!(relative_files(&dir), expect);
+        remove_part_files(&dir).unwrap();
+        assert!(!part_path(&dir.join("index.html")).exists());
+        assert!(!part_path(&dir.join("sub/page.html")).exists());
+        assert!(dir.join("notes.bunnysync-part").exists());
+        remove_part_files(&dir.join("missing")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_is_ignored_path() {
+        assert!(is_ignored_path(Path::new(".bunnysync")));
+        assert!(is_ignored_path(Path::new("sub/.bunnysync-state")));
+        assert!(is_ignored_path(Path::new(".git/config")));
+        assert!(is_ignored_path(Path::new("a/.svn/entries")));
+        assert!(!is_ignored_path(Path::new(".git")));
+        assert!(!is_ignored_path(Path::new("docs/index.html")));
+    }
+
     #[test]
     fn test_basic_path_combination() {
         // Test basic path combination
@@ -1,321 +1,944 @@
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{Result, anyhow};
+use chrono::FixedOffset;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 use serde::Deserialize;
-use std::{collections::HashMap, path::Path};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 use ureq::Agent;
 
+use exit::Status;
+use i18n::{Message, tr};
+use timeline::Timeline;
+use transport::{Rate, Schedule};
+
+mod api;
+mod backend;
+mod backoff;
+mod cache;
+mod checksum;
+mod commands;
+mod exit;
+mod faults;
+mod filter;
+mod freeze;
+mod history;
+mod i18n;
+mod interrupt;
+mod journal;
+mod keychain;
 mod local;
+mod manifest;
+mod output;
+mod paths;
+mod plan;
+mod plugin;
+mod priority;
+mod prompt;
+mod s3;
+mod schema;
+mod site_checks;
+mod state;
 mod storage;
+mod store;
+mod sync;
+mod timeline;
+mod timestamp;
+mod transform;
+mod transport;
+mod units;
+mod watch;
+mod webdav;
 
 /// A file synchronization tool for bunny.net storage zones that synchronizes
 /// a local directory with a remote storage zone.
+///
+/// Running without a subcommand, as in `bunnysync <SOURCE> <DESTINATION>`, is
+/// shorthand for `bunnysync sync <SOURCE> <DESTINATION>`.
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    /// Your bunny.net API key. Use of the env variable strongly recommended
-    #[arg(short, long, env = "BUNNYSYNC_API_KEY")]
+#[command(version, about, long_about = None, arg_required_else_help = true)]
+struct Cli {
+    #[command(flatten)]
+    global: GlobalArgs,
+
+    /// Print the JSON Schema for one of the JSON outputs and exit
+    #[arg(long, value_enum)]
+    schema: Option<schema::SchemaKind>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Options that apply to every subcommand.
+#[derive(clap::Args, Debug, Clone)]
+struct GlobalArgs {
+    /// Your bunny.net API key. Use of the env variable or `bunnysync login`
+    /// strongly recommended
+    #[arg(short, long, env = "BUNNYSYNC_API_KEY", global = true)]
     api_key: Option<String>,
 
-    /// Your bunny.net storage zone
+    /// Read the API key from a file, such as a mounted secret
+    #[arg(
+        long,
+        value_name = "FILE",
+        env = "BUNNYSYNC_API_KEY_FILE",
+        global = true
+    )]
+    api_key_file: Option<PathBuf>,
+
+    /// Read the API key from the first line of stdin
+    #[arg(long, global = true, conflicts_with = "api_key_file")]
+    api_key_stdin: bool,
+
+    /// A second API key to fall back on when the first is turned down, as
+    /// while rotating keys
+    #[arg(long, env = "BUNNYSYNC_SECONDARY_API_KEY", global = true)]
+    secondary_api_key: Option<String>,
+
+    /// The zone's read-only password, used instead of the API key for
+    /// listing and downloading
+    #[arg(long, env = "BUNNYSYNC_READ_ONLY_API_KEY", global = true)]
+    read_only_api_key: Option<String>,
+
+    /// Your bunny.net storage zone's region. With auto, each zone's region
+    /// is found the first time and remembered
     #[arg(short, long, env = "BUNNYSYNC_REGION",
     value_parser = clap::builder::PossibleValuesParser::new(["uk", "de", "us_ny", 
-    "ny" , "us_la", "la","sg", "se", "br", "sa", "au", "au_syd", "syd"]),
-    default_value = "de")]
+    "ny" , "us_la", "la","sg", "se", "br", "sa", "au", "au_syd", "syd", "auto"]),
+    default_value = "de", global = true)]
     region: String,
 
-    /// The source directory or storage zone. Storage zones have prefix zone://
-    source: String,
+    /// The storage API's URL, used instead of the region's, like
+    /// https://HOST[:PORT]. Plain HTTP is allowed to localhost, as for a mock
+    /// of the API
+    #[arg(long, value_name = "URL", env = "BUNNYSYNC_ENDPOINT", global = true)]
+    endpoint: Option<String>,
+
+    /// The timezone the storage API gives modification times in, as an
+    /// offset like +02:00. bunny.net gives them in UTC, the default
+    #[arg(long, value_name = "OFFSET", env = "BUNNYSYNC_SERVER_TIMEZONE",
+    value_parser = timestamp::parse_offset, global = true)]
+    server_timezone: Option<FixedOffset>,
+
+    /// Abort a transfer when no data moves for this long. Transfers have no
+    /// overall time limit. Use 0 to disable
+    #[arg(long, value_name = "DURATION", default_value = "30s",
+    value_parser = units::parse_duration, global = true)]
+    stall_timeout: Duration,
+
+    /// Give up connecting to a server after this long. Use 0 to wait as long
+    /// as the system does
+    #[arg(long, value_name = "DURATION", default_value = "30s",
+    value_parser = units::parse_duration, global = true)]
+    connect_timeout: Duration,
+
+    /// Give up on any request that takes longer than this, transfer and all.
+    /// There is no limit by default
+    #[arg(long, value_name = "DURATION",
+    value_parser = units::parse_duration, global = true)]
+    request_timeout: Option<Duration>,
+
+    /// Send requests through this proxy, like http://proxy:3128, instead of
+    /// the one in HTTPS_PROXY, HTTP_PROXY or ALL_PROXY. Hosts in NO_PROXY
+    /// are reached directly
+    #[arg(long, value_name = "URL", env = "BUNNYSYNC_PROXY", global = true)]
+    proxy: Option<String>,
+
+    /// Limit how fast data is sent and received, over all transfers
+    /// together, in bytes a second like 5M. Limits by local time of day are
+    /// given like 08:00-18:00=2M,18:00-08:00=0, where 0 is no limit
+    #[arg(long, value_name = "SIZE", env = "BUNNYSYNC_BWLIMIT",
+    value_parser = Schedule::parse, global = true)]
+    bwlimit: Option<Schedule>,
+
+    /// Send no more than this many requests a second, over all transfers
+    /// together, to stay under the API's rate limits
+    #[arg(long, value_name = "N", env = "BUNNYSYNC_MAX_REQUESTS_PER_SECOND",
+    value_parser = clap::value_parser!(u64).range(1..), global = true)]
+    max_requests_per_second: Option<u64>,
+
+    /// Run at a low I/O and CPU priority, so that a big sync doesn't slow
+    /// down other work on the machine
+    #[arg(long, global = true)]
+    nice_io: bool,
 
-    /// The destination directory or storage zone. Storage zones have prefix zone://
-    destination: String,
+    /// Fail this percentage of storage API requests on purpose, like 10%, to
+    /// try out how syncs cope with a flaky network
+    #[arg(long, value_name = "N%", value_parser = sync::parse_percent,
+    hide = true, global = true)]
+    simulate_failures: Option<f64>,
 
-    /// Perform a dry run
-    #[arg(long = "dryrun")]
-    dry_run: bool,
+    /// Write a timeline of every request made to this file, in the Chrome
+    /// trace event format, to open in a trace viewer like Perfetto
+    #[arg(long, value_name = "FILE", global = true)]
+    timeline: Option<PathBuf>,
 
-    /// Delete files that are not in the source directory
-    #[arg(long)]
-    delete: bool,
+    /// The language for messages. Defaults to the language of the locale
+    /// set by LC_ALL, LC_MESSAGES or LANG
+    #[arg(long, value_enum, global = true)]
+    lang: Option<i18n::Lang>,
 
-    /// Exclude files that match a pattern. You can use * as a wildcard
-    #[arg(long = "exclude", value_parser, num_args = 1.., value_delimiter = ',')]
-    exclude: Vec<String>,
+    /// Use the settings of a profile in .bunnysync, as in
+    /// [profiles.<NAME>], over those at the top of the file
+    #[arg(long, env = "BUNNYSYNC_PROFILE", global = true)]
+    profile: Option<String>,
+
+    /// Read settings from this file instead of .bunnysync in the current
+    /// directory
+    #[arg(long, value_name = "FILE", env = "BUNNYSYNC_CONFIG", global = true)]
+    config: Option<PathBuf>,
+
+    /// Read no config files, only the command line and environment
+    #[arg(long, global = true, conflicts_with = "config")]
+    no_config: bool,
+
+    /// The options left at their default values, which the config files may
+    /// set.
+    #[arg(skip)]
+    defaults: Vec<&'static str>,
+
+    /// The throughput shared by every agent, for `--bwlimit`.
+    #[arg(skip)]
+    bandwidth: Option<Arc<Rate>>,
+
+    /// The requests a second shared by every agent, for
+    /// `--max-requests-per-second`.
+    #[arg(skip)]
+    requests: Option<Arc<Rate>>,
+
+    /// Where requests are noted for `--timeline`.
+    #[arg(skip)]
+    trace: Option<Arc<Timeline>>,
+}
+
+impl GlobalArgs {
+    /// Whether an option was given neither on the command line nor in the
+    /// environment.
+    fn is_default(&self, id: &str) -> bool {
+        self.defaults.contains(&id)
+    }
+
+    /// The profile to store API keys under in the keychain.
+    fn keychain_profile(&self) -> &str {
+        self.profile.as_deref().unwrap_or(keychain::DEFAULT_PROFILE)
+    }
+
+    /// The project config file, `.bunnysync` unless `--config` names
+    /// another.
+    fn config_file(&self) -> &Path {
+        self.config
+            .as_deref()
+            .unwrap_or(Path::new(local::CONFIG_FILE))
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Sync a local directory with a storage zone
+    Sync(Box<sync::SyncArgs>),
+    /// Carry out a plan saved by `sync --dryrun --plan`
+    Apply(commands::apply::ApplyArgs),
+    /// Copy the zone of one profile onto another's, as set out in .bunnysync
+    Promote(commands::promote::PromoteArgs),
+    /// List the objects in a storage zone
+    Ls(commands::ls::LsArgs),
+    /// Print a remote object to stdout
+    Cat(commands::cat::CatArgs),
+    /// Show all metadata for a remote object
+    Stat(commands::stat::StatArgs),
+    /// Show how much space each directory in a zone uses
+    Du(commands::du::DuArgs),
+    /// Compare two zones without transferring any files
+    Diff(commands::diff::DiffArgs),
+    /// Check what the API key may do in a zone
+    Doctor(commands::doctor::DoctorArgs),
+    /// Serve a zone over local HTTP to preview what is deployed
+    Preview(commands::preview::PreviewArgs),
+    /// Stop bunnysync from changing a storage zone
+    Freeze(commands::freeze::FreezeArgs),
+    /// Let bunnysync change a frozen storage zone again
+    Unfreeze(commands::freeze::UnfreezeArgs),
+    /// Copy the contents of a storage zone into an S3 compatible bucket
+    Export(commands::export::ExportArgs),
+    /// Copy the contents of an S3 compatible bucket into a storage zone
+    Import(commands::import::ImportArgs),
+    /// Export or import filter rules
+    Filters(commands::filters::FiltersArgs),
+    /// List the syncs run from the current directory
+    History(commands::history::HistoryArgs),
+    /// Show or clean out what is kept between runs
+    State(commands::state::StateArgs),
+    /// Manage pull zone configuration
+    Cdn(commands::cdn::CdnArgs),
+    /// List the storage zones on your account
+    Zones(commands::zones::ZonesArgs),
+    /// Show how much a zone stores and serves
+    Stats(commands::stats::StatsArgs),
+    /// Create a storage zone and write a .bunnysync for it
+    Init(commands::init::InitArgs),
+    /// Store a storage zone password in the OS keychain
+    Login(commands::login::LoginArgs),
+    /// Run a sync job from the jobs table in .bunnysync
+    Run(commands::run::RunArgs),
+    /// Print a shell completion script
+    Completions(commands::completions::CompletionsArgs),
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default, Clone)]
 struct Config {
     api_key: Option<String>,
+    secondary_api_key: Option<String>,
+    read_only_api_key: Option<String>,
+    account_key: Option<String>,
     region: Option<String>,
+    endpoint: Option<String>,
+    server_timezone: Option<String>,
+    proxy: Option<String>,
+    bwlimit: Option<Schedule>,
+    max_requests_per_second: Option<u64>,
+    nice_io: Option<bool>,
     exclude: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "units::deserialize_size")]
+    quota_warn: Option<u64>,
+    pull_zone: Option<String>,
+    transform: Option<Vec<transform::TransformRule>>,
+    plugins: Option<plugin::Plugins>,
+    /// The zone to sync with when no destination is given.
+    zone: Option<String>,
+    #[serde(default)]
+    profiles: HashMap<String, Config>,
+    /// How to promote to each profile, by its name.
+    #[serde(default)]
+    promote: HashMap<String, commands::promote::Promotion>,
 }
 
-fn main() {
-    let mut args = Args::parse();
-    read_config_file(&mut args).expect("reading config file");
-    if let Some(api_key) = args.api_key {
-        let agent = storage::agent(&api_key).expect("built agent");
-        let base_url = storage::base_url(&args.region).expect("invalid region");
-
-        if !is_zone(&args.source) && is_zone(&args.destination) {
-            if !Path::new(&args.source).exists() {
-                println!("Source path does not exist");
-                return;
+impl Config {
+    /// The settings with those of a profile or a more specific config file on
+    /// top. Exclude patterns from both apply.
+    fn overlay(mut self, profile: Config) -> Config {
+        let exclude = match (self.exclude, profile.exclude) {
+            (Some(mut exclude), Some(more)) => {
+                exclude.extend(more);
+                Some(exclude)
             }
-            if let Err(e) = sync_to_remote(
-                &agent,
-                &base_url,
-                &args.source,
-                &args.destination,
-                args.dry_run,
-                args.delete,
-                args.exclude,
-            ) {
-                eprintln!("Error: {}", e);
-                std::process::exit(1);
-            }
-        } else if is_zone(&args.source) && !is_zone(&args.destination) {
-            // If the local directory does not exist, throw an error.
-            if !Path::new(&args.destination).exists() {
-                println!("Destination path does not exist");
-                return;
-            }
-            if let Err(e) = sync_to_local(
-                &agent,
-                &base_url,
-                &args.destination,
-                &args.source,
-                args.dry_run,
-                args.delete,
-                args.exclude,
-            ) {
-                eprintln!("Error: {}", e);
-                std::process::exit(1);
-            }
-        } else {
-            println!("Invalid source and destination");
-            std::process::exit(1);
+            (exclude, more) => more.or(exclude),
+        };
+        Config {
+            api_key: profile.api_key.or(self.api_key),
+            secondary_api_key: profile.secondary_api_key.or(self.secondary_api_key),
+            read_only_api_key: profile.read_only_api_key.or(self.read_only_api_key),
+            account_key: profile.account_key.or(self.account_key),
+            region: profile.region.or(self.region),
+            endpoint: profile.endpoint.or(self.endpoint),
+            server_timezone: profile.server_timezone.or(self.server_timezone),
+            proxy: profile.proxy.or(self.proxy),
+            bwlimit: profile.bwlimit.or(self.bwlimit),
+            max_requests_per_second: profile
+                .max_requests_per_second
+                .or(self.max_requests_per_second),
+            nice_io: profile.nice_io.or(self.nice_io),
+            exclude,
+            quota_warn: profile.quota_warn.or(self.quota_warn),
+            pull_zone: profile.pull_zone.or(self.pull_zone),
+            transform: profile.transform.or(self.transform),
+            plugins: profile.plugins.or(self.plugins),
+            zone: profile.zone.or(self.zone),
+            profiles: {
+                self.profiles.extend(profile.profiles);
+                self.profiles
+            },
+            promote: {
+                self.promote.extend(profile.promote);
+                self.promote
+            },
         }
-        println!("Sync complete");
-    } else {
-        println!("Please provide an API key");
-        return;
     }
 }
 
-/// Check for a .bunnysync file in the current directory and if it exists
-/// read it and parse it into the args struct.
-fn read_config_file(args: &mut Args) -> Result<()> {
-    if let Ok(config_file) = std::fs::read_to_string(".bunnysync") {
-        let config: Config = toml::from_str(&config_file)?;
-        if config.api_key.is_some() {
-            args.api_key = config.api_key;
+fn main() {
+    let mut cli = parse_cli();
+    i18n::set_language(cli.global.lang.unwrap_or_else(i18n::detect));
+    if let Some(kind) = cli.schema {
+        println!("{}", schema::generate(kind));
+        return;
+    }
+    if let Err(e) = read_api_key(&mut cli.global) {
+        eprintln!("{}", tr(Message::Error { error: &e }));
+        exit::exit(Status::Error);
+    }
+    // Logging in needs no config, even for a profile not in one yet.
+    if !matches!(cli.command, Some(Command::Login(_)))
+        && let Err(e) = read_config_file(&mut cli)
+    {
+        eprintln!("{}", tr(Message::Error { error: &e }));
+        exit::exit(Status::Error);
+    }
+    if let Some(offset) = cli.global.server_timezone {
+        timestamp::set_server_offset(offset);
+    }
+    if cli.global.nice_io
+        && let Err(e) = priority::lower()
+    {
+        eprintln!("{}", tr(Message::Warning { warning: &e }));
+    }
+    cli.global.bandwidth = cli
+        .global
+        .bwlimit
+        .clone()
+        .map(|schedule| Arc::new(Rate::scheduled(schedule)));
+    cli.global.requests = cli
+        .global
+        .max_requests_per_second
+        .map(|rate| Arc::new(Rate::new(rate)));
+    if cli.global.timeline.is_some() {
+        cli.global.trace = Some(Arc::new(Timeline::new()));
+    }
+    if let Some(percent) = cli.global.simulate_failures {
+        eprintln!("{}", tr(Message::SimulatingFailures { percent }));
+    }
+    let Some(command) = cli.command else {
+        Cli::command().print_help().expect("printing help");
+        exit::exit(Status::Error);
+    };
+
+    let result = match command {
+        // Commands that don't talk to bunny.net.
+        Command::Filters(args) => commands::filters::run(&args, cli.global.config_file()),
+        Command::History(args) => commands::history::run(&args),
+        Command::State(args) => commands::state::run(&args),
+        Command::Login(args) => commands::login::run(&args, cli.global.keychain_profile()),
+        Command::Run(args) => {
+            let config_file = (!cli.global.no_config).then(|| cli.global.config_file());
+            commands::run::run(&args, config_file, |name, job| {
+                run_job(&cli.global, name, job)
+            })
         }
-        if let Some(region) = config.region {
-            args.region = region;
+        Command::Promote(args) => promote(&cli.global, &args),
+        // Commands that use the management API rather than a storage zone.
+        Command::Cdn(args) => {
+            agent_options(&cli.global).and_then(|options| commands::cdn::run(&args, &options))
         }
-        if let Some(exclude_list) = config.exclude {
-            // Force exclusion of .bunnysync config as it likely contains
-            // secrets.
-            let mut new_list = exclude_list.clone();
-            new_list.push(".bunnysync".into());
-            args.exclude = new_list;
+        Command::Zones(args) => {
+            agent_options(&cli.global).and_then(|options| commands::zones::run(&args, &options))
         }
-    }
-    Ok(())
-}
-
-fn sync_to_remote(
-    agent: &Agent,
-    base_url: &str,
-    local: &str,
-    remote: &str,
-    dry_run: bool,
-    delete: bool,
-    exclude: Vec<String>,
-) -> Result<()> {
-    let remote = storage::strip_zone_prefix(remote);
-    let zone_name = storage::zone_name(remote);
-    let remote_files = get_remote_file_map(agent, base_url, &remote, &exclude)?;
-    let local_files = get_local_file_map(local, &zone_name, &exclude)?;
-
-    // Update files that are either changed locally or new.
-    for (remote_path, local_file) in &local_files {
-        // If the file matches an exclude, skip it.
-        let file_name = local_file.path.file_name().unwrap().to_str().unwrap();
-        if is_excluded(file_name, &exclude) {
-            continue;
+        Command::Stats(args) => {
+            agent_options(&cli.global).and_then(|options| commands::stats::run(&args, &options))
         }
-        // If the file exists and it's not changed, skip it.
-        if let Some(destination_file) = remote_files.get(remote_path) {
-            if local_file.last_changed <= destination_file.last_changed.and_utc()
-                && local_file.length == destination_file.length
-            {
-                continue;
+        Command::Init(args) => agent_options(&cli.global).and_then(|options| {
+            commands::init::run(
+                &args,
+                &cli.global.region,
+                cli.global.config_file(),
+                &options,
+            )
+        }),
+        Command::Completions(args) => agent_options(&cli.global)
+            .and_then(|options| commands::completions::run(&args, Cli::command(), &options)),
+        command => api_keys(&cli.global).and_then(|keys| {
+            let (agent, base_url) = connect(&cli.global, &keys)?;
+            match command {
+                Command::Sync(mut args) => {
+                    args.read_only = !keys.can_write();
+                    sync::run(&agent, &base_url, *args, &agent_options(&cli.global)?)
+                }
+                Command::Apply(args) => commands::apply::run(&agent, &base_url, &args),
+                Command::Ls(args) => commands::ls::run(&agent, &base_url, &args),
+                Command::Cat(args) => commands::cat::run(&agent, &base_url, &args),
+                Command::Stat(args) => commands::stat::run(&agent, &base_url, &args),
+                Command::Du(args) => commands::du::run(&agent, &base_url, &args),
+                Command::Doctor(args) => {
+                    let agents = keys
+                        .each()
+                        .into_iter()
+                        .map(|(key, keys)| Ok((key, connect(&cli.global, &keys)?.0)))
+                        .collect::<Result<Vec<_>>>()?;
+                    commands::doctor::run(&agents, &base_url, &args)
+                }
+                Command::Preview(args) => commands::preview::run(&agent, &base_url, &args),
+                Command::Diff(args) => {
+                    commands::diff::run(&agent, &base_url, &args, &agent_options(&cli.global)?)
+                }
+                Command::Export(args) => commands::export::run(&agent, &base_url, &args),
+                Command::Import(args) => commands::import::run(&agent, &base_url, &args),
+                Command::Freeze(args) => commands::freeze::run(&agent, &base_url, &args),
+                Command::Unfreeze(args) => commands::freeze::run_unfreeze(&agent, &base_url, &args),
+                Command::Filters(_)
+                | Command::History(_)
+                | Command::State(_)
+                | Command::Login(_)
+                | Command::Run(_)
+                | Command::Promote(_)
+                | Command::Cdn(_)
+                | Command::Zones(_)
+                | Command::Init(_)
+                | Command::Stats(_)
+                | Command::Completions(_) => unreachable!(),
             }
+        }),
+    };
+    if let (Some(path), Some(trace)) = (&cli.global.timeline, &cli.global.trace)
+        && let Err(e) = trace.write(path)
+    {
+        eprintln!("{}", tr(Message::Warning { warning: &e }));
+    }
+    if let Err(e) = result {
+        if !e.is::<exit::ChangesFound>() {
+            eprintln!("{}", tr(Message::Error { error: &e }));
         }
+        exit::exit(exit::status_of(&e));
+    }
+}
 
-        if !dry_run {
-            // Read the local file and send it to the destination.
-            let file_data = std::fs::read(&local_file.path)?;
-            storage::put_object(agent, base_url, &remote_path, &file_data)?;
-            println!(
-                "Updated: {} -> {}",
-                local_file.path.to_string_lossy(),
-                remote_path
-            );
-        } else {
-            println!(
-                "Would update: {} -> {}",
-                local_file.path.to_string_lossy(),
-                remote_path
-            );
-        }
+/// The API keys from the global options. Without an API key, the one stored
+/// in the keychain for the profile is used. The read-only key will do on its
+/// own.
+fn api_keys(global: &GlobalArgs) -> Result<storage::ApiKeys> {
+    let keys = storage::ApiKeys {
+        primary: (global.api_key.clone()).or_else(|| keychain::load(global.keychain_profile())),
+        secondary: global.secondary_api_key.clone(),
+        read_only: global.read_only_api_key.clone(),
+    };
+    if keys.primary.is_none() && keys.read_only.is_none() {
+        return Err(anyhow!(tr(Message::MissingApiKey)));
     }
+    Ok(keys)
+}
 
-    // Delete files that are not present locally.
-    if delete {
-        for (path, _) in remote_files {
-            if !local_files.contains_key(&path) {
-                if !dry_run {
-                    storage::delete_object(agent, base_url, &path)?;
-                    println!("Deleted: {}", path);
-                } else {
-                    println!("Would delete: {}", path);
-                }
-            }
+/// Build an agent with the keys and work out the storage endpoint from the
+/// global options. An endpoint given outright wins over the region.
+fn connect(global: &GlobalArgs, keys: &storage::ApiKeys) -> Result<(Agent, String)> {
+    // With auto, requests start out for the default region and the agent
+    // sends them on to their zone's.
+    let region = match global.region.as_str() {
+        "auto" => "de",
+        region => region,
+    };
+    let base_url = match &global.endpoint {
+        Some(endpoint) => storage::endpoint(endpoint)?,
+        None => storage::base_url(region).ok_or_else(|| anyhow!("Invalid region {}", region))?,
+    };
+    let agent = storage::agent(keys, &base_url, &agent_options(global)?)?;
+    Ok((agent, base_url))
+}
+
+/// The HTTP settings from the global options.
+fn agent_options(global: &GlobalArgs) -> Result<storage::AgentOptions> {
+    Ok(storage::AgentOptions {
+        stall_timeout: (!global.stall_timeout.is_zero()).then_some(global.stall_timeout),
+        connect_timeout: (!global.connect_timeout.is_zero()).then_some(global.connect_timeout),
+        request_timeout: global.request_timeout,
+        proxy: global.proxy.clone(),
+        bandwidth: global.bandwidth.clone(),
+        requests: global.requests.clone(),
+        simulate_failures: global.simulate_failures,
+        timeline: global.trace.clone(),
+        allow_http: (global.endpoint.as_deref()).is_some_and(|url| url.starts_with("http://")),
+        detect_region: global.region == "auto" && global.endpoint.is_none(),
+    })
+}
+
+/// Parse the command line, treating arguments that don't start with a known
+/// subcommand as arguments to `sync`.
+fn parse_cli() -> Cli {
+    use clap::error::ErrorKind;
+
+    let args: Vec<_> = std::env::args_os().collect();
+    let matches = match Cli::command().try_get_matches_from(&args) {
+        Ok(matches) => matches,
+        Err(e)
+            if matches!(
+                e.kind(),
+                ErrorKind::InvalidSubcommand | ErrorKind::UnknownArgument
+            ) =>
+        {
+            let mut sync_args = args;
+            sync_args.insert(1.min(sync_args.len()), "sync".into());
+            Cli::command()
+                .try_get_matches_from(sync_args)
+                .unwrap_or_else(|e| usage_error(e))
         }
+        Err(e) => usage_error(e),
+    };
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| usage_error(e));
+    cli.global.defaults = ["region"]
+        .into_iter()
+        .filter(|id| matches.value_source(id) == Some(clap::parser::ValueSource::DefaultValue))
+        .collect();
+    cli
+}
+
+/// Print a command line error and exit. Help and the version go to stdout
+/// and exit with success as usual, but a mistake exits with 1 rather than
+/// clap's 2, which is for keys that are turned down.
+fn usage_error(e: clap::Error) -> ! {
+    if !e.use_stderr() {
+        e.exit();
     }
-    Ok(())
+    let _ = e.print();
+    exit::exit(Status::Error)
 }
 
-fn sync_to_local(
-    agent: &Agent,
-    base_url: &str,
-    local: &str,
-    remote: &str,
-    dry_run: bool,
-    delete: bool,
-    exclude: Vec<String>,
-) -> anyhow::Result<()> {
-    let remote = storage::strip_zone_prefix(remote);
-    let zone_name = storage::zone_name(&remote);
-    let remote_files = get_remote_file_map(agent, base_url, &remote, &exclude)?;
-    let local_files = get_local_file_map(local, &zone_name, &exclude)?;
-
-    // Sync the files.
-    for (path, remote_file) in &remote_files {
-        // If the file exists locally and it's not changed, skip it.
-        if let Some(local_file) = local_files.get(path) {
-            if local_file.last_changed <= remote_file.last_changed.and_utc()
-                && local_file.length == remote_file.length
-            {
-                continue;
-            }
-        }
+/// Run a sync job with the config file applied as for any other sync. A
+/// job's own key and region win over those for the whole directory.
+fn run_job(global: &GlobalArgs, name: &str, job: &commands::run::Job) -> Result<()> {
+    let mut cli = Cli {
+        global: global.clone(),
+        schema: None,
+        command: Some(Command::Sync(Box::new(job.sync_args(name)?))),
+    };
+    read_config_file(&mut cli)?;
+    if job.api_key.is_some() {
+        cli.global.api_key = job.api_key.clone();
+    }
+    if let Some(region) = &job.region {
+        cli.global.region = region.clone();
+    }
+    let Some(Command::Sync(mut args)) = cli.command else {
+        unreachable!()
+    };
+    let keys = api_keys(&cli.global)?;
+    args.read_only = !keys.can_write();
+    let (agent, base_url) = connect(&cli.global, &keys)?;
+    sync::run(&agent, &base_url, *args, &agent_options(&cli.global)?)
+}
 
-        // Get a local file path for the remote.
-        let local_path = local::get_path(local, &zone_name, path);
+/// Promote the zone of one profile to another's. Each end uses the key and
+/// region of its profile, falling back on the global ones.
+fn promote(global: &GlobalArgs, args: &commands::promote::PromoteArgs) -> Result<()> {
+    use commands::promote::End;
 
-        if !dry_run {
-            // Download the file and save it locally.
-            let remote_path = format!("{}/{}", remote_file.path, remote_file.object_name);
-            let file_data = storage::get_object(agent, base_url, &remote_path)?;
+    let mut config = load_config(global)?.unwrap_or_default();
+    let promotion = config.promote.remove(&args.to).unwrap_or_default();
+    let profile = |name: &str| -> Result<Config> {
+        let profile = config
+            .profiles
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!(tr(Message::NoSuchProfile { name })))?;
+        Ok(config.clone().overlay(profile))
+    };
+    let end = |name: &str, config: &Config| -> Result<End> {
+        let zone = config
+            .zone
+            .as_deref()
+            .ok_or_else(|| anyhow!(tr(Message::ProfileNeedsZone { name })))?;
+        let mut global = global.clone();
+        global.profile = Some(name.to_string());
+        if config.api_key.is_some() || config.read_only_api_key.is_some() {
+            global.api_key = config.api_key.clone();
+            global.secondary_api_key = config.secondary_api_key.clone();
+            global.read_only_api_key = config.read_only_api_key.clone();
+        }
+        if let Some(region) = &config.region {
+            global.region = region.clone();
+        }
+        if config.endpoint.is_some() {
+            global.endpoint = config.endpoint.clone();
+        }
+        let (agent, base_url) = connect(&global, &api_keys(&global)?)?;
+        Ok(End {
+            zone: format!(
+                "zone://{}/",
+                storage::strip_zone_prefix(zone).trim_end_matches('/')
+            ),
+            agent,
+            base_url,
+        })
+    };
+    let from_config = profile(&args.from)?;
+    let to_config = profile(&args.to)?;
+    let from = end(&args.from, &from_config)?;
+    let to = end(&args.to, &to_config)?;
 
-            // Create the directory if it doesn't exist.
+    let purge = if (promotion.purge || promotion.purge_all) && !args.dry_run {
+        let pull_zone = (to_config.pull_zone.clone())
+            .ok_or_else(|| anyhow!(tr(Message::PurgeNeedsPullZone)))?;
+        let account_key = (to_config.account_key.clone())
+            .or_else(|| std::env::var("BUNNYSYNC_ACCOUNT_KEY").ok())
+            .ok_or_else(|| anyhow!(tr(Message::MissingAccountKey)))?;
+        Some((
+            api::Client::new(&account_key, &agent_options(global)?)?,
+            pull_zone,
+        ))
+    } else {
+        None
+    };
+    commands::promote::run(args, &promotion, from, &to, purge)
+}
 
-            if let Some(dir) = local_path.parent() {
-                if !dir.exists() {
-                    std::fs::create_dir_all(dir)?;
-                }
+/// Read the API key from a file or stdin if asked to. A key read this way
+/// wins over `--api-key`, as it was asked for by name.
+fn read_api_key(global: &mut GlobalArgs) -> Result<()> {
+    let text = if let Some(path) = &global.api_key_file {
+        // A key file kept in the directory being synced must never be
+        // uploaded.
+        local::protect(path);
+        std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Could not read {}: {}", path.to_string_lossy(), e))?
+    } else if global.api_key_stdin {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        line
+    } else {
+        return Ok(());
+    };
+    let key = text.lines().next().unwrap_or_default().trim();
+    if key.is_empty() {
+        return Err(anyhow!(tr(Message::MissingApiKey)));
+    }
+    global.api_key = Some(key.to_string());
+    Ok(())
+}
+
+/// Read the global config file and the project config file on top of it, if
+/// either exists. A config file given with `--config` must exist.
+fn load_config(global: &GlobalArgs) -> Result<Option<Config>> {
+    if global.no_config {
+        return Ok(None);
+    }
+    let files = local::global_config_file()
+        .into_iter()
+        .chain([global.config_file().to_path_buf()]);
+    let mut config: Option<Config> = None;
+    for path in files {
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if global.config.as_ref() == Some(&path) => {
+                return Err(anyhow!("Could not read {}: {}", path.to_string_lossy(), e));
             }
+            Err(_) => continue,
+        };
+        let file: Config = toml::from_str(&text)
+            .map_err(|e| anyhow!("Could not read {}: {}", path.to_string_lossy(), e))?;
+        local::protect(&path);
+        config = Some(match config {
+            Some(config) => config.overlay(file),
+            None => file,
+        });
+    }
+    Ok(config)
+}
 
-            // Write the file.
-            std::fs::write(&local_path, file_data)?;
-            println!("Updated: {} -> {}", path, &local_path.to_str().unwrap());
-        } else {
-            println!(
-                "Would update: {} -> {}",
-                path,
-                &local_path.to_str().unwrap()
-            );
-        }
+/// Fill in the options not given on the command line or in the environment
+/// from the config files.
+fn read_config_file(cli: &mut Cli) -> Result<()> {
+    let config = load_config(&cli.global)?;
+    if config.is_none()
+        && let Some(profile) = &cli.global.profile
+    {
+        return Err(anyhow!(tr(Message::NoSuchProfile { name: profile })));
     }
-    // Delete files that are not present remotely.
-    if delete {
-        for (path, _) in local_files {
-            if !remote_files.contains_key(&path) {
-                if !dry_run {
-                    std::fs::remove_file(&path)?;
-                    println!("Deleted: {}", path);
-                } else {
-                    println!("Would delete: {}", path);
-                }
+    if let Some(mut config) = config {
+        if let Some(name) = &cli.global.profile {
+            let profile = config
+                .profiles
+                .remove(name)
+                .ok_or_else(|| anyhow!(tr(Message::NoSuchProfile { name })))?;
+            config = config.overlay(profile);
+        }
+        if cli.global.api_key.is_none() {
+            cli.global.api_key = config.api_key;
+        }
+        if cli.global.secondary_api_key.is_none() {
+            cli.global.secondary_api_key = config.secondary_api_key;
+        }
+        if cli.global.read_only_api_key.is_none() {
+            cli.global.read_only_api_key = config.read_only_api_key;
+        }
+        if let Some(region) = config.region
+            && cli.global.is_default("region")
+        {
+            cli.global.region = region;
+        }
+        if cli.global.endpoint.is_none() {
+            cli.global.endpoint = config.endpoint;
+        }
+        if cli.global.proxy.is_none() {
+            cli.global.proxy = config.proxy;
+        }
+        if cli.global.bwlimit.is_none() {
+            cli.global.bwlimit = config.bwlimit;
+        }
+        if cli.global.max_requests_per_second.is_none() {
+            cli.global.max_requests_per_second = config.max_requests_per_second;
+        }
+        if let Some(nice_io) = config.nice_io
+            && !cli.global.nice_io
+        {
+            cli.global.nice_io = nice_io;
+        }
+        if cli.global.server_timezone.is_none()
+            && let Some(offset) = &config.server_timezone
+        {
+            cli.global.server_timezone = Some(timestamp::parse_offset(offset)?);
+        }
+        if let Some(Command::Sync(args)) = &mut cli.command {
+            if args.quota_warn.is_none() {
+                args.quota_warn = config.quota_warn;
+            }
+            args.transforms = config.transform.unwrap_or_default();
+            args.plugins = config.plugins.unwrap_or_default();
+            if args.pull_zone.is_none() {
+                args.pull_zone = config.pull_zone;
+            }
+            if args.destination.is_empty()
+                && let Some(zone) = &config.zone
+            {
+                args.destination = format!("zone://{}/", storage::strip_zone_prefix(zone));
             }
         }
+        let zone = match &mut cli.command {
+            Some(Command::Doctor(args)) => Some(args.zone_mut()),
+            Some(Command::Preview(args)) => Some(args.zone_mut()),
+            _ => None,
+        };
+        if let Some(zone) = zone
+            && zone.is_none()
+        {
+            *zone = config.zone.clone();
+        }
+        let account_key = match &mut cli.command {
+            Some(Command::Sync(args)) => Some(&mut args.account_key),
+            Some(Command::Cdn(args)) => Some(args.account_key_mut()),
+            Some(Command::Zones(args)) => Some(args.account_key_mut()),
+            Some(Command::Init(args)) => Some(args.account_key_mut()),
+            Some(Command::Stats(args)) => Some(args.account_key_mut()),
+            Some(Command::Completions(args)) => Some(args.account_key_mut()),
+            _ => None,
+        };
+        if let Some(account_key) = account_key
+            && account_key.is_none()
+        {
+            *account_key = config.account_key;
+        }
+        let exclude = match &mut cli.command {
+            Some(Command::Sync(args)) => Some(&mut args.exclude),
+            Some(Command::Filters(args)) => args.exclude_mut(),
+            _ => None,
+        };
+        if let Some(exclude) = exclude {
+            // Config patterns apply on top of any given on the command line.
+            // Exclude the .bunnysync config whatever it says, as it likely
+            // contains secrets. The planner refuses to upload it regardless.
+            exclude.extend(config.exclude.unwrap_or_default());
+            exclude.push(local::CONFIG_FILE.into());
+        }
     }
     Ok(())
 }
 
-/// Check if the path is a zone.
-fn is_zone(path: &str) -> bool {
-    path.starts_with("zone://")
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-/// Get the remote files as a map.
-fn get_remote_file_map(
-    agent: &Agent,
-    base_url: &str,
-    remote: &str,
-    exclude: &[String],
-) -> anyhow::Result<HashMap<String, storage::StorageObject>> {
-    let remote_files = storage::get_all_objects(agent, base_url, &remote)?;
-    // Create a map for quick lookup of destination files.
-    let remote_file_map = remote_files
-        .into_iter()
-        // Skip directories.
-        .filter(|file| !file.is_directory)
-        // Skip excluded files.
-        .filter(|file| !is_excluded(&file.object_name, exclude))
-        .map(|file| (format!("{}{}", file.path.clone(), &file.object_name), file))
-        .collect();
-    Ok(remote_file_map)
-}
+    #[test]
+    fn test_profile() {
+        let mut config: Config = toml::from_str(
+            r#"
+            api_key = "shared"
+            region = "de"
+            exclude = ["*.log"]
+            [profiles.prod]
+            region = "ny"
+            zone = "my-prod"
+            exclude = ["drafts/*"]
+            "#,
+        )
+        .unwrap();
+        let prod = config.profiles.remove("prod").unwrap();
+        let config = config.overlay(prod);
+        assert_eq!(config.api_key.as_deref(), Some("shared"));
+        assert_eq!(config.region.as_deref(), Some("ny"));
+        assert_eq!(config.zone.as_deref(), Some("my-prod"));
+        assert_eq!(
+            config.exclude,
+            Some(vec!["*.log".to_string(), "drafts/*".to_string()])
+        );
+    }
 
-/// Get the local files as a map.
-fn get_local_file_map(
-    local: &str,
-    zone_name: &str,
-    exclude: &[String],
-) -> anyhow::Result<HashMap<String, local::LocalFile>> {
-    let local_files = local::get_files(local.as_ref())?;
-    // Create a map for quick lookup of local files. We construct a destination
-    // path from the relative path of the local file.
-    let local_file_map: HashMap<_, _> = local_files
-        .into_iter()
-        // Skip directories.
-        .filter(|file| !file.is_directory)
-        // Skip excluded files.
-        .filter(|file| {
-            let filename = file.path.file_name().unwrap().to_str().unwrap();
-            !is_excluded(filename, exclude)
-        })
-        .map(|file| {
-            (
-                format!(
-                    "/{}/{}",
-                    zone_name,
-                    file.relative_path.to_string_lossy().to_string()
-                ),
-                file,
-            )
-        })
-        .collect();
-    Ok(local_file_map)
-}
+    #[test]
+    fn test_global_config() {
+        let global: Config = toml::from_str(
+            r#"
+            api_key = "global"
+            account_key = "account"
+            region = "ny"
+            [profiles.backup]
+            zone = "my-backup"
+            "#,
+        )
+        .unwrap();
+        let project: Config = toml::from_str(
+            r#"
+            api_key = "project"
+            [profiles.prod]
+            zone = "my-prod"
+            "#,
+        )
+        .unwrap();
+        let config = global.overlay(project);
+        assert_eq!(config.api_key.as_deref(), Some("project"));
+        assert_eq!(config.account_key.as_deref(), Some("account"));
+        assert_eq!(config.region.as_deref(), Some("ny"));
+        assert!(config.profiles.contains_key("backup"));
+        assert!(config.profiles.contains_key("prod"));
+    }
+
+    #[test]
+    fn test_api_key_file() {
+        let dir = std::env::temp_dir().join(format!("bunnysync-key-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("key");
+        std::fs::write(&path, "zone-password\n").unwrap();
+        let args = ["bunnysync", "--api-key", "other", "--api-key-file"];
+        let mut cli = Cli::try_parse_from(args.iter().copied().chain([
+            path.to_str().unwrap(),
+            "ls",
+            "zone://z/",
+        ]))
+        .unwrap();
+        read_api_key(&mut cli.global).unwrap();
+        assert_eq!(cli.global.api_key.as_deref(), Some("zone-password"));
 
-/// Check if a file is excluded based on the exclude patterns.
-fn is_excluded(file_name: &str, exclude_patterns: &[String]) -> bool {
-    exclude_patterns
-        .iter()
-        .any(|pattern| glob_match::glob_match(file_name, pattern))
+        // The key file is never pushed along with the directory it is in.
+        std::fs::write(dir.join("index.html"), "home").unwrap();
+        let paths = paths::PathMap::new("z/");
+        let local_files: HashMap<_, _> = local::get_files(&dir, &filter::Filter::default())
+            .unwrap()
+            .files
+            .into_iter()
+            .filter(|file| !file.is_directory)
+            .map(|file| (paths.remote_key(&file.relative_path), file))
+            .collect();
+        let actions = plan::plan_to_remote(
+            &local_files,
+            &plan::RemoteIndex::default(),
+            false,
+            chrono::TimeDelta::zero(),
+        );
+        let uploaded: Vec<_> = actions
+            .iter()
+            .filter(|action| matches!(action, plan::SyncAction::Upload { .. }))
+            .map(|action| action.key())
+            .collect();
+        assert_eq!(uploaded, vec!["/z/index.html"]);
+
+        std::fs::write(&path, "\n").unwrap();
+        assert!(read_api_key(&mut cli.global).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_config_flags() {
+        let parse = |args: &[&str]| Cli::try_parse_from(["bunnysync"].iter().chain(args));
+        let cli = parse(&["ls", "--no-config", "zone://z/"]).unwrap();
+        assert!(load_config(&cli.global).unwrap().is_none());
+        let cli = parse(&["--config", "no-such-config.toml", "ls", "zone://z/"]).unwrap();
+        assert_eq!(cli.global.config_file(), Path::new("no-such-config.toml"));
+        assert!(load_config(&cli.global).is_err());
+        assert!(parse(&["--config", "a.toml", "--no-config", "ls", "zone://z/"]).is_err());
+    }
 }
@@ -0,0 +1,138 @@
+//! The manifest a push can leave in the zone. It lists every file under the
+//! synced path, so a later push can download one file instead of listing
+//! each directory.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use ureq::Agent;
+
+use crate::{
+    plan::object_key,
+    storage::{self, StorageObject},
+    timestamp::RemoteTime,
+};
+
+/// The manifest's name, in the remote directory being synced.
+pub const MANIFEST_FILE: &str = ".bunnysync-manifest.json";
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    /// Files by remote key, e.g. `/my-zone/css/site.css`.
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub length: u64,
+    pub last_changed: RemoteTime,
+    /// The upper case hex SHA256 of the content.
+    pub sha256: Option<String>,
+}
+
+impl Manifest {
+    pub const VERSION: u32 = 1;
+
+    /// Describe the files in a listing. Directories are left out.
+    pub fn new(objects: &[StorageObject]) -> Self {
+        let files = objects
+            .iter()
+            .filter(|object| !object.is_directory)
+            .map(|object| {
+                let entry = ManifestEntry {
+                    length: object.length,
+                    last_changed: object.last_changed,
+                    sha256: object.checksum.clone(),
+                };
+                (object_key(object), entry)
+            })
+            .collect();
+        Manifest {
+            version: Manifest::VERSION,
+            files,
+        }
+    }
+
+    /// The files as storage objects, as a listing would give them.
+    pub fn objects(&self) -> Vec<StorageObject> {
+        self.files
+            .iter()
+            .map(|(key, entry)| {
+                let (path, name) = key.rsplit_once('/').unwrap_or(("", key));
+                StorageObject {
+                    storage_zone_name: storage::zone_name(key),
+                    path: format!("{}/", path),
+                    object_name: name.to_string(),
+                    length: entry.length,
+                    last_changed: entry.last_changed,
+                    date_created: entry.last_changed,
+                    checksum: entry.sha256.clone(),
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
+    /// Download the manifest for a remote path, if there is one.
+    pub fn fetch(agent: &Agent, base_url: &str, remote: &str) -> Result<Option<Self>> {
+        let Some(data) = storage::find_object(agent, base_url, &path(remote))? else {
+            return Ok(None);
+        };
+        let manifest: Manifest = serde_json::from_slice(&data)
+            .map_err(|e| anyhow!("Could not read {}: {}", MANIFEST_FILE, e))?;
+        if manifest.version != Manifest::VERSION {
+            // Written by another version, so list the zone instead.
+            return Ok(None);
+        }
+        Ok(Some(manifest))
+    }
+}
+
+/// Where the manifest for a remote path is stored.
+pub fn path(remote: &str) -> String {
+    format!("{}/{}", remote.trim_end_matches('/'), MANIFEST_FILE)
+}
+
+/// The remote key of the manifest for a remote path.
+pub fn key(remote: &str) -> String {
+    format!("/{}", path(remote.trim_start_matches('/')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let changed = RemoteTime::from_utc(DateTime::from_timestamp(100, 0).unwrap());
+        let objects = vec![
+            StorageObject {
+                storage_zone_name: "myzone".to_string(),
+                path: "/myzone/css/".to_string(),
+                object_name: "site.css".to_string(),
+                length: 5,
+                last_changed: changed,
+                date_created: changed,
+                checksum: Some("AB".to_string()),
+                ..Default::default()
+            },
+            StorageObject {
+                storage_zone_name: "myzone".to_string(),
+                path: "/myzone/".to_string(),
+                object_name: "css".to_string(),
+                is_directory: true,
+                ..Default::default()
+            },
+        ];
+        let manifest = Manifest::new(&objects);
+        assert_eq!(
+            manifest.files.keys().collect::<Vec<_>>(),
+            vec!["/myzone/css/site.css"]
+        );
+        assert_eq!(manifest.objects(), objects[..1]);
+        assert_eq!(key("myzone/sub/"), "/myzone/sub/.bunnysync-manifest.json");
+        assert_eq!(key("myzone"), "/myzone/.bunnysync-manifest.json");
+    }
+}
@@ -0,0 +1,582 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+use crate::{
+    checksum,
+    i18n::{Message, tr},
+    plan::SkipReason,
+};
+
+/// How sync results are printed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// One human readable line per change
+    #[default]
+    Text,
+    /// One JSON object per line for every action, then a summary object
+    Json,
+}
+
+/// Something that happened, or on a dry run would have happened, during a
+/// sync. In JSON output each event is printed as one line.
+#[derive(Debug, Serialize, JsonSchema, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Event {
+    /// A local file was uploaded to a remote path.
+    Uploaded {
+        path: String,
+        local_path: String,
+        bytes: u64,
+        dry_run: bool,
+    },
+    /// A remote file was downloaded to a local path.
+    Downloaded {
+        path: String,
+        local_path: String,
+        bytes: u64,
+        dry_run: bool,
+    },
+    /// A remote file was found unchanged in the `--link-dest` snapshot and
+    /// linked or copied from there instead of downloaded.
+    Linked {
+        path: String,
+        local_path: String,
+        snapshot_path: String,
+        dry_run: bool,
+    },
+    /// A file was deleted. Local deletes include the local path.
+    Deleted {
+        path: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        local_path: Option<String>,
+        bytes: u64,
+        dry_run: bool,
+    },
+    /// A file was left alone.
+    Skipped { path: String, reason: SkipReason },
+    /// A file could not be transferred or deleted, and the sync went on
+    /// with `--continue-on-error`. The error is in the language chosen for
+    /// output.
+    Failed { path: String, error: String },
+    /// Something worth a look that didn't stop the sync. The message is in
+    /// the language chosen for output.
+    Warning {
+        kind: WarningKind,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+        message: String,
+    },
+    /// Totals for the run, printed after the file events. In watch mode it
+    /// covers the initial sync.
+    Summary(Summary),
+}
+
+impl Event {
+    /// The event with each path replaced by [`redact`]. Sizes and counts are
+    /// kept.
+    fn redacted(self) -> Event {
+        match self {
+            Event::Uploaded {
+                path,
+                local_path,
+                bytes,
+                dry_run,
+            } => Event::Uploaded {
+                path: redact(&path),
+                local_path: redact(&local_path),
+                bytes,
+                dry_run,
+            },
+            Event::Downloaded {
+                path,
+                local_path,
+                bytes,
+                dry_run,
+            } => Event::Downloaded {
+                path: redact(&path),
+                local_path: redact(&local_path),
+                bytes,
+                dry_run,
+            },
+            Event::Linked {
+                path,
+                local_path,
+                snapshot_path,
+                dry_run,
+            } => Event::Linked {
+                path: redact(&path),
+                local_path: redact(&local_path),
+                snapshot_path: redact(&snapshot_path),
+                dry_run,
+            },
+            Event::Deleted {
+                path,
+                local_path,
+                bytes,
+                dry_run,
+            } => Event::Deleted {
+                path: redact(&path),
+                local_path: local_path.as_deref().map(redact),
+                bytes,
+                dry_run,
+            },
+            Event::Skipped { path, reason } => Event::Skipped {
+                path: redact(&path),
+                reason,
+            },
+            Event::Failed { path, error } => Event::Failed {
+                error: error.replace(&path, &redact(&path)),
+                path: redact(&path),
+            },
+            Event::Warning {
+                kind,
+                path: Some(path),
+                message,
+            } => Event::Warning {
+                kind,
+                message: message.replace(&path, &redact(&path)),
+                path: Some(redact(&path)),
+            },
+            event => event,
+        }
+    }
+}
+
+/// A stable stand-in for a path, for `--redact-paths`. The same path always
+/// gives the same hash, so redacted logs of different runs can still be
+/// compared.
+pub fn redact(path: &str) -> String {
+    let hash = checksum::sha256_hex(path.as_bytes()).to_ascii_lowercase();
+    format!("redacted:{}", &hash[..16])
+}
+
+/// What a warning is about.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningKind {
+    /// A local file that is neither a regular file nor a directory, like a
+    /// socket or FIFO, was passed over.
+    SpecialFile,
+    /// A local file went away before it could be uploaded.
+    Vanished,
+    /// A file changed on both sides since the last bidirectional sync.
+    Conflict,
+    /// The zone has files changed later than the local clock says it is.
+    ClockSkew,
+    /// The zone would grow past `--quota-warn`.
+    Quota,
+    /// A dry run would delete more files than `--max-delete` allows.
+    DeleteLimit,
+}
+
+/// Totals for a sync run.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+pub struct Summary {
+    /// Distinct files seen on either side.
+    pub scanned: u64,
+    pub uploaded: u64,
+    pub downloaded: u64,
+    pub deleted: u64,
+    /// Files taken from the `--link-dest` snapshot rather than downloaded.
+    pub linked: u64,
+    pub skipped: u64,
+    /// Skipped files that went away locally before they could be uploaded.
+    #[serde(default)]
+    pub vanished: u64,
+    #[serde(default)]
+    pub warnings: u64,
+    /// Files that failed under `--continue-on-error`.
+    #[serde(default)]
+    pub failed: u64,
+    pub bytes_uploaded: u64,
+    pub bytes_downloaded: u64,
+    /// Bytes uploaded plus bytes downloaded.
+    pub bytes_transferred: u64,
+    /// Size of the deleted files, where known.
+    pub bytes_deleted: u64,
+    /// Wall clock time from the start of the run to the summary.
+    pub elapsed_ms: u64,
+    pub dry_run: bool,
+}
+
+impl Summary {
+    fn record(&mut self, event: &Event) {
+        match event {
+            Event::Uploaded { bytes, .. } => {
+                self.uploaded += 1;
+                self.bytes_uploaded += bytes;
+                self.bytes_transferred += bytes;
+            }
+            Event::Downloaded { bytes, .. } => {
+                self.downloaded += 1;
+                self.bytes_downloaded += bytes;
+                self.bytes_transferred += bytes;
+            }
+            Event::Deleted { bytes, .. } => {
+                self.deleted += 1;
+                self.bytes_deleted += bytes;
+            }
+            Event::Linked { .. } => self.linked += 1,
+            Event::Skipped { reason, .. } => {
+                self.skipped += 1;
+                if *reason == SkipReason::Vanished {
+                    self.vanished += 1;
+                }
+            }
+            Event::Warning { .. } => self.warnings += 1,
+            Event::Failed { .. } => self.failed += 1,
+            Event::Summary(_) => {}
+        }
+    }
+
+    /// Average transfer rate in bytes per second.
+    pub fn throughput(&self) -> u64 {
+        if self.elapsed_ms == 0 {
+            return self.bytes_transferred;
+        }
+        self.bytes_transferred * 1000 / self.elapsed_ms
+    }
+}
+
+/// Prints sync events in the chosen format and keeps the totals.
+pub struct Reporter {
+    format: OutputFormat,
+    /// Print the detailed breakdown rather than a one line summary.
+    stats: bool,
+    /// Print hashes in place of paths.
+    redact: bool,
+    started: Instant,
+    summary: Summary,
+    /// The files that failed and why, for the report at the end.
+    failures: Vec<(String, String)>,
+}
+
+impl Reporter {
+    pub fn new(format: OutputFormat, dry_run: bool, stats: bool) -> Self {
+        Reporter {
+            format,
+            stats,
+            redact: false,
+            started: Instant::now(),
+            summary: Summary {
+                dry_run,
+                ..Default::default()
+            },
+            failures: Vec::new(),
+        }
+    }
+
+    /// Print a hash of each path in events instead of the path, for logs
+    /// shared outside the team.
+    pub fn redact_paths(&mut self) {
+        self.redact = true;
+    }
+
+    /// Count files looked at while planning.
+    pub fn scanned(&mut self, files: u64) {
+        self.summary.scanned += files;
+    }
+
+    /// Record an event and print it.
+    pub fn report(&mut self, event: Event) {
+        self.summary.record(&event);
+        let event = if self.redact { event.redacted() } else { event };
+        match self.format {
+            OutputFormat::Json => println!("{}", to_json_line(&event)),
+            OutputFormat::Text => {
+                if let Some(line) = text_line(&event) {
+                    println!("{}", line);
+                }
+            }
+        }
+    }
+
+    /// Record a warning. In text output it goes to stderr with the other
+    /// diagnostics, and in JSON output it is an event like any other.
+    pub fn warn(&mut self, kind: WarningKind, path: Option<&str>, message: String) {
+        self.diagnostic(Event::Warning {
+            kind,
+            path: path.map(String::from),
+            message,
+        });
+    }
+
+    /// Record a file that failed, to go on with the rest of the sync. It is
+    /// printed like a warning and again in a report at the end.
+    pub fn fail(&mut self, path: &str, error: &anyhow::Error) {
+        self.diagnostic(Event::Failed {
+            path: path.to_string(),
+            error: error.to_string(),
+        });
+    }
+
+    /// Record an event and print it, to stderr in text output.
+    fn diagnostic(&mut self, event: Event) {
+        self.summary.record(&event);
+        let event = if self.redact { event.redacted() } else { event };
+        if let Event::Failed { path, error } = &event {
+            self.failures.push((path.clone(), error.clone()));
+        }
+        match self.format {
+            OutputFormat::Json => println!("{}", to_json_line(&event)),
+            OutputFormat::Text => {
+                if let Some(line) = text_line(&event) {
+                    eprintln!("{}", line);
+                }
+            }
+        }
+    }
+
+    /// Print a free form status message. It goes to stderr in JSON output so
+    /// stdout stays machine readable.
+    pub fn message(&self, message: &str) {
+        match self.format {
+            OutputFormat::Json => eprintln!("{}", message),
+            OutputFormat::Text => println!("{}", message),
+        }
+    }
+
+    /// The totals so far.
+    pub fn summary(&self) -> &Summary {
+        &self.summary
+    }
+
+    /// Finish the run and print the summary.
+    pub fn finish(&mut self) {
+        self.summary.elapsed_ms = self.started.elapsed().as_millis() as u64;
+        let summary = &self.summary;
+        match self.format {
+            OutputFormat::Json => println!("{}", to_json_line(&Event::Summary(summary.clone()))),
+            OutputFormat::Text if self.stats => println!("{}", tr(Message::Stats { summary })),
+            OutputFormat::Text => println!("{}", tr(Message::Summary { summary })),
+        }
+        // JSON output already has an event for each.
+        if self.format == OutputFormat::Text && !self.failures.is_empty() {
+            let count = self.failures.len();
+            eprintln!("{}", tr(Message::FailureReport { count }));
+            for (path, error) in &self.failures {
+                eprintln!("  {}", tr(Message::Failed { path, error }));
+            }
+        }
+    }
+}
+
+fn to_json_line(event: &Event) -> String {
+    serde_json::to_string(event).expect("event serializes")
+}
+
+/// The human readable line for an event, if it gets one.
+fn text_line(event: &Event) -> Option<String> {
+    let message = match event {
+        Event::Uploaded {
+            path,
+            local_path,
+            dry_run,
+            ..
+        } => update(local_path, path, *dry_run),
+        Event::Downloaded {
+            path,
+            local_path,
+            dry_run,
+            ..
+        } => update(path, local_path, *dry_run),
+        Event::Linked {
+            local_path,
+            snapshot_path,
+            dry_run,
+            ..
+        } => {
+            let (from, to) = (snapshot_path.as_str(), local_path.as_str());
+            if *dry_run {
+                Message::WouldLink { from, to }
+            } else {
+                Message::Linked { from, to }
+            }
+        }
+        Event::Deleted {
+            path,
+            local_path,
+            dry_run,
+            ..
+        } => {
+            let path = local_path.as_ref().unwrap_or(path);
+            if *dry_run {
+                Message::WouldDelete { path }
+            } else {
+                Message::Deleted { path }
+            }
+        }
+        Event::Warning { message, .. } => Message::Warning { warning: message },
+        Event::Failed { path, error } => Message::Failed { path, error },
+        Event::Skipped { .. } | Event::Summary(_) => return None,
+    };
+    Some(tr(message))
+}
+
+fn update<'a>(from: &'a str, to: &'a str, dry_run: bool) -> Message<'a> {
+    if dry_run {
+        Message::WouldUpdate { from, to }
+    } else {
+        Message::Updated { from, to }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_lines() {
+        let upload = Event::Uploaded {
+            path: "/zone/a.html".to_string(),
+            local_path: "site/a.html".to_string(),
+            bytes: 3,
+            dry_run: false,
+        };
+        assert_eq!(
+            text_line(&upload).unwrap(),
+            "Updated: site/a.html -> /zone/a.html"
+        );
+        let delete = Event::Deleted {
+            path: "/zone/a.html".to_string(),
+            local_path: Some("site/a.html".to_string()),
+            bytes: 3,
+            dry_run: true,
+        };
+        assert_eq!(text_line(&delete).unwrap(), "Would delete: site/a.html");
+        let skip = Event::Skipped {
+            path: "/zone/a.html".to_string(),
+            reason: SkipReason::Unchanged,
+        };
+        assert_eq!(text_line(&skip), None);
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let download = Event::Downloaded {
+            path: "/zone/a.html".to_string(),
+            local_path: "site/a.html".to_string(),
+            bytes: 3,
+            dry_run: false,
+        };
+        assert_eq!(
+            to_json_line(&download),
+            r#"{"action":"downloaded","path":"/zone/a.html","local_path":"site/a.html","bytes":3,"dry_run":false}"#
+        );
+        let skip = Event::Skipped {
+            path: "/zone/b.html".to_string(),
+            reason: SkipReason::Unchanged,
+        };
+        assert_eq!(
+            to_json_line(&skip),
+            r#"{"action":"skipped","path":"/zone/b.html","reason":"unchanged"}"#
+        );
+        let warning = Event::Warning {
+            kind: WarningKind::SpecialFile,
+            path: Some("site/fifo".to_string()),
+            message: "site/fifo is not a regular file or directory and was skipped".to_string(),
+        };
+        assert_eq!(
+            to_json_line(&warning),
+            r#"{"action":"warning","kind":"special_file","path":"site/fifo","message":"site/fifo is not a regular file or directory and was skipped"}"#
+        );
+    }
+
+    #[test]
+    fn test_redacted() {
+        let upload = Event::Uploaded {
+            path: "/zone/secret/plan.pdf".to_string(),
+            local_path: "site/secret/plan.pdf".to_string(),
+            bytes: 3,
+            dry_run: false,
+        };
+        let Event::Uploaded {
+            path,
+            local_path,
+            bytes,
+            ..
+        } = upload.redacted()
+        else {
+            panic!("not an upload");
+        };
+        assert_eq!(path, redact("/zone/secret/plan.pdf"));
+        assert!(path.starts_with("redacted:") && !path.contains("secret"));
+        assert_ne!(local_path, path);
+        assert_eq!(bytes, 3);
+
+        let warning = Event::Warning {
+            kind: WarningKind::Vanished,
+            path: Some("/zone/secret.txt".to_string()),
+            message: "/zone/secret.txt went away".to_string(),
+        };
+        let line = to_json_line(&warning.redacted());
+        assert!(!line.contains("secret"));
+        assert!(line.contains(&format!("{} went away", redact("/zone/secret.txt"))));
+    }
+
+    #[test]
+    fn test_summary_totals() {
+        let mut summary = Summary {
+            elapsed_ms: 500,
+            ..Default::default()
+        };
+        summary.record(&Event::Uploaded {
+            path: String::new(),
+            local_path: String::new(),
+            bytes: 10,
+            dry_run: false,
+        });
+        summary.record(&Event::Downloaded {
+            path: String::new(),
+            local_path: String::new(),
+            bytes: 5,
+            dry_run: false,
+        });
+        summary.record(&Event::Deleted {
+            path: String::new(),
+            local_path: None,
+            bytes: 7,
+            dry_run: false,
+        });
+        summary.record(&Event::Skipped {
+            path: String::new(),
+            reason: SkipReason::Unchanged,
+        });
+        summary.record(&Event::Skipped {
+            path: String::new(),
+            reason: SkipReason::Vanished,
+        });
+        summary.record(&Event::Linked {
+            path: String::new(),
+            local_path: String::new(),
+            snapshot_path: String::new(),
+            dry_run: false,
+        });
+        summary.record(&Event::Failed {
+            path: String::new(),
+            error: String::new(),
+        });
+        assert_eq!(
+            summary,
+            Summary {
+                scanned: 0,
+                uploaded: 1,
+                downloaded: 1,
+                deleted: 1,
+                linked: 1,
+                skipped: 2,
+                vanished: 1,
+                warnings: 0,
+                failed: 1,
+                bytes_uploaded: 10,
+                bytes_downloaded: 5,
+                bytes_transferred: 15,
+                bytes_deleted: 7,
+                elapsed_ms: 500,
+                dry_run: false,
+            }
+        );
+        assert_eq!(summary.throughput(), 30);
+    }
+}
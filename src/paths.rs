@@ -0,0 +1,312 @@
+use anyhow::{Result, anyhow};
+use std::path::{Component, Path, PathBuf};
+
+use crate::{local, storage};
+
+/// How paths relative to the local directory map to remote keys. The local
+/// directory stands for a directory in the zone: the zone root, or the path
+/// given after the zone name in the zone URL, followed by any `--prefix`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathMap {
+    zone: String,
+    /// The directory within the zone, like `site/en/`, or empty for the
+    /// zone root.
+    prefix: String,
+}
+
+impl PathMap {
+    /// Map to the directory named by a zone URL, such as
+    /// `zone://my-zone/site/`. The `zone://` is optional.
+    pub fn new(remote: &str) -> Self {
+        let mut parts = storage::strip_zone_prefix(remote)
+            .split('/')
+            .filter(|part| !part.is_empty() && *part != ".");
+        let zone = parts.next().unwrap_or_default().to_string();
+        let prefix = parts.map(|part| format!("{}/", part)).collect();
+        PathMap { zone, prefix }
+    }
+
+    /// Map to a subdirectory of the current directory instead, such as
+    /// `blog/` or `v2/docs`.
+    pub fn with_prefix(mut self, prefix: &str) -> Result<Self> {
+        for part in prefix.split('/') {
+            match part {
+                "" | "." => {}
+                ".." => return Err(anyhow!("The prefix {} leaves the zone directory", prefix)),
+                part => {
+                    self.prefix.push_str(part);
+                    self.prefix.push('/');
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn zone(&self) -> &str {
+        &self.zone
+    }
+
+    /// The zone URL of the mapped directory, like `zone://my-zone/site/`.
+    pub fn url(&self) -> String {
+        format!("zone://{}/{}", self.zone, self.prefix)
+    }
+
+    /// The key of the mapped directory itself, like `/my-zone/site/`.
+    pub fn root(&self) -> String {
+        format!("/{}/{}", self.zone, self.prefix)
+    }
+
+    /// The remote key for a path relative to the local directory. For
+    /// example, `path/to/file.txt` maps to `/my-zone/site/path/to/file.txt`
+    /// when the directory is `zone://my-zone/site/`. Keys always use `/`,
+    /// whatever the local separator.
+    pub fn remote_key(&self, relative_path: &Path) -> String {
+        format!("{}{}", self.root(), slash_path(relative_path))
+    }
+
+    /// The local path for a remote key under the local base directory. For
+    /// example, `/my-zone/site/path/to/file.txt` maps to
+    /// `<local_base>/path/to/file.txt` when the directory is
+    /// `zone://my-zone/site/`.
+    pub fn local_path(&self, local_base: &str, key: &str) -> PathBuf {
+        let root = self.root();
+        local::get_path(local_base, root.trim_matches('/'), key)
+    }
+}
+
+/// A relative path with `/` between its parts, whatever the local
+/// separator.
+pub fn slash_path(relative_path: &Path) -> String {
+    let parts: Vec<_> = relative_path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+    parts.join("/")
+}
+
+/// The name of the directory a sync source brings along, following rsync.
+/// Without a trailing slash, `dist` is synced as a `dist` directory inside
+/// the destination, while `dist/` stands for just its contents. Neither the
+/// zone root nor names like `.` bring a directory along.
+pub fn source_name(source: &str) -> Option<String> {
+    let name = match source.strip_prefix("zone://") {
+        Some(remote) => remote.split_once('/')?.1.rsplit('/').next()?,
+        None => source.rsplit(std::path::is_separator).next()?,
+    };
+    match name {
+        "" | "." | ".." => None,
+        _ if source.starts_with("zone://") => Some(name.to_string()),
+        // Leaves out the likes of a bare Windows drive, `C:`.
+        _ => Path::new(source)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new() {
+        let root = PathMap::new("zone://my-zone/");
+        assert_eq!(root.zone(), "my-zone");
+        assert_eq!(root.root(), "/my-zone/");
+        assert_eq!(PathMap::new("my-zone"), root);
+        assert_eq!(PathMap::new("zone://my-zone"), root);
+
+        let site = PathMap::new("zone://my-zone/site/./en");
+        assert_eq!(site.root(), "/my-zone/site/en/");
+        assert_eq!(site.url(), "zone://my-zone/site/en/");
+    }
+
+    #[test]
+    fn test_with_prefix() {
+        let map = PathMap::new("zone://my-zone/site/")
+            .with_prefix("/v2//docs/")
+            .unwrap();
+        assert_eq!(map.root(), "/my-zone/site/v2/docs/");
+        assert_eq!(
+            PathMap::new("zone://my-zone").with_prefix("").unwrap(),
+            PathMap::new("zone://my-zone/")
+        );
+        assert!(
+            PathMap::new("zone://my-zone")
+                .with_prefix("../other")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_remote_key() {
+        let root = PathMap::new("zone://myzone/");
+        assert_eq!(
+            root.remote_key(Path::new("path/to/file")),
+            "/myzone/path/to/file"
+        );
+        assert_eq!(root.remote_key(Path::new("file")), "/myzone/file");
+        assert_eq!(root.remote_key(Path::new("./a/./b")), "/myzone/a/b");
+
+        let site = PathMap::new("zone://myzone/site")
+            .with_prefix("en")
+            .unwrap();
+        assert_eq!(
+            site.remote_key(Path::new("path/to/file")),
+            "/myzone/site/en/path/to/file"
+        );
+    }
+
+    #[test]
+    fn test_local_path() {
+        let site = PathMap::new("zone://myzone/site/");
+        assert_eq!(
+            site.local_path("/local/base", "/myzone/site/path/to/file"),
+            PathBuf::from("/local/base/path/to/file")
+        );
+        // Mapping there and back gives the same key.
+        let relative = Path::new("dir/file.txt");
+        let key = site.remote_key(relative);
+        assert_eq!(
+            site.local_path("/local/base", &key),
+            Path::new("/local/base").join(relative)
+        );
+    }
+
+    #[test]
+    fn test_source_name() {
+        assert_eq!(source_name("dist"), Some("dist".to_string()));
+        assert_eq!(source_name("./site/dist"), Some("dist".to_string()));
+        assert_eq!(source_name("dist/"), None);
+        assert_eq!(source_name("dist/."), None);
+        assert_eq!(source_name("."), None);
+        assert_eq!(source_name(".."), None);
+        assert_eq!(source_name("/"), None);
+
+        assert_eq!(source_name("zone://my-zone/blog"), Some("blog".to_string()));
+        assert_eq!(
+            source_name("zone://my-zone/site/blog"),
+            Some("blog".to_string())
+        );
+        assert_eq!(source_name("zone://my-zone/blog/"), None);
+        assert_eq!(source_name("zone://my-zone/"), None);
+        assert_eq!(source_name("zone://my-zone"), None);
+    }
+
+    /// Makes up cases for the properties below. It is seeded, so a failing
+    /// case fails again on the next run.
+    struct Cases(u64);
+
+    impl Cases {
+        fn next(&mut self) -> u64 {
+            // xorshift64
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn pick<'a>(&mut self, from: &[&'a str]) -> &'a str {
+            from[self.next() as usize % from.len()]
+        }
+
+        /// Between one and `most` parts.
+        fn parts<'a>(&mut self, from: &[&'a str], most: usize) -> Vec<&'a str> {
+            let count = 1 + self.next() as usize % most;
+            (0..count).map(|_| self.pick(from)).collect()
+        }
+
+        fn map(&mut self) -> PathMap {
+            let map = PathMap::new(self.pick(&["zone://z/", "zone://my-zone/site", "z/a/b/"]));
+            map.with_prefix(self.pick(&["", "en", "v2/docs/"])).unwrap()
+        }
+    }
+
+    const BASE: &str = "/no-such-dir/bunnysync-base";
+    const NAMES: &[&str] = &[
+        "index.html",
+        "a",
+        "with space",
+        "...",
+        ".hidden",
+        "a.b.c",
+        "日本語",
+        "ü",
+        "-",
+        "x~y",
+        "%20",
+        "#",
+    ];
+
+    /// Whether a path is inside a directory once its `..` are resolved.
+    fn stays_inside(path: &Path, dir: &Path) -> bool {
+        let Ok(rest) = path.strip_prefix(dir) else {
+            return false;
+        };
+        let mut depth = 0;
+        rest.components().all(|component| {
+            match component {
+                Component::Normal(_) => depth += 1,
+                Component::CurDir => {}
+                Component::ParentDir => depth -= 1,
+                _ => return false,
+            }
+            depth >= 0
+        })
+    }
+
+    #[test]
+    fn test_round_trip_properties() {
+        let mut cases = Cases(0x5eed);
+        for _ in 0..500 {
+            let map = cases.map();
+            let parts = cases.parts(NAMES, 5);
+            let separator = cases.pick(&["/", std::path::MAIN_SEPARATOR_STR]);
+            let relative = PathBuf::from(parts.join(separator));
+
+            // Keys use `/` whatever separator the local path had.
+            let key = map.remote_key(&relative);
+            assert_eq!(key, format!("{}{}", map.root(), parts.join("/")));
+
+            // Local path to key to local path again is where it started.
+            let local = map.local_path(BASE, &key);
+            assert_eq!(local, Path::new(BASE).join(&relative), "{}", key);
+            let back = local.strip_prefix(BASE).unwrap();
+            assert_eq!(map.remote_key(back), key);
+        }
+    }
+
+    #[test]
+    fn test_no_escape_property() {
+        let mut cases = Cases(0xba5e);
+        let fragments = &[
+            "..", ".", "", "a", "b c", "z", "site", "en", "\\", "..\\..", "C:", "~", "/",
+        ];
+        for _ in 0..1000 {
+            let map = cases.map();
+            let key = format!(
+                "{}{}",
+                cases.pick(&["", "/", "//"]),
+                cases.parts(fragments, 8).join("/")
+            );
+            let local = map.local_path(BASE, &key);
+            assert!(
+                stays_inside(&local, Path::new(BASE)),
+                "{} maps to {}",
+                key,
+                local.display()
+            );
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_source_name_windows() {
+        assert_eq!(source_name(r"site\dist"), Some("dist".to_string()));
+        assert_eq!(source_name(r"site\dist\"), None);
+        assert_eq!(source_name(r"C:\"), None);
+        assert_eq!(source_name("C:"), None);
+    }
+}
@@ -0,0 +1,1249 @@
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, TimeDelta, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    filter::Filter, freeze::FREEZE_FILE, local, local::LocalFile, manifest::MANIFEST_FILE,
+    paths::PathMap, state::SyncState, storage::StorageObject, timestamp::RemoteTime,
+    transform::TransformRule,
+};
+
+/// The contents of a storage zone path. Files and directories are kept apart
+/// so that everything working on the remote side agrees on one
+/// representation. File keys look like `/zone/dir/file.txt` and directory
+/// keys like `/zone/dir/`.
+#[derive(Debug, Default)]
+pub struct RemoteIndex {
+    pub files: HashMap<String, StorageObject>,
+    pub dirs: BTreeSet<String>,
+}
+
+impl RemoteIndex {
+    /// Build an index from a storage listing, skipping files the filter
+    /// leaves out. The manifest describes the zone rather than being part of
+    /// it, so it is left out too.
+    pub fn new(objects: Vec<StorageObject>, filter: &Filter) -> Self {
+        let mut index = RemoteIndex::default();
+        index.extend(objects, filter);
+        index
+    }
+
+    /// Add more of a listing to the index, as for [`RemoteIndex::new`].
+    pub fn extend(&mut self, objects: Vec<StorageObject>, filter: &Filter) {
+        for object in objects {
+            if object.is_directory {
+                self.dirs.insert(directory_key(&object));
+            } else if object.object_name != MANIFEST_FILE && object.object_name != FREEZE_FILE {
+                let key = object_key(&object);
+                if !filter.excludes_key(&key) {
+                    self.files.insert(key, object);
+                }
+            }
+        }
+    }
+
+    /// The total size of all files in the index.
+    pub fn total_bytes(&self) -> u64 {
+        self.files.values().map(|file| file.length).sum()
+    }
+}
+
+/// The key of a file object.
+pub fn object_key(object: &StorageObject) -> String {
+    format!("{}{}", object.path, object.object_name)
+}
+
+/// The key of a directory object. It always ends with a slash.
+pub fn directory_key(object: &StorageObject) -> String {
+    format!("{}{}/", object.path, object.object_name)
+}
+
+/// A single decision the sync made about a file. Lengths are the size of
+/// the file being transferred or deleted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum SyncAction {
+    /// Upload a local file to a remote key.
+    Upload {
+        key: String,
+        path: PathBuf,
+        length: u64,
+    },
+    /// Download a remote key to a local file.
+    Download {
+        key: String,
+        path: PathBuf,
+        length: u64,
+        /// The remote modification time, given to the local copy.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        last_changed: Option<RemoteTime>,
+    },
+    /// Delete a remote file.
+    DeleteRemote { key: String, length: u64 },
+    /// Delete a local file that has no remote counterpart.
+    DeleteLocal {
+        key: String,
+        path: PathBuf,
+        length: u64,
+    },
+    /// Leave a file alone.
+    Skip { key: String, reason: SkipReason },
+}
+
+impl SyncAction {
+    /// The remote key the action is about.
+    pub fn key(&self) -> &str {
+        match self {
+            SyncAction::Upload { key, .. }
+            | SyncAction::Download { key, .. }
+            | SyncAction::DeleteRemote { key, .. }
+            | SyncAction::DeleteLocal { key, .. }
+            | SyncAction::Skip { key, .. } => key,
+        }
+    }
+}
+
+/// Why a file was not transferred.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// Both sides have the same size and the destination is not older.
+    Unchanged,
+    /// The change was turned down at an `--interactive` prompt.
+    Declined,
+    /// Both sides changed since the last bidirectional sync.
+    Conflict,
+    /// The local file changed within `--min-age`, so it may still be being
+    /// written.
+    TooRecent,
+    /// The local file went away after the scan, before it could be
+    /// uploaded.
+    Vanished,
+    /// An earlier run that was cut short already did this, and `--resume`
+    /// carried on from there.
+    AlreadyDone,
+}
+
+/// A plan saved by `sync --dryrun --plan` for `apply` to carry out later.
+#[derive(Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PlanFile {
+    /// Format version, bumped on incompatible changes.
+    pub version: u32,
+    pub source: String,
+    pub destination: String,
+    /// The transforms the upload sizes were worked out with. Applying the
+    /// plan uses the same ones.
+    #[serde(default)]
+    pub transforms: Vec<TransformRule>,
+    pub actions: Vec<SyncAction>,
+}
+
+impl PlanFile {
+    pub const VERSION: u32 = 1;
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)? + "\n")?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Could not read {}: {}", path.to_string_lossy(), e))?;
+        let plan: PlanFile = serde_json::from_str(&text)?;
+        if plan.version != Self::VERSION {
+            return Err(anyhow!(
+                "{} is a version {} plan, this bunnysync reads version {}",
+                path.to_string_lossy(),
+                plan.version,
+                Self::VERSION
+            ));
+        }
+        Ok(plan)
+    }
+}
+
+/// Check if a local file and a remote object hold the same content, going by
+/// size and modification time. The local copy may be up to `modify_window`
+/// newer, to allow for clocks and file systems that don't quite agree.
+fn is_unchanged(
+    local_file: &LocalFile,
+    remote_file: &StorageObject,
+    modify_window: TimeDelta,
+) -> bool {
+    local_file.last_changed <= remote_file.last_changed.utc() + modify_window
+        && local_file.length == remote_file.length
+}
+
+/// Plan pushing local files to the remote. Uploads and skips come first, then
+/// deletes of remote files that are not present locally.
+pub fn plan_to_remote(
+    local_files: &HashMap<String, LocalFile>,
+    remote: &RemoteIndex,
+    delete: bool,
+    modify_window: TimeDelta,
+) -> Vec<SyncAction> {
+    let mut actions: Vec<_> = sorted(local_files)
+        .into_iter()
+        .map(|(key, local_file)| push_file(key, local_file, remote, modify_window))
+        .filter(allowed)
+        .collect();
+    if delete {
+        actions.extend(remote_deletes(local_files, remote));
+    }
+    actions
+}
+
+/// Upload a local file unless the remote already has it.
+fn push_file(
+    key: &str,
+    local_file: &LocalFile,
+    remote: &RemoteIndex,
+    modify_window: TimeDelta,
+) -> SyncAction {
+    if let Some(remote_file) = remote.files.get(key)
+        && is_unchanged(local_file, remote_file, modify_window)
+    {
+        return SyncAction::Skip {
+            key: key.to_string(),
+            reason: SkipReason::Unchanged,
+        };
+    }
+    SyncAction::Upload {
+        key: key.to_string(),
+        path: local_file.path.clone(),
+        length: local_file.length,
+    }
+}
+
+/// Hold back uploads of files modified after `cutoff`, leaving them for a
+/// later sync.
+pub fn hold_back_recent(
+    actions: &mut [SyncAction],
+    local_files: &HashMap<String, LocalFile>,
+    cutoff: DateTime<Utc>,
+) {
+    for action in actions {
+        if let SyncAction::Upload { key, .. } = action
+            && local_files
+                .get(key.as_str())
+                .is_some_and(|file| file.last_changed > cutoff)
+        {
+            *action = SyncAction::Skip {
+                key: std::mem::take(key),
+                reason: SkipReason::TooRecent,
+            };
+        }
+    }
+}
+
+/// Check that an action may be carried out. Uploads of protected files, such
+/// as the config file with the API key, never are, whatever the exclude
+/// patterns say.
+fn allowed(action: &SyncAction) -> bool {
+    !matches!(action, SyncAction::Upload { path, .. } if local::is_protected(path))
+}
+
+/// Delete remote files that are not present locally.
+pub fn remote_deletes(
+    local_files: &HashMap<String, LocalFile>,
+    remote: &RemoteIndex,
+) -> Vec<SyncAction> {
+    sorted(&remote.files)
+        .into_iter()
+        .filter(|(key, _)| !local_files.contains_key(*key))
+        .map(|(key, remote_file)| SyncAction::DeleteRemote {
+            key: key.clone(),
+            length: remote_file.length,
+        })
+        .collect()
+}
+
+/// Plans a push while the zone is still being listed. A local file is
+/// planned as soon as the listing of its directory comes in, or the listing
+/// of a parent shows that its directory isn't in the zone at all, so that
+/// uploads to new directories needn't wait for the rest of the zone.
+pub struct PushPlanner<'a> {
+    /// Local files not yet planned, in key order.
+    waiting: BTreeMap<&'a str, &'a LocalFile>,
+    /// Everything listed so far.
+    pub remote: RemoteIndex,
+    modify_window: TimeDelta,
+}
+
+impl<'a> PushPlanner<'a> {
+    pub fn new(local_files: &'a HashMap<String, LocalFile>, modify_window: TimeDelta) -> Self {
+        PushPlanner {
+            waiting: local_files
+                .iter()
+                .map(|(key, file)| (key.as_str(), file))
+                .collect(),
+            remote: RemoteIndex::default(),
+            modify_window,
+        }
+    }
+
+    /// Take in the listing of one directory, keyed like `/zone/dir/`, and
+    /// plan the local files it settles.
+    pub fn listed(
+        &mut self,
+        dir: &str,
+        objects: Vec<StorageObject>,
+        filter: &Filter,
+    ) -> Vec<SyncAction> {
+        self.remote.extend(objects, filter);
+        let settled: Vec<&str> = self
+            .waiting
+            .range(dir..)
+            .map(|(key, _)| *key)
+            .take_while(|key| key.starts_with(dir))
+            .filter(|key| match key[dir.len()..].split_once('/') {
+                None => true,
+                Some((name, _)) => !self.remote.dirs.contains(&format!("{}{}/", dir, name)),
+            })
+            .collect();
+        settled
+            .into_iter()
+            .map(|key| {
+                let file = self.waiting.remove(key).unwrap();
+                push_file(key, file, &self.remote, self.modify_window)
+            })
+            .filter(allowed)
+            .collect()
+    }
+
+    /// Plan whatever the listings didn't settle, once the whole zone has
+    /// been listed.
+    pub fn finish(mut self) -> (Vec<SyncAction>, RemoteIndex) {
+        let waiting = std::mem::take(&mut self.waiting);
+        let actions = waiting
+            .into_iter()
+            .map(|(key, file)| push_file(key, file, &self.remote, self.modify_window))
+            .filter(allowed)
+            .collect();
+        (actions, self.remote)
+    }
+}
+
+/// Plan pulling remote files into the local directory. Downloads and skips
+/// come first, then deletes of local files that are not present remotely.
+pub fn plan_to_local(
+    local_files: &HashMap<String, LocalFile>,
+    remote: &RemoteIndex,
+    local_base: &str,
+    paths: &PathMap,
+    delete: bool,
+    modify_window: TimeDelta,
+) -> Vec<SyncAction> {
+    let mut actions = Vec::new();
+    for (key, remote_file) in sorted(&remote.files) {
+        if let Some(local_file) = local_files.get(key)
+            && is_unchanged(local_file, remote_file, modify_window)
+        {
+            actions.push(SyncAction::Skip {
+                key: key.clone(),
+                reason: SkipReason::Unchanged,
+            });
+            continue;
+        }
+        actions.push(SyncAction::Download {
+            key: key.clone(),
+            path: paths.local_path(local_base, key),
+            length: remote_file.length,
+            last_changed: Some(remote_file.last_changed),
+        });
+    }
+    if delete {
+        actions.extend(local_deletes(local_files, remote));
+    }
+    actions
+}
+
+/// Delete local files that are not present remotely.
+pub fn local_deletes(
+    local_files: &HashMap<String, LocalFile>,
+    remote: &RemoteIndex,
+) -> Vec<SyncAction> {
+    sorted(local_files)
+        .into_iter()
+        .filter(|(key, _)| !remote.files.contains_key(*key))
+        .map(|(key, local_file)| SyncAction::DeleteLocal {
+            key: key.clone(),
+            path: local_file.path.clone(),
+            length: local_file.length,
+        })
+        .collect()
+}
+
+/// How one side of a bidirectional sync differs from the saved state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Change {
+    /// Same as when last in sync.
+    Unchanged,
+    /// New or modified since the last sync.
+    Changed,
+    /// Deleted since the last sync.
+    Gone,
+    /// Not here now and not here last time.
+    Absent,
+}
+
+impl Change {
+    fn new(present: bool, previously: bool, same: bool) -> Self {
+        match (present, previously) {
+            (true, true) if same => Change::Unchanged,
+            (true, _) => Change::Changed,
+            (false, true) => Change::Gone,
+            (false, false) => Change::Absent,
+        }
+    }
+}
+
+/// What to do with a file that changed on both sides of a bidirectional
+/// sync.
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum ConflictPolicy {
+    /// Keep whichever copy was modified last. A copy that was edited wins
+    /// over a deletion
+    Newer,
+    /// Keep the local copy
+    Local,
+    /// Keep the remote copy
+    Remote,
+    /// Ask which copy to keep
+    Prompt,
+    /// Leave both copies alone and report the conflict
+    #[default]
+    Skip,
+}
+
+/// One side of a bidirectional sync.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Local,
+    Remote,
+}
+
+/// Plan a sync in both directions against the state of the last sync. Each
+/// side's changes are carried to the other side, and a file changed on both
+/// sides is resolved by the conflict policy. Conflicts that aren't resolved,
+/// including every one under [`ConflictPolicy::Prompt`], are skipped.
+/// Transfers and skips come first, then deletes.
+pub fn plan_bidirectional(
+    local_files: &HashMap<String, LocalFile>,
+    remote: &RemoteIndex,
+    state: &SyncState,
+    local_base: &str,
+    paths: &PathMap,
+    conflict: ConflictPolicy,
+    modify_window: TimeDelta,
+) -> Vec<SyncAction> {
+    let keys: BTreeSet<&String> = local_files
+        .keys()
+        .chain(remote.files.keys())
+        .chain(state.files.keys())
+        .collect();
+    let mut actions = Vec::new();
+    let mut deletes = Vec::new();
+    for key in keys {
+        let previous = state.files.get(key);
+        let local_file = local_files.get(key);
+        let remote_file = remote.files.get(key);
+        let local_change = Change::new(
+            local_file.is_some(),
+            previous.is_some(),
+            matches!((local_file, previous), (Some(file), Some(previous))
+                if file.length == previous.local_length
+                    && file.last_changed == previous.local_changed),
+        );
+        let remote_change = Change::new(
+            remote_file.is_some(),
+            previous.is_some(),
+            matches!((remote_file, previous), (Some(file), Some(previous))
+                if file.length == previous.remote_length
+                    && file.last_changed == previous.remote_changed),
+        );
+        let skip = |reason| SyncAction::Skip {
+            key: key.clone(),
+            reason,
+        };
+        let side = match conflict {
+            ConflictPolicy::Newer => newer_side(local_file, remote_file),
+            ConflictPolicy::Local => Some(Side::Local),
+            ConflictPolicy::Remote => Some(Side::Remote),
+            ConflictPolicy::Prompt | ConflictPolicy::Skip => None,
+        };
+        let mut resolve = |actions: &mut Vec<SyncAction>| match side {
+            Some(side) => {
+                let action = resolve_conflict(key, side, local_files, remote, local_base, paths);
+                match action {
+                    SyncAction::DeleteRemote { .. } | SyncAction::DeleteLocal { .. } => {
+                        deletes.push(action)
+                    }
+                    action => actions.push(action),
+                }
+            }
+            None => actions.push(skip(SkipReason::Conflict)),
+        };
+        match (local_change, remote_change) {
+            (Change::Unchanged, Change::Unchanged) => actions.push(skip(SkipReason::Unchanged)),
+            (Change::Changed, Change::Unchanged | Change::Absent) => {
+                let local_file = local_file.unwrap();
+                actions.push(SyncAction::Upload {
+                    key: key.clone(),
+                    path: local_file.path.clone(),
+                    length: local_file.length,
+                });
+            }
+            (Change::Unchanged | Change::Absent, Change::Changed) => {
+                actions.push(SyncAction::Download {
+                    key: key.clone(),
+                    path: paths.local_path(local_base, key),
+                    length: remote_file.unwrap().length,
+                    last_changed: Some(remote_file.unwrap().last_changed),
+                });
+            }
+            (Change::Gone, Change::Unchanged) => deletes.push(SyncAction::DeleteRemote {
+                key: key.clone(),
+                length: remote_file.unwrap().length,
+            }),
+            (Change::Unchanged, Change::Gone) => {
+                let local_file = local_file.unwrap();
+                deletes.push(SyncAction::DeleteLocal {
+                    key: key.clone(),
+                    path: local_file.path.clone(),
+                    length: local_file.length,
+                });
+            }
+            (Change::Changed, Change::Changed) => {
+                // Two copies that match need no resolving, as on the first
+                // sync of a directory that was already deployed.
+                if is_unchanged(local_file.unwrap(), remote_file.unwrap(), modify_window) {
+                    actions.push(skip(SkipReason::Unchanged));
+                } else {
+                    resolve(&mut actions);
+                }
+            }
+            (Change::Changed, Change::Gone) | (Change::Gone, Change::Changed) => {
+                resolve(&mut actions)
+            }
+            // Gone from both sides, or never on either.
+            _ => {}
+        }
+    }
+    actions.retain(allowed);
+    actions.extend(deletes);
+    actions
+}
+
+/// The action that settles a conflict in favour of one side. The kept side's
+/// copy is carried over, or its deletion if it has none.
+pub fn resolve_conflict(
+    key: &str,
+    side: Side,
+    local_files: &HashMap<String, LocalFile>,
+    remote: &RemoteIndex,
+    local_base: &str,
+    paths: &PathMap,
+) -> SyncAction {
+    let key = key.to_string();
+    match (side, local_files.get(&key), remote.files.get(&key)) {
+        (Side::Local, Some(local_file), _) => SyncAction::Upload {
+            key,
+            path: local_file.path.clone(),
+            length: local_file.length,
+        },
+        (Side::Local, None, remote_file) => SyncAction::DeleteRemote {
+            key,
+            length: remote_file.map_or(0, |file| file.length),
+        },
+        (Side::Remote, _, Some(remote_file)) => SyncAction::Download {
+            path: paths.local_path(local_base, &key),
+            key,
+            length: remote_file.length,
+            last_changed: Some(remote_file.last_changed),
+        },
+        (Side::Remote, local_file, None) => SyncAction::DeleteLocal {
+            path: local_file.map_or_else(
+                || paths.local_path(local_base, &key),
+                |file| file.path.clone(),
+            ),
+            key,
+            length: local_file.map_or(0, |file| file.length),
+        },
+    }
+}
+
+/// The side modified last. A deletion has no time, so a copy that is still
+/// there wins over one that is gone. Copies changed at the same moment are
+/// left to be resolved some other way.
+fn newer_side(local_file: Option<&LocalFile>, remote_file: Option<&StorageObject>) -> Option<Side> {
+    match (local_file, remote_file) {
+        (Some(local_file), Some(remote_file)) => {
+            match (local_file.last_changed).cmp(&remote_file.last_changed.utc()) {
+                std::cmp::Ordering::Greater => Some(Side::Local),
+                std::cmp::Ordering::Less => Some(Side::Remote),
+                std::cmp::Ordering::Equal => None,
+            }
+        }
+        (Some(_), None) => Some(Side::Local),
+        (None, Some(_)) => Some(Side::Remote),
+        (None, None) => None,
+    }
+}
+
+/// Reorder transfers so those matching a `first` pattern go before the rest
+/// and those matching a `last` pattern after, keeping the planned order
+/// otherwise. Patterns match the file name or the path within the zone.
+/// Deletes stay at the end.
+pub fn prioritize(actions: &mut [SyncAction], first: &[String], last: &[String]) {
+    let matches = |patterns: &[String], key: &str| {
+        let path = key
+            .trim_start_matches('/')
+            .split_once('/')
+            .map_or(key, |(_, path)| path);
+        let name = path.rsplit('/').next().unwrap_or(path);
+        patterns.iter().any(|pattern| {
+            glob_match::glob_match(pattern, path) || glob_match::glob_match(pattern, name)
+        })
+    };
+    actions.sort_by_key(|action| match action {
+        SyncAction::DeleteRemote { .. } | SyncAction::DeleteLocal { .. } => 3,
+        action if matches(first, action.key()) => 0,
+        action if matches(last, action.key()) => 2,
+        _ => 1,
+    });
+}
+
+/// Iterate a map in key order so plans are deterministic.
+fn sorted<V>(map: &HashMap<String, V>) -> Vec<(&String, &V)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by_key(|(key, _)| *key);
+    entries
+}
+
+#[cfg(test)]
+mod golden;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::FileState;
+    use chrono::DateTime;
+
+    fn time(secs: i64) -> RemoteTime {
+        RemoteTime::from_utc(DateTime::from_timestamp(secs, 0).unwrap())
+    }
+
+    fn remote_object(path: &str, name: &str, length: u64, changed: i64) -> StorageObject {
+        StorageObject {
+            storage_zone_name: "myzone".to_string(),
+            path: path.to_string(),
+            object_name: name.to_string(),
+            length,
+            last_changed: time(changed),
+            is_directory: false,
+            date_created: time(0),
+            ..Default::default()
+        }
+    }
+
+    fn remote_dir(path: &str, name: &str) -> StorageObject {
+        StorageObject {
+            is_directory: true,
+            ..remote_object(path, name, 0, 0)
+        }
+    }
+
+    fn local_file(relative: &str, length: u64, changed: i64) -> (String, LocalFile) {
+        let file = LocalFile {
+            relative_path: relative.into(),
+            path: PathBuf::from("/local").join(relative),
+            is_directory: false,
+            last_changed: time(changed).utc(),
+            length,
+        };
+        (PathMap::new("myzone").remote_key(&file.relative_path), file)
+    }
+
+    #[test]
+    fn test_remote_index_separates_directories() {
+        let index = RemoteIndex::new(
+            vec![
+                remote_object("/myzone/", "index.html", 10, 0),
+                remote_dir("/myzone/", "css"),
+                remote_object("/myzone/css/", "site.css", 5, 0),
+                remote_object("/myzone/", "notes.tmp", 1, 0),
+                remote_object("/myzone/", MANIFEST_FILE, 1, 0),
+                remote_object("/myzone/", FREEZE_FILE, 1, 0),
+            ],
+            &Filter::new(
+                vec!["*.tmp".to_string()],
+                Default::default(),
+                "/myzone/".to_string(),
+            ),
+        );
+        let mut files: Vec<_> = index.files.keys().cloned().collect();
+        files.sort();
+        assert_eq!(files, vec!["/myzone/css/site.css", "/myzone/index.html"]);
+        assert_eq!(
+            index.dirs.into_iter().collect::<Vec<_>>(),
+            vec!["/myzone/css/"]
+        );
+    }
+
+    #[test]
+    fn test_remote_index_total_bytes() {
+        let index = RemoteIndex::new(
+            vec![
+                remote_object("/myzone/", "a", 10, 0),
+                remote_object("/myzone/", "b", 5, 0),
+                remote_dir("/myzone/", "c"),
+            ],
+            &Filter::default(),
+        );
+        assert_eq!(index.total_bytes(), 15);
+    }
+
+    #[test]
+    fn test_plan_to_remote() {
+        let local_files = HashMap::from([
+            local_file("new.html", 10, 100),
+            local_file("same.html", 10, 100),
+            local_file("resized.html", 11, 100),
+            local_file("newer.html", 10, 300),
+        ]);
+        let remote = RemoteIndex::new(
+            vec![
+                remote_object("/myzone/", "same.html", 10, 200),
+                remote_object("/myzone/", "resized.html", 10, 200),
+                remote_object("/myzone/", "newer.html", 10, 200),
+                remote_object("/myzone/", "gone.html", 10, 200),
+                remote_dir("/myzone/", "empty"),
+            ],
+            &Filter::default(),
+        );
+
+        let upload = |name: &str, length| SyncAction::Upload {
+            key: format!("/myzone/{}", name),
+            path: PathBuf::from("/local").join(name),
+            length,
+        };
+        let uploads = vec![
+            upload("new.html", 10),
+            upload("newer.html", 10),
+            upload("resized.html", 11),
+            SyncAction::Skip {
+                key: "/myzone/same.html".to_string(),
+                reason: SkipReason::Unchanged,
+            },
+        ];
+        assert_eq!(
+            plan_to_remote(&local_files, &remote, false, TimeDelta::zero()),
+            uploads
+        );
+
+        // Within the window the newer local copy counts as unchanged.
+        let windowed = plan_to_remote(&local_files, &remote, false, TimeDelta::seconds(100));
+        assert_eq!(
+            windowed[1],
+            SyncAction::Skip {
+                key: "/myzone/newer.html".to_string(),
+                reason: SkipReason::Unchanged,
+            }
+        );
+
+        let mut with_deletes = uploads;
+        with_deletes.push(SyncAction::DeleteRemote {
+            key: "/myzone/gone.html".to_string(),
+            length: 10,
+        });
+        assert_eq!(
+            plan_to_remote(&local_files, &remote, true, TimeDelta::zero()),
+            with_deletes
+        );
+    }
+
+    #[test]
+    fn test_never_pushes_secrets() {
+        let dir = std::env::temp_dir()
+            .canonicalize()
+            .unwrap()
+            .join(format!("bunnysync-secrets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let key_file = dir.join("deploy.key");
+        std::fs::write(&key_file, "secret").unwrap();
+        local::protect(&key_file);
+
+        let secret = |relative: &str, path: PathBuf| {
+            let (key, file) = local_file(relative, 6, 100);
+            (key, LocalFile { path, ..file })
+        };
+        let local_files = HashMap::from([
+            local_file("index.html", 10, 100),
+            secret("deploy.key", key_file.clone()),
+            secret("sub/.bunnysync", PathBuf::from("/local/sub/.bunnysync")),
+        ]);
+        let uploaded = |actions: &[SyncAction]| -> Vec<String> {
+            actions
+                .iter()
+                .filter(|action| matches!(action, SyncAction::Upload { .. }))
+                .map(|action| action.key().to_string())
+                .collect()
+        };
+
+        // Not even a stale copy in the zone gets the secret uploaded.
+        let remote = RemoteIndex::new(
+            vec![remote_object("/myzone/", "deploy.key", 1, 0)],
+            &Filter::default(),
+        );
+        let pushed = plan_to_remote(&local_files, &remote, true, TimeDelta::zero());
+        assert_eq!(uploaded(&pushed), vec!["/myzone/index.html"]);
+
+        let mut planner = PushPlanner::new(&local_files, TimeDelta::zero());
+        let mut pipelined = planner.listed("/myzone/", vec![], &Filter::default());
+        pipelined.extend(planner.finish().0);
+        assert_eq!(uploaded(&pipelined), vec!["/myzone/index.html"]);
+
+        let both = plan_bidirectional(
+            &local_files,
+            &RemoteIndex::default(),
+            &SyncState::default(),
+            "/local",
+            &PathMap::new("myzone"),
+            ConflictPolicy::Local,
+            TimeDelta::zero(),
+        );
+        assert_eq!(uploaded(&both), vec!["/myzone/index.html"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_hold_back_recent() {
+        let local_files = HashMap::from([
+            local_file("done.mp4", 10, 100),
+            local_file("encoding.mp4", 10, 200),
+        ]);
+        let mut actions = plan_to_remote(
+            &local_files,
+            &RemoteIndex::default(),
+            false,
+            TimeDelta::zero(),
+        );
+        hold_back_recent(&mut actions, &local_files, time(150).utc());
+        assert_eq!(
+            actions,
+            vec![
+                SyncAction::Upload {
+                    key: "/myzone/done.mp4".to_string(),
+                    path: PathBuf::from("/local/done.mp4"),
+                    length: 10,
+                },
+                SyncAction::Skip {
+                    key: "/myzone/encoding.mp4".to_string(),
+                    reason: SkipReason::TooRecent,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_push_planner() {
+        let local_files = HashMap::from([
+            local_file("index.html", 10, 100),
+            local_file("css/site.css", 10, 100),
+            local_file("new/deep/page.html", 10, 100),
+        ]);
+        let mut planner = PushPlanner::new(&local_files, TimeDelta::zero());
+        let keys = |actions: Vec<SyncAction>| -> Vec<String> {
+            actions
+                .iter()
+                .map(|action| action.key().to_string())
+                .collect()
+        };
+
+        // The top level settles its own files and everything under
+        // directories the zone doesn't have, but not css/ which it does.
+        let top = planner.listed(
+            "/myzone/",
+            vec![
+                remote_object("/myzone/", "index.html", 10, 200),
+                remote_dir("/myzone/", "css"),
+            ],
+            &Filter::default(),
+        );
+        assert_eq!(
+            top,
+            vec![
+                SyncAction::Skip {
+                    key: "/myzone/index.html".to_string(),
+                    reason: SkipReason::Unchanged,
+                },
+                SyncAction::Upload {
+                    key: "/myzone/new/deep/page.html".to_string(),
+                    path: PathBuf::from("/local/new/deep/page.html"),
+                    length: 10,
+                },
+            ]
+        );
+        let css = planner.listed(
+            "/myzone/css/",
+            vec![remote_object("/myzone/css/", "old.css", 10, 200)],
+            &Filter::default(),
+        );
+        assert_eq!(keys(css), vec!["/myzone/css/site.css"]);
+        let (rest, remote) = planner.finish();
+        assert!(rest.is_empty());
+        assert_eq!(
+            keys(remote_deletes(&local_files, &remote)),
+            vec!["/myzone/css/old.css"]
+        );
+    }
+
+    #[test]
+    fn test_plan_bidirectional() {
+        let local_files = HashMap::from([
+            local_file("same.html", 10, 100),
+            local_file("local_edit.html", 11, 300),
+            local_file("remote_edit.html", 10, 100),
+            local_file("remote_deleted.html", 10, 100),
+            local_file("both.html", 12, 300),
+            local_file("new_local.html", 10, 300),
+        ]);
+        let remote = RemoteIndex::new(
+            vec![
+                remote_object("/myzone/", "same.html", 10, 200),
+                remote_object("/myzone/", "local_edit.html", 10, 200),
+                remote_object("/myzone/", "remote_edit.html", 13, 400),
+                remote_object("/myzone/", "local_deleted.html", 10, 200),
+                remote_object("/myzone/", "both.html", 13, 400),
+                remote_object("/myzone/", "new_remote.html", 10, 400),
+            ],
+            &Filter::default(),
+        );
+        let synced = FileState {
+            local_length: 10,
+            local_changed: time(100).utc(),
+            remote_length: 10,
+            remote_changed: time(200),
+        };
+        let mut state = SyncState::default();
+        for name in [
+            "same.html",
+            "local_edit.html",
+            "remote_edit.html",
+            "local_deleted.html",
+            "remote_deleted.html",
+            "both.html",
+            "gone.html",
+        ] {
+            state
+                .files
+                .insert(format!("/myzone/{}", name), synced.clone());
+        }
+
+        let key = |name: &str| format!("/myzone/{}", name);
+        let path = |name: &str| PathBuf::from("/local").join(name);
+        assert_eq!(
+            plan_bidirectional(
+                &local_files,
+                &remote,
+                &state,
+                "/local",
+                &PathMap::new("myzone"),
+                ConflictPolicy::Skip,
+                TimeDelta::zero()
+            ),
+            vec![
+                SyncAction::Skip {
+                    key: key("both.html"),
+                    reason: SkipReason::Conflict,
+                },
+                SyncAction::Upload {
+                    key: key("local_edit.html"),
+                    path: path("local_edit.html"),
+                    length: 11,
+                },
+                SyncAction::Upload {
+                    key: key("new_local.html"),
+                    path: path("new_local.html"),
+                    length: 10,
+                },
+                SyncAction::Download {
+                    key: key("new_remote.html"),
+                    path: path("new_remote.html"),
+                    length: 10,
+                    last_changed: Some(time(400)),
+                },
+                SyncAction::Download {
+                    key: key("remote_edit.html"),
+                    path: path("remote_edit.html"),
+                    length: 13,
+                    last_changed: Some(time(400)),
+                },
+                SyncAction::Skip {
+                    key: key("same.html"),
+                    reason: SkipReason::Unchanged,
+                },
+                SyncAction::DeleteRemote {
+                    key: key("local_deleted.html"),
+                    length: 10,
+                },
+                SyncAction::DeleteLocal {
+                    key: key("remote_deleted.html"),
+                    path: path("remote_deleted.html"),
+                    length: 10,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_bidirectional_without_state() {
+        // Matching copies on a first sync are not conflicts.
+        let local_files = HashMap::from([local_file("a.html", 10, 100)]);
+        let remote = RemoteIndex::new(
+            vec![remote_object("/myzone/", "a.html", 10, 200)],
+            &Filter::default(),
+        );
+        assert_eq!(
+            plan_bidirectional(
+                &local_files,
+                &remote,
+                &SyncState::default(),
+                "/local",
+                &PathMap::new("myzone"),
+                ConflictPolicy::Skip,
+                TimeDelta::zero()
+            ),
+            vec![SyncAction::Skip {
+                key: "/myzone/a.html".to_string(),
+                reason: SkipReason::Unchanged,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_plan_bidirectional_conflicts() {
+        let local_files = HashMap::from([
+            local_file("local_newer.html", 11, 500),
+            local_file("remote_newer.html", 11, 300),
+            local_file("remote_deleted.html", 11, 300),
+        ]);
+        let remote = RemoteIndex::new(
+            vec![
+                remote_object("/myzone/", "local_newer.html", 12, 400),
+                remote_object("/myzone/", "remote_newer.html", 12, 400),
+                remote_object("/myzone/", "local_deleted.html", 12, 400),
+            ],
+            &Filter::default(),
+        );
+        let synced = FileState {
+            local_length: 10,
+            local_changed: time(100).utc(),
+            remote_length: 10,
+            remote_changed: time(200),
+        };
+        let mut state = SyncState::default();
+        for name in [
+            "local_newer.html",
+            "remote_newer.html",
+            "local_deleted.html",
+            "remote_deleted.html",
+        ] {
+            state
+                .files
+                .insert(format!("/myzone/{}", name), synced.clone());
+        }
+        let plan = |conflict| {
+            plan_bidirectional(
+                &local_files,
+                &remote,
+                &state,
+                "/local",
+                &PathMap::new("myzone"),
+                conflict,
+                TimeDelta::zero(),
+            )
+        };
+        let key = |name: &str| format!("/myzone/{}", name);
+        let path = |name: &str| PathBuf::from("/local").join(name);
+        let upload = |name: &str| SyncAction::Upload {
+            key: key(name),
+            path: path(name),
+            length: 11,
+        };
+        let download = |name: &str| SyncAction::Download {
+            key: key(name),
+            path: path(name),
+            length: 12,
+            last_changed: Some(time(400)),
+        };
+
+        assert_eq!(
+            plan(ConflictPolicy::Newer),
+            vec![
+                download("local_deleted.html"),
+                upload("local_newer.html"),
+                upload("remote_deleted.html"),
+                download("remote_newer.html"),
+            ]
+        );
+        assert_eq!(
+            plan(ConflictPolicy::Local),
+            vec![
+                upload("local_newer.html"),
+                upload("remote_deleted.html"),
+                upload("remote_newer.html"),
+                SyncAction::DeleteRemote {
+                    key: key("local_deleted.html"),
+                    length: 12,
+                },
+            ]
+        );
+        assert_eq!(
+            plan(ConflictPolicy::Remote),
+            vec![
+                download("local_deleted.html"),
+                download("local_newer.html"),
+                download("remote_newer.html"),
+                SyncAction::DeleteLocal {
+                    key: key("remote_deleted.html"),
+                    path: path("remote_deleted.html"),
+                    length: 11,
+                },
+            ]
+        );
+        for conflict in [ConflictPolicy::Prompt, ConflictPolicy::Skip] {
+            assert!(plan(conflict).iter().all(|action| matches!(
+                action,
+                SyncAction::Skip {
+                    reason: SkipReason::Conflict,
+                    ..
+                }
+            )));
+        }
+    }
+
+    #[test]
+    fn test_prioritize() {
+        let upload = |key: &str| SyncAction::Upload {
+            key: key.to_string(),
+            path: PathBuf::from(key),
+            length: 1,
+        };
+        let mut actions = vec![
+            upload("/myzone/blog/index.html"),
+            upload("/myzone/css/site.css"),
+            SyncAction::DeleteRemote {
+                key: "/myzone/old.css".to_string(),
+                length: 1,
+            },
+            upload("/myzone/index.html"),
+            upload("/myzone/img/logo.png"),
+            upload("/myzone/sw.js"),
+        ];
+        prioritize(
+            &mut actions,
+            &["css/*".to_string(), "*.png".to_string()],
+            &["index.html".to_string(), "sw.js".to_string()],
+        );
+        let keys: Vec<_> = actions.iter().map(SyncAction::key).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "/myzone/css/site.css",
+                "/myzone/img/logo.png",
+                "/myzone/blog/index.html",
+                "/myzone/index.html",
+                "/myzone/sw.js",
+                "/myzone/old.css",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_file_round_trip() {
+        let plan = PlanFile {
+            version: PlanFile::VERSION,
+            source: "site".to_string(),
+            destination: "zone://myzone/".to_string(),
+            transforms: vec![],
+            actions: vec![
+                SyncAction::Upload {
+                    key: "/myzone/a.html".to_string(),
+                    path: PathBuf::from("site/a.html"),
+                    length: 3,
+                },
+                SyncAction::DeleteRemote {
+                    key: "/myzone/b.html".to_string(),
+                    length: 4,
+                },
+            ],
+        };
+        let path = std::env::temp_dir().join(format!("bunnysync-plan-{}.json", std::process::id()));
+        plan.save(&path).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.contains("\"action\": \"delete_remote\""));
+        assert_eq!(PlanFile::load(&path).unwrap(), plan);
+
+        std::fs::write(&path, text.replace("\"version\": 1", "\"version\": 99")).unwrap();
+        assert!(PlanFile::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_plan_to_local() {
+        let local_files = HashMap::from([
+            local_file("same.html", 10, 300),
+            local_file("stale.html", 9, 100),
+            local_file("extra.html", 10, 100),
+        ]);
+        let remote = RemoteIndex::new(
+            vec![
+                remote_object("/myzone/", "same.html", 10, 300),
+                remote_object("/myzone/", "stale.html", 10, 200),
+                remote_object("/myzone/sub/", "new.html", 10, 200),
+            ],
+            &Filter::default(),
+        );
+
+        assert_eq!(
+            plan_to_local(
+                &local_files,
+                &remote,
+                "/local",
+                &PathMap::new("myzone"),
+                true,
+                TimeDelta::zero()
+            ),
+            vec![
+                SyncAction::Skip {
+                    key: "/myzone/same.html".to_string(),
+                    reason: SkipReason::Unchanged,
+                },
+                SyncAction::Download {
+                    key: "/myzone/stale.html".to_string(),
+                    path: PathBuf::from("/local/stale.html"),
+                    length: 10,
+                    last_changed: Some(time(200)),
+                },
+                SyncAction::Download {
+                    key: "/myzone/sub/new.html".to_string(),
+                    path: PathBuf::from("/local/sub/new.html"),
+                    length: 10,
+                    last_changed: Some(time(200)),
+                },
+                SyncAction::DeleteLocal {
+                    key: "/myzone/extra.html".to_string(),
+                    path: PathBuf::from("/local/extra.html"),
+                    length: 10,
+                },
+            ]
+        );
+    }
+}
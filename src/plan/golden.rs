@@ -0,0 +1,138 @@
+//! Plans for made up local directories and zones, checked against the plans
+//! in `testdata/plans`. Each scenario is a `<name>.json` describing both
+//! sides, and `<name>.golden.json` holds the actions planned for it, so a
+//! change to how syncs are planned shows up as a diff of those files.
+//! Rewrite them after a deliberate change with
+//! `BUNNYSYNC_UPDATE_GOLDEN=1 cargo test`.
+
+use chrono::{DateTime, TimeDelta};
+use serde::Deserialize;
+use std::{collections::HashMap, path::PathBuf};
+
+use super::*;
+use crate::filter::IgnoreRules;
+
+/// Where the scenarios are synced between.
+const LOCAL: &str = "site";
+const REMOTE: &str = "zone://myzone/site/";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Direction {
+    Push,
+    Pull,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Scenario {
+    direction: Direction,
+    #[serde(default)]
+    delete: bool,
+    /// In seconds, as for `--modify-window`.
+    #[serde(default)]
+    modify_window: i64,
+    #[serde(default)]
+    exclude: Vec<String>,
+    #[serde(default)]
+    local: Vec<File>,
+    #[serde(default)]
+    remote: Vec<File>,
+}
+
+/// A file on either side, by its path relative to the synced directory.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    path: String,
+    length: u64,
+    /// When it last changed, in seconds since the epoch.
+    changed: i64,
+}
+
+impl Scenario {
+    fn plan(&self) -> Vec<SyncAction> {
+        let paths = PathMap::new(REMOTE);
+        let filter = Filter::new(self.exclude.clone(), IgnoreRules::default(), paths.root());
+        let changed = |file: &File| DateTime::from_timestamp(file.changed, 0).unwrap();
+        let local_files: HashMap<String, LocalFile> = self
+            .local
+            .iter()
+            .map(|file| {
+                let local_file = LocalFile {
+                    relative_path: PathBuf::from(&file.path),
+                    path: PathBuf::from(LOCAL).join(&file.path),
+                    is_directory: false,
+                    last_changed: changed(file),
+                    length: file.length,
+                };
+                (paths.remote_key(&local_file.relative_path), local_file)
+            })
+            .filter(|(key, _)| !filter.excludes_key(key))
+            .collect();
+        let objects = self
+            .remote
+            .iter()
+            .map(|file| {
+                let key = paths.remote_key(Path::new(&file.path));
+                let (dir, name) = key.rsplit_once('/').unwrap();
+                StorageObject {
+                    storage_zone_name: paths.zone().to_string(),
+                    path: format!("{}/", dir),
+                    object_name: name.to_string(),
+                    length: file.length,
+                    last_changed: RemoteTime::from_utc(changed(file)),
+                    ..Default::default()
+                }
+            })
+            .collect();
+        let remote = RemoteIndex::new(objects, &filter);
+        let modify_window = TimeDelta::seconds(self.modify_window);
+        match self.direction {
+            Direction::Push => plan_to_remote(&local_files, &remote, self.delete, modify_window),
+            Direction::Pull => plan_to_local(
+                &local_files,
+                &remote,
+                LOCAL,
+                &paths,
+                self.delete,
+                modify_window,
+            ),
+        }
+    }
+}
+
+#[test]
+fn test_golden_plans() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/plans");
+    let update = std::env::var_os("BUNNYSYNC_UPDATE_GOLDEN").is_some();
+    let mut scenarios: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+                && !path.to_string_lossy().ends_with(".golden.json")
+        })
+        .collect();
+    scenarios.sort();
+    assert!(!scenarios.is_empty(), "no scenarios in {}", dir.display());
+    for path in scenarios {
+        let scenario: Scenario =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let planned = serde_json::to_string_pretty(&scenario.plan()).unwrap() + "\n";
+        let golden = path.with_extension("golden.json");
+        if update {
+            std::fs::write(&golden, &planned).unwrap();
+            continue;
+        }
+        let expected = std::fs::read_to_string(&golden).unwrap_or_default();
+        assert_eq!(
+            planned,
+            expected,
+            "{} doesn't match {}",
+            path.display(),
+            golden.display()
+        );
+    }
+}
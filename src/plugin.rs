@@ -0,0 +1,255 @@
+//! Plugins are executables named `bunnysync-<NAME>` on the PATH, so niche
+//! integrations can live outside bunnysync. A plugin is run with the name of
+//! a hook as its argument and a JSON object on stdin holding the protocol
+//! `version`, the `hook` and what the hook is about:
+//!
+//! - `plan`: the `source`, `destination` and `actions` of a sync before
+//!   anything is transferred. The plugin may print `{"actions": [...]}` to
+//!   change the plan, or nothing to leave it.
+//! - `transform`: the base64 `data` of a file about to be uploaded. The
+//!   plugin prints `{"data": "..."}` with the contents to upload instead.
+//! - `finished`: the record of a sync, as kept in the history. Anything
+//!   printed is ignored.
+//!
+//! A plugin that exits with an error fails the sync, except at `finished`.
+
+use anyhow::{Result, anyhow};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use crate::{
+    history::RunRecord,
+    i18n::{Message, tr},
+    plan::SyncAction,
+    transform::Transform,
+};
+
+/// Bumped on incompatible changes to what plugins are sent or print.
+pub const VERSION: u32 = 1;
+
+/// The plugins to run at each hook, by name, as set in the `[plugins]`
+/// table of the config file. Transform plugins are named in `[[transform]]`
+/// like the built in transforms.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Plugins {
+    #[serde(default)]
+    pub plan: Vec<String>,
+    #[serde(default)]
+    pub finished: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Request<'a, T> {
+    version: u32,
+    hook: &'a str,
+    #[serde(flatten)]
+    body: T,
+}
+
+#[derive(Serialize)]
+struct PlanRequest<'a> {
+    source: &'a str,
+    destination: &'a str,
+    actions: &'a [SyncAction],
+}
+
+#[derive(Deserialize)]
+struct PlanReply {
+    actions: Vec<SyncAction>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Data {
+    data: String,
+}
+
+/// Find a plugin's executable on the PATH.
+pub fn find(name: &str) -> Option<PathBuf> {
+    let file = format!("bunnysync-{}{}", name, std::env::consts::EXE_SUFFIX);
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(&file))
+        .find(|path| path.is_file())
+}
+
+/// Run a plugin at a hook, returning what it printed.
+fn call(name: &str, hook: &str, body: impl Serialize) -> Result<Vec<u8>> {
+    let path = find(name).ok_or_else(|| anyhow!(tr(Message::NoSuchPlugin { name })))?;
+    run(&path, name, hook, body)
+}
+
+fn run(path: &Path, name: &str, hook: &str, body: impl Serialize) -> Result<Vec<u8>> {
+    let request = serde_json::to_vec(&Request {
+        version: VERSION,
+        hook,
+        body,
+    })?;
+    let mut child = Command::new(path)
+        .arg(hook)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    // A plugin may not read all of its input, which is no reason to fail.
+    let _ = child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(&request);
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Plugin {} failed at {}: {}",
+            name,
+            hook,
+            output.status
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// Parse what a plugin printed, where printing nothing means no reply.
+fn reply<T: DeserializeOwned>(name: &str, output: &[u8]) -> Result<Option<T>> {
+    if output.iter().all(u8::is_ascii_whitespace) {
+        return Ok(None);
+    }
+    serde_json::from_slice(output)
+        .map(Some)
+        .map_err(|e| anyhow!("Plugin {} printed a bad reply: {}", name, e))
+}
+
+/// Let each plan plugin change the plan of a sync, in the order they are
+/// configured.
+pub fn plan(
+    plugins: &[String],
+    source: &str,
+    destination: &str,
+    actions: &mut Vec<SyncAction>,
+) -> Result<()> {
+    for name in plugins {
+        let request = PlanRequest {
+            source,
+            destination,
+            actions,
+        };
+        let output = call(name, "plan", request)?;
+        if let Some(PlanReply { actions: changed }) = reply(name, &output)? {
+            *actions = changed;
+        }
+    }
+    Ok(())
+}
+
+/// Tell each finished plugin how a sync went. Failures are warnings, as the
+/// sync is over by then.
+pub fn finished(plugins: &[String], record: &RunRecord) {
+    for name in plugins {
+        if let Err(e) = call(name, "finished", record) {
+            eprintln!("{}", tr(Message::Warning { warning: &e }));
+        }
+    }
+}
+
+/// A transform done by a plugin.
+pub struct PluginTransform {
+    name: String,
+}
+
+impl PluginTransform {
+    /// The transform plugin with a name, if it is on the PATH.
+    pub fn find(name: &str) -> Option<Self> {
+        find(name).map(|_| PluginTransform {
+            name: name.to_string(),
+        })
+    }
+}
+
+impl Transform for PluginTransform {
+    fn apply(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        let request = Data {
+            data: STANDARD.encode(data),
+        };
+        let output = call(&self.name, "transform", request)?;
+        let Some(Data { data }) = reply(&self.name, &output)? else {
+            return Err(anyhow!("Plugin {} printed no data", self.name));
+        };
+        Ok(STANDARD.decode(data)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request() {
+        let actions = [SyncAction::DeleteRemote {
+            key: "/zone/old.html".to_string(),
+            length: 10,
+        }];
+        let request = Request {
+            version: VERSION,
+            hook: "plan",
+            body: PlanRequest {
+                source: "./site/",
+                destination: "zone://zone/",
+                actions: &actions,
+            },
+        };
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["version"], 1);
+        assert_eq!(json["hook"], "plan");
+        assert_eq!(json["source"], "./site/");
+        assert_eq!(json["actions"].as_array().unwrap().len(), 1);
+
+        let changed: Option<PlanReply> = reply("test", br#"{"actions": []}"#).unwrap();
+        assert!(changed.unwrap().actions.is_empty());
+        assert!(reply::<PlanReply>("test", b" \n").unwrap().is_none());
+        assert!(reply::<PlanReply>("test", b"oops").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("bunnysync-plugin-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("bunnysync-upper");
+        // Replies with the hook it was run at, shaped like a transform's
+        // reply.
+        let script = "#!/bin/sh\nread request\nprintf '{\"data\": \"%s\"}' \"$1\"\n";
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let output = run(
+            &path,
+            "upper",
+            "transform",
+            Data {
+                data: String::new(),
+            },
+        )
+        .unwrap();
+        let Data { data } = reply("upper", &output).unwrap().unwrap();
+        assert_eq!(data, "transform");
+
+        std::fs::write(&path, "#!/bin/sh\nexit 3\n").unwrap();
+        assert!(
+            run(
+                &path,
+                "upper",
+                "finished",
+                Data {
+                    data: String::new()
+                }
+            )
+            .is_err()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
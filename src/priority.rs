@@ -0,0 +1,70 @@
+//! Running at a low priority for `--nice-io`, so that a big sync leaves the
+//! disk and processor to whatever else the machine is doing. Lowered at
+//! startup, before any threads are made, as on Linux each thread has its own
+//! priority and new ones take their creator's.
+
+use anyhow::Result;
+
+/// The niceness to run at, as `nice` gives by default.
+#[cfg(all(unix, not(target_os = "macos")))]
+const NICENESS: libc::c_int = 10;
+
+/// Lower the priority of the process for the rest of the run.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn lower() -> Result<()> {
+    // The idle I/O class, as `ionice -c 3` sets.
+    #[cfg(target_os = "linux")]
+    {
+        const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+        const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+        const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+        let set = unsafe {
+            libc::syscall(
+                libc::SYS_ioprio_set,
+                IOPRIO_WHO_PROCESS,
+                0,
+                IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+            )
+        };
+        if set == -1 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, NICENESS) } == -1 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+/// Move the process to the background band, which throttles its disk I/O
+/// as well as lowering its CPU priority.
+#[cfg(target_os = "macos")]
+pub fn lower() -> Result<()> {
+    if unsafe { libc::setpriority(libc::PRIO_DARWIN_PROCESS, 0, libc::PRIO_DARWIN_BG) } == -1 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+unsafe extern "system" {
+    fn GetCurrentProcess() -> *mut std::ffi::c_void;
+    fn SetPriorityClass(process: *mut std::ffi::c_void, class: u32) -> i32;
+}
+
+/// Put the process in background processing mode, which lowers its I/O and
+/// memory priority as well as its CPU priority.
+#[cfg(windows)]
+pub fn lower() -> Result<()> {
+    const PROCESS_MODE_BACKGROUND_BEGIN: u32 = 0x0010_0000;
+    if unsafe { SetPriorityClass(GetCurrentProcess(), PROCESS_MODE_BACKGROUND_BEGIN) } == 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+/// Elsewhere the priority is left as it is.
+#[cfg(not(any(unix, windows)))]
+pub fn lower() -> Result<()> {
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use anyhow::Result;
+use std::io::{BufRead, IsTerminal, Write};
+
+use crate::plan::Side;
+
+/// A reply to a yes/no/all/quit question.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Answer {
+    Yes,
+    No,
+    All,
+    Quit,
+}
+
+/// Ask a question on stderr and read replies from stdin until one makes
+/// sense. The end of input counts as quitting.
+pub fn ask(question: &str) -> Result<Answer> {
+    Ok(read_reply(question, parse_answer)?.unwrap_or(Answer::Quit))
+}
+
+/// Ask which copy of a conflicting file to keep. `None` leaves both alone,
+/// as does the end of input.
+pub fn choose_side(question: &str) -> Result<Option<Side>> {
+    Ok(read_reply(question, parse_side)?.flatten())
+}
+
+/// Read a secret from stdin. When stdin is a terminal, ask for it on stderr
+/// and don't echo it.
+pub fn read_secret(question: &str) -> Result<String> {
+    let terminal = std::io::stdin().is_terminal();
+    if terminal {
+        eprint!("{}", question);
+        std::io::stderr().flush()?;
+    }
+    let hidden = terminal && set_echo(false);
+    let mut line = String::new();
+    let read = std::io::stdin().lock().read_line(&mut line);
+    if hidden {
+        set_echo(true);
+        eprintln!();
+    }
+    read?;
+    Ok(line.trim().to_string())
+}
+
+/// Turn terminal echo on or off with stty. Returns whether it worked.
+fn set_echo(on: bool) -> bool {
+    cfg!(unix)
+        && std::process::Command::new("stty")
+            .arg(if on { "echo" } else { "-echo" })
+            .status()
+            .is_ok_and(|status| status.success())
+}
+
+/// Prompt until `parse` accepts a reply. Returns `None` at the end of input.
+fn read_reply<T>(question: &str, parse: impl Fn(&str) -> Option<T>) -> Result<Option<T>> {
+    let stdin = std::io::stdin();
+    loop {
+        eprint!("{}", question);
+        std::io::stderr().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        if let Some(answer) = parse(&line) {
+            return Ok(Some(answer));
+        }
+    }
+}
+
+/// Parse a reply. German `j` for yes is accepted too.
+fn parse_answer(line: &str) -> Option<Answer> {
+    match line.trim().to_lowercase().as_str() {
+        "y" | "yes" | "j" | "ja" => Some(Answer::Yes),
+        "n" | "no" | "nein" => Some(Answer::No),
+        "a" | "all" | "alle" => Some(Answer::All),
+        "q" | "quit" => Some(Answer::Quit),
+        _ => None,
+    }
+}
+
+/// Parse a reply to [`choose_side`]. German `ü` for skip is accepted too.
+fn parse_side(line: &str) -> Option<Option<Side>> {
+    match line.trim().to_lowercase().as_str() {
+        "l" | "local" | "lokal" => Some(Some(Side::Local)),
+        "r" | "remote" => Some(Some(Side::Remote)),
+        "s" | "skip" | "ü" | "überspringen" => Some(None),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_answer() {
+        assert_eq!(parse_answer("y\n"), Some(Answer::Yes));
+        assert_eq!(parse_answer(" J "), Some(Answer::Yes));
+        assert_eq!(parse_answer("no"), Some(Answer::No));
+        assert_eq!(parse_answer("a"), Some(Answer::All));
+        assert_eq!(parse_answer("q"), Some(Answer::Quit));
+        assert_eq!(parse_answer(""), None);
+        assert_eq!(parse_answer("maybe"), None);
+    }
+
+    #[test]
+    fn test_parse_side() {
+        assert_eq!(parse_side("l\n"), Some(Some(Side::Local)));
+        assert_eq!(parse_side("Remote"), Some(Some(Side::Remote)));
+        assert_eq!(parse_side("ü"), Some(None));
+        assert_eq!(parse_side("s"), Some(None));
+        assert_eq!(parse_side("y"), None);
+    }
+}
@@ -0,0 +1,64 @@
+use clap::ValueEnum;
+use schemars::{JsonSchema, Schema, schema_for};
+
+use crate::{commands::stats::ZoneStats, output::Event, plan::PlanFile, storage::StorageObject};
+
+/// The JSON documents bunnysync emits. Each has a published schema in the
+/// `schemas` directory so tooling can be checked against it.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum SchemaKind {
+    /// A remote directory listing, as printed by `ls --json`
+    Listing,
+    /// A single remote object, as printed by `stat --json`
+    Object,
+    /// One line of `sync --output json`
+    Event,
+    /// A plan saved by `sync --dryrun --plan`
+    Plan,
+    /// The usage of a zone, as printed by `stats --json`
+    Stats,
+}
+
+/// Generate the JSON Schema for a kind of output.
+pub fn generate(kind: SchemaKind) -> String {
+    let schema = match kind {
+        SchemaKind::Listing => titled::<Vec<StorageObject>>("Listing"),
+        SchemaKind::Object => titled::<StorageObject>("StorageObject"),
+        SchemaKind::Event => titled::<Event>("Event"),
+        SchemaKind::Plan => titled::<PlanFile>("Plan"),
+        SchemaKind::Stats => titled::<ZoneStats>("ZoneStats"),
+    };
+    serde_json::to_string_pretty(&schema).expect("schema serializes")
+}
+
+fn titled<T: JsonSchema>(title: &str) -> Schema {
+    let mut schema = schema_for!(T);
+    schema.insert("title".into(), title.into());
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The published schemas must match what the code emits. Regenerate with
+    // `bunnysync --schema <kind> > schemas/<kind>.schema.json`.
+    #[test]
+    fn test_published_schemas_are_current() {
+        for kind in SchemaKind::value_variants() {
+            let name = kind.to_possible_value().unwrap().get_name().to_string();
+            let path = format!(
+                "{}/schemas/{}.schema.json",
+                env!("CARGO_MANIFEST_DIR"),
+                name
+            );
+            let published = std::fs::read_to_string(&path).unwrap_or_default();
+            assert_eq!(
+                published.trim_end(),
+                generate(*kind),
+                "{} is out of date",
+                path
+            );
+        }
+    }
+}
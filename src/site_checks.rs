@@ -0,0 +1,137 @@
+use anyhow::Result;
+use std::{collections::HashMap, path::Path};
+
+use crate::local::LocalFile;
+
+/// Hosts that only make sense on the machine the site was built on.
+const LOCAL_HOSTS: [&str; 4] = ["localhost", "127.0.0.1", "0.0.0.0", "[::1]"];
+
+/// Paths bunny.net serves as the not found page, depending on how the pull
+/// zone is set up.
+const ERROR_PAGES: [&str; 2] = ["404.html", "bunnycdn_errors/404.html"];
+
+/// Something that would leave the deployed site broken.
+#[derive(Debug, PartialEq)]
+pub enum Problem {
+    MissingIndex,
+    Missing404,
+    LocalhostUrl {
+        file: String,
+        line: usize,
+        url: String,
+    },
+}
+
+/// Check a local site before it is deployed.
+pub fn check_site(local_files: &HashMap<String, LocalFile>) -> Result<Vec<Problem>> {
+    let mut files: Vec<_> = local_files.values().collect();
+    files.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
+    let has = |path: &str| {
+        files
+            .iter()
+            .any(|file| file.relative_path == Path::new(path))
+    };
+
+    let mut problems = Vec::new();
+    if !has("index.html") {
+        problems.push(Problem::MissingIndex);
+    }
+    if !ERROR_PAGES.iter().any(|page| has(page)) {
+        problems.push(Problem::Missing404);
+    }
+    for file in &files {
+        let is_html = file
+            .path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("html") || ext.eq_ignore_ascii_case("htm"));
+        if !is_html {
+            continue;
+        }
+        let text = String::from_utf8_lossy(&std::fs::read(&file.path)?).into_owned();
+        for (line, url) in localhost_urls(&text) {
+            problems.push(Problem::LocalhostUrl {
+                file: file.relative_path.to_string_lossy().into_owned(),
+                line,
+                url,
+            });
+        }
+    }
+    Ok(problems)
+}
+
+/// Find absolute URLs pointing at the local machine, with their line numbers.
+fn localhost_urls(text: &str) -> Vec<(usize, String)> {
+    let mut urls = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let mut rest = line;
+        while let Some(found) = rest.find("://") {
+            let scheme_start = rest[..found]
+                .rfind(|c: char| !c.is_ascii_alphanumeric())
+                .map_or(0, |i| i + 1);
+            let after = &rest[found + 3..];
+            let end = after
+                .find(|c: char| c.is_whitespace() || "\"'<>()".contains(c))
+                .unwrap_or(after.len());
+            let host = &after[..end];
+            if scheme_start < found
+                && LOCAL_HOSTS.iter().any(|local| {
+                    host.strip_prefix(local)
+                        .is_some_and(|tail| tail.is_empty() || tail.starts_with([':', '/']))
+                })
+            {
+                urls.push((number + 1, rest[scheme_start..found + 3 + end].to_string()));
+            }
+            rest = &after[end..];
+        }
+    }
+    urls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn local_file(relative: &str) -> (String, LocalFile) {
+        let file = LocalFile {
+            relative_path: relative.into(),
+            path: PathBuf::from("/local").join(relative),
+            is_directory: false,
+            last_changed: Default::default(),
+            length: 0,
+        };
+        (relative.to_string(), file)
+    }
+
+    #[test]
+    fn test_required_pages() {
+        let files = HashMap::from([local_file("css/site.css")]);
+        assert_eq!(
+            check_site(&files).unwrap(),
+            vec![Problem::MissingIndex, Problem::Missing404]
+        );
+        let files = HashMap::from([
+            local_file("index.css"),
+            local_file("bunnycdn_errors/404.html.orig"),
+        ]);
+        assert_eq!(
+            check_site(&files).unwrap(),
+            vec![Problem::MissingIndex, Problem::Missing404]
+        );
+    }
+
+    #[test]
+    fn test_localhost_urls() {
+        let html = "<a href=\"https://example.com\">ok</a>\n\
+                    <img src='http://localhost:8080/logo.png'>\n\
+                    <p>See http://127.0.0.1/api</p>\n\
+                    <a href=\"https://localhost.example.com/\">fine</a>";
+        assert_eq!(
+            localhost_urls(html),
+            vec![
+                (2, "http://localhost:8080/logo.png".to_string()),
+                (3, "http://127.0.0.1/api".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use crate::{local::LocalFile, plan::RemoteIndex, store, timestamp::RemoteTime};
+
+/// The file the state is kept in.
+pub const STATE_FILE: &str = "state.json";
+
+/// What both sides looked like after the last successful bidirectional sync.
+/// Comparing against it tells a file deleted on one side apart from a file
+/// that is new on the other.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SyncState {
+    /// The remote the state was recorded against, e.g. `zone://my-zone/`.
+    pub remote: String,
+    /// Files present on both sides, by remote key.
+    pub files: BTreeMap<String, FileState>,
+}
+
+/// Both copies of a file as last seen in sync.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileState {
+    pub local_length: u64,
+    pub local_changed: DateTime<Utc>,
+    pub remote_length: u64,
+    pub remote_changed: RemoteTime,
+}
+
+impl SyncState {
+    /// Load the state kept in a state directory. A missing file, or one
+    /// recorded against a different remote, gives an empty state.
+    pub fn load(dir: &Path, remote: &str) -> Result<Self> {
+        match store::read::<SyncState>(&dir.join(STATE_FILE))? {
+            Some(state) if state.remote == remote => Ok(state),
+            _ => Ok(SyncState::empty(remote)),
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        store::write(&dir.join(STATE_FILE), self)
+    }
+
+    fn empty(remote: &str) -> Self {
+        SyncState {
+            remote: remote.to_string(),
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Record the files present on both sides. Files listed in `keep` are
+    /// still unresolved, so their previous state is carried over instead.
+    pub fn snapshot(
+        &self,
+        local_files: &HashMap<String, LocalFile>,
+        remote: &RemoteIndex,
+        keep: &[&str],
+    ) -> Self {
+        let mut files = BTreeMap::new();
+        for (key, local_file) in local_files {
+            if keep.contains(&key.as_str()) {
+                continue;
+            }
+            if let Some(remote_file) = remote.files.get(key) {
+                files.insert(
+                    key.clone(),
+                    FileState {
+                        local_length: local_file.length,
+                        local_changed: local_file.last_changed,
+                        remote_length: remote_file.length,
+                        remote_changed: remote_file.last_changed,
+                    },
+                );
+            }
+        }
+        for key in keep {
+            if let Some(previous) = self.files.get(*key) {
+                files.insert(key.to_string(), previous.clone());
+            }
+        }
+        SyncState {
+            remote: self.remote.clone(),
+            files,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_and_save() {
+        let dir = std::env::temp_dir().join(format!("bunnysync-state-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let remote = "zone://myzone/";
+        assert_eq!(
+            SyncState::load(&dir, remote).unwrap(),
+            SyncState::empty(remote)
+        );
+
+        let mut state = SyncState::empty(remote);
+        state.files.insert(
+            "/myzone/a.html".to_string(),
+            FileState {
+                local_length: 1,
+                local_changed: DateTime::from_timestamp(100, 0).unwrap(),
+                remote_length: 1,
+                remote_changed: RemoteTime::from_utc(DateTime::from_timestamp(200, 0).unwrap()),
+            },
+        );
+        state.save(&dir).unwrap();
+        assert_eq!(SyncState::load(&dir, remote).unwrap(), state);
+        // State for another zone doesn't apply.
+        assert_eq!(
+            SyncState::load(&dir, "zone://other/").unwrap(),
+            SyncState::empty("zone://other/")
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
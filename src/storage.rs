@@ -1,19 +1,44 @@
 use anyhow::{Result, anyhow};
-use chrono::NaiveDateTime;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    time::{Duration, Instant},
+};
 use ureq::{
-    Agent, Body, SendBody,
-    http::{HeaderValue, Request, Response, StatusCode, header},
+    Agent, Body, Proxy, SendBody,
+    config::ConfigBuilder,
+    http::{HeaderValue, Method, Request, Response, StatusCode, Uri, header},
     middleware::MiddlewareNext,
+    typestate::AgentScope,
+    unversioned::{
+        resolver::DefaultResolver,
+        transport::{Connector, DefaultConnector},
+    },
+};
+
+use crate::{
+    cache::RegionCache,
+    faults::{Fault, Faults},
+    i18n::{Message, tr},
+    timeline::Timeline,
+    timestamp::RemoteTime,
+    transport::{Rate, StallConnector, ThrottleConnector},
 };
 
 const API_KEY_HEADER: &str = "AccessKey";
-const USER_AGENT: &str = "bunnysync/0.1.0";
+pub const USER_AGENT: &str = "bunnysync/0.1.0";
 const APPLICATION_JSON: HeaderValue = HeaderValue::from_static("application/json");
 const APPLICATION_OCTET_STREAM: HeaderValue = HeaderValue::from_static("application/octet-stream");
 const ALL: HeaderValue = HeaderValue::from_static("*/*");
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema, PartialEq)]
 #[serde(rename_all = "PascalCase")]
 pub struct StorageObject {
     pub guid: String,
@@ -21,9 +46,25 @@ pub struct StorageObject {
     pub path: String,
     pub object_name: String,
     pub length: u64,
-    pub last_changed: NaiveDateTime,
+    pub last_changed: RemoteTime,
     pub is_directory: bool,
-    pub date_created: NaiveDateTime,
+    pub date_created: RemoteTime,
+    #[serde(default)]
+    pub server_id: i64,
+    #[serde(default)]
+    pub array_number: i64,
+    #[serde(default)]
+    pub user_id: String,
+    #[serde(default)]
+    pub content_type: String,
+    #[serde(default)]
+    pub storage_zone_id: u64,
+    /// The upper case hex SHA256 of the content. Directories have none.
+    #[serde(default)]
+    pub checksum: Option<String>,
+    /// Comma separated regions the object is replicated to.
+    #[serde(default)]
+    pub replicated_zones: Option<String>,
 }
 
 pub fn base_url(region: &str) -> Option<String> {
@@ -41,41 +82,540 @@ pub fn base_url(region: &str) -> Option<String> {
     }
 }
 
-pub fn agent(api_key: &str) -> Result<Agent> {
-    // Set api key.
-    let mut auth_value = HeaderValue::from_str(api_key)?;
-    auth_value.set_sensitive(true);
+/// The regions `--region auto` looks for zones in, most used first.
+const REGIONS: [&str; 9] = ["de", "ny", "la", "uk", "sg", "se", "syd", "br", "sa"];
+
+/// Whether a base URL is one of the regions rather than an endpoint given
+/// instead.
+pub fn is_region(url: &str) -> bool {
+    REGIONS
+        .into_iter()
+        .any(|region| base_url(region).as_deref() == Some(url))
+}
+
+/// The URL of an object that isn't there, asked for to learn whether a
+/// region holds a zone.
+fn probe_url(region: &str, zone: &str) -> String {
+    format!(
+        "{}/{}/.bunnysync-region-probe",
+        base_url(region).unwrap_or_default(),
+        zone
+    )
+}
+
+/// Whether a region answered a probe as one holding the zone.
+fn holds_zone(response: &Response<Body>) -> bool {
+    response.status() == StatusCode::NOT_FOUND || response.status().is_success()
+}
+
+/// The region holding a copy of a zone that answers fastest, and how long it
+/// took. Only the zone's own region and those it is replicated to accept its
+/// key.
+pub fn fastest_region(agent: &Agent, zone: &str) -> Option<(&'static str, Duration)> {
+    std::thread::scope(|scope| {
+        let probes: Vec<_> = REGIONS
+            .into_iter()
+            .map(|region| {
+                scope.spawn(move || {
+                    let started = Instant::now();
+                    let response = agent
+                        .get(probe_url(region, zone))
+                        .extension(Probe)
+                        .call()
+                        .ok()?;
+                    holds_zone(&response).then(|| (region, started.elapsed()))
+                })
+            })
+            .collect();
+        probes
+            .into_iter()
+            .filter_map(|probe| probe.join().ok().flatten())
+            .min_by_key(|(_, took)| *took)
+    })
+}
+
+/// The storage API at a URL given instead of a region, such as a mock of it
+/// for testing. Plain HTTP is only allowed to this machine, so that the API
+/// key never crosses the network unencrypted.
+pub fn endpoint(url: &str) -> Result<String> {
+    let url = url.trim_end_matches('/');
+    let (scheme, rest) = url
+        .split_once("://")
+        .ok_or_else(|| anyhow!("Invalid endpoint {}, use https://HOST[:PORT]", url))?;
+    let authority = rest.split('/').next().unwrap_or_default();
+    match scheme {
+        _ if authority.is_empty() => Err(anyhow!("Invalid endpoint {}, there is no host", url)),
+        "https" => Ok(url.to_string()),
+        "http" if is_loopback(authority) => Ok(url.to_string()),
+        "http" => Err(anyhow!(
+            "Plain HTTP is only allowed to this machine, use https for {}",
+            url
+        )),
+        _ => Err(anyhow!("Invalid endpoint {}, use https://HOST[:PORT]", url)),
+    }
+}
+
+/// Whether the host of a URL's `host[:port]` is this machine.
+fn is_loopback(authority: &str) -> bool {
+    let host = match authority.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => authority.split(':').next().unwrap_or_default(),
+    };
+    host == "localhost"
+        || host
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// The management API's code for a region, as used when creating zones.
+pub fn region_code(region: &str) -> Option<&'static str> {
+    match region {
+        "uk" => Some("UK"),
+        "us_ny" | "ny" => Some("NY"),
+        "us_la" | "la" => Some("LA"),
+        "sg" => Some("SG"),
+        "se" => Some("SE"),
+        "br" => Some("BR"),
+        "sa" => Some("JH"),
+        "au" | "au_syd" | "syd" => Some("SYD"),
+        "" | "de" => Some("DE"),
+        _ => None,
+    }
+}
+
+/// Options controlling how the agent talks to the storage API.
+#[derive(Debug, Clone, Default)]
+pub struct AgentOptions {
+    /// Fail a transfer when no bytes are sent or received for this long. There
+    /// is no overall timeout unless `request_timeout` is set, so that large
+    /// transfers on slow links can take as long as they need.
+    pub stall_timeout: Option<Duration>,
+    /// Give up connecting to a server after this long.
+    pub connect_timeout: Option<Duration>,
+    /// Give up on a request that hasn't finished after this long, response
+    /// and all.
+    pub request_timeout: Option<Duration>,
+    /// The proxy to send requests through, like `http://proxy:3128`, over
+    /// any set in the environment.
+    pub proxy: Option<String>,
+    /// The throughput that all agents built with these options share, for
+    /// `--bwlimit`.
+    pub bandwidth: Option<Arc<Rate>>,
+    /// The requests a second that all agents built with these options share,
+    /// for `--max-requests-per-second`.
+    pub requests: Option<Arc<Rate>>,
+    /// The percentage of storage API requests to fail on purpose, for
+    /// `--simulate-failures`.
+    pub simulate_failures: Option<f64>,
+    /// Where every agent built with these options notes its requests, for
+    /// `--timeline`.
+    pub timeline: Option<Arc<Timeline>>,
+    /// Allow plain HTTP, for an endpoint on this machine.
+    pub allow_http: bool,
+    /// Send requests to the region of the zone they are for, as found the
+    /// first time, for `--region auto`.
+    pub detect_region: bool,
+}
+
+/// The API keys to authenticate with, as given in the options or config.
+#[derive(Debug, Clone, Default)]
+pub struct ApiKeys {
+    /// The zone's password, which may change the zone.
+    pub primary: Option<String>,
+    /// A password to fall back on when the primary one is turned down.
+    pub secondary: Option<String>,
+    /// The zone's read-only password, used for listing and downloading
+    /// instead of the others when given.
+    pub read_only: Option<String>,
+}
+
+impl ApiKeys {
+    /// Whether there is a key that may change the zone.
+    pub fn can_write(&self) -> bool {
+        self.primary.is_some()
+    }
+
+    /// Each key on its own, as the only key for every request, so that
+    /// what one key may do can be told apart from what the others may.
+    pub fn each(&self) -> Vec<(Key, ApiKeys)> {
+        [
+            (Key::Primary, &self.primary),
+            (Key::Secondary, &self.secondary),
+            (Key::ReadOnly, &self.read_only),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let value = value.clone()?;
+            Some((
+                key,
+                ApiKeys {
+                    primary: Some(value),
+                    ..Default::default()
+                },
+            ))
+        })
+        .collect()
+    }
+}
+
+/// Which of the keys a request was sent with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Primary,
+    Secondary,
+    ReadOnly,
+}
+
+/// The API keys an agent authenticates with. Writes use the primary key
+/// until it is turned down, then the secondary key, so that a key can be
+/// rotated without scheduled syncs failing in between. Reads use the
+/// read-only key if there is one.
+struct Keys {
+    primary: Option<HeaderValue>,
+    secondary: Option<HeaderValue>,
+    read_only: Option<HeaderValue>,
+    use_secondary: AtomicBool,
+}
+
+impl Keys {
+    fn new(keys: &ApiKeys) -> Result<Self> {
+        let sensitive = |key: &String| -> Result<HeaderValue> {
+            let mut value = HeaderValue::from_str(key)?;
+            value.set_sensitive(true);
+            Ok(value)
+        };
+        Ok(Keys {
+            primary: keys.primary.as_ref().map(sensitive).transpose()?,
+            secondary: keys.secondary.as_ref().map(sensitive).transpose()?,
+            read_only: keys.read_only.as_ref().map(sensitive).transpose()?,
+            use_secondary: AtomicBool::new(false),
+        })
+    }
+
+    /// The key to send for a read or a write, and which it is. There is none
+    /// for a write with only the read-only key.
+    fn current(&self, write: bool) -> Option<(&HeaderValue, Key)> {
+        match (&self.read_only, &self.primary, &self.secondary) {
+            (Some(read_only), _, _) if !write => Some((read_only, Key::ReadOnly)),
+            (_, Some(_), Some(secondary)) if self.use_secondary.load(Ordering::Relaxed) => {
+                Some((secondary, Key::Secondary))
+            }
+            (_, Some(primary), _) => Some((primary, Key::Primary)),
+            _ => None,
+        }
+    }
+
+    /// Note that a request was turned down as unauthorized. Returns whether
+    /// it is worth sending again because the secondary key is now in use.
+    fn unauthorized(&self, key: Key) -> bool {
+        if key != Key::Primary || self.secondary.is_none() {
+            return false;
+        }
+        if !self.use_secondary.swap(true, Ordering::Relaxed) {
+            eprintln!("{}", tr(Message::SecondaryKey));
+        }
+        true
+    }
+}
+
+/// Sends requests to the region of the zone they are for. Any region holding
+/// a copy of a zone answers a read of an object that isn't there, replicas
+/// included, but only the zone's own region takes a delete of one. Only
+/// regions found that way are remembered for later runs.
+struct Router {
+    agent: Agent,
+    regions: Mutex<RegionCache>,
+    /// Regions that answered a read but not a delete, as when there was
+    /// only the read-only key to ask with. Good for reads in this run only.
+    replicas: Mutex<HashMap<String, &'static str>>,
+}
+
+impl Router {
+    fn new(options: &AgentOptions) -> Result<Self> {
+        let config = Agent::config_builder()
+            .user_agent(USER_AGENT)
+            .https_only(true)
+            .http_status_as_error(false);
+        Ok(Router {
+            agent: build_agent(config, &base_url("de").unwrap_or_default(), options)?,
+            regions: Mutex::new(RegionCache::load()),
+            replicas: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Send a request to the region of its zone. Requests for a zone whose
+    /// region can't be found go where they were going, and so do those
+    /// already meant for a region, like downloads from a replica.
+    fn route(&self, req: &mut Request<SendBody>, key: &HeaderValue) {
+        let zone = zone_name(req.uri().path());
+        if zone.is_empty() || req.uri().host() != Some("storage.bunnycdn.com") {
+            return;
+        }
+        let write = !matches!(*req.method(), Method::GET | Method::HEAD);
+        let Some(base_url) = self.base_url(&zone, key, write) else {
+            return;
+        };
+        let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+        if let Ok(uri) = format!("{}{}", base_url, path).parse() {
+            *req.uri_mut() = uri;
+        }
+    }
+
+    fn base_url(&self, zone: &str, key: &HeaderValue, write: bool) -> Option<String> {
+        let mut regions = self.regions.lock().unwrap();
+        if let Some(region) = regions.get(zone) {
+            return base_url(region);
+        }
+        let mut replicas = self.replicas.lock().unwrap();
+        if !write && let Some(region) = replicas.get(zone) {
+            return base_url(region);
+        }
+        let (region, primary) = find_region(|region, method| {
+            let url = probe_url(region, zone);
+            let request = match method {
+                Method::DELETE => self.agent.delete(url),
+                _ => self.agent.get(url),
+            };
+            let response = request.header(API_KEY_HEADER, key).call();
+            response.is_ok_and(|response| holds_zone(&response))
+        })?;
+        eprintln!("{}", tr(Message::FoundRegion { zone, region }));
+        if primary {
+            regions.insert(zone, region);
+            if let Err(e) = regions.save() {
+                eprintln!("{}", tr(Message::Warning { warning: &e }));
+            }
+        } else {
+            replicas.insert(zone.to_string(), region);
+        }
+        base_url(region)
+    }
+}
+
+/// Find a zone's region by how each region answers a probe: a read for any
+/// region holding a copy of the zone, and a delete for the zone's own. Gives
+/// the zone's own region and true, or failing that the first region that
+/// answered a read and false.
+fn find_region(answers: impl Fn(&'static str, Method) -> bool) -> Option<(&'static str, bool)> {
+    let mut replica = None;
+    for region in REGIONS {
+        if !answers(region, Method::GET) {
+            continue;
+        }
+        if answers(region, Method::DELETE) {
+            return Some((region, true));
+        }
+        replica.get_or_insert(region);
+    }
+    replica.map(|region| (region, false))
+}
+
+/// Marks a response to a request that the primary key was turned down for,
+/// now that the secondary key is in use.
+#[derive(Clone)]
+struct Rotated;
+
+/// Marks the made up response to a write that wasn't sent, as there was only
+/// the read-only key.
+#[derive(Clone)]
+struct NoWriteKey;
+
+/// Marks a request asking a region whether it holds a zone. A region turning
+/// the key down is an answer then, not a reason to switch keys.
+#[derive(Clone)]
+struct Probe;
+
+/// Build an agent for the storage API at a base URL that authenticates with
+/// the keys, falling back to the secondary key if bunny.net turns the
+/// primary one down.
+pub fn agent(keys: &ApiKeys, base_url: &str, options: &AgentOptions) -> Result<Agent> {
+    let keys = Arc::new(Keys::new(keys)?);
+    let router = (options.detect_region)
+        .then(|| Router::new(options))
+        .transpose()?;
+    let faults = options.simulate_failures.map(Faults::new);
 
     // Create headers middleware.
     let default_headers = move |mut req: Request<SendBody>,
                                 next: MiddlewareNext|
           -> Result<Response<Body>, ureq::Error> {
-        req.headers_mut().insert(API_KEY_HEADER, auth_value.clone());
-        next.handle(req)
+        let write = !matches!(*req.method(), Method::GET | Method::HEAD);
+        let Some((key, which)) = keys.current(write) else {
+            return Ok(Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .extension(NoWriteKey)
+                .body(Body::builder().data(Vec::new()))?);
+        };
+        let probe = req.extensions().get::<Probe>().is_some();
+        if let Some(router) = &router {
+            router.route(&mut req, key);
+        }
+        req.headers_mut().insert(API_KEY_HEADER, key.clone());
+        match faults.as_ref().and_then(Faults::draw) {
+            Some(Fault::Reset) => {
+                return Err(ureq::Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionReset,
+                    "simulated connection reset",
+                )));
+            }
+            Some(Fault::TooManyRequests) => {
+                return Ok(Response::builder()
+                    .status(StatusCode::TOO_MANY_REQUESTS)
+                    .body(Body::builder().data(Vec::new()))?);
+            }
+            Some(Fault::Slow(wait)) => std::thread::sleep(wait),
+            None => {}
+        }
+        let mut response = next.handle(req)?;
+        if response.status() == StatusCode::UNAUTHORIZED && !probe && keys.unauthorized(which) {
+            response.extensions_mut().insert(Rotated);
+        }
+        Ok(response)
     };
 
     let config = Agent::config_builder()
         .user_agent(USER_AGENT)
-        .https_only(true)
+        .https_only(!options.allow_http)
         .http_status_as_error(false)
-        .middleware(default_headers)
+        .middleware(default_headers);
+    build_agent(config, base_url, options)
+}
+
+/// Build an agent for a service from its config and the HTTP settings.
+pub fn build_agent(
+    mut config: ConfigBuilder<AgentScope>,
+    url: &str,
+    options: &AgentOptions,
+) -> Result<Agent> {
+    if let Some(requests) = options.requests.clone() {
+        config = config.middleware(move |req: Request<SendBody>, next: MiddlewareNext| {
+            requests.take(1);
+            next.handle(req)
+        });
+    }
+    if let Some(timeline) = options.timeline.clone() {
+        config = config.middleware(move |req: Request<SendBody>, next: MiddlewareNext| {
+            timeline.record(req, next)
+        });
+    }
+    let config = config
+        .timeout_connect(options.connect_timeout)
+        .timeout_global(options.request_timeout)
+        .proxy(proxy(options.proxy.as_deref(), url, |name| {
+            std::env::var(name).ok()
+        })?)
         .build();
-    let agent: Agent = config.into();
+    let resolver = DefaultResolver::default();
+    let throttle = |bandwidth: &Arc<Rate>| ThrottleConnector {
+        bandwidth: bandwidth.clone(),
+    };
+    let agent = match (options.stall_timeout, &options.bandwidth) {
+        (Some(stall_timeout), Some(bandwidth)) => {
+            let connector = DefaultConnector::new()
+                .chain(StallConnector { stall_timeout })
+                .chain(throttle(bandwidth));
+            Agent::with_parts(config, connector, resolver)
+        }
+        (Some(stall_timeout), None) => {
+            let connector = DefaultConnector::new().chain(StallConnector { stall_timeout });
+            Agent::with_parts(config, connector, resolver)
+        }
+        (None, Some(bandwidth)) => {
+            let connector = DefaultConnector::new().chain(throttle(bandwidth));
+            Agent::with_parts(config, connector, resolver)
+        }
+        (None, None) => config.into(),
+    };
     Ok(agent)
 }
 
+/// The proxy for requests to a URL: the one given, or else the one set in
+/// `HTTPS_PROXY` or `HTTP_PROXY` for the URL's scheme, or in `ALL_PROXY`.
+/// Hosts listed in `NO_PROXY` are reached directly either way.
+fn proxy(
+    given: Option<&str>,
+    url: &str,
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<Option<Proxy>> {
+    let env = |name: &str| {
+        var(name)
+            .or_else(|| var(&name.to_ascii_lowercase()))
+            .filter(|value| !value.is_empty())
+    };
+    let host = url
+        .parse::<Uri>()
+        .ok()
+        .and_then(|uri| uri.host().map(str::to_string));
+    if let (Some(no_proxy), Some(host)) = (env("NO_PROXY"), &host)
+        && bypasses_proxy(&no_proxy, host)
+    {
+        return Ok(None);
+    }
+    let scheme = if url.starts_with("https://") {
+        "HTTPS_PROXY"
+    } else {
+        "HTTP_PROXY"
+    };
+    let Some(proxy) = (given.map(str::to_string))
+        .or_else(|| env(scheme))
+        .or_else(|| env("ALL_PROXY"))
+    else {
+        return Ok(None);
+    };
+    Proxy::new(&proxy)
+        .map(Some)
+        .map_err(|e| anyhow!("Invalid proxy {}: {}", proxy, e))
+}
+
+/// Whether `NO_PROXY`, a comma separated list of hosts and domains, says to
+/// reach a host directly. A domain covers its subdomains, and `*` covers
+/// every host.
+fn bypasses_proxy(no_proxy: &str, host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    no_proxy
+        .split(',')
+        .map(|entry| {
+            entry
+                .trim()
+                .trim_start_matches("*.")
+                .trim_start_matches('.')
+        })
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| {
+            let entry = entry.to_ascii_lowercase();
+            entry == "*" || host == entry || host.ends_with(&format!(".{}", entry))
+        })
+}
+
+/// Send a request, and send it once more if it was turned down for the
+/// primary key and the secondary key is now in use.
+fn send(
+    request: impl Fn() -> Result<Response<Body>, ureq::Error>,
+) -> Result<Response<Body>, ureq::Error> {
+    let response = request()?;
+    if response.extensions().get::<Rotated>().is_some() {
+        return request();
+    }
+    Ok(response)
+}
+
 /// Get the list of objects at the destination
 pub fn get_objects(agent: &Agent, base_url: &str, path: &str) -> Result<Vec<StorageObject>> {
     let url = format!("{}/{}", base_url, path);
 
-    let mut response = agent
-        .get(&url)
-        .header(header::ACCEPT, APPLICATION_JSON)
-        .call()?;
+    let mut response = send(|| {
+        agent
+            .get(&url)
+            .header(header::ACCEPT, APPLICATION_JSON)
+            .call()
+    })?;
 
     match response.status() {
-        StatusCode::UNAUTHORIZED => Err(anyhow!("Remote unauthorized")),
-        StatusCode::NOT_FOUND => Err(anyhow!("Not found: Path {} does not exist", path)),
+        StatusCode::UNAUTHORIZED => Err(Unauthorized { write: false }.into()),
+        StatusCode::NOT_FOUND => Err(NotFound {
+            path: path.to_string(),
+        }
+        .into()),
         StatusCode::FORBIDDEN => Err(anyhow!("Forbidden: Access denied to path {}", path)),
         _ if response.status().is_success() => {
             let records = response.body_mut().read_json::<Vec<StorageObject>>()?;
@@ -89,36 +629,260 @@ pub fn get_objects(agent: &Agent, base_url: &str, path: &str) -> Result<Vec<Stor
     }
 }
 
-/// Get all objects in a directory and its subdirectories.
+/// A path that doesn't exist in the zone, when listing or deleting it.
+#[derive(Debug)]
+pub struct NotFound {
+    pub path: String,
+}
+
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Not found: Path {} does not exist", self.path)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+/// A request the API key was turned down for. A write may be turned down
+/// for a zone's read-only password, which can list and download but nothing
+/// else.
+#[derive(Debug)]
+pub struct Unauthorized {
+    pub write: bool,
+}
+
+impl fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.write {
+            write!(
+                f,
+                "Remote unauthorized to change the zone. Is the API key the read-only password?"
+            )
+        } else {
+            write!(f, "Remote unauthorized")
+        }
+    }
+}
+
+impl std::error::Error for Unauthorized {}
+
+/// A write that wasn't sent, as only the read-only key was given.
+#[derive(Debug)]
+pub struct ReadOnlyKey;
+
+impl fmt::Display for ReadOnlyKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", tr(Message::NeedsWriteKey))
+    }
+}
+
+impl std::error::Error for ReadOnlyKey {}
+
+/// The error for a write that was turned down, or not sent at all for want
+/// of a key that may make it.
+fn write_refused(response: &Response<Body>) -> anyhow::Error {
+    if response.extensions().get::<NoWriteKey>().is_some() {
+        ReadOnlyKey.into()
+    } else {
+        Unauthorized { write: true }.into()
+    }
+}
+
+/// What an API key may do in a zone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    None,
+    ReadOnly,
+    ReadWrite,
+}
+
+/// Find out what the API key may do in a zone by listing it and deleting an
+/// object that isn't there, which changes nothing either way.
+pub fn access(agent: &Agent, base_url: &str, zone: &str) -> Result<Access> {
+    let zone = zone_name(zone);
+    match get_objects(agent, base_url, &format!("{}/", zone)) {
+        Err(e) if e.is::<Unauthorized>() => return Ok(Access::None),
+        listed => listed?,
+    };
+    let probe = format!("{}/.bunnysync-access-probe", zone);
+    match delete_object(agent, base_url, &probe) {
+        Err(e) if e.is::<Unauthorized>() || e.is::<ReadOnlyKey>() => Ok(Access::ReadOnly),
+        Err(e) if e.is::<NotFound>() => Ok(Access::ReadWrite),
+        deleted => deleted.map(|_| Access::ReadWrite),
+    }
+}
+
+/// A write turned away because of contention or a problem on the server,
+/// which is worth trying again.
+#[derive(Debug)]
+pub struct Contention {
+    pub action: &'static str,
+    pub url: String,
+    pub status: StatusCode,
+}
+
+impl fmt::Display for Contention {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Failed to {} {}: HTTP {}",
+            self.action, self.url, self.status
+        )
+    }
+}
+
+impl std::error::Error for Contention {}
+
+/// Check for a status that means a write should be retried.
+fn is_contention(status: StatusCode) -> bool {
+    status == StatusCode::CONFLICT
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status.is_server_error()
+}
+
+/// How many directories are listed at once.
+const LISTING_THREADS: usize = 8;
+
+/// Get all objects in a directory and its subdirectories. Directories are
+/// listed concurrently, so the objects come back in no particular order.
 pub fn get_all_objects(agent: &Agent, base_url: &str, path: &str) -> Result<Vec<StorageObject>> {
-    let mut objects = Vec::new();
-    let mut paths = vec![path.to_string()];
-
-    while let Some(next_path) = paths.pop() {
-        let records = get_objects(agent, base_url, &next_path)?;
-        for record in &records {
-            if record.is_directory {
-                paths.push(format!("{}{}/", record.path, record.object_name));
+    let objects = Mutex::new(Vec::new());
+    walk(
+        path,
+        LISTING_THREADS,
+        |path| get_objects(agent, base_url, path),
+        |_, records| {
+            objects.lock().unwrap().extend(records);
+            Ok(())
+        },
+    )?;
+    Ok(objects.into_inner().unwrap())
+}
+
+/// List a directory and its subdirectories, handing each directory's path
+/// and objects to `listed` as soon as they arrive. `listed` runs on the
+/// calling thread while the listing carries on in the background. An error
+/// on either side stops both.
+pub fn stream_all_objects(
+    agent: &Agent,
+    base_url: &str,
+    path: &str,
+    mut listed: impl FnMut(&str, Vec<StorageObject>) -> Result<()>,
+) -> Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::scope(|scope| {
+        let lister = scope.spawn(move || {
+            walk(
+                path,
+                LISTING_THREADS,
+                |path| get_objects(agent, base_url, path),
+                |path, records| {
+                    sender
+                        .send((path.to_string(), records))
+                        .map_err(|_| anyhow!("Listing abandoned"))
+                },
+            )
+        });
+        let mut result = Ok(());
+        for (path, records) in &receiver {
+            result = listed(&path, records);
+            if result.is_err() {
+                break;
             }
         }
-        objects.extend(records);
+        // Hanging up makes the listing stop at its next directory.
+        drop(receiver);
+        result.and(lister.join().unwrap())
+    })
+}
+
+/// Walk a directory tree with a fixed number of threads, each listing one
+/// directory at a time and passing the result to `found`. The first error
+/// stops the walk.
+fn walk(
+    root: &str,
+    threads: usize,
+    list: impl Fn(&str) -> Result<Vec<StorageObject>> + Sync,
+    found: impl Fn(&str, Vec<StorageObject>) -> Result<()> + Sync,
+) -> Result<()> {
+    struct Walk {
+        paths: Vec<String>,
+        /// Directories being listed, which may still add more paths.
+        busy: usize,
+        error: Option<anyhow::Error>,
+    }
+
+    let state = Mutex::new(Walk {
+        paths: vec![root.to_string()],
+        busy: 0,
+        error: None,
+    });
+    let ready = Condvar::new();
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                let mut walk = state.lock().unwrap();
+                loop {
+                    if walk.error.is_some() {
+                        break;
+                    }
+                    let Some(path) = walk.paths.pop() else {
+                        if walk.busy == 0 {
+                            break;
+                        }
+                        walk = ready.wait(walk).unwrap();
+                        continue;
+                    };
+                    walk.busy += 1;
+                    drop(walk);
+                    let result = list(&path).and_then(|records| {
+                        let dirs: Vec<_> = records
+                            .iter()
+                            .filter(|record| record.is_directory)
+                            .map(|record| format!("{}{}/", record.path, record.object_name))
+                            .collect();
+                        found(&path, records)?;
+                        Ok(dirs)
+                    });
+                    walk = state.lock().unwrap();
+                    walk.busy -= 1;
+                    match result {
+                        Ok(dirs) => walk.paths.extend(dirs),
+                        Err(e) => walk.error = Some(e),
+                    }
+                    ready.notify_all();
+                }
+            });
+        }
+    });
+    match state.into_inner().unwrap().error {
+        Some(e) => Err(e),
+        None => Ok(()),
     }
-    Ok(objects)
 }
 
 /// Store an object.
 pub fn put_object(agent: &Agent, base_url: &str, path: &str, data: &[u8]) -> Result<()> {
     let url = format!("{}/{}", base_url, path);
-    let response = agent
-        .put(&url)
-        .header(header::CONTENT_TYPE, APPLICATION_OCTET_STREAM)
-        .send(data.to_vec())?;
+    let response = send(|| {
+        agent
+            .put(&url)
+            .header(header::CONTENT_TYPE, APPLICATION_OCTET_STREAM)
+            .header(header::CONTENT_LENGTH, data.len())
+            .send(data)
+    })?;
 
     match response.status() {
-        StatusCode::UNAUTHORIZED => Err(anyhow!("Remote unauthorized")),
+        StatusCode::UNAUTHORIZED => Err(write_refused(&response)),
         StatusCode::NOT_FOUND => Err(anyhow!("Not found: Path {} does not exist", path)),
         StatusCode::FORBIDDEN => Err(anyhow!("Forbidden: Access denied to path {}", path)),
         _ if response.status().is_success() => Ok(()),
+        status if is_contention(status) => Err(Contention {
+            action: "put object to",
+            url,
+            status,
+        }
+        .into()),
         _ => Err(anyhow!(
             "Failed to put object to {}: HTTP {}",
             &url,
@@ -130,9 +894,9 @@ pub fn put_object(agent: &Agent, base_url: &str, path: &str, data: &[u8]) -> Res
 /// Download an object.
 pub fn get_object(agent: &Agent, base_url: &str, path: &str) -> Result<Vec<u8>> {
     let url = format!("{}/{}", base_url, path);
-    let mut response = agent.get(&url).header(header::ACCEPT, ALL).call()?;
+    let mut response = send(|| agent.get(&url).header(header::ACCEPT, ALL).call())?;
     match response.status() {
-        StatusCode::UNAUTHORIZED => Err(anyhow!("Remote unauthorized")),
+        StatusCode::UNAUTHORIZED => Err(Unauthorized { write: false }.into()),
         StatusCode::NOT_FOUND => Err(anyhow!("Not found: Path {} does not exist", path)),
         StatusCode::FORBIDDEN => Err(anyhow!("Forbidden: Access denied to path {}", path)),
         _ if response.status().is_success() => Ok(response.body_mut().read_to_vec()?),
@@ -144,15 +908,41 @@ pub fn get_object(agent: &Agent, base_url: &str, path: &str) -> Result<Vec<u8>>
     }
 }
 
+/// Download an object if it exists.
+pub fn find_object(agent: &Agent, base_url: &str, path: &str) -> Result<Option<Vec<u8>>> {
+    let url = format!("{}/{}", base_url, path);
+    let mut response = send(|| agent.get(&url).header(header::ACCEPT, ALL).call())?;
+    match response.status() {
+        StatusCode::NOT_FOUND => Ok(None),
+        StatusCode::UNAUTHORIZED => Err(Unauthorized { write: false }.into()),
+        StatusCode::FORBIDDEN => Err(anyhow!("Forbidden: Access denied to path {}", path)),
+        _ if response.status().is_success() => Ok(Some(response.body_mut().read_to_vec()?)),
+        _ => Err(anyhow!(
+            "Failed to get object from {}: HTTP {}",
+            &url,
+            response.status()
+        )),
+    }
+}
+
 /// Delete an object.
 pub fn delete_object(agent: &Agent, base_url: &str, path: &str) -> Result<()> {
     let url = format!("{}/{}", base_url, path);
-    let response = agent.delete(&url).call()?;
+    let response = send(|| agent.delete(&url).call())?;
     match response.status() {
-        StatusCode::UNAUTHORIZED => Err(anyhow!("Remote unauthorized")),
-        StatusCode::NOT_FOUND => Err(anyhow!("Not found: Path {} does not exist", path)),
+        StatusCode::UNAUTHORIZED => Err(write_refused(&response)),
+        StatusCode::NOT_FOUND => Err(NotFound {
+            path: path.to_string(),
+        }
+        .into()),
         StatusCode::FORBIDDEN => Err(anyhow!("Forbidden: Access denied to path {}", path)),
         _ if response.status().is_success() => Ok(()),
+        status if is_contention(status) => Err(Contention {
+            action: "delete object from",
+            url,
+            status,
+        }
+        .into()),
         _ => Err(anyhow!(
             "Failed to delete object from {}: HTTP {}",
             &url,
@@ -182,6 +972,93 @@ pub fn strip_zone_prefix(path: &str) -> &str {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_keys() {
+        let key = |key: &str| Some(key.to_string());
+        let keys = Keys::new(&ApiKeys {
+            primary: key("primary"),
+            secondary: key("secondary"),
+            read_only: None,
+        })
+        .unwrap();
+        assert_eq!(
+            keys.current(false),
+            Some((&HeaderValue::from_static("primary"), Key::Primary))
+        );
+        // A refusal of the primary key switches to the secondary for good.
+        assert!(keys.unauthorized(Key::Primary));
+        assert_eq!(
+            keys.current(true),
+            Some((&HeaderValue::from_static("secondary"), Key::Secondary))
+        );
+        assert!(keys.unauthorized(Key::Primary));
+        assert!(!keys.unauthorized(Key::Secondary));
+
+        let single = Keys::new(&ApiKeys {
+            primary: key("primary"),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(!single.unauthorized(Key::Primary));
+        assert_eq!(single.current(true).unwrap().1, Key::Primary);
+
+        // Reads use the read-only key, and writes can't without another.
+        let read_only = ApiKeys {
+            read_only: key("read"),
+            ..Default::default()
+        };
+        assert!(!read_only.can_write());
+        let keys = Keys::new(&read_only).unwrap();
+        assert_eq!(keys.current(false).unwrap().1, Key::ReadOnly);
+        assert!(!keys.unauthorized(Key::ReadOnly));
+        assert!(keys.current(true).is_none());
+        let both = Keys::new(&ApiKeys {
+            primary: key("primary"),
+            read_only: key("read"),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(both.current(false).unwrap().1, Key::ReadOnly);
+        assert_eq!(both.current(true).unwrap().1, Key::Primary);
+    }
+
+    #[test]
+    fn test_each_key() {
+        let key = |key: &str| Some(key.to_string());
+        let each = ApiKeys {
+            primary: key("primary"),
+            secondary: None,
+            read_only: key("read"),
+        }
+        .each();
+        // Every key is sent for reads and writes alike, the read-only one
+        // included, so a probe shows what that key itself may do.
+        let keys: Vec<_> = each
+            .iter()
+            .map(|(which, keys)| (*which, keys.primary.as_deref()))
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                (Key::Primary, Some("primary")),
+                (Key::ReadOnly, Some("read"))
+            ]
+        );
+        assert!(
+            each.iter()
+                .all(|(_, keys)| keys.secondary.is_none() && keys.read_only.is_none())
+        );
+    }
+
+    #[test]
+    fn test_unauthorized() {
+        let read: anyhow::Error = Unauthorized { write: false }.into();
+        let write: anyhow::Error = Unauthorized { write: true }.into();
+        assert!(read.is::<Unauthorized>() && write.is::<Unauthorized>());
+        assert_eq!(read.to_string(), "Remote unauthorized");
+        assert!(write.to_string().contains("read-only password"));
+    }
+
     #[test]
     fn test_base_url() {
         assert_eq!(
@@ -193,6 +1070,112 @@ mod tests {
             Some("https://storage.bunnycdn.com".to_string())
         );
         assert_eq!(base_url("invalid"), None);
+        assert_eq!(region_code("sa"), Some("JH"));
+        assert_eq!(region_code("invalid"), None);
+    }
+
+    #[test]
+    fn test_route() {
+        let mut regions = RegionCache::default();
+        regions.insert("my-zone", "ny");
+        let router = Router {
+            agent: Agent::new_with_defaults(),
+            regions: Mutex::new(regions),
+            replicas: Mutex::new(HashMap::new()),
+        };
+        let mut req = Request::get("https://storage.bunnycdn.com/my-zone/css/a.css?x=1")
+            .body(SendBody::none())
+            .unwrap();
+        router.route(&mut req, &HeaderValue::from_static("key"));
+        assert_eq!(
+            req.uri().to_string(),
+            "https://ny.storage.bunnycdn.com/my-zone/css/a.css?x=1"
+        );
+
+        // Already meant for a region, like a download from a replica.
+        let mut req = Request::get("https://se.storage.bunnycdn.com/my-zone/a.css")
+            .body(SendBody::none())
+            .unwrap();
+        router.route(&mut req, &HeaderValue::from_static("key"));
+        assert_eq!(
+            req.uri().to_string(),
+            "https://se.storage.bunnycdn.com/my-zone/a.css"
+        );
+        assert!(is_region("https://se.storage.bunnycdn.com"));
+        assert!(!is_region("http://localhost:8080"));
+    }
+
+    #[test]
+    fn test_find_region() {
+        // Replicated to de, which is asked first, from its own region ny.
+        let replicated = |region, method| match region {
+            "ny" => true,
+            "de" => method == Method::GET,
+            _ => false,
+        };
+        assert_eq!(find_region(replicated), Some(("ny", true)));
+        // With only the read-only key, no region takes a delete.
+        let read_only = |region, method| region != "la" && method == Method::GET;
+        assert_eq!(find_region(read_only), Some(("de", false)));
+        assert_eq!(find_region(|_, _| false), None);
+    }
+
+    #[test]
+    fn test_endpoint() {
+        assert_eq!(
+            endpoint("https://storage.example.com:8443/").unwrap(),
+            "https://storage.example.com:8443"
+        );
+        assert_eq!(
+            endpoint("http://localhost:8080").unwrap(),
+            "http://localhost:8080"
+        );
+        assert!(endpoint("http://127.0.0.1:8080").is_ok());
+        assert!(endpoint("http://[::1]:8080").is_ok());
+        assert!(endpoint("http://storage.example.com").is_err());
+        assert!(endpoint("storage.example.com").is_err());
+        assert!(endpoint("ftp://localhost").is_err());
+        assert!(endpoint("https://").is_err());
+    }
+
+    #[test]
+    fn test_proxy() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        let storage = "https://storage.bunnycdn.com";
+        let uri = |proxy: Option<Proxy>| proxy.map(|proxy| proxy.uri().to_string());
+
+        assert_eq!(uri(proxy(None, storage, env(&[])).unwrap()), None);
+        let corporate = env(&[
+            ("https_proxy", "http://proxy.corp:3128"),
+            ("HTTP_PROXY", "http://plain.corp:3128"),
+            ("NO_PROXY", "localhost, .internal.corp"),
+        ]);
+        assert_eq!(
+            uri(proxy(None, storage, corporate).unwrap()).as_deref(),
+            Some("http://proxy.corp:3128/")
+        );
+        assert_eq!(
+            uri(proxy(None, "http://localhost:8080", corporate).unwrap()),
+            None
+        );
+        assert_eq!(
+            uri(proxy(None, "https://mock.internal.corp", corporate).unwrap()),
+            None
+        );
+        assert_eq!(
+            uri(proxy(Some("http://other:8080"), storage, corporate).unwrap()).as_deref(),
+            Some("http://other:8080/")
+        );
+        assert!(proxy(Some("http://"), storage, env(&[])).is_err());
+        assert!(bypasses_proxy("*", "storage.bunnycdn.com"));
+        assert!(bypasses_proxy("bunnycdn.com", "ny.storage.bunnycdn.com"));
+        assert!(!bypasses_proxy("cdn.com", "storage.bunnycdn.com"));
     }
 
     // Test deserialization of StorageObject.
@@ -200,28 +1183,99 @@ mod tests {
     fn test_storage_object_deserialization() {
         let json = "{\"Guid\":\"33ea1f9b-3012-4ddd-af33-24741c559ef0\",\"StorageZoneName\":\"my-storage-zone\",\"Path\":\"/my-storage-zone/\",\"ObjectName\":\"404.html\",\"Length\":11720,\"LastChanged\":\"2025-02-03T21:26:21.866\",\"ServerId\":12,\"ArrayNumber\":5,\"IsDirectory\":false,\"UserId\":\"0e64cafc-0bf2-47e1-9adc-257c80124475\",\"ContentType\":\"\",\"DateCreated\":\"2025-02-03T21:26:21.866\",\"StorageZoneId\":134123,\"Checksum\":\"312341234adfadsfasdf\",\"ReplicatedZones\":\"DE\"}";
 
-        let record: StorageObject = serde_json::from_str(&json).unwrap();
+        let record: StorageObject = serde_json::from_str(json).unwrap();
         let expect = StorageObject {
             guid: "33ea1f9b-3012-4ddd-af33-24741c559ef0".to_string(),
             storage_zone_name: "my-storage-zone".to_string(),
             path: "/my-storage-zone/".to_string(),
             object_name: "404.html".to_string(),
             length: 11720,
-            last_changed: NaiveDateTime::parse_from_str(
-                "2025-02-03T21:26:21.866",
-                "%Y-%m-%dT%H:%M:%S%.f",
-            )
-            .unwrap(),
+            last_changed: serde_json::from_str("\"2025-02-03T21:26:21.866\"").unwrap(),
             is_directory: false,
-            date_created: NaiveDateTime::parse_from_str(
-                "2025-02-03T21:26:21.866",
-                "%Y-%m-%dT%H:%M:%S%.f",
-            )
-            .unwrap(),
+            date_created: serde_json::from_str("\"2025-02-03T21:26:21.866\"").unwrap(),
+            server_id: 12,
+            array_number: 5,
+            user_id: "0e64cafc-0bf2-47e1-9adc-257c80124475".to_string(),
+            content_type: "".to_string(),
+            storage_zone_id: 134123,
+            checksum: Some("312341234adfadsfasdf".to_string()),
+            replicated_zones: Some("DE".to_string()),
         };
         assert_eq!(record, expect);
     }
 
+    // Directories come back with null checksums and replication info.
+    #[test]
+    fn test_directory_deserialization() {
+        let json = "{\"Guid\":\"ab\",\"StorageZoneName\":\"my-storage-zone\",\"Path\":\"/my-storage-zone/\",\"ObjectName\":\"css\",\"Length\":0,\"LastChanged\":\"2025-02-03T21:26:21.866\",\"ServerId\":0,\"ArrayNumber\":0,\"IsDirectory\":true,\"UserId\":\"0e64cafc-0bf2-47e1-9adc-257c80124475\",\"ContentType\":\"\",\"DateCreated\":\"2025-02-03T21:26:21.866\",\"StorageZoneId\":134123,\"Checksum\":null,\"ReplicatedZones\":null}";
+
+        let record: StorageObject = serde_json::from_str(json).unwrap();
+        assert!(record.is_directory);
+        assert_eq!(record.checksum, None);
+        assert_eq!(record.replicated_zones, None);
+    }
+
+    #[test]
+    fn test_walk() {
+        let object = |path: &str, name: &str, is_directory| StorageObject {
+            path: path.to_string(),
+            object_name: name.to_string(),
+            is_directory,
+            ..Default::default()
+        };
+        let list = |path: &str| match path {
+            "zone/" => Ok(vec![
+                object("/zone/", "a", true),
+                object("/zone/", "b", true),
+                object("/zone/", "index.html", false),
+            ]),
+            "/zone/a/" => Ok(vec![
+                object("/zone/a/", "deep", true),
+                object("/zone/a/", "one.txt", false),
+            ]),
+            "/zone/a/deep/" => Ok(vec![object("/zone/a/deep/", "two.txt", false)]),
+            "/zone/b/" => Ok(vec![]),
+            _ => Err(anyhow!("unexpected {}", path)),
+        };
+        let names = Mutex::new(Vec::new());
+        let listed = Mutex::new(Vec::new());
+        walk("zone/", 3, list, |path, records| {
+            listed.lock().unwrap().push(path.to_string());
+            let mut names = names.lock().unwrap();
+            for object in records {
+                names.push(format!("{}{}", object.path, object.object_name));
+            }
+            Ok(())
+        })
+        .unwrap();
+        let mut names = names.into_inner().unwrap();
+        names.sort();
+        let mut listed = listed.into_inner().unwrap();
+        listed.sort();
+        assert_eq!(
+            listed,
+            vec!["/zone/a/", "/zone/a/deep/", "/zone/b/", "zone/"]
+        );
+        assert_eq!(
+            names,
+            vec![
+                "/zone/a",
+                "/zone/a/deep",
+                "/zone/a/deep/two.txt",
+                "/zone/a/one.txt",
+                "/zone/b",
+                "/zone/index.html",
+            ]
+        );
+
+        let failing = |path: &str| match path {
+            "zone/" => Ok(vec![object("/zone/", "a", true)]),
+            _ => Err(anyhow!("listing failed")),
+        };
+        assert!(walk("zone/", 3, failing, |_, _| Ok(())).is_err());
+        assert!(walk("zone/", 3, list, |_, _| Err(anyhow!("stop"))).is_err());
+    }
+
     #[test]
     fn test_zone_name() {
         assert_eq!(zone_name("test/"), "test");
@@ -0,0 +1,386 @@
+//! The state directory, where bunnysync keeps what it remembers between
+//! runs: the state of bidirectional syncs, the hash, listing and zone caches
+//! and the history of runs. Each local directory gets a directory of its own
+//! in it, so nothing is left lying next to the files being synced.
+//!
+//! Files record the `version` of their layout and are brought up to date as
+//! they are read. They are replaced whole, so that a crash leaves either the
+//! old file or the new one and never half of one.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cache,
+    checksum::{Checksum, Xxh64},
+    history,
+    i18n::{Message, tr},
+    local, state,
+};
+
+/// The version of the layout of state files.
+pub const VERSION: u64 = 1;
+
+/// The steps bringing a file from each version to the next, starting with
+/// files from before versions were recorded, which only lack the version.
+const MIGRATIONS: [fn(&mut Value); VERSION as usize] = [|_| {}];
+
+/// Where the directories of local directories are kept in the state
+/// directory.
+const DIRS: &str = "dirs";
+
+/// The file in a local directory's directory saying which one it is for.
+const DIR_FILE: &str = "dir.json";
+
+#[derive(Serialize, Deserialize)]
+struct DirFile {
+    path: PathBuf,
+}
+
+/// The state directory: `$XDG_STATE_HOME/bunnysync`, where `XDG_STATE_HOME`
+/// defaults to `~/.local/state`, or `%LOCALAPPDATA%\bunnysync` on Windows.
+/// `BUNNYSYNC_STATE_DIR` overrides both.
+pub fn root() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|dir| Path::new(dir).is_absolute());
+    if let Some(dir) = var("BUNNYSYNC_STATE_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    let base = if cfg!(windows) {
+        var("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        var("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| var("HOME").map(|home| Path::new(&home).join(".local").join("state")))
+    }?;
+    Some(base.join("bunnysync"))
+}
+
+/// The directory holding the state of a local directory, taking over the
+/// files older versions kept in the local directory itself. Without a state
+/// directory, it is [`local::CACHE_DIR`] in the local directory.
+pub fn dir(local: &Path) -> PathBuf {
+    let Some(root) = root() else {
+        return local.join(local::CACHE_DIR);
+    };
+    let local = absolute(local);
+    let dir = dir_in(&root, &local);
+    if !dir.join(DIR_FILE).exists()
+        && let Err(e) = adopt(&local, &dir)
+    {
+        eprintln!("{}", tr(Message::Warning { warning: &e }));
+    }
+    dir
+}
+
+fn dir_in(root: &Path, local: &Path) -> PathBuf {
+    let hash = Xxh64.hex(local.to_string_lossy().as_bytes());
+    root.join(DIRS).join(hash.to_ascii_lowercase())
+}
+
+/// A path made absolute, with symbolic links resolved when it exists.
+fn absolute(path: &Path) -> PathBuf {
+    path.canonicalize()
+        .or_else(|_| std::path::absolute(path))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// The files older versions kept in a local directory, and their names in
+/// the state directory. Those without one are only a cache and are dropped.
+fn legacy_files(local: &Path) -> [(PathBuf, Option<&'static str>); 4] {
+    let cache_dir = local.join(local::CACHE_DIR);
+    [
+        (
+            local.join(local::LEGACY_STATE_FILE),
+            Some(state::STATE_FILE),
+        ),
+        (cache_dir.join(cache::HASHES_FILE), Some(cache::HASHES_FILE)),
+        (cache_dir.join("listings.json"), None),
+        (
+            local.join(local::LEGACY_HISTORY_FILE),
+            Some(history::HISTORY_FILE),
+        ),
+    ]
+}
+
+/// Start the directory of a local directory, moving in the files older
+/// versions kept in it.
+fn adopt(local: &Path, dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    for (legacy, name) in legacy_files(local) {
+        match name {
+            Some(name) if legacy.is_file() => move_file(&legacy, &dir.join(name))?,
+            None if legacy.is_file() => std::fs::remove_file(&legacy)?,
+            _ => {}
+        }
+    }
+    // Only removed once empty, as it is also where state goes without a
+    // state directory.
+    let _ = std::fs::remove_dir(local.join(local::CACHE_DIR));
+    write(
+        &dir.join(DIR_FILE),
+        &DirFile {
+            path: local.to_path_buf(),
+        },
+    )
+}
+
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if std::fs::rename(from, to).is_err() {
+        // Across file systems, which renaming can't do.
+        std::fs::copy(from, to)?;
+        std::fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+/// Read a state file, bringing it up to date. A missing or empty file gives
+/// `None`.
+pub fn read<T: DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if text.trim().is_empty() {
+        return Ok(None);
+    }
+    let unreadable =
+        |e: &dyn std::fmt::Display| anyhow!("Could not read {}: {}", path.display(), e);
+    let mut value: Value = serde_json::from_str(&text).map_err(|e| unreadable(&e))?;
+    migrate(&mut value).map_err(|e| unreadable(&e))?;
+    serde_json::from_value(value)
+        .map(Some)
+        .map_err(|e| unreadable(&e))
+}
+
+/// Bring the contents of a state file up to the current version.
+fn migrate(value: &mut Value) -> Result<()> {
+    let version = value.get("version").and_then(Value::as_u64).unwrap_or(0);
+    if version > VERSION {
+        return Err(anyhow!(tr(Message::NewerState { version })));
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(value);
+    }
+    if let Some(object) = value.as_object_mut() {
+        object.remove("version");
+    }
+    Ok(())
+}
+
+/// Write a state file at the current version.
+pub fn write<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let mut value = serde_json::to_value(value)?;
+    if let Some(object) = value.as_object_mut() {
+        object.insert("version".to_string(), VERSION.into());
+    }
+    replace(path, (serde_json::to_string(&value)? + "\n").as_bytes())
+}
+
+/// Change a state file while holding a lock on it, so that other runs
+/// changing it at the same time don't undo each other's changes. A file that
+/// can't be read is changed as if it were missing.
+pub fn update<T>(path: &Path, change: impl FnOnce(&mut T)) -> Result<()>
+where
+    T: Serialize + DeserializeOwned + Default,
+{
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // Locking the file itself wouldn't do, as it is replaced on writing.
+    let lock = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path.with_extension("lock"))?;
+    lock.lock()?;
+    let mut value = read(path).ok().flatten().unwrap_or_default();
+    change(&mut value);
+    write(path, &value)
+}
+
+/// Replace a file by writing next to it and renaming over it.
+fn replace(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let temporary = path.with_extension(format!("tmp-{}", std::process::id()));
+    let written = std::fs::File::create(&temporary).and_then(|mut file| {
+        file.write_all(data)?;
+        file.sync_all()
+    });
+    if let Err(e) = written.and_then(|_| std::fs::rename(&temporary, path)) {
+        let _ = std::fs::remove_file(&temporary);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// The state kept for a local directory.
+pub struct DirState {
+    /// The local directory, if it is known.
+    pub local: Option<PathBuf>,
+    pub dir: PathBuf,
+    /// The files in it and their sizes, by name.
+    pub files: Vec<(String, u64)>,
+}
+
+impl DirState {
+    fn new(dir: PathBuf) -> Result<Self> {
+        let local = read::<DirFile>(&dir.join(DIR_FILE))
+            .ok()
+            .flatten()
+            .map(|file| file.path);
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name == DIR_FILE || name.ends_with(".lock") {
+                continue;
+            }
+            if entry.file_type()?.is_dir() {
+                // Directories, like that of the listings, count as one.
+                let bytes = walkdir::WalkDir::new(entry.path())
+                    .into_iter()
+                    .filter_map(|entry| entry.ok()?.metadata().ok())
+                    .filter(|metadata| metadata.is_file())
+                    .map(|metadata| metadata.len())
+                    .sum();
+                files.push((name + "/", bytes));
+            } else {
+                files.push((name, entry.metadata()?.len()));
+            }
+        }
+        files.sort();
+        Ok(DirState { local, dir, files })
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.files.iter().map(|(_, bytes)| bytes).sum()
+    }
+}
+
+/// The state kept for every local directory.
+pub fn all() -> Result<Vec<DirState>> {
+    let Some(root) = root() else {
+        return Ok(Vec::new());
+    };
+    let dirs = match std::fs::read_dir(root.join(DIRS)) {
+        Ok(dirs) => dirs,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut states = Vec::new();
+    for entry in dirs {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            states.push(DirState::new(entry.path())?);
+        }
+    }
+    states.sort_by(|a, b| a.local.cmp(&b.local));
+    Ok(states)
+}
+
+/// The state kept for a local directory, if there is any, including files
+/// older versions kept in the local directory itself.
+pub fn find(local: &Path) -> Result<Option<DirState>> {
+    let Some(root) = root() else {
+        let dir = local.join(local::CACHE_DIR);
+        return if dir.is_dir() {
+            DirState::new(dir).map(Some)
+        } else {
+            Ok(None)
+        };
+    };
+    let kept = dir_in(&root, &absolute(local)).is_dir()
+        || legacy_files(local).iter().any(|(path, _)| path.is_file());
+    if !kept {
+        return Ok(None);
+    }
+    DirState::new(dir(local)).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_read_and_write() {
+        let dir = std::env::temp_dir().join(format!("bunnysync-store-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("listings.json");
+        assert!(read::<BTreeMap<String, u64>>(&path).unwrap().is_none());
+
+        let value = BTreeMap::from([("a".to_string(), 1u64)]);
+        write(&path, &value).unwrap();
+        assert!(
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .contains("\"version\":1")
+        );
+        assert_eq!(read(&path).unwrap(), Some(value.clone()));
+        // Files from before versions are read as they are, and files from a
+        // later version not at all.
+        std::fs::write(&path, r#"{"a": 1}"#).unwrap();
+        assert_eq!(read(&path).unwrap(), Some(value));
+        std::fs::write(&path, r#"{"a": 1, "version": 99}"#).unwrap();
+        assert!(read::<BTreeMap<String, u64>>(&path).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_update_concurrently() {
+        let dir = std::env::temp_dir().join(format!("bunnysync-update-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("counts.json");
+        std::thread::scope(|scope| {
+            for run in 0..8 {
+                let path = &path;
+                scope.spawn(move || {
+                    for _ in 0..10 {
+                        update(path, |counts: &mut BTreeMap<String, u64>| {
+                            *counts.entry("total".to_string()).or_default() += 1;
+                            *counts.entry(format!("run {}", run)).or_default() += 1;
+                        })
+                        .unwrap();
+                    }
+                });
+            }
+        });
+        let counts: BTreeMap<String, u64> = read(&path).unwrap().unwrap();
+        assert_eq!(counts["total"], 80);
+        assert_eq!(counts.len(), 9);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_adopt() {
+        let base = std::env::temp_dir().join(format!("bunnysync-adopt-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        let local = base.join("site");
+        std::fs::create_dir_all(local.join(local::CACHE_DIR)).unwrap();
+        std::fs::write(local.join(local::LEGACY_STATE_FILE), "{}").unwrap();
+        std::fs::write(local.join(local::CACHE_DIR).join(cache::HASHES_FILE), "{}").unwrap();
+
+        let dir = dir_in(&base.join("state"), &local);
+        adopt(&local, &dir).unwrap();
+        assert!(!local.join(local::LEGACY_STATE_FILE).exists());
+        assert!(!local.join(local::CACHE_DIR).exists());
+        let state = DirState::new(dir).unwrap();
+        assert_eq!(state.local, Some(local));
+        assert_eq!(
+            state.files,
+            vec![
+                (cache::HASHES_FILE.to_string(), 2),
+                (state::STATE_FILE.to_string(), 2)
+            ]
+        );
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+}
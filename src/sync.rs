@@ -0,0 +1,2449 @@
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, TimeDelta, Utc};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    hash::BuildHasher,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+use ureq::Agent;
+
+use crate::{
+    api,
+    backoff::Backoff,
+    cache::{self, HashCache, ListingCache},
+    checksum,
+    commands::cdn,
+    exit::ChangesFound,
+    filter::{self, Filter, IgnoreRules},
+    freeze,
+    history::{self, RunRecord},
+    i18n::{Message, tr},
+    interrupt,
+    journal::Journal,
+    local,
+    manifest::{self, Manifest},
+    output::{Event, OutputFormat, Reporter, WarningKind},
+    paths::{self, PathMap},
+    plan::{self, ConflictPolicy, PlanFile, RemoteIndex, SkipReason, SyncAction, object_key},
+    plugin::{self, Plugins},
+    prompt::{self, Answer},
+    s3::Bucket,
+    site_checks,
+    state::SyncState,
+    storage::{self, AgentOptions, StorageObject},
+    store,
+    timestamp::RemoteTime,
+    transform::{self, Pipeline, TransformRule},
+    units, watch,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct SyncArgs {
+    /// The source directory or storage zone. Storage zones have prefix zone://.
+    /// Without a trailing slash the directory itself is synced into the
+    /// destination, with one only its contents
+    source: String,
+
+    /// The destination directory or storage zone. Storage zones have prefix
+    /// zone://. Defaults to the zone set in .bunnysync
+    #[arg(default_value_t = String::new(), hide_default_value = true)]
+    pub destination: String,
+
+    /// Sync with this directory inside the zone, as if it were part of the
+    /// zone URL, e.g. --prefix blog/ with zone://my-zone/
+    #[arg(long, value_name = "PATH")]
+    prefix: Option<String>,
+
+    /// Perform a dry run
+    #[arg(long = "dryrun")]
+    dry_run: bool,
+
+    /// With --dryrun, exit with status 4 when the sync would change
+    /// anything and 0 when it wouldn't
+    #[arg(long, requires = "dry_run", conflicts_with = "watch")]
+    exit_code: bool,
+
+    /// Delete files that are not in the source directory
+    #[arg(long)]
+    delete: bool,
+
+    /// Treat files of the same size as unchanged when their modification
+    /// times are no more than this far apart, e.g. 2s
+    #[arg(long, value_name = "DURATION", default_value = "0", value_parser = units::parse_duration)]
+    modify_window: Duration,
+
+    /// Leave files modified less than this long ago for a later sync, as
+    /// they may still be being written, e.g. 30s, 5m or 1h
+    #[arg(long, value_name = "DURATION", value_parser = units::parse_duration)]
+    min_age: Option<Duration>,
+
+    /// Give downloaded files the current time as their modification time
+    /// instead of the remote one
+    #[arg(long)]
+    no_times: bool,
+
+    /// When pulling, hard link files from this earlier copy of the zone
+    /// instead of downloading them, if they have the same size and
+    /// modification time there
+    #[arg(long, value_name = "DIR")]
+    link_dest: Option<PathBuf>,
+
+    /// Abort before deleting anything when the sync would delete more than
+    /// this many files, or this percentage of the destination's files when
+    /// given as e.g. 10%
+    #[arg(long, value_name = "N[%]")]
+    max_delete: Option<DeleteLimit>,
+
+    /// Delete even when --max-delete would abort the sync
+    #[arg(long)]
+    force: bool,
+
+    /// After pushing, download this percentage of the uploaded files, e.g.
+    /// 5%, and check they match what was sent
+    #[arg(long, value_name = "N%", value_parser = parse_percent)]
+    spot_check: Option<f64>,
+
+    /// Before uploading, check that the site has an index.html and a
+    /// 404.html and that no page links to localhost
+    #[arg(long)]
+    site_checks: bool,
+
+    /// Transfer files matching this pattern before the others, e.g. assets
+    /// that new pages depend on. Matches the file name or the path within
+    /// the zone
+    #[arg(long, value_name = "PATTERN")]
+    priority: Vec<String>,
+
+    /// Transfer files matching this pattern after the others, e.g.
+    /// index.html, so they flip over last
+    #[arg(long, value_name = "PATTERN")]
+    priority_last: Vec<String>,
+
+    /// Ask before each upload, download or delete
+    #[arg(short, long, conflicts_with_all = ["dry_run", "watch"])]
+    interactive: bool,
+
+    /// Exclude files that match a pattern. You can use * as a wildcard
+    #[arg(long = "exclude", value_parser, num_args = 1.., value_delimiter = ',')]
+    pub exclude: Vec<String>,
+
+    /// Read exclude patterns from a file, one per line. Lines starting with
+    /// # are comments
+    #[arg(long, value_name = "FILE")]
+    exclude_from: Vec<PathBuf>,
+
+    /// Sync files that match a pattern even if an exclude pattern or ignore
+    /// file says otherwise
+    #[arg(long = "include", value_parser, num_args = 1.., value_delimiter = ',')]
+    include: Vec<String>,
+
+    /// Read include patterns from a file, one per line. Lines starting with
+    /// # are comments
+    #[arg(long, value_name = "FILE")]
+    include_from: Vec<PathBuf>,
+
+    /// When pushing, transfer only the files listed in this file, one path
+    /// relative to the source per line, or - to read the list from stdin.
+    /// The zone isn't listed, so listed files are always uploaded. With
+    /// --delete, listed files that don't exist locally are deleted from the
+    /// zone
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["bidirectional", "watch", "manifest", "cached_listing", "max_delete"]
+    )]
+    files_from: Option<PathBuf>,
+
+    /// When pushing, transfer only the files git reports as changed between
+    /// this commit, branch or tag and the working tree. Files deleted since
+    /// are deleted from the zone. Files git doesn't track are left out
+    #[arg(
+        long,
+        value_name = "REF",
+        conflicts_with_all = ["files_from", "bidirectional", "watch", "manifest", "cached_listing", "max_delete"]
+    )]
+    git_changed: Option<String>,
+
+    /// Also leave out files that git ignores, going by the .gitignore files
+    /// in the local directory and .git/info/exclude
+    #[arg(long)]
+    git_ignore: bool,
+
+    /// Carry changes both ways, using a snapshot of the last sync to tell
+    /// deletions apart from new files. Files changed on both sides are
+    /// resolved by --conflict
+    #[arg(long, conflicts_with_all = ["watch", "plan"])]
+    bidirectional: bool,
+
+    /// With --bidirectional, what to do with files changed on both sides
+    /// since the last sync
+    #[arg(long, value_enum, default_value_t = ConflictPolicy::Skip, requires = "bidirectional")]
+    conflict: ConflictPolicy,
+
+    /// With --dryrun, save the planned actions to a file for `bunnysync
+    /// apply` to carry out later
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "dry_run",
+        conflicts_with = "watch"
+    )]
+    plan: Option<PathBuf>,
+
+    /// Don't read or write the cache of file hashes and zone listings kept
+    /// in the state directory
+    #[arg(long)]
+    no_cache: bool,
+
+    /// When pushing, use the zone listing cached by the last push instead of
+    /// listing the zone again. Only safe when nothing else changes the zone
+    #[arg(long, conflicts_with_all = ["no_cache", "bidirectional"])]
+    cached_listing: bool,
+
+    /// When pushing, read the zone's contents from a manifest the last push
+    /// left in the zone instead of listing every directory, and write a new
+    /// one afterwards. Only safe when nothing else changes the zone
+    #[arg(long, conflicts_with_all = ["bidirectional", "watch"])]
+    manifest: bool,
+
+    /// After the initial sync, keep watching the local source directory and
+    /// push files to the zone as they change
+    #[arg(long)]
+    watch: bool,
+
+    /// Stop starting transfers once the sync has run this long, e.g. 10m,
+    /// printing what was done and failing. Transfers under way finish first
+    #[arg(long, value_name = "DURATION", value_parser = units::parse_duration, conflicts_with = "watch")]
+    deadline: Option<Duration>,
+
+    /// Carry on from where the last run of this sync was cut short, skipping
+    /// the transfers and deletes it got done
+    #[arg(long, conflicts_with_all = ["dry_run", "watch"])]
+    resume: bool,
+
+    /// Go on with the rest of the files when one fails to transfer or
+    /// delete. The failures are listed at the end, and the exit status is 3
+    #[arg(long)]
+    continue_on_error: bool,
+
+    /// After a sync that changed the zone, purge the uploaded and deleted
+    /// files from the CDN cache of --pull-zone
+    #[arg(long, conflicts_with = "watch")]
+    purge: bool,
+
+    /// After a sync that changed the zone, purge everything --pull-zone has
+    /// cached
+    #[arg(long, conflicts_with_all = ["watch", "purge"])]
+    purge_all: bool,
+
+    /// The pull zone serving the storage zone, by ID or hostname, for
+    /// --purge and --purge-all
+    #[arg(long, value_name = "ID|HOSTNAME")]
+    pub pull_zone: Option<String>,
+
+    /// Your bunny.net account API key, needed to purge
+    #[arg(long, env = "BUNNYSYNC_ACCOUNT_KEY")]
+    pub account_key: Option<String>,
+
+    /// Warn before syncing when the zone would grow past this size, e.g.
+    /// 10GiB
+    #[arg(long, value_name = "SIZE", env = "BUNNYSYNC_QUOTA_WARN", value_parser = units::parse_size)]
+    pub quota_warn: Option<u64>,
+
+    /// How to print results. JSON prints one object per file and a final
+    /// summary, and sends status messages to stderr
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Print a detailed breakdown of the run instead of a one line summary
+    #[arg(long)]
+    stats: bool,
+
+    /// Print a stable hash in place of each file's path, so logs can be
+    /// shared without showing what is in the site. Counts and sizes are kept
+    #[arg(long)]
+    redact_paths: bool,
+
+    /// Remove metadata such as GPS position from JPEG and PNG images before
+    /// uploading them
+    #[arg(long)]
+    strip_exif: bool,
+
+    /// Transforms to apply to uploads, from the config file
+    #[arg(skip)]
+    pub transforms: Vec<TransformRule>,
+
+    /// Plugins to run, from the config file
+    #[arg(skip)]
+    pub plugins: Plugins,
+
+    /// Whether only the read-only API key was given
+    #[arg(skip)]
+    pub read_only: bool,
+
+    /// When downloading from a zone replicated to other regions, download
+    /// from whichever region answers fastest, falling back to the zone's own
+    /// region on errors
+    #[arg(long)]
+    auto_region: bool,
+}
+
+/// Options shared by both sync directions.
+#[derive(Default)]
+pub struct SyncOptions {
+    pub dry_run: bool,
+    pub delete: bool,
+    /// Don't give downloaded files their remote modification time.
+    pub no_times: bool,
+    /// How far apart modification times may be for files to count as
+    /// unchanged.
+    pub modify_window: TimeDelta,
+    /// How long ago a file must have last changed to be uploaded.
+    pub min_age: Option<TimeDelta>,
+    /// An earlier copy of the zone to take unchanged files from.
+    pub link_dest: Option<Snapshot>,
+    pub filter: Filter,
+    pub quota_warn: Option<u64>,
+    pub max_delete: Option<DeleteLimit>,
+    pub force: bool,
+    pub spot_check: Option<f64>,
+    pub interactive: bool,
+    pub site_checks: bool,
+    pub conflict: ConflictPolicy,
+    pub cache: bool,
+    pub cached_listing: bool,
+    pub manifest: bool,
+    pub priority: Vec<String>,
+    pub priority_last: Vec<String>,
+    pub transforms: Pipeline,
+    /// Plugins that may change the plan before anything is transferred.
+    pub planners: Vec<String>,
+    /// Take uploads from here rather than from local files.
+    pub origin: Option<Origin>,
+    /// A faster region's copy of the zone to download from, falling back to
+    /// the zone's own region on errors.
+    pub replica: Option<String>,
+    /// When to stop starting transfers.
+    pub deadline: Option<Instant>,
+    /// Where to note what is done, so that `--resume` can skip it.
+    pub journal: Option<Journal>,
+    /// Note files that fail and go on with the rest.
+    pub continue_on_error: bool,
+}
+
+/// A sync stopped at its deadline, with what was done so far kept.
+#[derive(Debug)]
+pub struct DeadlineReached;
+
+impl fmt::Display for DeadlineReached {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", tr(Message::DeadlineReached))
+    }
+}
+
+impl std::error::Error for DeadlineReached {}
+
+/// A sync stopped by Ctrl-C, with what was done so far kept.
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", tr(Message::Interrupted))
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// A sync that went on past files that failed, with `--continue-on-error`.
+#[derive(Debug)]
+pub struct FilesFailed {
+    pub count: u64,
+}
+
+impl fmt::Display for FilesFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", tr(Message::FilesFailed { count: self.count }))
+    }
+}
+
+impl std::error::Error for FilesFailed {}
+
+/// Where uploads come from when they aren't local files.
+pub enum Origin {
+    /// An S3 bucket. Upload paths are object URLs.
+    Bucket(Bucket),
+    /// Another zone, which may need its own key. Upload paths are keys in
+    /// that zone.
+    Zone { agent: Agent, base_url: String },
+}
+
+impl Origin {
+    /// The contents of an upload. One that has gone is an io NotFound error,
+    /// like a local file that has.
+    fn read(&self, path: &str) -> Result<Vec<u8>> {
+        match self {
+            Origin::Bucket(bucket) => bucket.download(path),
+            Origin::Zone { agent, base_url } => {
+                storage::find_object(agent, base_url, path.trim_start_matches('/'))?
+                    .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound).into())
+            }
+        }
+    }
+}
+
+/// An earlier pull of the zone directory, laid out like the local one.
+pub struct Snapshot {
+    pub dir: PathBuf,
+    pub paths: PathMap,
+}
+
+/// The most files a sync may delete.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeleteLimit {
+    Count(usize),
+    /// A percentage of the files present at the destination.
+    Percent(f64),
+}
+
+impl DeleteLimit {
+    /// Check whether deleting `deletes` of the `existing` destination files
+    /// stays within the limit.
+    fn allows(&self, deletes: usize, existing: usize) -> bool {
+        match *self {
+            DeleteLimit::Count(max) => deletes <= max,
+            DeleteLimit::Percent(max) => deletes as f64 <= existing as f64 * max / 100.0,
+        }
+    }
+}
+
+impl FromStr for DeleteLimit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.ends_with('%') {
+            Ok(DeleteLimit::Percent(parse_percent(s)?))
+        } else {
+            Ok(DeleteLimit::Count(
+                s.parse().map_err(|_| anyhow!("invalid count {}", s))?,
+            ))
+        }
+    }
+}
+
+impl fmt::Display for DeleteLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeleteLimit::Count(max) => write!(f, "{}", max),
+            DeleteLimit::Percent(max) => write!(f, "{}%", max),
+        }
+    }
+}
+
+/// Parse a percentage between 0 and 100. The % sign is optional.
+pub fn parse_percent(s: &str) -> Result<f64> {
+    let percent: f64 = s
+        .strip_suffix('%')
+        .unwrap_or(s)
+        .parse()
+        .map_err(|_| anyhow!("invalid percentage {}", s))?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err(anyhow!("percentage must be between 0 and 100"));
+    }
+    Ok(percent)
+}
+
+/// Sync in whichever direction the source and destination describe. The
+/// agent options are for the requests made to the API, as for purging.
+pub fn run(
+    agent: &Agent,
+    base_url: &str,
+    args: SyncArgs,
+    agent_options: &AgentOptions,
+) -> Result<()> {
+    let mut record = RunRecord::start(&args.source, &args.destination, args.dry_run);
+    let mut reporter = Reporter::new(args.output, args.dry_run, args.stats);
+    if args.redact_paths {
+        reporter.redact_paths();
+    }
+    let exit_code = args.exit_code;
+    let finished = args.plugins.finished.clone();
+    interrupt::install();
+    let result = sync(agent, base_url, args, agent_options, &mut reporter);
+    // What got done before the deadline or Ctrl-C is worth knowing.
+    if let Err(e) = &result
+        && (e.is::<DeadlineReached>() || e.is::<Interrupted>())
+    {
+        reporter.finish();
+    }
+    // The rest of the files were synced, but the run still failed.
+    let result = result.and_then(|changed| match reporter.summary().failed {
+        0 => Ok(changed),
+        count => Err(FilesFailed { count }.into()),
+    });
+    record.finish(&result, reporter.summary());
+    if let Err(e) = history::append(&store::dir(Path::new(".")), &record) {
+        eprintln!("{}", tr(Message::Warning { warning: &e }));
+    }
+    plugin::finished(&finished, &record);
+    let changed = result?;
+    if exit_code && changed {
+        return Err(ChangesFound.into());
+    }
+    Ok(())
+}
+
+/// Carry out a sync, returning whether it changed anything or on a dry run
+/// would have.
+fn sync(
+    agent: &Agent,
+    base_url: &str,
+    mut args: SyncArgs,
+    agent_options: &AgentOptions,
+    reporter: &mut Reporter,
+) -> Result<bool> {
+    let started = Instant::now();
+    if args.destination.is_empty() {
+        return Err(anyhow!(tr(Message::DestinationNeeded)));
+    }
+    if args.strip_exif {
+        args.transforms.push(TransformRule {
+            pattern: transform::IMAGE_PATTERN.to_string(),
+            name: "strip-exif".to_string(),
+        });
+    }
+    // Following rsync, a source without a trailing slash is synced as a
+    // directory of the same name inside the destination. The prefix counts
+    // as part of the zone URL for this.
+    let source_name = match &args.prefix {
+        Some(prefix) if is_zone(&args.source) => {
+            paths::source_name(&format!("{}/{}", args.source.trim_end_matches('/'), prefix))
+        }
+        _ => paths::source_name(&args.source),
+    };
+    // The prefix is a part of the zone URL from here on, so that plans and
+    // the sync state record where files really went.
+    let zone_arg = if is_zone(&args.source) {
+        &mut args.source
+    } else {
+        &mut args.destination
+    };
+    if let Some(prefix) = &args.prefix
+        && is_zone(zone_arg)
+    {
+        *zone_arg = PathMap::new(zone_arg).with_prefix(prefix)?.url();
+    }
+    let destination_base = args.destination.clone();
+    if let Some(name) = source_name
+        && is_zone(&args.source) != is_zone(&args.destination)
+    {
+        args.destination = if is_zone(&args.destination) {
+            PathMap::new(&args.destination).with_prefix(&name)?.url()
+        } else {
+            let local = Path::new(&args.destination).join(&name);
+            if !args.dry_run && local.parent().is_some_and(Path::exists) {
+                std::fs::create_dir_all(&local)?;
+            }
+            local.to_string_lossy().into_owned()
+        };
+    }
+    let (local_arg, zone_arg) = if is_zone(&args.source) {
+        (&args.destination, &args.source)
+    } else {
+        (&args.source, &args.destination)
+    };
+    // Make sure the local side is there before going to the network.
+    if is_zone(&args.source) != is_zone(&args.destination) {
+        if args.bidirectional || !is_zone(&args.source) {
+            if !Path::new(local_arg).exists() {
+                return Err(anyhow!(tr(Message::SourceMissing)));
+            }
+        } else if !Path::new(&destination_base).exists() {
+            // A directory named after the source is made in it as needed.
+            return Err(anyhow!(tr(Message::DestinationMissing)));
+        }
+    }
+    // A frozen zone can still be pulled from, just not changed, and so can
+    // one with only the read-only key. Fail now rather than at the first
+    // upload.
+    if !args.dry_run
+        && (is_zone(&args.destination) || args.bidirectional)
+        && is_zone(&args.source) != is_zone(&args.destination)
+    {
+        if args.read_only {
+            return Err(storage::ReadOnlyKey.into());
+        }
+        freeze::check(agent, base_url, zone_arg)?;
+    }
+    // Make sure purging can work before changing anything.
+    let purge = if (args.purge || args.purge_all) && !args.dry_run {
+        let pull_zone =
+            (args.pull_zone.clone()).ok_or_else(|| anyhow!(tr(Message::PurgeNeedsPullZone)))?;
+        let account_key =
+            (args.account_key.as_deref()).ok_or_else(|| anyhow!(tr(Message::MissingAccountKey)))?;
+        let api = api::Client::new(account_key, agent_options)?;
+        Some((api, pull_zone))
+    } else {
+        None
+    };
+    let paths = PathMap::new(zone_arg);
+    let mut ignore = if args.git_ignore {
+        IgnoreRules::load_git(Path::new(local_arg))?
+    } else {
+        IgnoreRules::default()
+    };
+    ignore.extend(IgnoreRules::load(Path::new(local_arg))?);
+    for file in &args.exclude_from {
+        args.exclude.extend(filter::read_patterns(file)?);
+    }
+    for file in &args.include_from {
+        args.include.extend(filter::read_patterns(file)?);
+    }
+    let filter = Filter::new(args.exclude, ignore, paths.root()).with_include(args.include);
+    // Only worth asking around when downloading from a zone in the regions,
+    // not at an endpoint given instead.
+    let replica = if args.auto_region
+        && !args.dry_run
+        && (is_zone(&args.source) || args.bidirectional)
+        && is_zone(&args.source) != is_zone(&args.destination)
+        && storage::is_region(base_url)
+    {
+        storage::fastest_region(agent, paths.zone()).and_then(|(region, took)| {
+            reporter.message(&tr(Message::FastestRegion {
+                region,
+                millis: took.as_millis(),
+            }));
+            storage::base_url(region).filter(|url| url != base_url)
+        })
+    } else {
+        None
+    };
+    let mut options = SyncOptions {
+        dry_run: args.dry_run,
+        // Files deleted since the ref are deleted from the zone too.
+        delete: args.delete || args.git_changed.is_some(),
+        no_times: args.no_times,
+        modify_window: TimeDelta::from_std(args.modify_window).unwrap_or(TimeDelta::MAX),
+        min_age: args
+            .min_age
+            .map(|min_age| TimeDelta::from_std(min_age).unwrap_or(TimeDelta::MAX)),
+        link_dest: args.link_dest.map(|dir| Snapshot { dir, paths }),
+        filter,
+        quota_warn: args.quota_warn,
+        max_delete: args.max_delete,
+        force: args.force,
+        spot_check: args.spot_check,
+        interactive: args.interactive,
+        site_checks: args.site_checks,
+        conflict: args.conflict,
+        cache: !args.no_cache,
+        cached_listing: args.cached_listing,
+        manifest: args.manifest,
+        priority: args.priority,
+        priority_last: args.priority_last,
+        transforms: Pipeline::new(&args.transforms)?,
+        planners: args.plugins.plan.clone(),
+        origin: None,
+        replica,
+        deadline: args.deadline.map(|deadline| started + deadline),
+        journal: (!args.dry_run && is_zone(&args.source) != is_zone(&args.destination))
+            .then(|| {
+                Journal::start(
+                    &store::dir(Path::new(local_arg)),
+                    &args.source,
+                    &args.destination,
+                    args.resume,
+                )
+            })
+            .transpose()?,
+        continue_on_error: args.continue_on_error,
+    };
+    if let Some(journal) = &options.journal
+        && journal.resumed() > 0
+    {
+        reporter.message(&tr(Message::Resuming {
+            count: journal.resumed(),
+        }));
+    }
+
+    let actions = if args.bidirectional && is_zone(&args.source) != is_zone(&args.destination) {
+        let (local, remote) = if is_zone(&args.source) {
+            (&args.destination, &args.source)
+        } else {
+            (&args.source, &args.destination)
+        };
+        sync_bidirectional(agent, base_url, local, remote, &options, reporter)?
+    } else if !is_zone(&args.source) && is_zone(&args.destination) {
+        let names = match (&args.files_from, &args.git_changed) {
+            (Some(list), _) => Some(read_file_list(list)?),
+            (None, Some(reference)) => Some(git_changed(&args.source, reference)?),
+            (None, None) => None,
+        };
+        let actions = match names {
+            Some(names) => push_files_from(
+                agent,
+                base_url,
+                &args.source,
+                &args.destination,
+                &names,
+                &options,
+                reporter,
+            )?,
+            None => sync_to_remote(
+                agent,
+                base_url,
+                &args.source,
+                &args.destination,
+                &options,
+                reporter,
+            )?,
+        };
+        if args.watch {
+            reporter.finish();
+            reporter.message(&tr(Message::InitialSyncComplete));
+            if let Some(journal) = options.journal.take() {
+                journal.remove()?;
+            }
+            // Watching changes the zone behind the cached listing's back.
+            if options.cache && !options.dry_run {
+                ListingCache::remove(
+                    &store::dir(Path::new(&args.source)),
+                    storage::strip_zone_prefix(&args.destination),
+                )?;
+            }
+            watch::watch_to_remote(
+                agent,
+                base_url,
+                &args.source,
+                &args.destination,
+                &options,
+                reporter,
+            )?;
+            return Ok(false);
+        }
+        actions
+    } else if is_zone(&args.source) && !is_zone(&args.destination) {
+        if args.watch {
+            return Err(anyhow!(tr(Message::WatchNeedsZone)));
+        }
+        let push_only = [
+            ("--files-from", args.files_from.is_some()),
+            ("--git-changed", args.git_changed.is_some()),
+        ];
+        if let Some((flag, _)) = push_only.iter().find(|(_, given)| *given) {
+            return Err(anyhow!(tr(Message::PushOnly { flag })));
+        }
+        sync_to_local(
+            agent,
+            base_url,
+            &args.destination,
+            &args.source,
+            &options,
+            reporter,
+        )?
+    } else {
+        return Err(anyhow!(tr(Message::InvalidSourceAndDestination)));
+    };
+    if let Some(journal) = options.journal.take() {
+        journal.remove()?;
+    }
+    let changes = actions
+        .iter()
+        .any(|action| !matches!(action, SyncAction::Skip { .. }));
+    if let Some((api, pull_zone)) = &purge {
+        purge_cdn(api, pull_zone, args.purge_all, &actions, reporter)?;
+    }
+    if let Some(file) = &args.plan {
+        let count = actions.len();
+        PlanFile {
+            version: PlanFile::VERSION,
+            source: args.source,
+            destination: args.destination,
+            transforms: args.transforms,
+            actions,
+        }
+        .save(file)?;
+        reporter.message(&tr(Message::PlanSaved {
+            count,
+            file: &file.to_string_lossy(),
+        }));
+    }
+    reporter.finish();
+    reporter.message(&tr(Message::SyncComplete));
+    Ok(changes)
+}
+
+/// Purge what a sync changed in the zone from the CDN cache, for --purge
+/// and --purge-all.
+pub fn purge_cdn(
+    api: &api::Client,
+    pull_zone: &str,
+    all: bool,
+    actions: &[SyncAction],
+    reporter: &mut Reporter,
+) -> Result<()> {
+    let paths = changed_paths(actions);
+    if paths.is_empty() {
+        return Ok(());
+    }
+    if all {
+        cdn::purge_all(api, pull_zone)?;
+        reporter.message(&tr(Message::PurgedAll { pull_zone }));
+    } else {
+        cdn::purge_paths(api, pull_zone, &paths)?;
+        reporter.message(&tr(Message::Purged {
+            count: paths.len(),
+            pull_zone,
+        }));
+    }
+    Ok(())
+}
+
+/// The paths within the zone, like `css/site.css`, of the files a sync
+/// uploaded or deleted.
+fn changed_paths(actions: &[SyncAction]) -> Vec<String> {
+    actions
+        .iter()
+        .filter_map(|action| match action {
+            SyncAction::Upload { key, .. } | SyncAction::DeleteRemote { key, .. } => key
+                .trim_start_matches('/')
+                .split_once('/')
+                .map(|(_, path)| path.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn sync_to_remote(
+    agent: &Agent,
+    base_url: &str,
+    local: &str,
+    remote: &str,
+    options: &SyncOptions,
+    reporter: &mut Reporter,
+) -> Result<Vec<SyncAction>> {
+    let remote = storage::strip_zone_prefix(remote);
+    let paths = PathMap::new(remote);
+    let cached = (options.cache && options.cached_listing)
+        .then(|| ListingCache::load(&store::dir(Path::new(local)), remote))
+        .flatten();
+    let found = match cached {
+        None if options.manifest => Manifest::fetch(agent, base_url, remote)?,
+        _ => None,
+    };
+    let local_files = get_local_file_map(local, &paths, options, Some(reporter))?;
+    if options.site_checks {
+        let problems = site_checks::check_site(&local_files)?;
+        if !problems.is_empty() {
+            let mut message = tr(Message::SiteChecksFailed);
+            for problem in &problems {
+                message.push_str("\n  ");
+                message.push_str(&tr(Message::SiteProblem { problem }));
+            }
+            return Err(anyhow!(message));
+        }
+    }
+    let cache = options.cache && !options.dry_run;
+    let (mut objects, actions, pending, manifest_exists) = match (cached, found) {
+        (Some(objects), _) => push_planned(
+            agent,
+            base_url,
+            local,
+            remote,
+            objects,
+            false,
+            &local_files,
+            options,
+            reporter,
+        )?,
+        (None, Some(found)) => push_planned(
+            agent,
+            base_url,
+            local,
+            remote,
+            found.objects(),
+            true,
+            &local_files,
+            options,
+            reporter,
+        )?,
+        (None, None) if can_pipeline(options) => {
+            if cache {
+                ListingCache::remove(&store::dir(Path::new(local)), remote)?;
+            }
+            push_pipelined(agent, base_url, remote, &local_files, options, reporter)?
+        }
+        (None, None) => push_planned(
+            agent,
+            base_url,
+            local,
+            remote,
+            missing_as_empty(storage::get_all_objects(agent, base_url, remote), remote)?,
+            false,
+            &local_files,
+            options,
+            reporter,
+        )?,
+    };
+    let changes = actions
+        .iter()
+        .any(|action| !matches!(action, SyncAction::Skip { .. }));
+    if options.manifest && !options.dry_run && (changes || !manifest_exists) {
+        write_manifest(
+            agent,
+            base_url,
+            remote,
+            &mut objects,
+            &actions,
+            &pending,
+            options,
+        )?;
+    }
+    if cache {
+        ListingCache::save(&store::dir(Path::new(local)), remote, objects)?;
+    }
+    if let Some(percent) = options.spot_check
+        && !options.dry_run
+    {
+        let uploaded: Vec<_> = actions
+            .iter()
+            .filter(|action| {
+                matches!(action, SyncAction::Upload { .. })
+                    && !pending.iter().any(|key| key == action.key())
+            })
+            .collect();
+        spot_check(agent, base_url, &uploaded, percent, options, reporter)?;
+    }
+    Ok(actions)
+}
+
+/// What a push did: the zone listing as it is afterwards, the actions,
+/// the keys left pending and whether the zone had a manifest beforehand.
+type Pushed = (Vec<StorageObject>, Vec<SyncAction>, Vec<String>, bool);
+
+/// Whether transfers may start while the zone is still being listed. Some
+/// options need the whole plan before anything changes.
+fn can_pipeline(options: &SyncOptions) -> bool {
+    !options.interactive
+        && options.quota_warn.is_none()
+        && options.priority.is_empty()
+        && options.priority_last.is_empty()
+        && options.planners.is_empty()
+}
+
+/// Plan a push from a complete listing of the zone, then carry it out.
+/// `from_manifest` says the listing came from the zone's manifest.
+#[allow(clippy::too_many_arguments)]
+fn push_planned(
+    agent: &Agent,
+    base_url: &str,
+    local: &str,
+    remote: &str,
+    mut objects: Vec<StorageObject>,
+    from_manifest: bool,
+    local_files: &HashMap<String, local::LocalFile>,
+    options: &SyncOptions,
+    reporter: &mut Reporter,
+) -> Result<Pushed> {
+    let manifest_key = manifest::key(remote);
+    let is_manifest = |object: &StorageObject| object_key(object) == manifest_key;
+    let manifest_exists = from_manifest || objects.iter().any(is_manifest);
+    let remote_index = RemoteIndex::new(objects.clone(), &options.filter);
+    reporter.scanned(scanned_files(local_files, &remote_index));
+    check_clock_skew(&remote_index, reporter);
+    let mut actions = plan::plan_to_remote(
+        local_files,
+        &remote_index,
+        options.delete,
+        options.modify_window,
+    );
+    hold_back_recent(&mut actions, local_files, options);
+    plan::prioritize(&mut actions, &options.priority, &options.priority_last);
+    plugin::plan(&options.planners, local, remote, &mut actions)?;
+
+    if let Some(threshold) = options.quota_warn {
+        let usage = projected_usage(&remote_index, &actions);
+        if usage > threshold {
+            let message = tr(Message::QuotaWarning { usage, threshold });
+            reporter.warn(WarningKind::Quota, None, message);
+        }
+    }
+    check_delete_limit(&actions, remote_index.files.len(), options, reporter)?;
+
+    if options.cache && !options.dry_run {
+        // Drop the listing while the zone changes, so a failed push can't
+        // leave a stale one behind.
+        ListingCache::remove(&store::dir(Path::new(local)), remote)?;
+    }
+    let changes = actions
+        .iter()
+        .any(|action| !matches!(action, SyncAction::Skip { .. }));
+    // Likewise the manifest, which a push without --manifest would also
+    // leave out of date.
+    if manifest_exists && !options.dry_run && (changes || !options.manifest) {
+        storage::delete_object(agent, base_url, &manifest::path(remote))?;
+        objects.retain(|object| !is_manifest(object));
+    }
+    let pending = execute(agent, base_url, &actions, options, reporter)?;
+    let objects = cache::apply_actions(objects, &actions, &pending);
+    Ok((objects, actions, pending, manifest_exists))
+}
+
+/// Push while the zone is still being listed, so that listing and
+/// transferring overlap rather than one waiting on the other. Each file is
+/// transferred once the listing has settled what to do with it, and deletes
+/// wait until the whole zone has been listed.
+fn push_pipelined(
+    agent: &Agent,
+    base_url: &str,
+    remote: &str,
+    local_files: &HashMap<String, local::LocalFile>,
+    options: &SyncOptions,
+    reporter: &mut Reporter,
+) -> Result<Pushed> {
+    let manifest_key = manifest::key(remote);
+    let mut planner = plan::PushPlanner::new(local_files, options.modify_window);
+    let mut objects = Vec::new();
+    let mut actions = Vec::new();
+    let mut pending = Vec::new();
+    let mut manifest_exists = false;
+    let backoff = Backoff::default();
+    let listed = storage::stream_all_objects(agent, base_url, remote, |dir, mut listing| {
+        // The manifest is at the top, which is listed first, so it goes
+        // before anything changes.
+        if let Some(i) = listing
+            .iter()
+            .position(|object| object_key(object) == manifest_key)
+        {
+            manifest_exists = true;
+            if !options.dry_run {
+                storage::delete_object(agent, base_url, &manifest::path(remote))?;
+                listing.remove(i);
+            }
+        }
+        objects.extend(listing.iter().cloned());
+        let mut batch = planner.listed(&dir_key(dir), listing, &options.filter);
+        hold_back_recent(&mut batch, local_files, options);
+        pending.extend(execute_with_backoff(
+            agent, base_url, &batch, options, reporter, &backoff,
+        )?);
+        actions.extend(batch);
+        Ok(())
+    });
+    missing_as_empty(listed, remote)?;
+    let (mut rest, remote_index) = planner.finish();
+    hold_back_recent(&mut rest, local_files, options);
+    pending.extend(execute_with_backoff(
+        agent, base_url, &rest, options, reporter, &backoff,
+    )?);
+    actions.extend(rest);
+    reporter.scanned(scanned_files(local_files, &remote_index));
+    check_clock_skew(&remote_index, reporter);
+    if options.delete {
+        let deletes = plan::remote_deletes(local_files, &remote_index);
+        check_delete_limit(&deletes, remote_index.files.len(), options, reporter)?;
+        pending.extend(execute_with_backoff(
+            agent, base_url, &deletes, options, reporter, &backoff,
+        )?);
+        actions.extend(deletes);
+    }
+    let objects = cache::apply_actions(objects, &actions, &pending);
+    Ok((objects, actions, pending, manifest_exists))
+}
+
+/// Read the list of paths for `--files-from`, from stdin for `-`. Blank
+/// lines and lines starting with `#` are passed over.
+fn read_file_list(list: &Path) -> Result<Vec<String>> {
+    let text = if list == Path::new("-") {
+        std::io::read_to_string(std::io::stdin())?
+    } else {
+        std::fs::read_to_string(list)
+            .map_err(|e| anyhow!("Could not read {}: {}", list.display(), e))?
+    };
+    Ok(text
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+/// The files under the local directory that changed between a git ref and
+/// the working tree, relative to the local directory.
+fn git_changed(local: &str, reference: &str) -> Result<Vec<String>> {
+    if reference.starts_with('-') {
+        return Err(anyhow!("{} is not a git ref", reference));
+    }
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(local)
+        .args(["diff", "--name-only", "--no-renames", "--relative", "-z"])
+        .arg(reference)
+        .arg("--")
+        .output()
+        .map_err(|e| anyhow!("Could not run git: {}", e))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "git diff against {} failed: {}",
+            reference,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8(output.stdout)?
+        .split('\0')
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// Plan pushing just the listed files, without listing the zone. Listed
+/// files that don't exist locally are deleted from the zone with `--delete`.
+/// Directories aren't expanded, and paths leaving the local directory are
+/// refused.
+fn plan_files_from(
+    local: &str,
+    paths: &PathMap,
+    names: &[String],
+    options: &SyncOptions,
+) -> Result<(HashMap<String, local::LocalFile>, Vec<SyncAction>)> {
+    let mut local_files = HashMap::new();
+    let mut missing = Vec::new();
+    for name in names {
+        let relative_path = Path::new(name);
+        if relative_path
+            .components()
+            .any(|part| !matches!(part, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(anyhow!("{} is not a path inside {}", name, local));
+        }
+        if local::is_ignored_path(relative_path)
+            || options
+                .filter
+                .excludes(&paths::slash_path(relative_path), false)
+        {
+            continue;
+        }
+        let path = Path::new(local).join(relative_path);
+        let key = paths.remote_key(relative_path);
+        match std::fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() => {
+                let file = local::LocalFile {
+                    relative_path: relative_path.to_path_buf(),
+                    path,
+                    is_directory: false,
+                    last_changed: metadata.modified()?.into(),
+                    length: metadata.len(),
+                };
+                local_files.insert(key, file);
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => missing.push(key),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    // Against an empty index, every file is uploaded.
+    let mut actions = plan::plan_to_remote(
+        &local_files,
+        &RemoteIndex::default(),
+        false,
+        options.modify_window,
+    );
+    hold_back_recent(&mut actions, &local_files, options);
+    if options.delete {
+        missing.sort();
+        missing.dedup();
+        actions.extend(
+            missing
+                .into_iter()
+                .map(|key| SyncAction::DeleteRemote { key, length: 0 }),
+        );
+    }
+    Ok((local_files, actions))
+}
+
+/// Push just the files listed for `--files-from`.
+fn push_files_from(
+    agent: &Agent,
+    base_url: &str,
+    local: &str,
+    remote: &str,
+    names: &[String],
+    options: &SyncOptions,
+    reporter: &mut Reporter,
+) -> Result<Vec<SyncAction>> {
+    let remote = storage::strip_zone_prefix(remote);
+    let paths = PathMap::new(remote);
+    let (local_files, mut actions) = plan_files_from(local, &paths, names, options)?;
+    plugin::plan(&options.planners, local, remote, &mut actions)?;
+    reporter.scanned(local_files.len() as u64);
+    if !options.dry_run {
+        // Neither the cached listing nor the manifest would match the zone
+        // afterwards.
+        if options.cache {
+            ListingCache::remove(&store::dir(Path::new(local)), remote)?;
+        }
+        match storage::delete_object(agent, base_url, &manifest::path(remote)) {
+            Err(e) if e.is::<storage::NotFound>() => {}
+            deleted => deleted?,
+        }
+    }
+    execute(agent, base_url, &actions, options, reporter)?;
+    Ok(actions)
+}
+
+/// Hold back uploads of files changed within `--min-age`.
+fn hold_back_recent(
+    actions: &mut [SyncAction],
+    local_files: &HashMap<String, local::LocalFile>,
+    options: &SyncOptions,
+) {
+    if let Some(min_age) = options.min_age {
+        plan::hold_back_recent(actions, local_files, Utc::now() - min_age);
+    }
+}
+
+/// Treat a zone directory that doesn't exist yet as empty, so that pushing to
+/// a new directory creates it.
+pub fn missing_as_empty<T: Default>(listed: Result<T>, remote: &str) -> Result<T> {
+    match listed {
+        Err(e)
+            if e.downcast_ref::<storage::NotFound>()
+                .is_some_and(|missing| missing.path == remote) =>
+        {
+            Ok(T::default())
+        }
+        listed => listed,
+    }
+}
+
+/// The directory key, like `/zone/dir/`, of a path handed to the lister.
+fn dir_key(path: &str) -> String {
+    format!("/{}/", path.trim_matches('/'))
+}
+
+/// Write a manifest of the zone as it is after a push, and add it to the
+/// listing. Checksums of uploaded files are worked out from the local copy.
+fn write_manifest(
+    agent: &Agent,
+    base_url: &str,
+    remote: &str,
+    objects: &mut Vec<StorageObject>,
+    actions: &[SyncAction],
+    pending: &[String],
+    options: &SyncOptions,
+) -> Result<()> {
+    for action in actions {
+        let SyncAction::Upload { key, path, .. } = action else {
+            continue;
+        };
+        if pending.contains(key) {
+            continue;
+        }
+        let sha256 = checksum::sha256_hex(&read_upload(path, &options.transforms)?);
+        if let Some(object) = objects
+            .iter_mut()
+            .find(|object| !object.is_directory && object_key(object) == *key)
+        {
+            object.checksum = Some(sha256);
+        }
+    }
+    let data = serde_json::to_vec(&Manifest::new(objects))?;
+    let path = manifest::path(remote);
+    storage::put_object(agent, base_url, &path, &data)?;
+    let (dir, name) = path.rsplit_once('/').unwrap_or_default();
+    objects.push(StorageObject {
+        storage_zone_name: PathMap::new(remote).zone().to_string(),
+        path: format!("/{}/", dir),
+        object_name: name.to_string(),
+        length: data.len() as u64,
+        last_changed: RemoteTime::from_utc(Utc::now()),
+        ..Default::default()
+    });
+    Ok(())
+}
+
+/// Download a random sample of uploads and compare their checksums with the
+/// data that was sent. The sample size is rounded up, so any percentage above
+/// zero checks at least one file.
+fn spot_check(
+    agent: &Agent,
+    base_url: &str,
+    uploaded: &[&SyncAction],
+    percent: f64,
+    options: &SyncOptions,
+    reporter: &Reporter,
+) -> Result<()> {
+    let count = ((uploaded.len() as f64 * percent / 100.0).ceil() as usize).min(uploaded.len());
+    // Ordering by a randomly keyed hash gives a fresh sample every run.
+    let random = std::collections::hash_map::RandomState::new();
+    let mut sample = uploaded.to_vec();
+    sample.sort_by_cached_key(|action| random.hash_one(action.key()));
+    sample.truncate(count);
+    if sample.is_empty() {
+        return Ok(());
+    }
+
+    let mut mismatched = Vec::new();
+    for action in &sample {
+        let SyncAction::Upload { key, path, .. } = action else {
+            continue;
+        };
+        let sent = checksum::sha256_hex(&read_upload(path, &options.transforms)?);
+        let stored = checksum::sha256_hex(&storage::get_object(agent, base_url, key)?);
+        if sent != stored {
+            mismatched.push(key.as_str());
+        }
+    }
+    if !mismatched.is_empty() {
+        let mut message = tr(Message::SpotCheckFailed {
+            mismatched: mismatched.len(),
+            checked: sample.len(),
+        });
+        for key in mismatched {
+            message.push_str("\n  ");
+            message.push_str(key);
+        }
+        return Err(anyhow!(message));
+    }
+    reporter.message(&tr(Message::SpotCheckPassed {
+        checked: sample.len(),
+        uploaded: uploaded.len(),
+    }));
+    Ok(())
+}
+
+fn sync_to_local(
+    agent: &Agent,
+    base_url: &str,
+    local: &str,
+    remote: &str,
+    options: &SyncOptions,
+    reporter: &mut Reporter,
+) -> Result<Vec<SyncAction>> {
+    let remote = storage::strip_zone_prefix(remote);
+    let paths = PathMap::new(remote);
+    if !options.dry_run {
+        local::remove_part_files(Path::new(local))?;
+    }
+    let local_files = get_local_file_map(local, &paths, options, Some(reporter))?;
+    if can_pipeline(options) {
+        return pull_pipelined(
+            agent,
+            base_url,
+            local,
+            remote,
+            &local_files,
+            options,
+            reporter,
+        );
+    }
+    let remote_index = get_remote_index(agent, base_url, remote, &options.filter)?;
+    reporter.scanned(scanned_files(&local_files, &remote_index));
+    check_clock_skew(&remote_index, reporter);
+    let mut actions = plan::plan_to_local(
+        &local_files,
+        &remote_index,
+        local,
+        &paths,
+        options.delete,
+        options.modify_window,
+    );
+    plan::prioritize(&mut actions, &options.priority, &options.priority_last);
+    plugin::plan(&options.planners, remote, local, &mut actions)?;
+    check_delete_limit(&actions, local_files.len(), options, reporter)?;
+    execute(agent, base_url, &actions, options, reporter)?;
+    Ok(actions)
+}
+
+/// Pull while the zone is still being listed, downloading each directory's
+/// files as soon as its listing arrives. Deletes wait until the whole zone
+/// has been listed.
+fn pull_pipelined(
+    agent: &Agent,
+    base_url: &str,
+    local: &str,
+    remote: &str,
+    local_files: &HashMap<String, local::LocalFile>,
+    options: &SyncOptions,
+    reporter: &mut Reporter,
+) -> Result<Vec<SyncAction>> {
+    let paths = PathMap::new(remote);
+    let mut remote_index = RemoteIndex::default();
+    let mut actions = Vec::new();
+    let backoff = Backoff::default();
+    storage::stream_all_objects(agent, base_url, remote, |_, listing| {
+        let listed = RemoteIndex::new(listing, &options.filter);
+        let batch = plan::plan_to_local(
+            local_files,
+            &listed,
+            local,
+            &paths,
+            false,
+            options.modify_window,
+        );
+        execute_with_backoff(agent, base_url, &batch, options, reporter, &backoff)?;
+        actions.extend(batch);
+        remote_index.files.extend(listed.files);
+        remote_index.dirs.extend(listed.dirs);
+        Ok(())
+    })?;
+    reporter.scanned(scanned_files(local_files, &remote_index));
+    check_clock_skew(&remote_index, reporter);
+    if options.delete {
+        let deletes = plan::local_deletes(local_files, &remote_index);
+        check_delete_limit(&deletes, local_files.len(), options, reporter)?;
+        execute_with_backoff(agent, base_url, &deletes, options, reporter, &backoff)?;
+        actions.extend(deletes);
+    }
+    Ok(actions)
+}
+
+fn sync_bidirectional(
+    agent: &Agent,
+    base_url: &str,
+    local: &str,
+    remote: &str,
+    options: &SyncOptions,
+    reporter: &mut Reporter,
+) -> Result<Vec<SyncAction>> {
+    let state = SyncState::load(&store::dir(Path::new(local)), remote)?;
+    let remote = storage::strip_zone_prefix(remote);
+    let paths = PathMap::new(remote);
+    let remote_index = get_remote_index(agent, base_url, remote, &options.filter)?;
+    if !options.dry_run {
+        local::remove_part_files(Path::new(local))?;
+    }
+    let local_files = get_local_file_map(local, &paths, options, Some(reporter))?;
+    let scanned = scanned_files(&local_files, &remote_index);
+    reporter.scanned(scanned);
+    check_clock_skew(&remote_index, reporter);
+    let mut actions = plan::plan_bidirectional(
+        &local_files,
+        &remote_index,
+        &state,
+        local,
+        &paths,
+        options.conflict,
+        options.modify_window,
+    );
+    // A dry run only reports conflicts, so there is nothing to ask about.
+    if options.conflict == ConflictPolicy::Prompt && !options.dry_run {
+        actions = ask_conflicts(actions, &local_files, &remote_index, local, &paths)?;
+    }
+    hold_back_recent(&mut actions, &local_files, options);
+    plan::prioritize(&mut actions, &options.priority, &options.priority_last);
+    plugin::plan(&options.planners, local, remote, &mut actions)?;
+
+    let mut unresolved: Vec<String> = actions
+        .iter()
+        .filter(|action| {
+            matches!(
+                action,
+                SyncAction::Skip {
+                    reason: SkipReason::Conflict,
+                    ..
+                }
+            )
+        })
+        .map(|action| action.key().to_string())
+        .collect();
+    for key in &unresolved {
+        let conflict = tr(Message::Conflict { path: key });
+        reporter.warn(WarningKind::Conflict, Some(key), conflict);
+    }
+    // Files held back by --min-age come up again next time too.
+    unresolved.extend(
+        actions
+            .iter()
+            .filter(|action| {
+                matches!(
+                    action,
+                    SyncAction::Skip {
+                        reason: SkipReason::TooRecent,
+                        ..
+                    }
+                )
+            })
+            .map(|action| action.key().to_string()),
+    );
+    check_delete_limit(&actions, scanned as usize, options, reporter)?;
+    unresolved.extend(execute(agent, base_url, &actions, options, reporter)?);
+
+    if !options.dry_run {
+        // Snapshot both sides as they are now, keeping the old state for
+        // anything still to be resolved so it comes up again next time.
+        let remote_index = get_remote_index(agent, base_url, remote, &options.filter)?;
+        let local_files = get_local_file_map(local, &paths, options, None)?;
+        let keep: Vec<&str> = unresolved.iter().map(String::as_str).collect();
+        state
+            .snapshot(&local_files, &remote_index, &keep)
+            .save(&store::dir(Path::new(local)))?;
+    }
+    Ok(actions)
+}
+
+/// Ask which copy to keep for each conflict in a bidirectional plan. Deletes
+/// chosen this way go after the rest of the plan, like planned deletes.
+fn ask_conflicts(
+    actions: Vec<SyncAction>,
+    local_files: &HashMap<String, local::LocalFile>,
+    remote_index: &RemoteIndex,
+    local: &str,
+    paths: &PathMap,
+) -> Result<Vec<SyncAction>> {
+    let mut resolved = Vec::new();
+    let mut deletes = Vec::new();
+    for action in actions {
+        let SyncAction::Skip {
+            key,
+            reason: SkipReason::Conflict,
+        } = &action
+        else {
+            resolved.push(action);
+            continue;
+        };
+        let question = tr(Message::ChooseSide { path: key });
+        let Some(side) = prompt::choose_side(&question)? else {
+            resolved.push(action);
+            continue;
+        };
+        match plan::resolve_conflict(key, side, local_files, remote_index, local, paths) {
+            delete @ (SyncAction::DeleteRemote { .. } | SyncAction::DeleteLocal { .. }) => {
+                deletes.push(delete)
+            }
+            transfer => resolved.push(transfer),
+        }
+    }
+    resolved.extend(deletes);
+    Ok(resolved)
+}
+
+/// Carry out the planned actions in order, or just report them on a dry run.
+/// Returns the keys of changes that were turned down or never reached at an
+/// `--interactive` prompt.
+pub fn execute(
+    agent: &Agent,
+    base_url: &str,
+    actions: &[SyncAction],
+    options: &SyncOptions,
+    reporter: &mut Reporter,
+) -> Result<Vec<String>> {
+    execute_with_backoff(
+        agent,
+        base_url,
+        actions,
+        options,
+        reporter,
+        &Backoff::default(),
+    )
+}
+
+/// [`execute`], sharing what is known about contended directories with
+/// other batches of the same sync.
+fn execute_with_backoff(
+    agent: &Agent,
+    base_url: &str,
+    actions: &[SyncAction],
+    options: &SyncOptions,
+    reporter: &mut Reporter,
+    backoff: &Backoff,
+) -> Result<Vec<String>> {
+    let mut confirm = options.interactive;
+    let mut pending = Vec::new();
+    for (i, action) in actions.iter().enumerate() {
+        if let Some(journal) = &options.journal
+            && journal.is_done(action)
+        {
+            reporter.report(Event::Skipped {
+                path: action.key().to_string(),
+                reason: SkipReason::AlreadyDone,
+            });
+            continue;
+        }
+        if options
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+            && !matches!(action, SyncAction::Skip { .. })
+        {
+            return Err(DeadlineReached.into());
+        }
+        if interrupt::interrupted() && !matches!(action, SyncAction::Skip { .. }) {
+            return Err(Interrupted.into());
+        }
+        if confirm && !matches!(action, SyncAction::Skip { .. }) {
+            let question = tr(Message::Confirm {
+                action: &describe(action),
+            });
+            match prompt::ask(&question)? {
+                Answer::Yes => {}
+                Answer::All => confirm = false,
+                Answer::No => {
+                    pending.push(action.key().to_string());
+                    reporter.report(Event::Skipped {
+                        path: action.key().to_string(),
+                        reason: SkipReason::Declined,
+                    });
+                    continue;
+                }
+                Answer::Quit => {
+                    pending.extend(
+                        actions[i..]
+                            .iter()
+                            .filter(|action| !matches!(action, SyncAction::Skip { .. }))
+                            .map(|action| action.key().to_string()),
+                    );
+                    break;
+                }
+            }
+        }
+        let event = match carry_out(agent, base_url, action, options, reporter, backoff) {
+            Err(e) if options.continue_on_error => {
+                reporter.fail(action.key(), &e);
+                pending.push(action.key().to_string());
+                continue;
+            }
+            event => event?,
+        };
+        if let Event::Skipped {
+            path,
+            reason: SkipReason::Vanished,
+        } = &event
+        {
+            pending.push(path.clone());
+        } else if let Some(journal) = &options.journal
+            && !matches!(action, SyncAction::Skip { .. })
+        {
+            journal.record(action)?;
+        }
+        reporter.report(event);
+    }
+    Ok(pending)
+}
+
+/// Carry out one action and say what happened. An upload whose local file
+/// went away since the scan is skipped with a warning.
+fn carry_out(
+    agent: &Agent,
+    base_url: &str,
+    action: &SyncAction,
+    options: &SyncOptions,
+    reporter: &mut Reporter,
+    backoff: &Backoff,
+) -> Result<Event> {
+    let dry_run = options.dry_run;
+    Ok(match action {
+        SyncAction::Upload { key, path, length } => {
+            let mut bytes = *length;
+            if !dry_run {
+                // Read the local file and send it to the destination.
+                // As with rsync, a file that went away since the scan is
+                // passed over rather than failing the sync.
+                let read = match &options.origin {
+                    Some(origin) => origin.read(&path.to_string_lossy()),
+                    None => read_upload(path, &options.transforms),
+                };
+                let file_data = match read {
+                    Err(e) if is_vanished(&e) => {
+                        let vanished = tr(Message::Vanished { path: key });
+                        reporter.warn(WarningKind::Vanished, Some(key), vanished);
+                        return Ok(Event::Skipped {
+                            path: key.clone(),
+                            reason: SkipReason::Vanished,
+                        });
+                    }
+                    file_data => file_data?,
+                };
+                bytes = file_data.len() as u64;
+                backoff.run(key, || {
+                    storage::put_object(agent, base_url, key, &file_data)
+                })?;
+            }
+            Event::Uploaded {
+                path: key.clone(),
+                local_path: path.to_string_lossy().into_owned(),
+                bytes,
+                dry_run,
+            }
+        }
+        SyncAction::Download {
+            key,
+            path,
+            length,
+            last_changed,
+        } if let Some(snapshot) = options.link_dest.as_ref().and_then(|snapshot| {
+            find_in_snapshot(snapshot, key, *length, *last_changed, options.modify_window)
+        }) =>
+        {
+            if !dry_run {
+                link_or_copy(&snapshot, path, *last_changed)?;
+            }
+            Event::Linked {
+                path: key.clone(),
+                local_path: path.to_string_lossy().into_owned(),
+                snapshot_path: snapshot.to_string_lossy().into_owned(),
+                dry_run,
+            }
+        }
+        SyncAction::Download {
+            key,
+            path,
+            length,
+            last_changed,
+        } => {
+            if !dry_run {
+                // Download the file and save it locally, creating the
+                // directory if it doesn't exist.
+                let file_data = download(agent, base_url, key, options)?;
+                if let Some(dir) = path.parent()
+                    && !dir.exists()
+                {
+                    std::fs::create_dir_all(dir)?;
+                }
+                write_whole(path, &file_data)?;
+                // Matching the remote time keeps the next run from
+                // taking the fresh copy for a local change.
+                if let Some(last_changed) = last_changed
+                    && !options.no_times
+                {
+                    set_modified(path, *last_changed)?;
+                }
+            }
+            Event::Downloaded {
+                path: key.clone(),
+                local_path: path.to_string_lossy().into_owned(),
+                bytes: *length,
+                dry_run,
+            }
+        }
+        SyncAction::DeleteRemote { key, length } => {
+            if !dry_run {
+                // A file that is already gone counts as deleted.
+                match backoff.run(key, || storage::delete_object(agent, base_url, key)) {
+                    Err(e) if e.is::<storage::NotFound>() => {}
+                    deleted => deleted?,
+                }
+            }
+            Event::Deleted {
+                path: key.clone(),
+                local_path: None,
+                bytes: *length,
+                dry_run,
+            }
+        }
+        SyncAction::DeleteLocal { key, path, length } => {
+            if !dry_run {
+                std::fs::remove_file(path)?;
+            }
+            Event::Deleted {
+                path: key.clone(),
+                local_path: Some(path.to_string_lossy().into_owned()),
+                bytes: *length,
+                dry_run,
+            }
+        }
+        SyncAction::Skip { key, reason } => Event::Skipped {
+            path: key.clone(),
+            reason: *reason,
+        },
+    })
+}
+
+/// Download an object from the replica, or from the zone's own region if
+/// there is none or it fails, such as for a file not replicated yet.
+fn download(agent: &Agent, base_url: &str, key: &str, options: &SyncOptions) -> Result<Vec<u8>> {
+    if let Some(replica) = &options.replica
+        && let Ok(data) = storage::get_object(agent, replica, key)
+    {
+        return Ok(data);
+    }
+    storage::get_object(agent, base_url, key)
+}
+
+/// Write a downloaded file next to where it goes, then move it into place,
+/// so that a sync cut short never leaves half a file behind.
+fn write_whole(path: &Path, data: &[u8]) -> Result<()> {
+    let part = local::part_path(path);
+    let written = std::fs::write(&part, data).and_then(|()| std::fs::rename(&part, path));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&part);
+    }
+    Ok(written?)
+}
+
+/// Set a file's modification time to a remote one.
+fn set_modified(path: &Path, time: RemoteTime) -> Result<()> {
+    std::fs::File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(time.utc().into())?;
+    Ok(())
+}
+
+/// The copy of a remote file in a `--link-dest` snapshot, if it has the same
+/// size and modification time, give or take `modify_window`, as the remote
+/// file.
+fn find_in_snapshot(
+    snapshot: &Snapshot,
+    key: &str,
+    length: u64,
+    last_changed: Option<RemoteTime>,
+    modify_window: TimeDelta,
+) -> Option<PathBuf> {
+    let last_changed = last_changed?;
+    let path = snapshot
+        .paths
+        .local_path(&snapshot.dir.to_string_lossy(), key);
+    let metadata = std::fs::metadata(&path).ok()?;
+    let modified: chrono::DateTime<chrono::Utc> = metadata.modified().ok()?.into();
+    let skew = (modified - last_changed.utc()).abs();
+    (metadata.is_file() && metadata.len() == length && skew <= modify_window).then_some(path)
+}
+
+/// Hard link a snapshot file into place, or copy it where that isn't
+/// possible, such as across file systems.
+fn link_or_copy(snapshot: &Path, path: &Path, last_changed: Option<RemoteTime>) -> Result<()> {
+    if let Some(dir) = path.parent()
+        && !dir.exists()
+    {
+        std::fs::create_dir_all(dir)?;
+    }
+    if path.symlink_metadata().is_ok() {
+        std::fs::remove_file(path)?;
+    }
+    if std::fs::hard_link(snapshot, path).is_err() {
+        std::fs::copy(snapshot, path)?;
+        if let Some(last_changed) = last_changed {
+            set_modified(path, last_changed)?;
+        }
+    }
+    Ok(())
+}
+
+/// Describe an action that is about to happen, for confirmation prompts.
+fn describe(action: &SyncAction) -> String {
+    match action {
+        SyncAction::Upload { key, path, .. } => tr(Message::WouldUpdate {
+            from: &path.to_string_lossy(),
+            to: key,
+        }),
+        SyncAction::Download { key, path, .. } => tr(Message::WouldUpdate {
+            from: key,
+            to: &path.to_string_lossy(),
+        }),
+        SyncAction::DeleteRemote { key, .. } => tr(Message::WouldDelete { path: key }),
+        SyncAction::DeleteLocal { path, .. } => tr(Message::WouldDelete {
+            path: &path.to_string_lossy(),
+        }),
+        SyncAction::Skip { key, .. } => key.clone(),
+    }
+}
+
+/// Stop before deleting too much of the destination, which usually means the
+/// source path was wrong. Dry runs only warn, so the plan can be reviewed.
+fn check_delete_limit(
+    actions: &[SyncAction],
+    existing: usize,
+    options: &SyncOptions,
+    reporter: &mut Reporter,
+) -> Result<()> {
+    let Some(limit) = options.max_delete else {
+        return Ok(());
+    };
+    let deletes = actions
+        .iter()
+        .filter(|action| {
+            matches!(
+                action,
+                SyncAction::DeleteRemote { .. } | SyncAction::DeleteLocal { .. }
+            )
+        })
+        .count();
+    if options.force || limit.allows(deletes, existing) {
+        return Ok(());
+    }
+    let message = tr(Message::TooManyDeletes {
+        deletes,
+        existing,
+        limit: &limit,
+    });
+    if options.dry_run {
+        reporter.warn(WarningKind::DeleteLimit, None, message);
+        Ok(())
+    } else {
+        Err(anyhow!(message))
+    }
+}
+
+/// Estimate how many bytes the remote will hold once the planned uploads and
+/// deletes are applied.
+fn projected_usage(remote: &RemoteIndex, actions: &[SyncAction]) -> u64 {
+    let mut usage = remote.total_bytes();
+    for action in actions {
+        match action {
+            SyncAction::Upload { key, length, .. } => {
+                // Actions from a plugin or an earlier run can be out of step
+                // with the listing, so usage can't go below nothing.
+                if let Some(remote_file) = remote.files.get(key) {
+                    usage = usage.saturating_sub(remote_file.length);
+                }
+                usage += length;
+            }
+            SyncAction::DeleteRemote { length, .. } => usage = usage.saturating_sub(*length),
+            _ => {}
+        }
+    }
+    usage
+}
+
+/// Count the distinct files on either side.
+fn scanned_files(local_files: &HashMap<String, local::LocalFile>, remote: &RemoteIndex) -> u64 {
+    let remote_only = remote
+        .files
+        .keys()
+        .filter(|key| !local_files.contains_key(*key))
+        .count();
+    (local_files.len() + remote_only) as u64
+}
+
+/// How far ahead of the local clock the zone's modification times may be
+/// before it looks like one of the clocks is off.
+const CLOCK_SKEW_TOLERANCE: TimeDelta = TimeDelta::minutes(1);
+
+/// How far the latest change in the zone is ahead of `now`, if that is more
+/// than the tolerance allows.
+fn clock_skew(remote: &RemoteIndex, now: DateTime<Utc>) -> Option<TimeDelta> {
+    let latest = remote.files.values().map(|file| file.last_changed).max()?;
+    Some(latest.utc() - now).filter(|ahead| *ahead > CLOCK_SKEW_TOLERANCE)
+}
+
+/// Warn when the zone has changes from the future, as comparing modification
+/// times only works when the clocks agree.
+fn check_clock_skew(remote: &RemoteIndex, reporter: &mut Reporter) {
+    if let Some(ahead) = clock_skew(remote, Utc::now()) {
+        let seconds = ahead.num_seconds();
+        reporter.warn(
+            WarningKind::ClockSkew,
+            None,
+            tr(Message::ClockSkew { seconds }),
+        );
+    }
+}
+
+/// Check if the path is a zone.
+fn is_zone(path: &str) -> bool {
+    path.starts_with("zone://")
+}
+
+/// Get an index of the remote files and directories.
+fn get_remote_index(
+    agent: &Agent,
+    base_url: &str,
+    remote: &str,
+    filter: &Filter,
+) -> Result<RemoteIndex> {
+    let remote_files = storage::get_all_objects(agent, base_url, remote)?;
+    Ok(RemoteIndex::new(remote_files, filter))
+}
+
+/// Get the local files as a map.
+fn get_local_file_map(
+    local: &str,
+    paths: &PathMap,
+    options: &SyncOptions,
+    reporter: Option<&mut Reporter>,
+) -> Result<HashMap<String, local::LocalFile>> {
+    // A dry run pulling into a directory that isn't made yet finds it empty.
+    if !Path::new(local).exists() {
+        return Ok(HashMap::new());
+    }
+    let scan = local::get_files(local.as_ref(), &options.filter)?;
+    if let Some(reporter) = reporter {
+        for relative_path in &scan.special {
+            let path = Path::new(local).join(relative_path);
+            let path = path.to_string_lossy();
+            reporter.warn(
+                WarningKind::SpecialFile,
+                Some(&path),
+                tr(Message::SpecialFile { path: &path }),
+            );
+        }
+    }
+    // Create a map for quick lookup of local files. We construct a destination
+    // path from the relative path of the local file.
+    let mut local_file_map: HashMap<_, _> = scan
+        .files
+        .into_iter()
+        // Skip directories.
+        .filter(|file| !file.is_directory)
+        .map(|file| (paths.remote_key(&file.relative_path), file))
+        .collect();
+    // Transformed files are compared by the size they have once uploaded.
+    // Only transformed files need reading, so without transforms there is
+    // nothing worth caching.
+    let mut hashes = (options.cache && !options.transforms.rules().is_empty())
+        .then(|| HashCache::load(&store::dir(Path::new(local)), &options.transforms));
+    for file in local_file_map.values_mut() {
+        if options.transforms.matches(&file_name(&file.path)) {
+            file.length = match &mut hashes {
+                Some(hashes) => hashes.upload_info(file, &options.transforms)?.upload_length,
+                None => read_upload(&file.path, &options.transforms)?.len() as u64,
+            };
+        }
+    }
+    if let Some(mut hashes) = hashes {
+        let present: HashSet<_> = local_file_map
+            .values()
+            .map(|file| file.relative_path.to_string_lossy())
+            .collect();
+        hashes.retain(|path| present.contains(path));
+        hashes.save(&store::dir(Path::new(local)))?;
+    }
+    Ok(local_file_map)
+}
+
+/// Read a local file as it will be uploaded, with any transforms applied.
+pub fn read_upload(path: &Path, transforms: &Pipeline) -> Result<Vec<u8>> {
+    let data = std::fs::read(path)?;
+    let file_name = file_name(path);
+    if !transforms.matches(&file_name) {
+        return Ok(data);
+    }
+    transforms
+        .apply(&file_name, data)
+        .map_err(|e| anyhow!("{}: {}", path.to_string_lossy(), e))
+}
+
+/// Whether reading a local file failed because it no longer exists.
+fn is_vanished(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Check if a file is excluded based on the exclude patterns.
+pub fn is_excluded(file_name: &str, exclude_patterns: &[String]) -> bool {
+    exclude_patterns
+        .iter()
+        .any(|pattern| glob_match::glob_match(pattern, file_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_excluded() {
+        let patterns = vec!["*.tmp".to_string(), "secret.txt".to_string()];
+        assert!(is_excluded("notes.tmp", &patterns));
+        assert!(is_excluded("secret.txt", &patterns));
+        assert!(!is_excluded("index.html", &patterns));
+        assert!(!is_excluded("notes.tmp", &[]));
+    }
+
+    #[test]
+    fn test_delete_limit() {
+        assert_eq!("5".parse::<DeleteLimit>().unwrap(), DeleteLimit::Count(5));
+        assert_eq!(
+            "12.5%".parse::<DeleteLimit>().unwrap(),
+            DeleteLimit::Percent(12.5)
+        );
+        assert!("150%".parse::<DeleteLimit>().is_err());
+        assert!("lots".parse::<DeleteLimit>().is_err());
+
+        assert!(DeleteLimit::Count(5).allows(5, 10));
+        assert!(!DeleteLimit::Count(5).allows(6, 10));
+        assert!(DeleteLimit::Percent(10.0).allows(10, 100));
+        assert!(!DeleteLimit::Percent(10.0).allows(11, 100));
+        assert!(DeleteLimit::Percent(0.0).allows(0, 0));
+    }
+
+    #[test]
+    fn test_missing_as_empty() {
+        let missing = |path: &str| -> Result<Vec<StorageObject>> {
+            Err(storage::NotFound {
+                path: path.to_string(),
+            }
+            .into())
+        };
+        assert!(
+            missing_as_empty(missing("myzone/new/"), "myzone/new/")
+                .unwrap()
+                .is_empty()
+        );
+        // Only the directory being synced may be missing.
+        assert!(missing_as_empty(missing("myzone/new/sub/"), "myzone/new/").is_err());
+        assert!(missing_as_empty::<()>(Err(anyhow!("Remote unauthorized")), "myzone/").is_err());
+    }
+
+    #[test]
+    fn test_link_from_snapshot() {
+        let dir = std::env::temp_dir().join(format!("bunnysync-link-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("snapshot/css")).unwrap();
+        std::fs::write(dir.join("snapshot/css/site.css"), "body{}").unwrap();
+        let time = RemoteTime::from_utc(DateTime::from_timestamp(1_700_000_000, 0).unwrap());
+        set_modified(&dir.join("snapshot/css/site.css"), time).unwrap();
+
+        let snapshot = Snapshot {
+            dir: dir.join("snapshot"),
+            paths: PathMap::new("zone://myzone/site/"),
+        };
+        let key = "/myzone/site/css/site.css";
+        let exact = TimeDelta::zero();
+        let found = find_in_snapshot(&snapshot, key, 6, Some(time), exact).unwrap();
+        assert_eq!(
+            found,
+            snapshot.dir.canonicalize().unwrap().join("css/site.css")
+        );
+        assert_eq!(find_in_snapshot(&snapshot, key, 7, Some(time), exact), None);
+        assert_eq!(find_in_snapshot(&snapshot, key, 6, None, exact), None);
+        let later = RemoteTime::from_utc(time.utc() + TimeDelta::seconds(1));
+        assert_eq!(
+            find_in_snapshot(&snapshot, key, 6, Some(later), exact),
+            None
+        );
+        let window = TimeDelta::seconds(2);
+        assert!(find_in_snapshot(&snapshot, key, 6, Some(later), window).is_some());
+
+        let restored = dir.join("restore/css/site.css");
+        link_or_copy(&found, &restored, Some(time)).unwrap();
+        assert_eq!(std::fs::read_to_string(&restored).unwrap(), "body{}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_vanished_upload_is_skipped() {
+        let missing = std::env::temp_dir().join(format!("bunnysync-gone-{}", std::process::id()));
+        let actions = [SyncAction::Upload {
+            key: "/myzone/gone.log".to_string(),
+            path: missing,
+            length: 3,
+        }];
+        let mut reporter = Reporter::new(OutputFormat::Json, false, false);
+        let pending = execute(
+            &Agent::new_with_defaults(),
+            "http://127.0.0.1:9",
+            &actions,
+            &SyncOptions::default(),
+            &mut reporter,
+        )
+        .unwrap();
+        assert_eq!(pending, vec!["/myzone/gone.log"]);
+        assert_eq!(reporter.summary().skipped, 1);
+        assert_eq!(reporter.summary().vanished, 1);
+    }
+
+    #[test]
+    fn test_continue_on_error() {
+        let missing = std::env::temp_dir().join(format!("bunnysync-failed-{}", std::process::id()));
+        let actions = [
+            SyncAction::DeleteLocal {
+                key: "/myzone/old.html".to_string(),
+                path: missing,
+                length: 3,
+            },
+            SyncAction::Skip {
+                key: "/myzone/same.html".to_string(),
+                reason: SkipReason::Unchanged,
+            },
+        ];
+        let agent = Agent::new_with_defaults();
+        let base_url = "http://127.0.0.1:9";
+        let mut reporter = Reporter::new(OutputFormat::Json, false, false);
+        let options = SyncOptions::default();
+        assert!(execute(&agent, base_url, &actions, &options, &mut reporter).is_err());
+
+        let mut reporter = Reporter::new(OutputFormat::Json, false, false);
+        let options = SyncOptions {
+            continue_on_error: true,
+            ..Default::default()
+        };
+        let pending = execute(&agent, base_url, &actions, &options, &mut reporter).unwrap();
+        assert_eq!(pending, vec!["/myzone/old.html"]);
+        assert_eq!(reporter.summary().failed, 1);
+        assert_eq!(reporter.summary().deleted, 0);
+        assert_eq!(reporter.summary().skipped, 1);
+    }
+
+    #[test]
+    fn test_push_includes_ignored() {
+        let dir = std::env::temp_dir().join(format!("bunnysync-include-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("drafts")).unwrap();
+        std::fs::write(dir.join("drafts/keep.md"), "keep").unwrap();
+        std::fs::write(dir.join("drafts/post.md"), "post").unwrap();
+        std::fs::write(dir.join("index.html"), "home").unwrap();
+
+        let paths = PathMap::new("myzone/");
+        let filter = Filter::new(
+            Vec::new(),
+            IgnoreRules::parse("drafts/\n", ""),
+            paths.root(),
+        )
+        .with_include(vec!["keep.*".to_string()]);
+        let remote_file = |path: &str, name: &str| StorageObject {
+            path: path.to_string(),
+            object_name: name.to_string(),
+            length: 4,
+            ..Default::default()
+        };
+        let remote = RemoteIndex::new(
+            vec![
+                remote_file("/myzone/drafts/", "keep.md"),
+                remote_file("/myzone/drafts/", "post.md"),
+                remote_file("/myzone/", "old.html"),
+            ],
+            &filter,
+        );
+        let options = SyncOptions {
+            delete: true,
+            filter,
+            ..Default::default()
+        };
+        let local_files =
+            get_local_file_map(dir.to_str().unwrap(), &paths, &options, None).unwrap();
+        let actions = plan::plan_to_remote(&local_files, &remote, true, TimeDelta::zero());
+        let keys = |wanted: fn(&SyncAction) -> bool| -> Vec<&str> {
+            actions
+                .iter()
+                .filter(|action| wanted(action))
+                .map(|action| action.key())
+                .collect()
+        };
+        assert_eq!(
+            keys(|action| matches!(action, SyncAction::Upload { .. })),
+            vec!["/myzone/drafts/keep.md", "/myzone/index.html"]
+        );
+        assert_eq!(
+            keys(|action| matches!(action, SyncAction::DeleteRemote { .. })),
+            vec!["/myzone/old.html"]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_deadline() {
+        let skip = SyncAction::Skip {
+            key: "/myzone/same.html".to_string(),
+            reason: SkipReason::Unchanged,
+        };
+        let upload = SyncAction::Upload {
+            key: "/myzone/new.html".to_string(),
+            path: PathBuf::from("new.html"),
+            length: 3,
+        };
+        let options = SyncOptions {
+            deadline: Some(Instant::now()),
+            ..Default::default()
+        };
+        let mut reporter = Reporter::new(OutputFormat::Json, false, false);
+        let agent = Agent::new_with_defaults();
+        let base_url = "http://127.0.0.1:9";
+        // Skipping takes no time, so there is nothing to stop.
+        assert!(
+            execute(
+                &agent,
+                base_url,
+                std::slice::from_ref(&skip),
+                &options,
+                &mut reporter
+            )
+            .is_ok()
+        );
+        let e = execute(&agent, base_url, &[skip, upload], &options, &mut reporter).unwrap_err();
+        assert!(e.is::<DeadlineReached>());
+        assert_eq!(reporter.summary().skipped, 2);
+        assert_eq!(reporter.summary().uploaded, 0);
+    }
+
+    #[test]
+    fn test_plan_files_from() {
+        let dir = std::env::temp_dir().join(format!("bunnysync-files-from-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("index.html"), "hi").unwrap();
+        std::fs::write(dir.join("sub/site.css"), "body{}").unwrap();
+        std::fs::write(dir.join("notes.tmp"), "").unwrap();
+        std::fs::write(dir.join(".bunnysync"), "api_key = \"secret\"").unwrap();
+        let local = dir.to_str().unwrap();
+        let paths = PathMap::new("zone://myzone/site/");
+        let names = [
+            "index.html",
+            "./sub/site.css",
+            "sub",
+            "notes.tmp",
+            ".bunnysync",
+            "old.html",
+            "old.html",
+        ]
+        .map(String::from);
+        let options = SyncOptions {
+            delete: true,
+            filter: Filter::new(vec!["*.tmp".to_string()], Default::default(), paths.root()),
+            ..Default::default()
+        };
+
+        let (local_files, actions) = plan_files_from(local, &paths, &names, &options).unwrap();
+        assert_eq!(local_files.len(), 2);
+        let planned: Vec<_> = actions
+            .iter()
+            .map(|action| match action {
+                SyncAction::Upload { key, .. } => format!("upload {}", key),
+                SyncAction::DeleteRemote { key, .. } => format!("delete {}", key),
+                action => panic!("unexpected {:?}", action),
+            })
+            .collect();
+        assert_eq!(
+            planned,
+            vec![
+                "upload /myzone/site/index.html",
+                "upload /myzone/site/sub/site.css",
+                "delete /myzone/site/old.html",
+            ]
+        );
+
+        let outside = ["../secret.txt".to_string()];
+        assert!(plan_files_from(local, &paths, &outside, &options).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_git_changed() {
+        let dir =
+            std::env::temp_dir().join(format!("bunnysync-git-changed-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("site")).unwrap();
+        std::fs::write(dir.join("README"), "").unwrap();
+        std::fs::write(dir.join("site/index.html"), "hi").unwrap();
+        std::fs::write(dir.join("site/old.html"), "old").unwrap();
+        std::fs::write(dir.join("site/same.html"), "same").unwrap();
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .arg("-C")
+                .arg(&dir)
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success());
+        };
+        git(&["init", "-q"]);
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "First"]);
+        std::fs::write(dir.join("README"), "changed").unwrap();
+        std::fs::write(dir.join("site/index.html"), "hello").unwrap();
+        std::fs::remove_file(dir.join("site/old.html")).unwrap();
+
+        let site = dir.join("site");
+        let mut changed = git_changed(site.to_str().unwrap(), "HEAD").unwrap();
+        changed.sort();
+        assert_eq!(changed, vec!["index.html", "old.html"]);
+        assert!(git_changed(site.to_str().unwrap(), "no-such-ref").is_err());
+        assert!(git_changed(site.to_str().unwrap(), "--output=x").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_changed_paths() {
+        let actions = [
+            SyncAction::Upload {
+                key: "/myzone/css/site.css".to_string(),
+                path: PathBuf::from("css/site.css"),
+                length: 1,
+            },
+            SyncAction::Skip {
+                key: "/myzone/index.html".to_string(),
+                reason: SkipReason::Unchanged,
+            },
+            SyncAction::DeleteRemote {
+                key: "/myzone/old.html".to_string(),
+                length: 1,
+            },
+        ];
+        assert_eq!(changed_paths(&actions), vec!["css/site.css", "old.html"]);
+    }
+
+    #[test]
+    fn test_clock_skew() {
+        let now = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let changed_at = |last_changed| StorageObject {
+            path: "/myzone/".to_string(),
+            object_name: "index.html".to_string(),
+            last_changed: RemoteTime::from_utc(last_changed),
+            ..Default::default()
+        };
+        let index =
+            |last_changed| RemoteIndex::new(vec![changed_at(last_changed)], &Filter::default());
+        assert_eq!(clock_skew(&RemoteIndex::default(), now), None);
+        assert_eq!(clock_skew(&index(now - TimeDelta::hours(1)), now), None);
+        assert_eq!(clock_skew(&index(now + TimeDelta::seconds(30)), now), None);
+        assert_eq!(
+            clock_skew(&index(now + TimeDelta::minutes(5)), now),
+            Some(TimeDelta::minutes(5))
+        );
+    }
+
+    #[test]
+    fn test_projected_usage() {
+        let object = |name: &str, length| StorageObject {
+            path: "/myzone/".to_string(),
+            object_name: name.to_string(),
+            length,
+            ..Default::default()
+        };
+        let remote = RemoteIndex::new(vec![object("a.html", 100)], &Filter::default());
+        let upload = SyncAction::Upload {
+            key: "/myzone/a.html".to_string(),
+            path: PathBuf::from("a.html"),
+            length: 150,
+        };
+        assert_eq!(projected_usage(&remote, &[upload]), 150);
+        // A delete bigger than the zone, as a plugin might plan.
+        let delete = SyncAction::DeleteRemote {
+            key: "/myzone/b.html".to_string(),
+            length: 500,
+        };
+        assert_eq!(projected_usage(&remote, &[delete]), 0);
+        assert_eq!(
+            tr(Message::QuotaWarning {
+                usage: 1536,
+                threshold: 1024
+            }),
+            "Sync would bring zone usage to 1.5 KiB, above the quota warning threshold of 1.0 KiB"
+        );
+    }
+
+    #[test]
+    fn test_parse_percent() {
+        assert_eq!(parse_percent("5%").unwrap(), 5.0);
+        assert_eq!(parse_percent("0.5").unwrap(), 0.5);
+        assert!(parse_percent("-1%").is_err());
+        assert!(parse_percent("%").is_err());
+    }
+
+    #[test]
+    fn test_write_whole() {
+        let dir = std::env::temp_dir().join(format!("bunnysync-whole-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("page.html");
+        std::fs::write(&path, "old").unwrap();
+        write_whole(&path, b"new").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        // A file can't be moved over a directory, and no part is left.
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        assert!(write_whole(&dir.join("sub"), b"data").is_err());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,200 @@
+//! A record of every request made in a run, for `--timeline`. It is written
+//! in the Chrome trace event format, so a slow sync can be opened in a trace
+//! viewer such as Perfetto or `chrome://tracing` and looked over request by
+//! request. Each thread that sends requests gets its own track.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    cell::Cell,
+    path::Path,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use ureq::{
+    Body, SendBody,
+    http::{Request, Response, header},
+    middleware::MiddlewareNext,
+};
+
+/// The requests of a run, from when each was sent until its response came
+/// back. Response bodies are read after that, so a download's span ends
+/// when its first bytes arrive.
+#[derive(Debug)]
+pub struct Timeline {
+    started: Instant,
+    events: Mutex<Vec<TraceEvent>>,
+}
+
+/// A complete event, as the trace event format calls a span with a start and
+/// a duration, both in microseconds.
+#[derive(Debug, Serialize)]
+struct TraceEvent {
+    name: String,
+    cat: &'static str,
+    ph: &'static str,
+    ts: u64,
+    dur: u64,
+    pid: u32,
+    tid: u64,
+    args: RequestArgs,
+}
+
+#[derive(Debug, Serialize)]
+struct RequestArgs {
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    /// The size of the request body, when known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sent: Option<u64>,
+    /// The size of the response body, when known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    received: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Trace<'a> {
+    trace_events: &'a [TraceEvent],
+    display_time_unit: &'static str,
+}
+
+/// A small number for the current thread, as thread ids are opaque.
+fn thread_number() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    thread_local! {
+        static NUMBER: Cell<u64> = const { Cell::new(0) };
+    }
+    NUMBER.with(|number| {
+        if number.get() == 0 {
+            number.set(NEXT.fetch_add(1, Ordering::Relaxed));
+        }
+        number.get()
+    })
+}
+
+fn content_length(headers: &header::HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Timeline {
+            started: Instant::now(),
+            events: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Send a request on, noting how it went. Meant to be called from a
+    /// middleware.
+    pub fn record(
+        &self,
+        request: Request<SendBody>,
+        next: MiddlewareNext,
+    ) -> Result<Response<Body>, ureq::Error> {
+        let name = format!("{} {}", request.method(), request.uri().path());
+        let url = request.uri().to_string();
+        let sent = content_length(request.headers());
+        let start = Instant::now();
+        let result = next.handle(request);
+        let args = match &result {
+            Ok(response) => RequestArgs {
+                url,
+                status: Some(response.status().as_u16()),
+                sent,
+                received: content_length(response.headers()),
+                error: None,
+            },
+            Err(e) => RequestArgs {
+                url,
+                status: None,
+                sent,
+                received: None,
+                error: Some(e.to_string()),
+            },
+        };
+        self.push(name, start, start.elapsed(), args);
+        result
+    }
+
+    fn push(&self, name: String, start: Instant, took: Duration, args: RequestArgs) {
+        let event = TraceEvent {
+            name,
+            cat: "request",
+            ph: "X",
+            ts: start.saturating_duration_since(self.started).as_micros() as u64,
+            dur: took.as_micros() as u64,
+            pid: std::process::id(),
+            tid: thread_number(),
+            args,
+        };
+        self.events.lock().unwrap().push(event);
+    }
+
+    /// The trace of the requests so far.
+    fn to_json(&self) -> Result<String> {
+        let events = self.events.lock().unwrap();
+        let trace = Trace {
+            trace_events: &events,
+            display_time_unit: "ms",
+        };
+        Ok(serde_json::to_string(&trace)?)
+    }
+
+    /// Write the trace of the requests so far to a file.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeline() {
+        let timeline = Timeline::new();
+        let start = timeline.started + Duration::from_millis(5);
+        timeline.push(
+            "PUT /zone/a.txt".to_string(),
+            start,
+            Duration::from_micros(1500),
+            RequestArgs {
+                url: "https://storage.bunnycdn.com/zone/a.txt".to_string(),
+                status: Some(201),
+                sent: Some(10),
+                received: None,
+                error: None,
+            },
+        );
+        let trace: serde_json::Value = serde_json::from_str(&timeline.to_json().unwrap()).unwrap();
+        let event = &trace["traceEvents"][0];
+        assert_eq!(event["name"], "PUT /zone/a.txt");
+        assert_eq!(event["ph"], "X");
+        assert_eq!(event["ts"], 5000);
+        assert_eq!(event["dur"], 1500);
+        assert_eq!(event["tid"], thread_number());
+        assert_eq!(event["args"]["status"], 201);
+        assert_eq!(event["args"]["sent"], 10);
+        assert!(event["args"].get("received").is_none());
+        assert_eq!(trace["displayTimeUnit"], "ms");
+    }
+}
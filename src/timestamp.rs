@@ -0,0 +1,113 @@
+//! Times as the storage API gives them. `LastChanged` and `DateCreated` come
+//! without a timezone, and bunny.net gives them in UTC. A server that
+//! doesn't, like a mock of the API running in local time, can be given its
+//! offset with `--server-timezone` so remote times still compare right with
+//! local ones.
+
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{fmt, sync::OnceLock};
+
+static SERVER_OFFSET: OnceLock<FixedOffset> = OnceLock::new();
+
+/// Set how far the server's clock is from UTC for the rest of the run. Only
+/// the first call counts.
+pub fn set_server_offset(offset: FixedOffset) {
+    let _ = SERVER_OFFSET.set(offset);
+}
+
+fn server_offset() -> FixedOffset {
+    SERVER_OFFSET
+        .get()
+        .copied()
+        .unwrap_or(FixedOffset::east_opt(0).unwrap())
+}
+
+/// Parse a timezone given as an offset from UTC, like `+02:00`, or as `UTC`.
+pub fn parse_offset(text: &str) -> Result<FixedOffset> {
+    if text.eq_ignore_ascii_case("utc") || text.eq_ignore_ascii_case("z") {
+        return Ok(FixedOffset::east_opt(0).unwrap());
+    }
+    text.parse().map_err(|_| {
+        anyhow!(
+            "Invalid timezone {}, use an offset like +02:00 or UTC",
+            text
+        )
+    })
+}
+
+/// A time as the storage API gives it, in the server's timezone. It is kept
+/// as given, so listings and state stay the same whatever the offset, and
+/// only turned into UTC to be compared.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    JsonSchema,
+)]
+#[serde(transparent)]
+pub struct RemoteTime(NaiveDateTime);
+
+impl RemoteTime {
+    /// The time the server would give for a moment.
+    pub fn from_utc(time: DateTime<Utc>) -> Self {
+        Self::from_utc_at(time, server_offset())
+    }
+
+    /// The moment the time stands for.
+    pub fn utc(self) -> DateTime<Utc> {
+        self.utc_at(server_offset())
+    }
+
+    fn from_utc_at(time: DateTime<Utc>, offset: FixedOffset) -> Self {
+        RemoteTime(time.with_timezone(&offset).naive_local())
+    }
+
+    fn utc_at(self, offset: FixedOffset) -> DateTime<Utc> {
+        offset
+            .from_local_datetime(&self.0)
+            .single()
+            .expect("a fixed offset has one time for each local time")
+            .with_timezone(&Utc)
+    }
+}
+
+impl fmt::Display for RemoteTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.utc().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_time() {
+        let time: RemoteTime = serde_json::from_str("\"2024-01-15T12:30:45.123\"").unwrap();
+        assert_eq!(
+            serde_json::to_string(&time).unwrap(),
+            "\"2024-01-15T12:30:45.123\""
+        );
+        assert_eq!(time.utc().to_rfc3339(), "2024-01-15T12:30:45.123+00:00");
+        assert_eq!(RemoteTime::from_utc(time.utc()), time);
+
+        let offset = parse_offset("+02:00").unwrap();
+        let moment = time.utc_at(offset);
+        assert_eq!(moment.to_rfc3339(), "2024-01-15T10:30:45.123+00:00");
+        assert_eq!(RemoteTime::from_utc_at(moment, offset), time);
+        assert_eq!(parse_offset("UTC").unwrap().local_minus_utc(), 0);
+        assert_eq!(parse_offset("-05:30").unwrap().local_minus_utc(), -19800);
+        assert!(parse_offset("Europe/Berlin").is_err());
+    }
+}
@@ -0,0 +1,326 @@
+use anyhow::{Result, anyhow};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::plugin::PluginTransform;
+
+/// A change applied to the contents of a file before it is uploaded.
+pub trait Transform {
+    fn apply(&self, data: Vec<u8>) -> Result<Vec<u8>>;
+}
+
+/// A transform to run on files whose name matches a pattern, as given in the
+/// config file. Names other than the built in ones are plugins:
+///
+/// ```toml
+/// [[transform]]
+/// pattern = "*.css"
+/// name = "minify-css"
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct TransformRule {
+    pub pattern: String,
+    pub name: String,
+}
+
+/// The transforms that apply to uploads, run in the order they are
+/// configured.
+#[derive(Default)]
+pub struct Pipeline {
+    rules: Vec<TransformRule>,
+    transforms: Vec<(String, Box<dyn Transform>)>,
+}
+
+impl Pipeline {
+    pub fn new(rules: &[TransformRule]) -> Result<Self> {
+        let transforms = rules
+            .iter()
+            .map(|rule| {
+                let transform = builtin(&rule.name)
+                    .or_else(|| {
+                        PluginTransform::find(&rule.name)
+                            .map(|plugin| Box::new(plugin) as Box<dyn Transform>)
+                    })
+                    .ok_or_else(|| anyhow!("Unknown transform {}", rule.name))?;
+                Ok((rule.pattern.clone(), transform))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Pipeline {
+            rules: rules.to_vec(),
+            transforms,
+        })
+    }
+
+    /// The rules the pipeline was built from.
+    pub fn rules(&self) -> &[TransformRule] {
+        &self.rules
+    }
+
+    /// Check if any transform applies to a file.
+    pub fn matches(&self, file_name: &str) -> bool {
+        self.transforms
+            .iter()
+            .any(|(pattern, _)| glob_match::glob_match(pattern, file_name))
+    }
+
+    /// Run every matching transform over a file's contents.
+    pub fn apply(&self, file_name: &str, mut data: Vec<u8>) -> Result<Vec<u8>> {
+        for (pattern, transform) in &self.transforms {
+            if glob_match::glob_match(pattern, file_name) {
+                data = transform.apply(data)?;
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// Look up a built in transform by name.
+fn builtin(name: &str) -> Option<Box<dyn Transform>> {
+    match name {
+        "minify-css" => Some(Box::new(MinifyCss)),
+        "strip-exif" => Some(Box::new(StripExif)),
+        _ => None,
+    }
+}
+
+/// A conservative CSS minifier. It drops comments and collapses whitespace,
+/// leaving strings alone. Whitespace is only removed entirely next to
+/// braces and semicolons, where it can never be significant.
+struct MinifyCss;
+
+impl Transform for MinifyCss {
+    fn apply(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        let css = String::from_utf8(data).map_err(|_| anyhow!("CSS file is not UTF-8"))?;
+        let mut out = String::with_capacity(css.len());
+        let mut chars = css.chars().peekable();
+        let mut pending_space = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '/' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    let mut prev = ' ';
+                    for c in chars.by_ref() {
+                        if prev == '*' && c == '/' {
+                            break;
+                        }
+                        prev = c;
+                    }
+                    pending_space = true;
+                }
+                c if c.is_whitespace() => pending_space = true,
+                '"' | '\'' => {
+                    push_space(&mut out, &mut pending_space, c);
+                    out.push(c);
+                    while let Some(s) = chars.next() {
+                        out.push(s);
+                        if s == '\\' {
+                            if let Some(escaped) = chars.next() {
+                                out.push(escaped);
+                            }
+                        } else if s == c {
+                            break;
+                        }
+                    }
+                }
+                c => {
+                    push_space(&mut out, &mut pending_space, c);
+                    out.push(c);
+                }
+            }
+        }
+        Ok(out.into_bytes())
+    }
+}
+
+/// The images `strip-exif` understands.
+pub const IMAGE_PATTERN: &str = "*.{jpg,jpeg,png,JPG,JPEG,PNG}";
+
+/// Removes metadata such as camera details and GPS position from JPEG and
+/// PNG images. Pixel data and colour profiles are kept. Other files pass
+/// through unchanged.
+struct StripExif;
+
+impl Transform for StripExif {
+    fn apply(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        if data.starts_with(JPEG_SOI) {
+            strip_jpeg(&data)
+        } else if data.starts_with(PNG_SIGNATURE) {
+            strip_png(&data)
+        } else {
+            Ok(data)
+        }
+    }
+}
+
+const JPEG_SOI: &[u8] = &[0xFF, 0xD8];
+const PNG_SIGNATURE: &[u8] = &[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// Drop the APP1 (Exif and XMP), APP13 (IPTC) and comment segments that come
+/// before the image data.
+fn strip_jpeg(data: &[u8]) -> Result<Vec<u8>> {
+    let malformed = || anyhow!("malformed JPEG");
+    let mut out = JPEG_SOI.to_vec();
+    let mut pos = JPEG_SOI.len();
+    loop {
+        if data.get(pos) != Some(&0xFF) {
+            return Err(malformed());
+        }
+        let marker = *data.get(pos + 1).ok_or_else(malformed)?;
+        match marker {
+            // Fill byte before a marker.
+            0xFF => {
+                pos += 1;
+                continue;
+            }
+            // Start of scan or end of image. Everything from here on is
+            // image data.
+            0xDA | 0xD9 => {
+                out.extend_from_slice(&data[pos..]);
+                return Ok(out);
+            }
+            _ => {}
+        }
+        let length = data
+            .get(pos + 2..pos + 4)
+            .map(|len| u16::from_be_bytes([len[0], len[1]]) as usize)
+            .ok_or_else(malformed)?;
+        let end = pos + 2 + length;
+        if length < 2 || end > data.len() {
+            return Err(malformed());
+        }
+        if !matches!(marker, 0xE1 | 0xED | 0xFE) {
+            out.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+    }
+}
+
+/// Drop the Exif, text and timestamp chunks.
+fn strip_png(data: &[u8]) -> Result<Vec<u8>> {
+    let malformed = || anyhow!("malformed PNG");
+    let mut out = PNG_SIGNATURE.to_vec();
+    let mut pos = PNG_SIGNATURE.len();
+    while pos < data.len() {
+        let header = data.get(pos..pos + 8).ok_or_else(malformed)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        // Length, type, data and CRC.
+        let end = pos + 12 + length;
+        if end > data.len() {
+            return Err(malformed());
+        }
+        if !matches!(
+            &header[4..8],
+            b"eXIf" | b"tEXt" | b"zTXt" | b"iTXt" | b"tIME"
+        ) {
+            out.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+    }
+    Ok(out)
+}
+
+/// Emit a single space for a run of whitespace or comments, unless it sits
+/// next to a brace or semicolon.
+fn push_space(out: &mut String, pending_space: &mut bool, next: char) {
+    let tight = |c: char| matches!(c, '{' | '}' | ';');
+    if *pending_space && !out.is_empty() && !tight(next) && !out.ends_with(tight) {
+        out.push(' ');
+    }
+    *pending_space = false;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minify(css: &str) -> String {
+        String::from_utf8(MinifyCss.apply(css.into()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_minify_css() {
+        assert_eq!(
+            minify("/* header */\nbody {\n  margin: 0;\n  width: calc(100% - 2px);\n}\n"),
+            "body{margin: 0;width: calc(100% - 2px);}"
+        );
+        assert_eq!(minify("a :hover , b{}"), "a :hover , b{}");
+        assert_eq!(
+            minify("a::after { content: \"  /* kept */  \"; }"),
+            "a::after{content: \"  /* kept */  \";}"
+        );
+    }
+
+    #[test]
+    fn test_strip_jpeg() {
+        let segment = |marker: u8, body: &[u8]| {
+            let mut segment = vec![0xFF, marker];
+            segment.extend_from_slice(&(body.len() as u16 + 2).to_be_bytes());
+            segment.extend_from_slice(body);
+            segment
+        };
+        let jfif = segment(0xE0, b"JFIF\0");
+        let exif = segment(0xE1, b"Exif\0\0gps");
+        let comment = segment(0xFE, b"hello");
+        let icc = segment(0xE2, b"ICC_PROFILE\0");
+        let scan = [0xFF, 0xDA, 0x00, 0x02, 0x12, 0xFF, 0xE1, 0xFF, 0xD9];
+
+        let image = [JPEG_SOI, &jfif, &exif, &comment, &icc, &scan].concat();
+        let stripped = StripExif.apply(image).unwrap();
+        assert_eq!(stripped, [JPEG_SOI, &jfif, &icc, &scan].concat());
+
+        assert!(StripExif.apply([JPEG_SOI, &exif[..5]].concat()).is_err());
+    }
+
+    #[test]
+    fn test_strip_png() {
+        let chunk = |kind: &[u8], body: &[u8]| {
+            let mut chunk = (body.len() as u32).to_be_bytes().to_vec();
+            chunk.extend_from_slice(kind);
+            chunk.extend_from_slice(body);
+            // The CRC isn't checked.
+            chunk.extend_from_slice(&[0; 4]);
+            chunk
+        };
+        let header = chunk(b"IHDR", &[0; 13]);
+        let text = chunk(b"tEXt", b"Author\0me");
+        let exif = chunk(b"eXIf", b"MM\0*");
+        let data = chunk(b"IDAT", &[1, 2, 3]);
+        let end = chunk(b"IEND", &[]);
+
+        let image = [PNG_SIGNATURE, &header, &text, &exif, &data, &end].concat();
+        let stripped = StripExif.apply(image).unwrap();
+        assert_eq!(stripped, [PNG_SIGNATURE, &header, &data, &end].concat());
+
+        assert_eq!(StripExif.apply(b"GIF89a".to_vec()).unwrap(), b"GIF89a");
+        assert!(glob_match::glob_match(IMAGE_PATTERN, "photo.JPG"));
+        assert!(!glob_match::glob_match(IMAGE_PATTERN, "photo.gif"));
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let rules = [TransformRule {
+            pattern: "*.css".to_string(),
+            name: "minify-css".to_string(),
+        }];
+        let pipeline = Pipeline::new(&rules).unwrap();
+        assert!(pipeline.matches("site.css"));
+        assert!(!pipeline.matches("index.html"));
+        assert_eq!(
+            pipeline.apply("site.css", b"a { }".to_vec()).unwrap(),
+            b"a{}"
+        );
+        assert_eq!(
+            pipeline.apply("index.html", b"a { }".to_vec()).unwrap(),
+            b"a { }"
+        );
+    }
+
+    #[test]
+    fn test_unknown_transform() {
+        let rules = [TransformRule {
+            pattern: "*".to_string(),
+            name: "nope".to_string(),
+        }];
+        assert!(Pipeline::new(&rules).is_err());
+    }
+}
@@ -0,0 +1,422 @@
+use anyhow::{Result, anyhow};
+use chrono::{Local, NaiveTime};
+use serde::{Deserialize, Deserializer};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use ureq::{
+    Error, Timeout,
+    unversioned::transport::{
+        Buffers, ConnectionDetails, Connector, NextTimeout, Transport, time::Duration as Wait,
+    },
+};
+
+use crate::units;
+
+/// A connector that wraps the transport produced by the previous connector in
+/// the chain with stall detection. A stall is a period where no bytes at all
+/// are sent or received, which is distinct from the total timeouts ureq
+/// provides: a multi-gigabyte transfer may take hours but should never go
+/// silent for long.
+#[derive(Debug)]
+pub struct StallConnector {
+    pub stall_timeout: Duration,
+}
+
+impl Connector<Box<dyn Transport>> for StallConnector {
+    type Out = StallTransport;
+
+    fn connect(
+        &self,
+        _details: &ConnectionDetails,
+        chained: Option<Box<dyn Transport>>,
+    ) -> Result<Option<Self::Out>, Error> {
+        Ok(chained.map(|inner| StallTransport {
+            inner,
+            stall_timeout: self.stall_timeout,
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct StallTransport {
+    inner: Box<dyn Transport>,
+    stall_timeout: Duration,
+}
+
+impl StallTransport {
+    /// Clamp the timeout ureq wants to wait for to the stall timeout. Returns
+    /// the new timeout and whether it was shortened.
+    fn clamp(&self, timeout: NextTimeout) -> (NextTimeout, bool) {
+        clamp_timeout(timeout, self.stall_timeout)
+    }
+
+    fn stall_error(&self) -> Error {
+        Error::Io(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!(
+                "transfer stalled: no data for {} seconds",
+                self.stall_timeout.as_secs()
+            ),
+        ))
+    }
+}
+
+impl Transport for StallTransport {
+    fn buffers(&mut self) -> &mut dyn Buffers {
+        self.inner.buffers()
+    }
+
+    fn transmit_output(&mut self, amount: usize, timeout: NextTimeout) -> Result<(), Error> {
+        let (timeout, clamped) = self.clamp(timeout);
+        match self.inner.transmit_output(amount, timeout) {
+            Err(Error::Timeout(_)) if clamped => Err(self.stall_error()),
+            result => result,
+        }
+    }
+
+    fn await_input(&mut self, timeout: NextTimeout) -> Result<bool, Error> {
+        let (timeout, clamped) = self.clamp(timeout);
+        match self.inner.await_input(timeout) {
+            Err(Error::Timeout(_)) if clamped => Err(self.stall_error()),
+            result => result,
+        }
+    }
+
+    fn is_open(&mut self) -> bool {
+        self.inner.is_open()
+    }
+
+    fn is_tls(&self) -> bool {
+        self.inner.is_tls()
+    }
+}
+
+/// Shorten a ureq timeout to the stall timeout if it is longer. Awaiting a
+/// 100-continue is expected to time out and is left alone.
+fn clamp_timeout(timeout: NextTimeout, stall_timeout: Duration) -> (NextTimeout, bool) {
+    if timeout.reason == Timeout::Await100 || *timeout.after <= stall_timeout {
+        return (timeout, false);
+    }
+    let clamped = NextTimeout {
+        after: Wait::Exact(stall_timeout),
+        reason: timeout.reason,
+    };
+    (clamped, true)
+}
+
+/// How much may be used a second at each time of day, for `--bwlimit`.
+/// Outside all windows, or in one with a rate of 0, there is no limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule(Vec<Window>);
+
+/// A rate from one local time of day until another. A window that ends
+/// before it starts runs past midnight, and one that ends when it starts
+/// lasts all day.
+#[derive(Debug, Clone, PartialEq)]
+struct Window {
+    start: NaiveTime,
+    end: NaiveTime,
+    rate: u64,
+}
+
+impl Window {
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+impl Schedule {
+    /// The same rate all day.
+    pub fn fixed(rate: u64) -> Self {
+        Schedule(vec![Window {
+            start: NaiveTime::MIN,
+            end: NaiveTime::MIN,
+            rate,
+        }])
+    }
+
+    /// Parse a size like `5M`, for the same rate all day, or windows like
+    /// `08:00-18:00=2M,18:00-08:00=0`.
+    pub fn parse(text: &str) -> Result<Self> {
+        if !text.contains('=') {
+            return Ok(Schedule::fixed(units::parse_size(text)?));
+        }
+        let windows = text
+            .split(',')
+            .map(|window| {
+                let (times, rate) = window
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("invalid window {}, use e.g. 08:00-18:00=2M", window))?;
+                Self::window(times, units::parse_size(rate)?)
+            })
+            .collect::<Result<_>>()?;
+        Ok(Schedule(windows))
+    }
+
+    /// A window from times like `08:00-18:00`.
+    fn window(times: &str, rate: u64) -> Result<Window> {
+        let invalid = || anyhow!("invalid time window {}, use e.g. 08:00-18:00", times);
+        let (start, end) = times.split_once('-').ok_or_else(invalid)?;
+        let time =
+            |text: &str| NaiveTime::parse_from_str(text.trim(), "%H:%M").map_err(|_| invalid());
+        Ok(Window {
+            start: time(start)?,
+            end: time(end)?,
+            rate,
+        })
+    }
+
+    /// The rate at a time of day, or `None` if there is no limit then.
+    pub fn rate_at(&self, time: NaiveTime) -> Option<u64> {
+        self.0
+            .iter()
+            .find(|window| window.contains(time))
+            .map(|window| window.rate)
+            .filter(|rate| *rate > 0)
+    }
+}
+
+/// A schedule in a config file may be a size, or a table of sizes by time
+/// window like `{ "08:00-18:00" = "2M", "18:00-08:00" = "0" }`.
+impl<'de> Deserialize<'de> for Schedule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Size {
+            Bytes(u64),
+            Text(String),
+        }
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Limit {
+            Size(Size),
+            Windows(BTreeMap<String, Size>),
+        }
+        let size = |size: Size| match size {
+            Size::Bytes(bytes) => Ok(bytes),
+            Size::Text(text) => units::parse_size(&text),
+        };
+        let schedule = match Limit::deserialize(deserializer)? {
+            Limit::Size(rate) => size(rate).map(Schedule::fixed),
+            Limit::Windows(windows) => windows
+                .into_iter()
+                .map(|(times, rate)| Self::window(&times, size(rate)?))
+                .collect::<Result<_>>()
+                .map(Schedule),
+        };
+        schedule.map_err(serde::de::Error::custom)
+    }
+}
+
+/// A token bucket shared by every agent of a run, so that together they use
+/// no more than a rate a second of something, like bytes for `--bwlimit` or
+/// requests for `--max-requests-per-second`. Up to a second's worth may go
+/// at once after a quiet spell.
+#[derive(Debug)]
+pub struct Rate {
+    schedule: Schedule,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Below zero when more was taken than had been earned.
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Rate {
+    pub fn new(rate: u64) -> Self {
+        Self::scheduled(Schedule::fixed(rate))
+    }
+
+    /// A rate that changes with the local time of day.
+    pub fn scheduled(schedule: Schedule) -> Self {
+        Rate {
+            schedule,
+            bucket: Mutex::new(Bucket {
+                // Full, whatever the rate turns out to be.
+                tokens: f64::INFINITY,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Wait until using an amount keeps within the rate.
+    pub fn take(&self, amount: usize) {
+        let Some(rate) = self.schedule.rate_at(Local::now().time()) else {
+            return;
+        };
+        let wait = self.reserve(amount, Instant::now(), rate);
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// Take an amount from the bucket, returning how long to wait for it to
+    /// have been earned at a rate.
+    fn reserve(&self, amount: usize, now: Instant, rate: u64) -> Duration {
+        let rate = rate as f64;
+        let mut bucket = self.bucket.lock().expect("rate lock poisoned");
+        let earned = now.saturating_duration_since(bucket.refilled).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + earned).min(rate) - amount as f64;
+        bucket.refilled = now;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / rate)
+        }
+    }
+}
+
+/// A connector that wraps the transport produced by the previous connector in
+/// the chain so its traffic counts against a shared [`Rate`].
+#[derive(Debug)]
+pub struct ThrottleConnector {
+    pub bandwidth: Arc<Rate>,
+}
+
+impl<In: Transport> Connector<In> for ThrottleConnector {
+    type Out = ThrottleTransport<In>;
+
+    fn connect(
+        &self,
+        _details: &ConnectionDetails,
+        chained: Option<In>,
+    ) -> Result<Option<Self::Out>, Error> {
+        Ok(chained.map(|inner| ThrottleTransport {
+            inner,
+            bandwidth: self.bandwidth.clone(),
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct ThrottleTransport<T> {
+    inner: T,
+    bandwidth: Arc<Rate>,
+}
+
+impl<T: Transport> Transport for ThrottleTransport<T> {
+    fn buffers(&mut self) -> &mut dyn Buffers {
+        self.inner.buffers()
+    }
+
+    fn transmit_output(&mut self, amount: usize, timeout: NextTimeout) -> Result<(), Error> {
+        self.bandwidth.take(amount);
+        self.inner.transmit_output(amount, timeout)
+    }
+
+    fn await_input(&mut self, timeout: NextTimeout) -> Result<bool, Error> {
+        let before = self.inner.buffers().input().len();
+        let progress = self.inner.await_input(timeout)?;
+        let after = self.inner.buffers().input().len();
+        self.bandwidth.take(after.saturating_sub(before));
+        Ok(progress)
+    }
+
+    fn is_open(&mut self) -> bool {
+        self.inner.is_open()
+    }
+
+    fn is_tls(&self) -> bool {
+        self.inner.is_tls()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clamp_unbounded_timeout() {
+        let timeout = NextTimeout {
+            after: Wait::NotHappening,
+            reason: Timeout::RecvBody,
+        };
+        let (clamped, was_clamped) = clamp_timeout(timeout, Duration::from_secs(30));
+        assert!(was_clamped);
+        assert_eq!(*clamped.after, Duration::from_secs(30));
+        assert_eq!(clamped.reason, Timeout::RecvBody);
+    }
+
+    #[test]
+    fn test_clamp_keeps_shorter_timeout() {
+        let timeout = NextTimeout {
+            after: Wait::Exact(Duration::from_secs(5)),
+            reason: Timeout::Global,
+        };
+        let (clamped, was_clamped) = clamp_timeout(timeout, Duration::from_secs(30));
+        assert!(!was_clamped);
+        assert_eq!(*clamped.after, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_clamp_ignores_await_100() {
+        let timeout = NextTimeout {
+            after: Wait::Exact(Duration::from_secs(60)),
+            reason: Timeout::Await100,
+        };
+        let (_, was_clamped) = clamp_timeout(timeout, Duration::from_secs(30));
+        assert!(!was_clamped);
+    }
+
+    #[test]
+    fn test_rate() {
+        let bandwidth = Rate::new(1000);
+        let start = bandwidth.bucket.lock().unwrap().refilled;
+        // A second's worth goes at once, then the rest waits to be earned.
+        assert_eq!(bandwidth.reserve(1000, start, 1000), Duration::ZERO);
+        assert_eq!(
+            bandwidth.reserve(500, start, 1000),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            bandwidth.reserve(250, start + Duration::from_millis(500), 1000),
+            Duration::from_millis(250)
+        );
+        // A quiet spell earns no more than a second's worth.
+        let later = start + Duration::from_secs(60);
+        assert_eq!(bandwidth.reserve(1000, later, 1000), Duration::ZERO);
+        assert_eq!(
+            bandwidth.reserve(100, later, 1000),
+            Duration::from_millis(100)
+        );
+    }
+
+    #[test]
+    fn test_schedule() {
+        let at = |text: &str| NaiveTime::parse_from_str(text, "%H:%M").unwrap();
+        let fixed = Schedule::parse("5M").unwrap();
+        assert_eq!(fixed.rate_at(at("03:00")), Some(5 << 20));
+        assert_eq!(Schedule::parse("0").unwrap().rate_at(at("03:00")), None);
+
+        let office = Schedule::parse("08:00-18:00=2M,18:00-08:00=0").unwrap();
+        assert_eq!(office.rate_at(at("08:00")), Some(2 << 20));
+        assert_eq!(office.rate_at(at("17:59")), Some(2 << 20));
+        assert_eq!(office.rate_at(at("18:00")), None);
+        assert_eq!(office.rate_at(at("02:00")), None);
+        let lunch = Schedule::parse("12:00-13:00=1M").unwrap();
+        assert_eq!(lunch.rate_at(at("11:00")), None);
+        assert!(Schedule::parse("08:00=2M").is_err());
+        assert!(Schedule::parse("8am-6pm=2M").is_err());
+
+        #[derive(Deserialize)]
+        struct Config {
+            bwlimit: Schedule,
+        }
+        let config: Config =
+            toml::from_str(r#"bwlimit = { "08:00-18:00" = "2M", "18:00-08:00" = "0" }"#).unwrap();
+        assert_eq!(config.bwlimit, office);
+        let config: Config = toml::from_str("bwlimit = 1000").unwrap();
+        assert_eq!(config.bwlimit, Schedule::fixed(1000));
+        assert!(toml::from_str::<Config>(r#"bwlimit = { "08:00" = "2M" }"#).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+//! Parsing and printing of durations and sizes, shared by every flag that
+//! takes one so they all accept the same forms.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Deserializer};
+use std::time::Duration;
+
+/// Parse a duration like `500ms`, `30s`, `5m`, `1.5h` or `1d`. A bare number
+/// is in seconds.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let invalid = || {
+        anyhow!(
+            "invalid duration {}, use e.g. 500ms, 30s, 5m, 1.5h or 1d",
+            s
+        )
+    };
+    let (number, unit) = split_number(s);
+    let nanos: f64 = match unit {
+        "ms" => 1e6,
+        "" | "s" => 1e9,
+        "m" => 60e9,
+        "h" => 60.0 * 60e9,
+        "d" => 24.0 * 60.0 * 60e9,
+        _ => return Err(invalid()),
+    };
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    let nanos = (number * nanos).round();
+    if nanos >= u64::MAX as f64 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_nanos(nanos as u64))
+}
+
+/// Parse a size like `500K`, `10MB` or `1.5GiB`. `KB`, `MB`, `GB` and `TB`
+/// are powers of 1000, while `KiB` and the like, as well as `K`, `M`, `G`
+/// and `T` on their own, are powers of 1024. A bare number is in bytes.
+pub fn parse_size(s: &str) -> Result<u64> {
+    let invalid = || anyhow!("invalid size {}, use e.g. 500K, 10MB or 1.5GiB", s);
+    let (number, unit) = split_number(s);
+    let factor: f64 = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "k" | "kib" => 1024.0,
+        "m" | "mib" => 1024.0 * 1024.0,
+        "g" | "gib" => 1024.0 * 1024.0 * 1024.0,
+        "t" | "tib" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        "kb" => 1e3,
+        "mb" => 1e6,
+        "gb" => 1e9,
+        "tb" => 1e12,
+        _ => return Err(invalid()),
+    };
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    let bytes = (number * factor).round();
+    if bytes >= u64::MAX as f64 {
+        return Err(invalid());
+    }
+    Ok(bytes as u64)
+}
+
+/// Read a size from a config file, where it may be a number of bytes or a
+/// string like `10GiB`.
+pub fn deserialize_size<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+    match Option::<Size>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Size::Bytes(bytes)) => Ok(Some(bytes)),
+        Some(Size::Text(text)) => parse_size(&text)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
+}
+
+/// Split a value into its number and the unit after it.
+fn split_number(s: &str) -> (&str, &str) {
+    let i = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    s.split_at(i)
+}
+
+/// Format a byte count with binary units, e.g. `1.5 KiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1024), "1.0 KiB");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1024), "5.0 GiB");
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(86400));
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("1.5h").unwrap(), Duration::from_secs(5400));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("5 m").is_err());
+        assert!(parse_duration("1w").is_err());
+        assert!(parse_duration("1.2.3s").is_err());
+        assert!(parse_duration("-1s").is_err());
+        assert!(parse_duration("99999999999999d").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("100").unwrap(), 100);
+        assert_eq!(parse_size("500K").unwrap(), 500 * 1024);
+        assert_eq!(parse_size("10MB").unwrap(), 10_000_000);
+        assert_eq!(parse_size("1.5GiB").unwrap(), 1536 * 1024 * 1024);
+        assert_eq!(parse_size("2gib").unwrap(), 2 << 30);
+        assert!(parse_size("10 MB").is_err());
+        assert!(parse_size("10XB").is_err());
+        assert!(parse_size("GB").is_err());
+
+        #[derive(Deserialize)]
+        struct Config {
+            #[serde(default, deserialize_with = "deserialize_size")]
+            quota: Option<u64>,
+        }
+        let quota = |toml: &str| toml::from_str::<Config>(toml).map(|config| config.quota);
+        assert_eq!(quota("quota = 2048").unwrap(), Some(2048));
+        assert_eq!(quota("quota = \"2K\"").unwrap(), Some(2048));
+        assert_eq!(quota("").unwrap(), None);
+        assert!(quota("quota = \"lots\"").is_err());
+    }
+}
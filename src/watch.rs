@@ -0,0 +1,309 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError},
+    time::Duration,
+};
+use ureq::Agent;
+
+use crate::{
+    freeze,
+    i18n::{Message, tr},
+    interrupt, local,
+    output::Reporter,
+    paths::{self, PathMap},
+    plan::{RemoteIndex, SyncAction},
+    storage::{self, StorageObject},
+    sync::{self, SyncOptions},
+};
+
+/// How long the directory must be quiet before a batch of changes is pushed.
+/// Editors often write a file several times in quick succession on save.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// How often files held back by `--min-age` are looked at again.
+const SETTLE_CHECK: Duration = Duration::from_secs(1);
+
+/// Watch the local directory and push changed files to the remote as they
+/// are saved. The caller is expected to have done a full sync first.
+pub fn watch_to_remote(
+    agent: &Agent,
+    base_url: &str,
+    local: &str,
+    remote: &str,
+    options: &SyncOptions,
+    reporter: &mut Reporter,
+) -> Result<()> {
+    let remote = storage::strip_zone_prefix(remote);
+    let paths = PathMap::new(remote);
+    // Watch the canonical path so event paths can be made relative to it.
+    let root = Path::new(local).canonicalize()?;
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(&root, RecursiveMode::Recursive)?;
+    reporter.message(&tr(Message::Watching { path: local }));
+
+    // What the zone holds, kept up to date as changes are pushed, to know
+    // whether a path that is gone was a file or a directory there.
+    let mut zone = RemoteIndex::new(
+        sync::missing_as_empty(storage::get_all_objects(agent, base_url, remote), remote)?,
+        &options.filter,
+    );
+    // Files changed too recently to upload, to try again later.
+    let mut waiting = BTreeSet::new();
+    let mut frozen = false;
+    while !interrupt::interrupted() {
+        let mut changed = std::mem::take(&mut waiting);
+        // Wait until something happens, or until it's time to look at the
+        // waiting files again or check for Ctrl-C, then keep collecting
+        // until things quiet down.
+        let event = match rx.recv_timeout(SETTLE_CHECK) {
+            Ok(event) => Some(event),
+            Err(RecvTimeoutError::Timeout) if changed.is_empty() => continue,
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if let Some(event) = event {
+            collect_paths(event, &mut changed);
+            while let Ok(event) = rx.recv_timeout(DEBOUNCE) {
+                collect_paths(event, &mut changed);
+            }
+        }
+
+        // A zone frozen while watching keeps the changes until it thaws.
+        if !options.dry_run
+            && let Err(e) = freeze::check(agent, base_url, remote)
+        {
+            if !frozen {
+                eprintln!("{}", tr(Message::Error { error: &e }));
+                frozen = true;
+            }
+            waiting = changed;
+            continue;
+        }
+        frozen = false;
+        for path in changed {
+            let mut change = Change::default();
+            if let Err(e) = plan_change(&root, &paths, &path, options, &zone, &mut change) {
+                eprintln!("{}", tr(Message::Error { error: &e }));
+            }
+            waiting.extend(change.waiting);
+            for action in change.actions {
+                match sync::execute(
+                    agent,
+                    base_url,
+                    std::slice::from_ref(&action),
+                    options,
+                    reporter,
+                ) {
+                    Ok(pending) if pending.is_empty() => record(&mut zone, &action),
+                    Ok(_) => {}
+                    Err(e) => eprintln!("{}", tr(Message::Error { error: &e })),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Add the paths touched by a file system event to the set of changes.
+fn collect_paths(event: notify::Result<notify::Event>, changed: &mut BTreeSet<PathBuf>) {
+    match event {
+        Ok(event) => {
+            if matches!(
+                event.kind,
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+            ) {
+                changed.extend(event.paths);
+            }
+        }
+        Err(e) => eprintln!("{}", tr(Message::Error { error: &e })),
+    }
+}
+
+/// What to do about a changed path.
+#[derive(Default)]
+struct Change {
+    actions: Vec<SyncAction>,
+    /// Files changed within `--min-age`, which have to wait.
+    waiting: Vec<PathBuf>,
+}
+
+/// Plan the upload or delete of a changed path. A directory made or moved
+/// into the tree brings its files along, and one that is gone is deleted
+/// from the zone with everything in it.
+fn plan_change(
+    root: &Path,
+    paths: &PathMap,
+    path: &Path,
+    options: &SyncOptions,
+    zone: &RemoteIndex,
+    change: &mut Change,
+) -> Result<()> {
+    let Ok(relative_path) = path.strip_prefix(root) else {
+        return Ok(());
+    };
+    if options
+        .filter
+        .excludes(&paths::slash_path(relative_path), path.is_dir())
+        || local::is_ignored_path(relative_path)
+        || local::is_protected(path)
+    {
+        return Ok(());
+    }
+    let key = paths.remote_key(relative_path);
+
+    if path.is_dir() {
+        for entry in walkdir::WalkDir::new(path).min_depth(1).sort_by_file_name() {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                plan_change(root, paths, entry.path(), options, zone, change)?;
+            }
+        }
+    } else if path.is_file() {
+        if let Some(min_age) = options.min_age
+            && DateTime::<Utc>::from(path.metadata()?.modified()?) > Utc::now() - min_age
+        {
+            change.waiting.push(path.to_path_buf());
+            return Ok(());
+        }
+        change.actions.push(SyncAction::Upload {
+            key,
+            path: path.to_path_buf(),
+            length: sync::read_upload(path, &options.transforms)?.len() as u64,
+        });
+    } else if options.delete {
+        let dir = format!("{}/", key);
+        if zone.dirs.contains(&dir) {
+            let length = (zone.files.iter())
+                .filter(|(key, _)| key.starts_with(&dir))
+                .map(|(_, file)| file.length)
+                .sum();
+            change
+                .actions
+                .push(SyncAction::DeleteRemote { key: dir, length });
+        } else if let Some(file) = zone.files.get(&key) {
+            let length = file.length;
+            change
+                .actions
+                .push(SyncAction::DeleteRemote { key, length });
+        }
+    }
+    Ok(())
+}
+
+/// Bring the index of the zone up to date with an action carried out.
+fn record(zone: &mut RemoteIndex, action: &SyncAction) {
+    match action {
+        SyncAction::Upload { key, length, .. } => {
+            // Every directory above the file is in the zone now. Keys start
+            // with the zone, as in `/zone/dir/file.txt`.
+            for (end, _) in key.match_indices('/').skip(2) {
+                zone.dirs.insert(key[..=end].to_string());
+            }
+            let (path, name) = key.rsplit_once('/').unwrap_or(("", key));
+            zone.files.insert(
+                key.clone(),
+                StorageObject {
+                    path: format!("{}/", path),
+                    object_name: name.to_string(),
+                    length: *length,
+                    ..Default::default()
+                },
+            );
+        }
+        SyncAction::DeleteRemote { key, .. } if key.ends_with('/') => {
+            zone.dirs.retain(|dir| !dir.starts_with(key.as_str()));
+            zone.files.retain(|file, _| !file.starts_with(key.as_str()));
+        }
+        SyncAction::DeleteRemote { key, .. } => {
+            zone.files.remove(key);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make a directory to watch, with files at the relative paths.
+    fn watched(name: &str, files: &[&str]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("bunnysync-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for file in files {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "text").unwrap();
+        }
+        std::fs::create_dir_all(&root).unwrap();
+        root.canonicalize().unwrap()
+    }
+
+    fn plan(root: &Path, path: &str, options: &SyncOptions, zone: &RemoteIndex) -> Vec<SyncAction> {
+        let mut change = Change::default();
+        let paths = PathMap::new("myzone/");
+        plan_change(root, &paths, &root.join(path), options, zone, &mut change).unwrap();
+        change.actions
+    }
+
+    fn keys(actions: &[SyncAction]) -> Vec<&str> {
+        actions.iter().map(SyncAction::key).collect()
+    }
+
+    #[test]
+    fn test_plan_change_skips_ignored() {
+        let root = watched("watch-ignored", &[".git/HEAD", "index.html"]);
+        let options = SyncOptions::default();
+        let zone = RemoteIndex::default();
+        assert!(plan(&root, ".git/HEAD", &options, &zone).is_empty());
+        assert_eq!(
+            keys(&plan(&root, "index.html", &options, &zone)),
+            vec!["/myzone/index.html"]
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_plan_change_directories() {
+        let root = watched(
+            "watch-dirs",
+            &["docs/a.html", "docs/guide/b.html", "docs/.git/HEAD"],
+        );
+        let options = SyncOptions {
+            delete: true,
+            ..Default::default()
+        };
+        // A directory moved in has its files uploaded.
+        let mut zone = RemoteIndex::default();
+        let uploads = plan(&root, "docs", &options, &zone);
+        assert_eq!(
+            keys(&uploads),
+            vec!["/myzone/docs/a.html", "/myzone/docs/guide/b.html"]
+        );
+        for action in &uploads {
+            record(&mut zone, action);
+        }
+        assert!(zone.dirs.contains("/myzone/docs/guide/"));
+
+        // Once it is gone, it is deleted from the zone with its files, which
+        // then need no deletes of their own.
+        std::fs::remove_dir_all(root.join("docs")).unwrap();
+        let deletes = plan(&root, "docs", &options, &zone);
+        assert_eq!(
+            deletes,
+            vec![SyncAction::DeleteRemote {
+                key: "/myzone/docs/".to_string(),
+                length: 8,
+            }]
+        );
+        record(&mut zone, &deletes[0]);
+        assert!(plan(&root, "docs/a.html", &options, &zone).is_empty());
+        assert!(zone.files.is_empty() && zone.dirs.is_empty());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,365 @@
+//! Writing to WebDAV servers, which many NAS devices offer, for
+//! `bunnysync export`. Credentials come from `BUNNYSYNC_WEBDAV_USER` and
+//! `BUNNYSYNC_WEBDAV_PASSWORD`.
+
+use anyhow::{Result, anyhow};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use std::{collections::HashSet, fmt, sync::Mutex};
+use ureq::{
+    Agent,
+    http::{Request, StatusCode},
+};
+
+use crate::{
+    backend::{Backend, BackendFile},
+    s3::uri_encode,
+};
+
+/// A request the WebDAV credentials were turned down for.
+#[derive(Debug)]
+pub struct Unauthorized;
+
+impl fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "WebDAV server unauthorized, check BUNNYSYNC_WEBDAV_USER and BUNNYSYNC_WEBDAV_PASSWORD"
+        )
+    }
+}
+
+impl std::error::Error for Unauthorized {}
+
+/// The properties asked for when listing a directory.
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<propfind xmlns="DAV:"><prop><resourcetype/><getcontentlength/><getlastmodified/></prop></propfind>"#;
+
+/// A directory on a WebDAV server, named by a URL like
+/// `https://nas.local/dav/backups/`.
+pub struct WebDav {
+    /// The URL's scheme and host, like `https://nas.local`.
+    origin: String,
+    /// The directory's path, like `/dav/backups/`, as it appears in URLs.
+    root: String,
+    authorization: Option<String>,
+    agent: Agent,
+    /// Directories known to exist, so each is only made once.
+    made: Mutex<HashSet<String>>,
+}
+
+impl WebDav {
+    pub fn from_url(url: &str) -> Result<Self> {
+        let (scheme, rest) = url
+            .split_once("://")
+            .ok_or_else(|| anyhow!("{} is not a URL", url))?;
+        let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let path = path.trim_end_matches('/');
+        let authorization = match std::env::var("BUNNYSYNC_WEBDAV_USER") {
+            Ok(user) => {
+                let password = std::env::var("BUNNYSYNC_WEBDAV_PASSWORD").unwrap_or_default();
+                let credentials = format!("{}:{}", user, password);
+                Some(format!(
+                    "Basic {}",
+                    base64::engine::general_purpose::STANDARD.encode(credentials)
+                ))
+            }
+            Err(_) => None,
+        };
+        let config = Agent::config_builder()
+            .http_status_as_error(false)
+            .allow_non_standard_methods(true)
+            .build();
+        Ok(WebDav {
+            origin: format!("{}://{}", scheme, host),
+            root: if path.is_empty() {
+                "/".to_string()
+            } else {
+                format!("/{}/", path)
+            },
+            authorization,
+            agent: config.into(),
+            made: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// Send a request to a path on the server, like `/dav/backups/a.txt`.
+    fn send(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<(StatusCode, Vec<u8>)> {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(format!("{}{}", self.origin, path));
+        if let Some(authorization) = &self.authorization {
+            request = request.header("Authorization", authorization);
+        }
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let mut response = self.agent.run(request.body(body)?)?;
+        let body = response.body_mut().read_to_vec()?;
+        match response.status() {
+            StatusCode::UNAUTHORIZED => Err(Unauthorized.into()),
+            status => Ok((status, body)),
+        }
+    }
+
+    /// The path of a URL made by [`Backend::url_for`].
+    fn path<'a>(&self, url: &'a str) -> Result<&'a str> {
+        url.strip_prefix(&self.origin)
+            .filter(|path| path.starts_with(&self.root))
+            .ok_or_else(|| anyhow!("{} is not in {}{}", url, self.origin, self.root))
+    }
+
+    /// Make the directories from the root down to a file's path that don't
+    /// exist yet.
+    fn make_parents(&self, path: &str) -> Result<()> {
+        let mut dirs = vec![self.root.clone()];
+        let relative = &path[self.root.len()..];
+        if let Some((parents, _)) = relative.rsplit_once('/') {
+            for part in parents.split('/') {
+                dirs.push(format!("{}{}/", dirs[dirs.len() - 1], part));
+            }
+        }
+        let mut made = self.made.lock().unwrap();
+        for dir in dirs {
+            if dir == "/" || made.contains(&dir) {
+                continue;
+            }
+            let (status, _) = self.send("MKCOL", &dir, &[], &[])?;
+            // 405 means the directory is already there.
+            if !status.is_success() && status != StatusCode::METHOD_NOT_ALLOWED {
+                return Err(anyhow!("Could not make directory {}: HTTP {}", dir, status));
+            }
+            made.insert(dir);
+        }
+        Ok(())
+    }
+}
+
+impl Backend for WebDav {
+    fn url_for(&self, relative: &str) -> String {
+        format!(
+            "{}{}{}",
+            self.origin,
+            self.root,
+            uri_encode(relative, false)
+        )
+    }
+
+    /// Lists one directory at a time, as many servers turn down listing a
+    /// whole tree at once. A directory that doesn't exist yet is empty.
+    fn list(&self) -> Result<Vec<BackendFile>> {
+        let root = percent_decode(&self.root);
+        let mut files = Vec::new();
+        let mut dirs = vec![self.root.clone()];
+        while let Some(dir) = dirs.pop() {
+            let headers = [
+                ("Depth", "1"),
+                ("Content-Type", "application/xml; charset=utf-8"),
+            ];
+            let (status, body) = self.send("PROPFIND", &dir, &headers, PROPFIND_BODY.as_bytes())?;
+            if status == StatusCode::NOT_FOUND && dir == self.root {
+                return Ok(Vec::new());
+            }
+            if !status.is_success() {
+                return Err(anyhow!("Could not list {}: HTTP {}", dir, status));
+            }
+            for entry in parse_multistatus(&String::from_utf8_lossy(&body))? {
+                let path = percent_decode(&entry.href);
+                let Some(relative) = path.strip_prefix(&root) else {
+                    continue;
+                };
+                let relative = relative.trim_end_matches('/');
+                if relative.is_empty()
+                    || entry.href.trim_end_matches('/') == dir.trim_end_matches('/')
+                {
+                    continue;
+                }
+                if entry.collection {
+                    dirs.push(format!("{}/", entry.href.trim_end_matches('/')));
+                } else {
+                    files.push(BackendFile {
+                        url: self.url_for(relative),
+                        relative: relative.to_string(),
+                        length: entry.length,
+                        last_modified: entry.last_modified,
+                    });
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    fn upload(&self, url: &str, data: &[u8]) -> Result<()> {
+        let path = self.path(url)?;
+        self.make_parents(path)?;
+        let (status, _) = self.send("PUT", path, &[], data)?;
+        if !status.is_success() {
+            return Err(anyhow!("Could not upload {}: HTTP {}", url, status));
+        }
+        Ok(())
+    }
+
+    fn delete(&self, url: &str) -> Result<()> {
+        let (status, _) = self.send("DELETE", self.path(url)?, &[], &[])?;
+        if !status.is_success() && status != StatusCode::NOT_FOUND {
+            return Err(anyhow!("Could not delete {}: HTTP {}", url, status));
+        }
+        Ok(())
+    }
+}
+
+/// One `response` in a PROPFIND answer.
+#[derive(Debug, PartialEq)]
+struct Entry {
+    /// The path, percent encoded, as the server gave it.
+    href: String,
+    collection: bool,
+    length: u64,
+    last_modified: DateTime<Utc>,
+}
+
+/// Read the entries of a PROPFIND answer. Servers use all sorts of namespace
+/// prefixes, so elements are matched by their local name.
+fn parse_multistatus(xml: &str) -> Result<Vec<Entry>> {
+    elements(xml, "response")
+        .into_iter()
+        .filter_map(|response| {
+            let href = elements(response, "href").into_iter().next()?.trim();
+            // Some servers give full URLs rather than paths.
+            let href = match href.split_once("://") {
+                Some((_, rest)) => rest.find('/').map_or("/", |start| &rest[start..]),
+                None => href,
+            };
+            let collection = !elements(response, "collection").is_empty();
+            let length = elements(response, "getcontentlength")
+                .into_iter()
+                .next()
+                .and_then(|length| length.trim().parse().ok())
+                .unwrap_or(0);
+            let last_modified = match elements(response, "getlastmodified").into_iter().next() {
+                Some(time) => match DateTime::parse_from_rfc2822(time.trim()) {
+                    Ok(time) => time.into(),
+                    Err(e) => {
+                        return Some(Err(anyhow!("Bad time {} from WebDAV server: {}", time, e)));
+                    }
+                },
+                None => DateTime::UNIX_EPOCH,
+            };
+            Some(Ok(Entry {
+                href: href.to_string(),
+                collection,
+                length,
+                last_modified,
+            }))
+        })
+        .collect()
+}
+
+/// The contents of every element with a local name, whatever its namespace
+/// prefix. Empty elements like `<d:collection/>` have empty contents.
+fn elements<'a>(xml: &'a str, local_name: &str) -> Vec<&'a str> {
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .unwrap_or(rest.len());
+        let name = &rest[..end];
+        let local = name.rsplit(':').next().unwrap_or(name);
+        if local != local_name || name.is_empty() {
+            continue;
+        }
+        let Some(close) = rest.find('>') else {
+            break;
+        };
+        if rest[..close].ends_with('/') {
+            found.push("");
+            rest = &rest[close + 1..];
+            continue;
+        }
+        rest = &rest[close + 1..];
+        let closing = format!("</{}>", name);
+        let Some(end) = rest.find(&closing) else {
+            break;
+        };
+        found.push(&rest[..end]);
+        rest = &rest[end + closing.len()..];
+    }
+    found
+}
+
+/// Undo percent encoding, leaving anything that isn't valid as it is.
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = text
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            out.push(byte);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_multistatus() {
+        let xml = r#"<?xml version="1.0"?>
+            <d:multistatus xmlns:d="DAV:">
+              <d:response>
+                <d:href>/dav/backups/</d:href>
+                <d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop></d:propstat>
+              </d:response>
+              <d:response>
+                <d:href>https://nas.local/dav/backups/my%20site.html</d:href>
+                <d:propstat><d:prop>
+                  <d:resourcetype/>
+                  <d:getcontentlength>12</d:getcontentlength>
+                  <d:getlastmodified>Mon, 12 Jan 1998 09:25:56 GMT</d:getlastmodified>
+                </d:prop></d:propstat>
+              </d:response>
+            </d:multistatus>"#;
+        let entries = parse_multistatus(xml).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].href, "/dav/backups/");
+        assert!(entries[0].collection);
+        assert_eq!(
+            entries[1],
+            Entry {
+                href: "/dav/backups/my%20site.html".to_string(),
+                collection: false,
+                length: 12,
+                last_modified: DateTime::from_timestamp(884597156, 0).unwrap(),
+            }
+        );
+        assert_eq!(
+            percent_decode(&entries[1].href),
+            "/dav/backups/my site.html"
+        );
+    }
+
+    #[test]
+    fn test_elements() {
+        let xml = r#"<D:prop xmlns:D="DAV:"><D:href>/a</D:href><href>/b</href><lp1:href x="1">/c</lp1:href><D:hrefs>no</D:hrefs></D:prop>"#;
+        assert_eq!(elements(xml, "href"), vec!["/a", "/b", "/c"]);
+        assert_eq!(percent_decode("100%25%zz"), "100%%zz");
+    }
+}